@@ -0,0 +1,100 @@
+// Generates an extracted ROM, and a ROM rebuilt from it, to benchmark against.
+// Everything is deterministic so numbers can be compared between runs.
+
+use std::env;
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use gcmod::{paths::*, OrderStrategy, ROMRebuilder};
+
+pub struct Fixture {
+    pub dir: PathBuf,
+    pub root: PathBuf,
+    pub iso: PathBuf,
+    // The path of every file in the file system, relative to the root
+    pub files: Vec<PathBuf>,
+}
+
+impl Fixture {
+    // Makes `dirs` directories with `files_per_dir` files each. The files are
+    // between 1 and `max_file_size` bytes.
+    pub fn new(name: &str, dirs: usize, files_per_dir: usize, max_file_size: usize, alignment: u64) -> io::Result<Fixture> {
+        let dir = env::temp_dir().join(format!("gcmod-bench-{}-{}", name, process::id()));
+        if dir.exists() {
+            remove_dir_all(&dir)?;
+        }
+        let root = dir.join("root");
+        create_dir_all(root.join("&&systemdata"))?;
+
+        write_header(File::create(root.join(HEADER_PATH))?)?;
+        write_apploader(File::create(root.join(APPLOADER_PATH))?)?;
+        write_dol(File::create(root.join(DOL_PATH))?)?;
+
+        let mut files = Vec::with_capacity(dirs * files_per_dir);
+        let mut data = Vec::with_capacity(max_file_size);
+        for d in 0..dirs {
+            let dir_path = Path::new(&format!("dir{:03}", d)).to_path_buf();
+            create_dir_all(root.join(&dir_path))?;
+            for f in 0..files_per_dir {
+                let i = d * files_per_dir + f;
+                let size = 1 + (i * 7919) % max_file_size;
+                data.clear();
+                data.extend((0..size).map(|b| (b + i) as u8));
+
+                let path = dir_path.join(format!("file{:05}.bin", i));
+                File::create(root.join(&path))?.write_all(&data)?;
+                files.push(path);
+            }
+        }
+
+        let iso = dir.join("fixture.iso");
+        ROMRebuilder::rebuild_to_file(&root, alignment, &iso, Some(&OrderStrategy::FstOrder), false, |_| false, |_, _| {})?;
+
+        Ok(Fixture { dir, root, iso, files })
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.dir);
+    }
+}
+
+fn write_header(mut w: impl Write) -> io::Result<()> {
+    let mut header = vec![0; 0x2440];
+    header[..6].copy_from_slice(b"GBNCE8");
+    (&mut header[0x1c..]).write_u32::<BigEndian>(0xc2339f3d)?;
+    header[0x20..0x2d].copy_from_slice(b"Bench Fixture");
+    // The rebuild fills in the real DOL and FST offsets, but these have to
+    // look valid for the header to be read at all
+    (&mut header[0x420..]).write_u32::<BigEndian>(0x10000)?;
+    (&mut header[0x424..]).write_u32::<BigEndian>(0x20000)?;
+    w.write_all(&header)
+}
+
+fn write_apploader(mut w: impl Write) -> io::Result<()> {
+    let code_size = 0x100;
+    let mut apploader = vec![0; 0x20 + code_size];
+    apploader[..10].copy_from_slice(b"2003/04/17");
+    (&mut apploader[0x10..]).write_u32::<BigEndian>(0x81200000)?;
+    (&mut apploader[0x14..]).write_u32::<BigEndian>(code_size as u32)?;
+    w.write_all(&apploader)
+}
+
+fn write_dol(mut w: impl Write) -> io::Result<()> {
+    let text_size = 0x100;
+    let mut dol = vec![0; 0x100 + text_size];
+    (&mut dol[0x00..]).write_u32::<BigEndian>(0x100)?;
+    (&mut dol[0x48..]).write_u32::<BigEndian>(0x80003100)?;
+    (&mut dol[0x90..]).write_u32::<BigEndian>(text_size as u32)?;
+    (&mut dol[0xe0..]).write_u32::<BigEndian>(0x80003100)?;
+    for word in dol[0x100..].chunks_mut(4) {
+        // blr
+        (&mut word[..]).write_u32::<BigEndian>(0x4e800020)?;
+    }
+    w.write_all(&dol)
+}
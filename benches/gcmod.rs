@@ -0,0 +1,112 @@
+#[macro_use]
+extern crate criterion;
+extern crate byteorder;
+extern crate gcmod;
+
+mod fixture;
+
+use std::fs::File;
+use std::io::{self, BufReader, Seek, SeekFrom};
+use std::path::Path;
+
+use criterion::{black_box, Criterion, Throughput};
+
+use gcmod::{
+    extract_section,
+    Game,
+    hash::{hash_reader, HashAlgo},
+    MIN_ALIGNMENT,
+    OrderStrategy,
+    ROMRebuilder,
+};
+
+#[cfg(feature = "mmap")]
+use gcmod::source::mmap::MmapDisc;
+
+use fixture::Fixture;
+
+fn open_game(iso: &Path) -> (Game, BufReader<File>) {
+    let mut iso = BufReader::new(File::open(iso).unwrap());
+    let game = Game::open(&mut iso, 0).unwrap();
+    (game, iso)
+}
+
+// ~50k entries, like a big game
+fn large_fst(c: &mut Criterion) {
+    let fixture = Fixture::new("large", 100, 500, 64, MIN_ALIGNMENT).unwrap();
+    let (game, _) = open_game(&fixture.iso);
+
+    c.bench_function("Game::open (50k entries)", |b| b.iter(|| {
+        black_box(open_game(&fixture.iso))
+    }));
+
+    let last = Path::new("/").join(fixture.files.last().unwrap());
+    let last_name = last.file_name().unwrap().to_os_string();
+    c.bench_function("FST::entry_for_path (full path)", |b| b.iter(|| {
+        black_box(game.fst.entry_for_path(&last)).unwrap();
+    }));
+    c.bench_function("FST::entry_for_path (name only)", |b| b.iter(|| {
+        black_box(game.fst.entry_for_path(&last_name)).unwrap();
+    }));
+}
+
+fn extract_and_rebuild(c: &mut Criterion) {
+    let fixture = Fixture::new("small", 10, 100, 16 * 1024, 32).unwrap();
+    let (game, mut iso) = open_game(&fixture.iso);
+    let files = game.fst.entries.iter().filter_map(|e| e.as_file()).collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("1000 small files");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(game.fst.total_file_system_size as u64));
+
+    group.bench_function("extract to a null sink", |b| b.iter(|| {
+        for f in &files {
+            iso.seek(SeekFrom::Start(f.file_offset)).unwrap();
+            extract_section(&mut iso, f.size, io::sink()).unwrap();
+        }
+    }));
+
+    // `FileEntry::extract` writes straight from the reader's buffer when the
+    // whole file is already in it. These write to memory, since a null sink
+    // would never touch the mapped pages.
+    let mut buf = Vec::with_capacity(16 * 1024);
+    group.bench_function("FileEntry::extract to memory", |b| b.iter(|| {
+        for f in &files {
+            buf.clear();
+            f.extract(&mut iso, &mut buf).unwrap();
+        }
+    }));
+
+    #[cfg(feature = "mmap")]
+    {
+        let mut disc = MmapDisc::open(&fixture.iso).unwrap();
+        group.bench_function("FileEntry::extract to memory (mmap)", |b| b.iter(|| {
+            for f in &files {
+                buf.clear();
+                f.extract(&mut disc, &mut buf).unwrap();
+            }
+        }));
+    }
+
+    group.bench_function("rebuild to a null sink", |b| b.iter(|| {
+        black_box(ROMRebuilder::rebuild(&fixture.root, 32, io::sink(), Some(&OrderStrategy::FstOrder), false, |_| false, |_, _| {}).unwrap())
+    }));
+    group.finish();
+}
+
+fn hashing(c: &mut Criterion) {
+    let data = (0..16 * 1024 * 1024).map(|i| (i * 31 % 251) as u8).collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("hash 16MiB");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    for &algo in HashAlgo::ALL.iter().filter(|a| a.is_supported()) {
+        group.bench_function(algo.name(), |b| b.iter(|| {
+            black_box(hash_reader(&[algo], &data[..]).unwrap())
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, large_fst, extract_and_rebuild, hashing);
+criterion_main!(benches);
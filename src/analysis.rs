@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use disassembler::{Disassembler, Symbol, SymbolMap};
+use sections::dol::AddressOffset;
+use sections::dol::DOLHeader;
+use sections::dol::segment::SegmentType;
+
+// The opcode of an unconditional `blr`
+const BLR: u32 = 0x4e80_0020;
+
+// The SDK functions that are easy to pick out, since they're the same in
+// every game, by their first instructions. Relative branches only have to
+// match up to where they go, so they're found wherever they're linked.
+const SDK_SIGNATURES: &[(&str, &[u32])] = &[
+    ("memcpy", &[
+        0x7c04_1840, 0x4180_0028, 0x3884_ffff, 0x38c3_ffff,
+        0x38a5_0001, 0x4800_000c, 0x8c04_0001, 0x9c06_0001,
+        0x34a5_ffff, 0x4082_fff4, BLR,
+    ]),
+    ("DCFlushRange", &[
+        0x2804_0000, 0x4c81_0020, 0x5465_06ff, 0x4182_0008,
+        0x3884_0020, 0x3884_001f, 0x5484_d97e, 0x7c89_03a6,
+        0x7c00_18ac, 0x3863_0020, 0x4200_fff8, 0x4400_0002, BLR,
+    ]),
+    ("DCStoreRange", &[
+        0x2804_0000, 0x4c81_0020, 0x5465_06ff, 0x4182_0008,
+        0x3884_0020, 0x3884_001f, 0x5484_d97e, 0x7c89_03a6,
+        0x7c00_186c, 0x3863_0020, 0x4200_fff8, 0x4400_0002, BLR,
+    ]),
+    ("DCInvalidateRange", &[
+        0x2804_0000, 0x4c81_0020, 0x5465_06ff, 0x4182_0008,
+        0x3884_0020, 0x3884_001f, 0x5484_d97e, 0x7c89_03a6,
+        0x7c00_1bac, 0x3863_0020, 0x4200_fff8, BLR,
+    ]),
+    ("ICInvalidateRange", &[
+        0x2804_0000, 0x4c81_0020, 0x5465_06ff, 0x4182_0008,
+        0x3884_0020, 0x3884_001f, 0x5484_d97e, 0x7c89_03a6,
+        0x7c00_1fac, 0x3863_0020, 0x4200_fff8, 0x7c00_04ac,
+        0x4c00_012c, BLR,
+    ]),
+    ("PPCMfmsr", &[0x7c60_00a6, BLR]),
+    ("PPCMtmsr", &[0x7c60_0124, BLR]),
+    ("PPCMfhid0", &[0x7c70_faa6, BLR]),
+    ("PPCMthid0", &[0x7c70_fba6, BLR]),
+];
+
+// Which functions call which, found by following the `bl`s in the DOL
+#[derive(Clone, Debug, Default)]
+pub struct CallGraph {
+    // Start address -> size in bytes
+    functions: BTreeMap<u64, u64>,
+    // (caller, callee), both function start addresses
+    calls: BTreeSet<(u64, u64)>,
+    // Calls through a register (`bctrl`, `blrl`), which can't be followed
+    pub indirect_calls: usize,
+}
+
+impl CallGraph {
+    // (address, size) of every function found
+    pub fn functions(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.functions.iter().map(|(&a, &s)| (a, s))
+    }
+
+    // The function containing `address`
+    pub fn function_at(&self, address: u64) -> Option<u64> {
+        let (&start, &size) = self.functions.range(..=address).next_back()?;
+        if address < start + size { Some(start) } else { None }
+    }
+
+    pub fn calls(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.calls.iter().cloned()
+    }
+
+    pub fn callers_of(&self, address: u64) -> Vec<u64> {
+        self.calls.iter()
+            .filter(|&&(_, callee)| callee == address)
+            .map(|&(caller, _)| caller)
+            .collect()
+    }
+
+    pub fn callees_of(&self, address: u64) -> Vec<u64> {
+        self.calls.range((address, 0)..=(address, u64::MAX))
+            .map(|&(_, callee)| callee)
+            .collect()
+    }
+
+    // Writes the graph in Graphviz's format
+    pub fn write_dot(&self, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "digraph calls {{")?;
+        writeln!(output, "    node [shape=box, fontname=monospace];")?;
+        for (address, size) in self.functions() {
+            writeln!(output, "    \"{:08x}\" [label=\"fn_{:08x}\\n{:#x} bytes\"];", address, address, size)?;
+        }
+        for (caller, callee) in self.calls() {
+            writeln!(output, "    \"{:08x}\" -> \"{:08x}\";", caller, callee)?;
+        }
+        writeln!(output, "}}")
+    }
+}
+
+// Walks every text segment in `dol` to find its functions and the calls
+// between them. A function starts at the entry point, at anything called
+// with `bl`, and after every `blr`, so functions with early returns end up
+// split into pieces.
+pub fn call_graph(dol: &DOLHeader, mut iso: impl Read + Seek) -> io::Result<CallGraph> {
+    let disassembler = Disassembler::new();
+    let segments = dol.iter_segments()
+        .filter(|s| s.seg_type == SegmentType::Text)
+        .collect::<Vec<_>>();
+    let in_text = |address: u64| segments.iter().any(|s|
+        s.loading_address <= address && address < s.loading_address + s.size as u64
+    );
+
+    let mut starts = BTreeSet::new();
+    // (where the call is, what it calls)
+    let mut direct_calls = Vec::new();
+    let mut indirect_calls = 0;
+
+    if in_text(dol.entry_point) {
+        starts.insert(dol.entry_point);
+    }
+    for segment in &segments {
+        starts.insert(segment.loading_address);
+        let end = segment.loading_address + segment.size as u64;
+        for ins in disassembler.disasm(&mut iso, *segment, None)? {
+            let ins = ins?;
+            if ins.opcode == BLR && ins.location + 4 < end {
+                starts.insert(ins.location + 4);
+            }
+            if !ins.is_call() { continue }
+            match ins.branch_target() {
+                Some(target) if in_text(target) => {
+                    starts.insert(target);
+                    direct_calls.push((ins.location, target));
+                },
+                Some(_) => {},
+                None => indirect_calls += 1,
+            }
+        }
+    }
+
+    // Each function runs until the next one, or the end of its segment
+    let mut graph = CallGraph { indirect_calls, ..CallGraph::default() };
+    for segment in &segments {
+        let end = segment.loading_address + segment.size as u64;
+        let mut in_segment = starts.range(segment.loading_address..end).peekable();
+        while let Some(&start) = in_segment.next() {
+            let next = in_segment.peek().map_or(end, |&&n| n);
+            graph.functions.insert(start, next - start);
+        }
+    }
+    for (location, target) in direct_calls {
+        if let Some(caller) = graph.function_at(location) {
+            graph.calls.insert((caller, target));
+        }
+    }
+    Ok(graph)
+}
+
+// A symbol map for the DOL with every function `call_graph` finds. They're
+// all called `fn_<address>`, except for the entry point, which is `__start`,
+// and the SDK functions that can be recognized.
+pub fn generate_map(dol: &DOLHeader, mut iso: impl Read + Seek) -> io::Result<SymbolMap> {
+    let graph = call_graph(dol, &mut iso)?;
+    let mut map = SymbolMap::default();
+    let mut found = BTreeSet::new();
+    for (address, size) in graph.functions() {
+        let name = if address == dol.entry_point {
+            "__start".to_string()
+        } else {
+            match sdk_function(dol, &mut iso, address, size)? {
+                // Only the first copy gets the name, in case the signature
+                // is short enough to turn up somewhere else too
+                Some(name) if found.insert(name) => name.to_string(),
+                _ => format!("fn_{:08x}", address),
+            }
+        };
+        map.insert(address, Symbol { name, size });
+    }
+    Ok(map)
+}
+
+// Which of `SDK_SIGNATURES` the function at `address` starts with
+fn sdk_function(dol: &DOLHeader, mut iso: impl Read + Seek, address: u64, size: u64) -> io::Result<Option<&'static str>> {
+    let longest = SDK_SIGNATURES.iter().map(|(_, s)| s.len()).max().unwrap_or(0);
+    let len = (size / 4).min(longest as u64) as usize;
+    let offset = match dol.address_to_offset(address) {
+        Some(AddressOffset::File(offset)) => offset,
+        _ => return Ok(None),
+    };
+    iso.seek(SeekFrom::Start(offset))?;
+    let mut code = Vec::with_capacity(len);
+    for _ in 0..len {
+        code.push(iso.read_u32::<BigEndian>()?);
+    }
+    Ok(SDK_SIGNATURES.iter()
+        .find(|(_, sig)| sig.len() <= code.len() && sig.iter().zip(&code).all(|(&s, &c)| same_instruction(s, c)))
+        .map(|&(name, _)| name))
+}
+
+// Whether `a` and `b` are the same, apart from where they branch to if
+// they're relative branches
+fn same_instruction(a: u32, b: u32) -> bool {
+    let mask = match a >> 26 {
+        // b, bl
+        18 => 0xfc00_0003,
+        // bc
+        16 => 0xffff_0003,
+        _ => 0xffff_ffff,
+    };
+    a & mask == b & mask
+}
@@ -0,0 +1,312 @@
+// Writes tar and zip archives, so a ROM can be extracted straight into one
+// without writing every file to disk first. Only what's needed for that is
+// here: directories and plain files, with zip files stored uncompressed.
+// https://www.gnu.org/software/tar/manual/html_node/Standard.html
+// https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use crc32fast::Hasher;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    // Guesses the format from `path`'s extension, like `game.tar`
+    pub fn from_path(path: impl AsRef<Path>) -> Option<ArchiveFormat> {
+        let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match &*ext {
+            "tar" => Some(ArchiveFormat::Tar),
+            "zip" => Some(ArchiveFormat::Zip),
+            _ => None,
+        }
+    }
+}
+
+pub trait ArchiveWriter {
+    // `path` uses `/` and doesn't start with one
+    fn add_directory(&mut self, path: &str) -> io::Result<()>;
+
+    // Fails if `data` doesn't have exactly `size` bytes
+    fn add_file(&mut self, path: &str, size: u64, data: impl Read) -> io::Result<()>;
+
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+const TAR_BLOCK_SIZE: usize = 512;
+
+pub struct TarWriter<W: Write> {
+    output: W,
+    mtime: u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(output: W) -> TarWriter<W> {
+        TarWriter { output, mtime: now() }
+    }
+
+    fn write_header(&mut self, path: &str, size: u64, mode: u32, kind: u8) -> io::Result<()> {
+        let mut header = [0; TAR_BLOCK_SIZE];
+        match split_tar_path(path) {
+            Some((prefix, name)) => {
+                header[..name.len()].copy_from_slice(name.as_bytes());
+                header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+            },
+            None => {
+                // Too long for a ustar header, so it goes in a GNU long name
+                // entry right before this one
+                self.write_header("././@LongLink", path.len() as u64 + 1, 0o644, b'L')?;
+                self.write_padded(path.as_bytes(), path.len() as u64 + 1)?;
+                header[..99].copy_from_slice(&path.as_bytes()[..99]);
+            },
+        }
+        write_octal(&mut header[100..108], mode as u64);
+        write_octal(&mut header[108..116], 0);
+        write_octal(&mut header[116..124], 0);
+        write_octal(&mut header[124..136], size);
+        write_octal(&mut header[136..148], self.mtime);
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        // The checksum is worked out as if its own field were spaces
+        header[148..156].copy_from_slice(b"        ");
+        let checksum = header.iter().map(|&b| b as u64).sum::<u64>();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        self.output.write_all(&header)
+    }
+
+    // Writes `data`, then zeros up to `len` and the end of the block
+    fn write_padded(&mut self, data: &[u8], len: u64) -> io::Result<()> {
+        self.output.write_all(data)?;
+        let padding = tar_padding(len) + len - data.len() as u64;
+        io::copy(&mut io::repeat(0).take(padding), &mut self.output)?;
+        Ok(())
+    }
+}
+
+impl<W: Write> ArchiveWriter for TarWriter<W> {
+    fn add_directory(&mut self, path: &str) -> io::Result<()> {
+        self.write_header(&format!("{}/", path), 0, 0o755, b'5')
+    }
+
+    fn add_file(&mut self, path: &str, size: u64, data: impl Read) -> io::Result<()> {
+        self.write_header(path, size, 0o644, b'0')?;
+        copy_exactly(data, size, &mut self.output, path)?;
+        io::copy(&mut io::repeat(0).take(tar_padding(size)), &mut self.output)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.write_all(&[0; TAR_BLOCK_SIZE * 2])?;
+        self.output.flush()
+    }
+}
+
+// Splits `path` into a ustar prefix and name, if it fits
+fn split_tar_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+fn tar_padding(len: u64) -> u64 {
+    (TAR_BLOCK_SIZE as u64 - len % TAR_BLOCK_SIZE as u64) % TAR_BLOCK_SIZE as u64
+}
+
+// A file that's already been written, for the central directory
+struct ZipEntry {
+    path: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+    is_dir: bool,
+}
+
+pub struct ZipWriter<W: Write + Seek> {
+    output: W,
+    entries: Vec<ZipEntry>,
+    position: u64,
+    dos_time: u16,
+    dos_date: u16,
+}
+
+impl<W: Write + Seek> ZipWriter<W> {
+    pub fn new(output: W) -> ZipWriter<W> {
+        let (dos_date, dos_time) = dos_date_time(now());
+        ZipWriter { output, entries: Vec::new(), position: 0, dos_time, dos_date }
+    }
+
+    fn write_local_header(&mut self, path: &str, crc: u32, size: u32) -> io::Result<()> {
+        let o = &mut self.output;
+        o.write_u32::<LittleEndian>(0x04034b50)?;
+        o.write_u16::<LittleEndian>(ZIP_VERSION)?;
+        o.write_u16::<LittleEndian>(ZIP_UTF8_FLAG)?;
+        o.write_u16::<LittleEndian>(0)?; // stored
+        o.write_u16::<LittleEndian>(self.dos_time)?;
+        o.write_u16::<LittleEndian>(self.dos_date)?;
+        o.write_u32::<LittleEndian>(crc)?;
+        o.write_u32::<LittleEndian>(size)?;
+        o.write_u32::<LittleEndian>(size)?;
+        o.write_u16::<LittleEndian>(path.len() as u16)?;
+        o.write_u16::<LittleEndian>(0)?;
+        o.write_all(path.as_bytes())?;
+        self.position += ZIP_LOCAL_HEADER_SIZE + path.len() as u64;
+        Ok(())
+    }
+
+    fn offset(&self) -> io::Result<u32> {
+        zip_u32(self.position)
+    }
+}
+
+const ZIP_VERSION: u16 = 20;
+const ZIP_UTF8_FLAG: u16 = 1 << 11;
+const ZIP_LOCAL_HEADER_SIZE: u64 = 30;
+// Where the CRC is in a local header
+const ZIP_CRC_OFFSET: u64 = 14;
+
+impl<W: Write + Seek> ArchiveWriter for ZipWriter<W> {
+    fn add_directory(&mut self, path: &str) -> io::Result<()> {
+        let path = format!("{}/", path);
+        let offset = self.offset()?;
+        self.write_local_header(&path, 0, 0)?;
+        self.entries.push(ZipEntry { path, crc: 0, size: 0, offset, is_dir: true });
+        Ok(())
+    }
+
+    fn add_file(&mut self, path: &str, size: u64, data: impl Read) -> io::Result<()> {
+        let offset = self.offset()?;
+        let size32 = zip_u32(size)?;
+        // The CRC isn't known until the data's been written, so it's filled
+        // in afterwards
+        self.write_local_header(path, 0, size32)?;
+        let mut hasher = Hasher::new();
+        copy_exactly(data, size, HashingWriter(&mut self.output, &mut hasher), path)?;
+        let crc = hasher.finalize();
+        self.position += size;
+
+        self.output.seek(SeekFrom::Current(-((size + path.len() as u64 + ZIP_LOCAL_HEADER_SIZE - ZIP_CRC_OFFSET) as i64)))?;
+        self.output.write_u32::<LittleEndian>(crc)?;
+        self.output.seek(SeekFrom::Current((size + path.len() as u64 + ZIP_LOCAL_HEADER_SIZE - ZIP_CRC_OFFSET - 4) as i64))?;
+
+        self.entries.push(ZipEntry { path: path.to_string(), crc, size: size32, offset, is_dir: false });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let directory_offset = self.offset()?;
+        let o = &mut self.output;
+        for e in &self.entries {
+            let mode: u32 = if e.is_dir { 0o040755 } else { 0o100644 };
+            o.write_u32::<LittleEndian>(0x02014b50)?;
+            // Made by Unix, so the mode is used
+            o.write_u16::<LittleEndian>(3 << 8 | ZIP_VERSION)?;
+            o.write_u16::<LittleEndian>(ZIP_VERSION)?;
+            o.write_u16::<LittleEndian>(ZIP_UTF8_FLAG)?;
+            o.write_u16::<LittleEndian>(0)?;
+            o.write_u16::<LittleEndian>(self.dos_time)?;
+            o.write_u16::<LittleEndian>(self.dos_date)?;
+            o.write_u32::<LittleEndian>(e.crc)?;
+            o.write_u32::<LittleEndian>(e.size)?;
+            o.write_u32::<LittleEndian>(e.size)?;
+            o.write_u16::<LittleEndian>(e.path.len() as u16)?;
+            o.write_u16::<LittleEndian>(0)?; // extra field
+            o.write_u16::<LittleEndian>(0)?; // comment
+            o.write_u16::<LittleEndian>(0)?; // disk
+            o.write_u16::<LittleEndian>(0)?; // internal attributes
+            o.write_u32::<LittleEndian>(mode << 16 | if e.is_dir { 0x10 } else { 0 })?;
+            o.write_u32::<LittleEndian>(e.offset)?;
+            o.write_all(e.path.as_bytes())?;
+            self.position += 46 + e.path.len() as u64;
+        }
+        let directory_size = zip_u32(self.position)? - directory_offset;
+        let count = u16::try_from(self.entries.len())
+            .map_err(|_| io::Error::other("There are too many files for a zip archive."))?;
+
+        let o = &mut self.output;
+        o.write_u32::<LittleEndian>(0x06054b50)?;
+        o.write_u16::<LittleEndian>(0)?;
+        o.write_u16::<LittleEndian>(0)?;
+        o.write_u16::<LittleEndian>(count)?;
+        o.write_u16::<LittleEndian>(count)?;
+        o.write_u32::<LittleEndian>(directory_size)?;
+        o.write_u32::<LittleEndian>(directory_offset)?;
+        o.write_u16::<LittleEndian>(0)?;
+        o.flush()
+    }
+}
+
+// Without ZIP64, sizes and offsets have to fit in 32 bits. A whole GameCube
+// ROM does, so this only fails for something that isn't one.
+fn zip_u32(n: u64) -> io::Result<u32> {
+    u32::try_from(n).map_err(|_| io::Error::other("The archive is too big for a zip file."))
+}
+
+struct HashingWriter<'a, W: 'a>(W, &'a mut Hasher);
+
+impl<'a, W: Write> Write for HashingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        self.1.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+fn copy_exactly(data: impl Read, size: u64, mut output: impl Write, path: &str) -> io::Result<()> {
+    let copied = io::copy(&mut data.take(size), &mut output)?;
+    if copied < size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} ended after {} of its {} bytes", path, copied, size),
+        ));
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// The date and time in the format zip files use, which can't go before 1980
+fn dos_date_time(unix_time: u64) -> (u16, u16) {
+    let days = unix_time / 86400;
+    let seconds = unix_time % 86400;
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    if year < 1980 {
+        return (1 << 5 | 1, 0);
+    }
+    let date = ((year - 1980) << 9 | (month << 5) | day) as u16;
+    let time = ((seconds / 3600) << 11 | (seconds % 3600 / 60) << 5 | (seconds % 60 / 2)) as u16;
+    (date, time)
+}
@@ -0,0 +1,586 @@
+// Yaz0 and Yay0, the LZ compression Nintendo's games use for a lot of their
+// files, like .szs archives. Both start with a 16 byte header with the magic
+// and how big the data is decompressed. Each chunk of data is either a byte
+// to copy as is, or a back-reference to up to 0x111 bytes somewhere in the
+// last 4 KiB of what's been decompressed so far. Yaz0 keeps them all in one
+// stream, with a byte of flags before every 8 of them, and Yay0 splits the
+// flags, back-references, and bytes into separate parts of the file.
+// http://www.amnoid.de/gc/yaz0.txt
+
+use std::fmt;
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use paths::COMPRESSED_LIST_PATH;
+use ::{read_fully, AppError, Game};
+
+// What `--dec-suffix` adds to decompressed files' names
+pub const DECOMPRESSED_SUFFIX: &str = ".dec";
+
+const HEADER_SIZE: usize = 16;
+const WINDOW_SIZE: usize = 0x1000;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 0x111;
+// Matches at least this long store their length in an extra byte
+const LONG_MATCH: usize = 0x12;
+// The most either format can grow by, which is a back-reference to 0x111
+// bytes for every 3 bytes or so. A header that says the data is bigger than
+// this is corrupt, so nothing that big is allocated for it.
+const MAX_EXPANSION: usize = 88;
+
+// How much of each chain of earlier positions `MatchFinder` looks through.
+// More finds better matches, but takes longer.
+const MAX_CHAIN: usize = 256;
+const HASH_BITS: u32 = 15;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum Compression {
+    Yaz0,
+    Yay0,
+}
+
+impl Compression {
+    // Goes by the magic at the start of `data`
+    pub fn detect(data: &[u8]) -> Option<Compression> {
+        match data.get(..4) {
+            Some(b"Yaz0") => Some(Compression::Yaz0),
+            Some(b"Yay0") => Some(Compression::Yay0),
+            _ => None,
+        }
+    }
+
+    fn magic(self) -> &'static [u8; 4] {
+        match self {
+            Compression::Yaz0 => b"Yaz0",
+            Compression::Yay0 => b"Yay0",
+        }
+    }
+
+    // `data` is the whole file, header and all. It's never decompressed to
+    // more than the size in the header.
+    pub fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        if Compression::detect(data) != Some(self) || data.len() < HEADER_SIZE {
+            return Err(invalid(format!("This isn't {} data.", self)));
+        }
+        let size = BigEndian::read_u32(&data[4..]) as usize;
+        if size > (data.len() - HEADER_SIZE).saturating_mul(MAX_EXPANSION) {
+            return Err(invalid(format!(
+                "The {} header says it's {} bytes decompressed, which is too big for {} bytes of data.",
+                self,
+                size,
+                data.len(),
+            )));
+        }
+        let mut out = Vec::with_capacity(size);
+        match self {
+            Compression::Yaz0 => decode_yaz0(data, &mut out, size)?,
+            Compression::Yay0 => decode_yay0(data, &mut out, size)?,
+        }
+        Ok(out)
+    }
+
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        if data.len() > u32::MAX as usize {
+            return Err(invalid(format!("{} can't hold more than 4 GiB.", self)));
+        }
+        let mut out = Vec::with_capacity(HEADER_SIZE + data.len() / 2);
+        out.extend_from_slice(self.magic());
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        match self {
+            Compression::Yaz0 => {
+                out.extend_from_slice(&[0; 8]);
+                encode_yaz0(data, &mut out);
+            },
+            Compression::Yay0 => encode_yay0(data, &mut out),
+        }
+        Ok(out)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Compression::Yaz0 => "Yaz0",
+            Compression::Yay0 => "Yay0",
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Compression, AppError> {
+        match s.to_lowercase().as_str() {
+            "yaz0" => Ok(Compression::Yaz0),
+            "yay0" => Ok(Compression::Yay0),
+            _ => Err(AppError::new(format!("Unknown compression: {}", s))),
+        }
+    }
+}
+
+// The bytes of one part of a compressed file, which fail to read past the end
+// of the file instead of panicking
+struct Bytes<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn u8(&mut self) -> io::Result<u8> {
+        let b = *self.data.get(self.pos)
+            .ok_or_else(|| invalid("The compressed data ends early.".to_string()))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from(self.u8()?) << 8 | u16::from(self.u8()?))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from(self.u16()?) << 16 | u32::from(self.u16()?))
+    }
+}
+
+// Copies `len` bytes from `dist` bytes back, which can overlap what's being
+// written, like a run of the same byte
+fn copy_back(out: &mut Vec<u8>, dist: usize, len: usize, size: usize) -> io::Result<()> {
+    if dist > out.len() {
+        return Err(invalid(format!("A back-reference at {:#x} goes before the start of the data.", out.len())));
+    }
+    if out.len() + len > size {
+        return Err(invalid("The compressed data goes past its decompressed size.".to_string()));
+    }
+    for _ in 0..len {
+        out.push(out[out.len() - dist]);
+    }
+    Ok(())
+}
+
+// A back-reference's length is in its top 4 bits, plus 2, or in the next byte
+// plus 0x12 if they're 0
+fn match_len(nibble: u16, bytes: &mut Bytes) -> io::Result<usize> {
+    Ok(match nibble {
+        0 => bytes.u8()? as usize + LONG_MATCH,
+        n => n as usize + 2,
+    })
+}
+
+fn decode_yaz0(data: &[u8], out: &mut Vec<u8>, size: usize) -> io::Result<()> {
+    let mut src = Bytes { data, pos: HEADER_SIZE };
+    while out.len() < size {
+        let flags = src.u8()?;
+        for bit in 0..8 {
+            if out.len() >= size { break }
+            if flags & (0x80 >> bit) != 0 {
+                out.push(src.u8()?);
+            } else {
+                let r = src.u16()?;
+                let len = match_len(r >> 12, &mut src)?;
+                copy_back(out, (r & 0xFFF) as usize + 1, len, size)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn decode_yay0(data: &[u8], out: &mut Vec<u8>, size: usize) -> io::Result<()> {
+    let link_offset = BigEndian::read_u32(&data[8..]) as usize;
+    let chunk_offset = BigEndian::read_u32(&data[12..]) as usize;
+    let mut masks = Bytes { data, pos: HEADER_SIZE };
+    let mut links = Bytes { data, pos: link_offset };
+    let mut chunks = Bytes { data, pos: chunk_offset };
+    let mut mask = 0;
+    let mut bits = 0;
+    while out.len() < size {
+        if bits == 0 {
+            mask = masks.u32()?;
+            bits = 32;
+        }
+        if mask & 0x8000_0000 != 0 {
+            out.push(chunks.u8()?);
+        } else {
+            let r = links.u16()?;
+            let len = match_len(r >> 12, &mut chunks)?;
+            copy_back(out, (r & 0xFFF) as usize + 1, len, size)?;
+        }
+        mask <<= 1;
+        bits -= 1;
+    }
+    Ok(())
+}
+
+// Finds the longest earlier match for each position, within the window a
+// back-reference can reach. Earlier positions are kept in chains by a hash
+// of their first 3 bytes, like zlib does.
+struct MatchFinder<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+const NO_POSITION: usize = usize::MAX;
+
+impl<'a> MatchFinder<'a> {
+    fn new(data: &'a [u8]) -> MatchFinder<'a> {
+        MatchFinder {
+            data,
+            head: vec![NO_POSITION; 1 << HASH_BITS],
+            prev: vec![NO_POSITION; data.len()],
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let d = &self.data[pos..pos + MIN_MATCH];
+        let h = (u32::from(d[0]) << 16 | u32::from(d[1]) << 8 | u32::from(d[2])).wrapping_mul(0x9E37_79B1);
+        (h >> (32 - HASH_BITS)) as usize
+    }
+
+    // Every position has to be added, in order, before `find` is called for
+    // anything after it
+    fn add(&mut self, pos: usize) {
+        if pos + MIN_MATCH > self.data.len() { return }
+        let h = self.hash(pos);
+        self.prev[pos] = self.head[h];
+        self.head[h] = pos;
+    }
+
+    // (how far back, how long), or a length of 0 if there's nothing at
+    // least `MIN_MATCH` long
+    fn find(&self, pos: usize) -> (usize, usize) {
+        let max_len = MAX_MATCH.min(self.data.len() - pos);
+        if max_len < MIN_MATCH { return (0, 0) }
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(pos)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == NO_POSITION || pos - candidate > WINDOW_SIZE { break }
+            let len = self.data[candidate..].iter()
+                .zip(&self.data[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best.1 {
+                best = (pos - candidate, len);
+                if len == max_len { break }
+            }
+            candidate = self.prev[candidate];
+        }
+        if best.1 < MIN_MATCH { (0, 0) } else { best }
+    }
+}
+
+// A chunk of compressed data
+enum Token {
+    Literal(u8),
+    // (how far back, how long)
+    BackReference(usize, usize),
+}
+
+// Splits data up into `Token`s, taking the longest match at each position
+struct Tokens<'a> {
+    finder: MatchFinder<'a>,
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(data: &'a [u8]) -> Tokens<'a> {
+        Tokens { finder: MatchFinder::new(data), pos: 0 }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let pos = self.pos;
+        let byte = *self.finder.data.get(pos)?;
+        let (token, len) = match self.finder.find(pos) {
+            (_, 0) => (Token::Literal(byte), 1),
+            (dist, len) => (Token::BackReference(dist, len), len),
+        };
+        for p in pos..pos + len {
+            self.finder.add(p);
+        }
+        self.pos += len;
+        Some(token)
+    }
+}
+
+fn encode_yaz0(data: &[u8], out: &mut Vec<u8>) {
+    let mut flags_pos = 0;
+    for (i, token) in Tokens::new(data).enumerate() {
+        if i % 8 == 0 {
+            flags_pos = out.len();
+            out.push(0);
+        }
+        match token {
+            Token::Literal(b) => {
+                out[flags_pos] |= 0x80 >> (i % 8);
+                out.push(b);
+            },
+            Token::BackReference(dist, len) => {
+                let d = dist - 1;
+                if len >= LONG_MATCH {
+                    out.extend_from_slice(&[(d >> 8) as u8, d as u8, (len - LONG_MATCH) as u8]);
+                } else {
+                    out.extend_from_slice(&[((len - 2) << 4 | d >> 8) as u8, d as u8]);
+                }
+            },
+        }
+    }
+}
+
+fn encode_yay0(data: &[u8], out: &mut Vec<u8>) {
+    let mut masks = Vec::new();
+    let mut links = Vec::new();
+    let mut chunks = Vec::new();
+    for (i, token) in Tokens::new(data).enumerate() {
+        if i % 32 == 0 {
+            masks.push(0u32);
+        }
+        match token {
+            Token::Literal(b) => {
+                *masks.last_mut().unwrap() |= 0x8000_0000 >> (i % 32);
+                chunks.push(b);
+            },
+            Token::BackReference(dist, len) => {
+                let d = (dist - 1) as u16;
+                if len >= LONG_MATCH {
+                    links.extend_from_slice(&d.to_be_bytes());
+                    chunks.push((len - LONG_MATCH) as u8);
+                } else {
+                    links.extend_from_slice(&(((len - 2) as u16) << 12 | d).to_be_bytes());
+                }
+            },
+        }
+    }
+    let link_offset = HEADER_SIZE + masks.len() * 4;
+    let chunk_offset = link_offset + links.len();
+    out.extend_from_slice(&(link_offset as u32).to_be_bytes());
+    out.extend_from_slice(&(chunk_offset as u32).to_be_bytes());
+    for m in masks {
+        out.extend_from_slice(&m.to_be_bytes());
+    }
+    out.extend_from_slice(&links);
+    out.extend_from_slice(&chunks);
+}
+
+// Decompresses the file at `path` if it's compressed, and says how it was.
+// With a `suffix`, the decompressed file gets it added to its name, like
+// `stage.szs.dec`, and the compressed one is removed. Returns where the
+// decompressed file is, which is `path` without a suffix.
+pub fn decompress_file(path: &Path, suffix: Option<&str>) -> io::Result<Option<(Compression, PathBuf)>> {
+    let mut magic = [0; 4];
+    let n = read_fully(File::open(path)?, &mut magic)?;
+    let compression = match Compression::detect(&magic[..n]) {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    let data = compression.decompress(&fs::read(path)?)?;
+    let output = match suffix {
+        Some(s) => {
+            let mut name = path.as_os_str().to_owned();
+            name.push(s);
+            PathBuf::from(name)
+        },
+        None => path.to_path_buf(),
+    };
+    fs::write(&output, data)?;
+    if output != path {
+        fs::remove_file(path)?;
+    }
+    Ok(Some((compression, output)))
+}
+
+// What `Game::decompress_extracted` did
+#[derive(Debug, Default)]
+pub struct DecompressReport {
+    // Where each decompressed file is now, relative to the root
+    pub decompressed: Vec<(PathBuf, Compression)>,
+    // Files that looked compressed but couldn't be decompressed, which are
+    // left the way they were
+    pub failed: Vec<(PathBuf, io::Error)>,
+}
+
+impl Game {
+    // Decompresses the files from the FST that were extracted to `root`, and
+    // lists the ones it did in `COMPRESSED_LIST_PATH`, so rebuilding can
+    // compress them again. With a `suffix`, they're renamed like in
+    // `decompress_file`, and the name map gets their original names. Files
+    // that weren't extracted, like ones that were filtered out, are skipped.
+    pub fn decompress_extracted(&mut self, root: &Path, suffix: Option<&str>) -> io::Result<DecompressReport> {
+        let mut report = DecompressReport::default();
+        let mut renamed = Vec::new();
+        for f in self.fst.entries.iter().filter_map(|e| e.as_file()) {
+            let relative = self.fst.disk_path(&f.info);
+            match decompress_file(&root.join(&relative), suffix) {
+                Ok(Some((compression, _))) => {
+                    let relative = match suffix {
+                        Some(s) => {
+                            let name = format!("{}{}", f.info.disk_name, s);
+                            renamed.push((f.info.index, name.clone()));
+                            relative.with_file_name(name)
+                        },
+                        None => relative,
+                    };
+                    report.decompressed.push((relative, compression));
+                },
+                Ok(None) => {},
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => report.failed.push((relative, e)),
+            }
+        }
+
+        create_dir_all(root.join("&&systemdata"))?;
+        if !renamed.is_empty() {
+            for (index, name) in renamed {
+                self.fst.entries[index].info_mut().disk_name = name;
+            }
+            self.fst.write_name_map(File::create(root.join(::paths::NAME_MAP_PATH))?)?;
+        }
+        if !report.decompressed.is_empty() {
+            write_compressed_list(&report.decompressed, File::create(root.join(COMPRESSED_LIST_PATH))?)?;
+        }
+        Ok(report)
+    }
+}
+
+// A line for each file, with where it is relative to the root, a tab, and how
+// it was compressed
+fn write_compressed_list(files: &[(PathBuf, Compression)], mut output: impl Write) -> io::Result<()> {
+    writeln!(output, "# These were decompressed when they were extracted. Each line is where one")?;
+    writeln!(output, "# was extracted to, then how it was compressed, separated by a tab.")?;
+    for (path, compression) in files {
+        let path = path.iter().map(|c| c.to_string_lossy()).collect::<Vec<_>>().join("/");
+        writeln!(output, "{}\t{}", path, compression)?;
+    }
+    Ok(())
+}
+
+// The files listed in `COMPRESSED_LIST_PATH` in the extracted ROM at `root`,
+// by where they are relative to it. Empty if there isn't a list.
+pub fn read_compressed_list(root: &Path) -> io::Result<Vec<(PathBuf, Compression)>> {
+    let file = match File::open(root.join(COMPRESSED_LIST_PATH)) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') { continue }
+        let bad_line = || invalid(format!("{} has a bad line: {}", COMPRESSED_LIST_PATH, line));
+        let (path, compression) = line.rsplit_once('\t').ok_or_else(bad_line)?;
+        let compression = compression.parse().map_err(|_| bad_line())?;
+        files.push((path.split('/').collect(), compression));
+    }
+    Ok(files)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOTH: [Compression; 2] = [Compression::Yaz0, Compression::Yay0];
+
+    // Bytes that don't repeat, so nothing matches
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x = 0x2545_f491u32;
+        (0..len).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            (x >> 24) as u8
+        }).collect()
+    }
+
+    fn round_trip(data: &[u8]) -> Vec<Vec<u8>> {
+        BOTH.iter().map(|&c| {
+            let compressed = c.compress(data).unwrap();
+            assert_eq!(Compression::detect(&compressed), Some(c));
+            let decompressed = c.decompress(&compressed).unwrap();
+            assert!(decompressed == data, "{} didn't round trip {} bytes", c, data.len());
+            compressed
+        }).collect()
+    }
+
+    #[test]
+    fn small_inputs() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(b"ab");
+        round_trip(b"abc");
+        round_trip(b"abcabc");
+        round_trip(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+    }
+
+    #[test]
+    fn text_like_inputs() {
+        let text = "the quick brown fox jumps over the lazy dog. ".repeat(500);
+        for compressed in round_trip(text.as_bytes()) {
+            assert!(compressed.len() < text.len() / 4);
+        }
+    }
+
+    // Every byte is a literal, which is the most either format grows by: a
+    // flag bit for each one
+    #[test]
+    fn incompressible_inputs() {
+        for &len in &[7, 8, 9, 31, 32, 33, 0x1000, 0x10001] {
+            let data = noise(len);
+            for compressed in round_trip(&data) {
+                assert!(compressed.len() <= HEADER_SIZE + len + len.div_ceil(8) + 4);
+            }
+        }
+    }
+
+    // A run of one byte is all the longest back-references, which is as much
+    // as the formats can expand, so it has to fit in `MAX_EXPANSION`
+    #[test]
+    fn maximum_expansion() {
+        let data = vec![0; 1 << 20];
+        for compressed in round_trip(&data) {
+            let ratio = data.len() as f64 / (compressed.len() - HEADER_SIZE) as f64;
+            assert!(ratio > 80.0 && ratio <= MAX_EXPANSION as f64, "the ratio was {}", ratio);
+        }
+    }
+
+    // Matches right at the edge of the window, and ones longer than a
+    // back-reference can be
+    #[test]
+    fn window_and_match_limits() {
+        let block = noise(WINDOW_SIZE);
+        let mut data = block.clone();
+        data.extend_from_slice(&block);
+        data.extend_from_slice(&block[..MAX_MATCH * 3 + 1]);
+        data.extend(noise(WINDOW_SIZE + 1));
+        data.extend_from_slice(&block[..100]);
+        round_trip(&data);
+    }
+
+    #[test]
+    fn headers_that_are_too_big_are_errors() {
+        for &c in &BOTH {
+            let mut compressed = c.compress(&noise(100)).unwrap();
+            let size = (compressed.len() - HEADER_SIZE) * MAX_EXPANSION + 1;
+            BigEndian::write_u32(&mut compressed[4..], size as u32);
+            assert!(c.decompress(&compressed).is_err());
+        }
+    }
+
+    #[test]
+    fn truncated_data_is_an_error() {
+        let data = noise(1000);
+        for &c in &BOTH {
+            let compressed = c.compress(&data).unwrap();
+            assert!(c.decompress(&compressed[..compressed.len() - 1]).is_err());
+        }
+    }
+}
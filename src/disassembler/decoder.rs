@@ -0,0 +1,616 @@
+// A decoder for the Gekko's instruction set, which is the PowerPC 750's plus
+// paired singles. The text follows GNU objdump's formatting (including the
+// simplified mnemonics it picks), so listings look the same with either
+// backend.
+// The instruction formats are in IBM's PowerPC Microprocessor Family: The
+// Programming Environments manual, and paired singles are documented in the
+// Broadway/Gekko user's manuals.
+
+use super::{Instruction, Operand};
+
+pub fn decode(opcode: u32, address: u64) -> Instruction {
+    match decode_parts(opcode, address) {
+        Some((mnemonic, operands)) => Instruction {
+            text: format_ins(&mnemonic, &operands),
+            mnemonic,
+            operands,
+            opcode,
+            location: address,
+            annotation: None,
+        },
+        None => Instruction {
+            text: format!(".long {:#x}", opcode),
+            mnemonic: ".long".to_string(),
+            operands: vec![Operand::Imm(opcode as i32)],
+            opcode,
+            location: address,
+            annotation: None,
+        },
+    }
+}
+
+// Extracts bits `start` through `end` (inclusive), where bit 0 is the most
+// significant bit, like the PowerPC manuals number them.
+fn bits(op: u32, start: u32, end: u32) -> u32 {
+    (op >> (31 - end)) & ((1 << (end - start + 1)) - 1)
+}
+
+fn format_ins(mnemonic: &str, operands: &[Operand]) -> String {
+    if operands.is_empty() {
+        mnemonic.to_string()
+    } else {
+        let operands = operands.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        // objdump pads mnemonics to 8 columns, with at least one space
+        format!("{:<7} {}", mnemonic, operands.join(","))
+    }
+}
+
+macro_rules! ins {
+    ($mnemonic:expr) => { Some(($mnemonic.to_string(), Vec::new())) };
+    ($mnemonic:expr, $($operand:expr),+) => {
+        Some(($mnemonic.to_string(), vec![$(Operand::from($operand)),+]))
+    };
+}
+
+fn r(n: u32) -> Operand {
+    Operand::Gpr(n as u8)
+}
+
+// An rA operand where r0 means the value 0
+fn r0(n: u32) -> Operand {
+    if n == 0 { Operand::Imm(0) } else { r(n) }
+}
+
+fn f(n: u32) -> Operand {
+    Operand::Fpr(n as u8)
+}
+
+fn cr(n: u32) -> Operand {
+    Operand::Cr(n as u8)
+}
+
+fn crb(n: u32) -> Operand {
+    Operand::CrBit(n as u8)
+}
+
+fn mem(d: i16, ra: u32) -> Operand {
+    Operand::Mem { offset: d, base: ra as u8 }
+}
+
+fn hex(n: u64) -> Operand {
+    Operand::BranchTarget(n)
+}
+
+fn suffix(mnemonic: &str, oe: bool, rc: bool) -> String {
+    format!("{}{}{}", mnemonic, if oe { "o" } else { "" }, if rc { "." } else { "" })
+}
+
+fn spr_name(spr: u32) -> Option<&'static str> {
+    Some(match spr {
+        1 => "xer",
+        8 => "lr",
+        9 => "ctr",
+        18 => "dsisr",
+        19 => "dar",
+        22 => "dec",
+        25 => "sdr1",
+        26 => "srr0",
+        27 => "srr1",
+        _ => return None,
+    })
+}
+
+type Parts = Option<(String, Vec<Operand>)>;
+
+fn decode_parts(op: u32, address: u64) -> Parts {
+    let rd = bits(op, 6, 10);
+    let ra = bits(op, 11, 15);
+    let rb = bits(op, 16, 20);
+    let rc = bits(op, 21, 25);
+    let simm = op as u16 as i16;
+    let uimm = op & 0xffff;
+    let rc_bit = op & 1 != 0;
+
+    match bits(op, 0, 5) {
+        3 => ins!("twi", rd, r(ra), simm),
+        4 => decode_paired_single(op),
+        7 => ins!("mulli", r(rd), r(ra), simm),
+        8 => ins!("subfic", r(rd), r(ra), simm),
+        10 | 11 => {
+            let (name, imm) = if bits(op, 0, 5) == 10 {
+                ("cmplwi", uimm as i32)
+            } else {
+                ("cmpwi", simm as i32)
+            };
+            // The L bit picks doubleword compares, which the Gekko doesn't have
+            if bits(op, 10, 10) != 0 { return None }
+            match rd >> 2 {
+                0 => ins!(name, r(ra), imm),
+                crf => ins!(name, cr(crf), r(ra), imm),
+            }
+        },
+        12 => ins!("addic", r(rd), r(ra), simm),
+        13 => ins!("addic.", r(rd), r(ra), simm),
+        14 if ra == 0 => ins!("li", r(rd), simm),
+        14 => ins!("addi", r(rd), r(ra), simm),
+        15 if ra == 0 => ins!("lis", r(rd), simm),
+        15 => ins!("addis", r(rd), r(ra), simm),
+        16 => decode_bc(op, address),
+        17 if op & 2 != 0 => ins!("sc"),
+        18 => {
+            let li = ((op & 0x03ff_fffc) << 6) as i32 >> 6;
+            let aa = op & 2 != 0;
+            let target = if aa { li as u32 as u64 } else { address.wrapping_add(li as i64 as u64) & 0xffff_ffff };
+            let name = format!("b{}{}", if op & 1 != 0 { "l" } else { "" }, if aa { "a" } else { "" });
+            ins!(name, hex(target))
+        },
+        19 => decode_19(op),
+        20 => ins!(suffix("rlwimi", false, rc_bit), r(ra), r(rd), rb, rc, bits(op, 26, 30)),
+        21 => {
+            let (sh, mb, me) = (rb, rc, bits(op, 26, 30));
+            if mb == 0 && me == 31 {
+                ins!(suffix("rotlwi", false, rc_bit), r(ra), r(rd), sh)
+            } else if sh == 0 && me == 31 {
+                ins!(suffix("clrlwi", false, rc_bit), r(ra), r(rd), mb)
+            } else {
+                ins!(suffix("rlwinm", false, rc_bit), r(ra), r(rd), sh, mb, me)
+            }
+        },
+        23 => {
+            let (mb, me) = (rc, bits(op, 26, 30));
+            if mb == 0 && me == 31 {
+                ins!(suffix("rotlw", false, rc_bit), r(ra), r(rd), r(rb))
+            } else {
+                ins!(suffix("rlwnm", false, rc_bit), r(ra), r(rd), r(rb), mb, me)
+            }
+        },
+        24 if op == 0x6000_0000 => ins!("nop"),
+        24 => ins!("ori", r(ra), r(rd), uimm),
+        25 => ins!("oris", r(ra), r(rd), uimm),
+        26 => ins!("xori", r(ra), r(rd), uimm),
+        27 => ins!("xoris", r(ra), r(rd), uimm),
+        28 => ins!("andi.", r(ra), r(rd), uimm),
+        29 => ins!("andis.", r(ra), r(rd), uimm),
+        31 => decode_31(op),
+        primary @ 32..=55 => {
+            let name = [
+                "lwz", "lwzu", "lbz", "lbzu", "stw", "stwu", "stb", "stbu",
+                "lhz", "lhzu", "lha", "lhau", "sth", "sthu", "lmw", "stmw",
+                "lfs", "lfsu", "lfd", "lfdu", "stfs", "stfsu", "stfd", "stfdu",
+            ][primary as usize - 32];
+            if primary >= 48 {
+                ins!(name, f(rd), mem(simm, ra))
+            } else {
+                ins!(name, r(rd), mem(simm, ra))
+            }
+        },
+        primary @ (56 | 57 | 60 | 61) => {
+            let name = match primary {
+                56 => "psq_l",
+                57 => "psq_lu",
+                60 => "psq_st",
+                _ => "psq_stu",
+            };
+            // A 12-bit signed displacement
+            let d = ((op & 0xfff) << 20) as i32 >> 20;
+            ins!(name, f(rd), mem(d as i16, ra), bits(op, 16, 16), bits(op, 17, 19))
+        },
+        59 => decode_fp_arith(op, true),
+        63 => decode_fp_arith(op, false).or_else(|| decode_63(op)),
+        _ => None,
+    }
+}
+
+// The conditional part of a branch's simplified mnemonic, like `eq` or `dnz`,
+// and the operand for the condition register, if there is one.
+// Returns None for "branch always".
+fn branch_condition(bo: u32, bi: u32) -> Option<(String, Option<Operand>)> {
+    let uses_ctr = bo & 0b00100 == 0;
+    let uses_cond = bo & 0b10000 == 0;
+    if !uses_ctr && !uses_cond {
+        return None;
+    }
+
+    let cond = if uses_cond {
+        let names = if bo & 0b01000 != 0 {
+            ["lt", "gt", "eq", "so"]
+        } else {
+            ["ge", "le", "ne", "ns"]
+        };
+        Some(names[(bi % 4) as usize])
+    } else {
+        None
+    };
+
+    Some(if uses_ctr {
+        let base = if bo & 0b00010 != 0 { "dz" } else { "dnz" };
+        match cond {
+            // bdnzt and friends take the whole bit, not just a field
+            Some(_) => (
+                format!("{}{}", base, if bo & 0b01000 != 0 { "t" } else { "f" }),
+                Some(crb(bi)),
+            ),
+            None => (base.to_string(), None),
+        }
+    } else {
+        let field = if bi >= 4 { Some(cr(bi / 4)) } else { None };
+        (cond.unwrap().to_string(), field)
+    })
+}
+
+// The static prediction hint objdump adds to simplified branch mnemonics
+fn branch_hint(bo: u32, backward: bool) -> &'static str {
+    match (bo & 1 != 0, backward) {
+        (false, _) => "",
+        (true, false) => "+",
+        (true, true) => "-",
+    }
+}
+
+fn decode_bc(op: u32, address: u64) -> Parts {
+    let bo = bits(op, 6, 10);
+    let bi = bits(op, 11, 15);
+    let bd = ((op & 0xfffc) as u16 as i16) as i64;
+    let aa = op & 2 != 0;
+    let lk = op & 1 != 0;
+    let target = if aa { bd as u64 & 0xffff_ffff } else { address.wrapping_add(bd as u64) & 0xffff_ffff };
+    let suffixes = format!("{}{}", if lk { "l" } else { "" }, if aa { "a" } else { "" });
+
+    match branch_condition(bo, bi) {
+        Some((cond, field)) => {
+            let name = format!("b{}{}{}", cond, suffixes, branch_hint(bo, bd < 0));
+            match field {
+                Some(field) => ins!(name, field, hex(target)),
+                None => ins!(name, hex(target)),
+            }
+        },
+        None => ins!(format!("bc{}", suffixes), bo, bi, hex(target)),
+    }
+}
+
+// bclr and bcctr
+fn decode_branch_to_register(op: u32, to: &str) -> Parts {
+    let bo = bits(op, 6, 10);
+    let bi = bits(op, 11, 15);
+    let lk = if op & 1 != 0 { "l" } else { "" };
+
+    match branch_condition(bo, bi) {
+        Some((cond, field)) => {
+            let name = format!("b{}{}{}{}", cond, to, lk, branch_hint(bo, false));
+            match field {
+                Some(field) => ins!(name, field),
+                None => ins!(name),
+            }
+        },
+        None => ins!(format!("b{}{}", to, lk)),
+    }
+}
+
+fn decode_19(op: u32) -> Parts {
+    let (bt, ba, bb) = (bits(op, 6, 10), bits(op, 11, 15), bits(op, 16, 20));
+    match bits(op, 21, 30) {
+        0 => ins!("mcrf", cr(bt >> 2), cr(ba >> 2)),
+        16 => decode_branch_to_register(op, "lr"),
+        528 => decode_branch_to_register(op, "ctr"),
+        50 => ins!("rfi"),
+        150 => ins!("isync"),
+        33 if ba == bb => ins!("crnot", crb(bt), crb(ba)),
+        449 if ba == bb => ins!("crmove", crb(bt), crb(ba)),
+        193 if bt == ba && ba == bb => ins!("crclr", crb(bt)),
+        289 if bt == ba && ba == bb => ins!("crset", crb(bt)),
+        xo => {
+            let name = match xo {
+                33 => "crnor",
+                129 => "crandc",
+                193 => "crxor",
+                225 => "crnand",
+                257 => "crand",
+                289 => "creqv",
+                417 => "crorc",
+                449 => "cror",
+                _ => return None,
+            };
+            ins!(name, crb(bt), crb(ba), crb(bb))
+        },
+    }
+}
+
+fn decode_31(op: u32) -> Parts {
+    let rd = bits(op, 6, 10);
+    let ra = bits(op, 11, 15);
+    let rb = bits(op, 16, 20);
+    let rc = op & 1 != 0;
+    let oe = bits(op, 21, 21) != 0;
+
+    // The XO-form arithmetic instructions have a 9-bit extended opcode, with
+    // the overflow bit before it
+    let arith = match bits(op, 22, 30) {
+        8 => Some("subc"),
+        10 => Some("addc"),
+        40 => Some("sub"),
+        136 => Some("subfe"),
+        138 => Some("adde"),
+        235 => Some("mullw"),
+        266 => Some("add"),
+        459 => Some("divwu"),
+        491 => Some("divw"),
+        _ => None,
+    };
+    if let Some(name) = arith {
+        // sub and subc are subf and subfc with rA and rB swapped
+        return if name.starts_with("sub") && !name.starts_with("subf") {
+            ins!(suffix(name, oe, rc), r(rd), r(rb), r(ra))
+        } else {
+            ins!(suffix(name, oe, rc), r(rd), r(ra), r(rb))
+        };
+    }
+    let unary = match bits(op, 22, 30) {
+        104 => Some("neg"),
+        200 => Some("subfze"),
+        202 => Some("addze"),
+        232 => Some("subfme"),
+        234 => Some("addme"),
+        _ => None,
+    };
+    if let Some(name) = unary {
+        return ins!(suffix(name, oe, rc), r(rd), r(ra));
+    }
+
+    let xo = bits(op, 21, 30);
+    match xo {
+        0 | 32 => {
+            if bits(op, 10, 10) != 0 { return None }
+            let name = if xo == 0 { "cmpw" } else { "cmplw" };
+            match rd >> 2 {
+                0 => ins!(name, r(ra), r(rb)),
+                crf => ins!(name, cr(crf), r(ra), r(rb)),
+            }
+        },
+        4 if rd == 31 && ra == 0 && rb == 0 => ins!("trap"),
+        4 => ins!("tw", rd, r(ra), r(rb)),
+        11 => ins!(suffix("mulhwu", false, rc), r(rd), r(ra), r(rb)),
+        75 => ins!(suffix("mulhw", false, rc), r(rd), r(ra), r(rb)),
+        19 => ins!("mfcr", r(rd)),
+        83 => ins!("mfmsr", r(rd)),
+        146 => ins!("mtmsr", r(rd)),
+        144 if bits(op, 12, 19) == 0xff => ins!("mtcr", r(rd)),
+        144 => ins!("mtcrf", bits(op, 12, 19), r(rd)),
+        210 => ins!("mtsr", ra & 0xf, r(rd)),
+        242 => ins!("mtsrin", r(rd), r(rb)),
+        595 => ins!("mfsr", r(rd), ra & 0xf),
+        659 => ins!("mfsrin", r(rd), r(rb)),
+        512 => ins!("mcrxr", cr(rd >> 2)),
+        339 | 467 | 371 => {
+            // The two halves of the SPR number are swapped
+            let spr = ra | (rb << 5);
+            match (xo, spr_name(spr)) {
+                (339, Some(name)) => ins!(format!("mf{}", name), r(rd)),
+                (339, None) => ins!("mfspr", r(rd), Operand::Spr(spr as u16)),
+                (467, Some(name)) => ins!(format!("mt{}", name), r(rd)),
+                (467, None) => ins!("mtspr", Operand::Spr(spr as u16), r(rd)),
+                (_, _) => match spr {
+                    268 => ins!("mftb", r(rd)),
+                    269 => ins!("mftbu", r(rd)),
+                    _ => ins!("mftb", r(rd), Operand::Spr(spr as u16)),
+                },
+            }
+        },
+        24 | 28 | 60 | 124 | 284 | 316 | 412 | 444 | 476 | 536 | 792 => {
+            if xo == 444 && rd == rb {
+                return ins!(suffix("mr", false, rc), r(ra), r(rd));
+            }
+            if xo == 124 && rd == rb {
+                return ins!(suffix("not", false, rc), r(ra), r(rd));
+            }
+            let name = match xo {
+                24 => "slw",
+                28 => "and",
+                60 => "andc",
+                124 => "nor",
+                284 => "eqv",
+                316 => "xor",
+                412 => "orc",
+                444 => "or",
+                476 => "nand",
+                536 => "srw",
+                _ => "sraw",
+            };
+            ins!(suffix(name, false, rc), r(ra), r(rd), r(rb))
+        },
+        26 => ins!(suffix("cntlzw", false, rc), r(ra), r(rd)),
+        922 => ins!(suffix("extsh", false, rc), r(ra), r(rd)),
+        954 => ins!(suffix("extsb", false, rc), r(ra), r(rd)),
+        824 => ins!(suffix("srawi", false, rc), r(ra), r(rd), rb),
+        54 | 86 | 246 | 278 | 470 | 982 | 1014 => {
+            let name = match xo {
+                54 => "dcbst",
+                86 => "dcbf",
+                246 => "dcbtst",
+                278 => "dcbt",
+                470 => "dcbi",
+                982 => "icbi",
+                _ => "dcbz",
+            };
+            ins!(name, r0(ra), r(rb))
+        },
+        306 => ins!("tlbie", r(rb)),
+        566 => ins!("tlbsync"),
+        598 => ins!("sync"),
+        854 => ins!("eieio"),
+        150 => ins!("stwcx.", r(rd), r0(ra), r(rb)),
+        597 => ins!("lswi", r(rd), r0(ra), if rb == 0 { 32 } else { rb }),
+        725 => ins!("stswi", r(rd), r0(ra), if rb == 0 { 32 } else { rb }),
+        _ => {
+            // Indexed loads and stores. The update forms can't use r0 as rA.
+            let (name, fp, update) = match xo {
+                20 => ("lwarx", false, false),
+                23 => ("lwzx", false, false),
+                55 => ("lwzux", false, true),
+                87 => ("lbzx", false, false),
+                119 => ("lbzux", false, true),
+                151 => ("stwx", false, false),
+                183 => ("stwux", false, true),
+                215 => ("stbx", false, false),
+                247 => ("stbux", false, true),
+                279 => ("lhzx", false, false),
+                311 => ("lhzux", false, true),
+                310 => ("eciwx", false, false),
+                343 => ("lhax", false, false),
+                375 => ("lhaux", false, true),
+                407 => ("sthx", false, false),
+                438 => ("ecowx", false, false),
+                439 => ("sthux", false, true),
+                533 => ("lswx", false, false),
+                534 => ("lwbrx", false, false),
+                661 => ("stswx", false, false),
+                662 => ("stwbrx", false, false),
+                790 => ("lhbrx", false, false),
+                918 => ("sthbrx", false, false),
+                535 => ("lfsx", true, false),
+                567 => ("lfsux", true, true),
+                599 => ("lfdx", true, false),
+                631 => ("lfdux", true, true),
+                663 => ("stfsx", true, false),
+                695 => ("stfsux", true, true),
+                727 => ("stfdx", true, false),
+                759 => ("stfdux", true, true),
+                983 => ("stfiwx", true, false),
+                _ => return None,
+            };
+            let base = if update { r(ra) } else { r0(ra) };
+            if fp {
+                ins!(name, f(rd), base, r(rb))
+            } else {
+                ins!(name, r(rd), base, r(rb))
+            }
+        },
+    }
+}
+
+// The A-form instructions in the 59 (single precision) and 63 (double
+// precision) opcodes
+fn decode_fp_arith(op: u32, single: bool) -> Parts {
+    let (fd, fa, fb, fc) = (bits(op, 6, 10), bits(op, 11, 15), bits(op, 16, 20), bits(op, 21, 25));
+    let rc = op & 1 != 0;
+    let name = |n: &str| suffix(&format!("{}{}", n, if single { "s" } else { "" }), false, rc);
+
+    match bits(op, 26, 30) {
+        18 => ins!(name("fdiv"), f(fd), f(fa), f(fb)),
+        20 => ins!(name("fsub"), f(fd), f(fa), f(fb)),
+        21 => ins!(name("fadd"), f(fd), f(fa), f(fb)),
+        25 => ins!(name("fmul"), f(fd), f(fa), f(fc)),
+        28 => ins!(name("fmsub"), f(fd), f(fa), f(fc), f(fb)),
+        29 => ins!(name("fmadd"), f(fd), f(fa), f(fc), f(fb)),
+        30 => ins!(name("fnmsub"), f(fd), f(fa), f(fc), f(fb)),
+        31 => ins!(name("fnmadd"), f(fd), f(fa), f(fc), f(fb)),
+        24 if single => ins!(suffix("fres", false, rc), f(fd), f(fb)),
+        23 if !single => ins!(suffix("fsel", false, rc), f(fd), f(fa), f(fc), f(fb)),
+        26 if !single => ins!(suffix("frsqrte", false, rc), f(fd), f(fb)),
+        _ => None,
+    }
+}
+
+// The X-form instructions in opcode 63
+fn decode_63(op: u32) -> Parts {
+    let (fd, fa, fb) = (bits(op, 6, 10), bits(op, 11, 15), bits(op, 16, 20));
+    let rc = op & 1 != 0;
+
+    match bits(op, 21, 30) {
+        0 => ins!("fcmpu", cr(fd >> 2), f(fa), f(fb)),
+        32 => ins!("fcmpo", cr(fd >> 2), f(fa), f(fb)),
+        64 => ins!("mcrfs", cr(fd >> 2), cr(fa >> 2)),
+        38 => ins!(suffix("mtfsb1", false, rc), fd),
+        70 => ins!(suffix("mtfsb0", false, rc), fd),
+        134 => ins!(suffix("mtfsfi", false, rc), cr(fd >> 2), fb >> 1),
+        583 => ins!(suffix("mffs", false, rc), f(fd)),
+        711 => ins!(suffix("mtfsf", false, rc), bits(op, 7, 14), f(fb)),
+        xo => {
+            let name = match xo {
+                12 => "frsp",
+                14 => "fctiw",
+                15 => "fctiwz",
+                40 => "fneg",
+                72 => "fmr",
+                136 => "fnabs",
+                264 => "fabs",
+                _ => return None,
+            };
+            ins!(suffix(name, false, rc), f(fd), f(fb))
+        },
+    }
+}
+
+// Opcode 4, which the Gekko uses for paired single instructions
+fn decode_paired_single(op: u32) -> Parts {
+    let (fd, fa, fb, fc) = (bits(op, 6, 10), bits(op, 11, 15), bits(op, 16, 20), bits(op, 21, 25));
+    let rc = op & 1 != 0;
+
+    let a_form = match bits(op, 26, 30) {
+        10 => Some("ps_sum0"),
+        11 => Some("ps_sum1"),
+        12 => Some("ps_muls0"),
+        13 => Some("ps_muls1"),
+        14 => Some("ps_madds0"),
+        15 => Some("ps_madds1"),
+        18 => Some("ps_div"),
+        20 => Some("ps_sub"),
+        21 => Some("ps_add"),
+        23 => Some("ps_sel"),
+        24 => Some("ps_res"),
+        25 => Some("ps_mul"),
+        26 => Some("ps_rsqrte"),
+        28 => Some("ps_msub"),
+        29 => Some("ps_madd"),
+        30 => Some("ps_nmsub"),
+        31 => Some("ps_nmadd"),
+        _ => None,
+    };
+    if let Some(name) = a_form {
+        let name = suffix(name, false, rc);
+        return match bits(op, 26, 30) {
+            18 | 20 | 21 => ins!(name, f(fd), f(fa), f(fb)),
+            12 | 13 | 25 => ins!(name, f(fd), f(fa), f(fc)),
+            24 | 26 => ins!(name, f(fd), f(fb)),
+            _ => ins!(name, f(fd), f(fa), f(fc), f(fb)),
+        };
+    }
+
+    // The indexed quantized loads and stores have a 6-bit extended opcode
+    let indexed = match bits(op, 25, 30) {
+        6 => Some(("psq_lx", false)),
+        7 => Some(("psq_stx", false)),
+        38 => Some(("psq_lux", true)),
+        39 => Some(("psq_stux", true)),
+        _ => None,
+    };
+    if let Some((name, update)) = indexed {
+        let base = if update { r(fa) } else { r0(fa) };
+        return ins!(name, f(fd), base, r(fb), bits(op, 21, 21), bits(op, 22, 24));
+    }
+
+    match bits(op, 21, 30) {
+        0 => ins!("ps_cmpu0", cr(fd >> 2), f(fa), f(fb)),
+        32 => ins!("ps_cmpo0", cr(fd >> 2), f(fa), f(fb)),
+        64 => ins!("ps_cmpu1", cr(fd >> 2), f(fa), f(fb)),
+        96 => ins!("ps_cmpo1", cr(fd >> 2), f(fa), f(fb)),
+        1014 => ins!("dcbz_l", r0(fa), r(fb)),
+        xo => {
+            let name = match xo {
+                40 => "ps_neg",
+                72 => "ps_mr",
+                136 => "ps_nabs",
+                264 => "ps_abs",
+                528 => "ps_merge00",
+                560 => "ps_merge01",
+                592 => "ps_merge10",
+                624 => "ps_merge11",
+                _ => return None,
+            };
+            if xo >= 528 {
+                ins!(suffix(name, false, rc), f(fd), f(fa), f(fb))
+            } else {
+                ins!(suffix(name, false, rc), f(fd), f(fb))
+            }
+        },
+    }
+}
@@ -0,0 +1,162 @@
+// A listing of a whole DOL, for reading through it or diffing it against
+// another version of the game. The text segments are disassembled with
+// labels, and the data segments are written out as `.word`s and `.byte`s,
+// with anything that looks like a string in a comment.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use analysis::generate_map;
+use sections::dol::DOLHeader;
+use sections::dol::segment::{Segment, SegmentType};
+
+use super::{CodeSection, Disassembler, Symbol, SymbolMap};
+
+// How short a run of characters can be and still be taken for a string
+const MIN_STRING_LEN: usize = 4;
+// The most `.byte`s on one line
+const BYTES_PER_LINE: usize = 16;
+
+// `stwu r1, -N(r1)`, which starts almost every function that has a stack
+// frame, sometimes after a `mflr r0`
+const STWU_R1_MASK: u32 = 0xffff_8000;
+const STWU_R1: u32 = 0x9421_8000;
+const MFLR_R0: u32 = 0x7c08_02a6;
+
+impl Disassembler {
+    // Writes every text segment in order, then the data segments and the BSS.
+    // Functions are named from `symbols` if it's given, otherwise they're
+    // found the same way as `generate_map` does, and by their prologues.
+    // Other branch targets get `loc_` labels, like `write_with_sections`.
+    // Everything's written as it's read, so the listing's never all in
+    // memory, and it's the same every time for the same DOL.
+    pub fn write_listing(
+        &self,
+        mut iso: impl Read + Seek,
+        dol: &DOLHeader,
+        symbols: Option<&SymbolMap>,
+        mut output: impl Write,
+    ) -> io::Result<usize> {
+        let text = dol.iter_segments()
+            .filter(|s| s.seg_type == SegmentType::Text)
+            .map(CodeSection::from)
+            .collect::<Vec<_>>();
+        let found;
+        let functions = match symbols {
+            Some(s) => s,
+            None => {
+                found = find_functions(dol, &mut iso)?;
+                &found
+            },
+        };
+
+        writeln!(output, "# Entry point: {:#010x}", dol.entry_point)?;
+        writeln!(output)?;
+        let unparsed = self.write_with_sections(&mut iso, &text, Some(functions), &mut output)?;
+
+        for segment in dol.iter_segments().filter(|s| s.seg_type == SegmentType::Data) {
+            writeln!(output)?;
+            write_data_segment(&mut iso, segment, &mut output)?;
+        }
+        if dol.bss_size > 0 {
+            writeln!(output)?;
+            writeln!(output, ".bss:  # {:#010x}, {:#x} bytes", dol.bss_address, dol.bss_size)?;
+            writeln!(output, "{:08x}:  .skip {:#x}", dol.bss_address, dol.bss_size)?;
+        }
+        output.flush()?;
+        Ok(unparsed)
+    }
+}
+
+// Everything `generate_map` finds, plus anything that starts with a
+// prologue, which catches functions that are only called through pointers
+// and come after one that ends in a tail call instead of a `blr`
+fn find_functions(dol: &DOLHeader, mut iso: impl Read + Seek) -> io::Result<SymbolMap> {
+    let mut functions = generate_map(dol, &mut iso)?;
+    let disassembler = Disassembler::new();
+    for segment in dol.iter_segments().filter(|s| s.seg_type == SegmentType::Text) {
+        let mut previous: Option<(u64, u32)> = None;
+        for ins in disassembler.disasm(&mut iso, segment, None)? {
+            let ins = ins?;
+            if ins.opcode & STWU_R1_MASK == STWU_R1 {
+                let start = match previous {
+                    Some((location, MFLR_R0)) => location,
+                    _ => ins.location,
+                };
+                if functions.get(start).is_none() {
+                    functions.insert(start, Symbol { name: format!("fn_{:08x}", start), size: 0 });
+                }
+            }
+            previous = Some((ins.location, ins.opcode));
+        }
+    }
+    Ok(functions)
+}
+
+fn write_data_segment(mut iso: impl Read + Seek, segment: &Segment, mut output: impl Write) -> io::Result<()> {
+    writeln!(
+        output, "{}:  # {:#010x}, {:#x} bytes",
+        segment.to_string(), segment.loading_address, segment.size,
+    )?;
+
+    iso.seek(SeekFrom::Start(segment.offset))?;
+    let mut data = Vec::with_capacity(segment.size);
+    iso.take(segment.size as u64).read_to_end(&mut data)?;
+    if data.len() != segment.size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} is cut off", segment.to_string()),
+        ));
+    }
+
+    let address = segment.loading_address;
+    let mut pos = 0;
+    while pos < data.len() {
+        if let Some(len) = string_at(&data[pos..]) {
+            // The string's terminator is part of it
+            let string = String::from_utf8_lossy(&data[pos..pos + len - 1]);
+            for (i, chunk) in data[pos..pos + len].chunks(BYTES_PER_LINE).enumerate() {
+                write!(output, "{:08x}:  .byte {}", address + (pos + i * BYTES_PER_LINE) as u64, byte_list(chunk))?;
+                if i == 0 {
+                    write!(output, "  # {:?}", string)?;
+                }
+                writeln!(output)?;
+            }
+            pos += len;
+        } else if (address + pos as u64).is_multiple_of(4) && pos + 4 <= data.len()
+            && (1..4).all(|i| string_at(&data[pos + i..]).is_none())
+        {
+            let word = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+            writeln!(output, "{:08x}:  .word {:#010x}", address + pos as u64, word)?;
+            pos += 4;
+        } else {
+            // Bytes up to the next word, or to a string that starts partway
+            // through one
+            let start = pos;
+            pos += 1;
+            while pos < data.len() && !(address + pos as u64).is_multiple_of(4) && string_at(&data[pos..]).is_none() {
+                pos += 1;
+            }
+            writeln!(output, "{:08x}:  .byte {}", address + start as u64, byte_list(&data[start..pos]))?;
+        }
+    }
+    Ok(())
+}
+
+// The length of the null terminated string at the start of `data`,
+// including the terminator
+fn string_at(data: &[u8]) -> Option<usize> {
+    let len = data.iter().position(|&b| !is_string_char(b))?;
+    if len >= MIN_STRING_LEN && data[len] == 0 {
+        Some(len + 1)
+    } else {
+        None
+    }
+}
+
+fn is_string_char(b: u8) -> bool {
+    (0x20..0x7f).contains(&b) || b == b'\t' || b == b'\n' || b == b'\r'
+}
+
+fn byte_list(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:#04x}", b)).collect::<Vec<_>>().join(", ")
+}
@@ -0,0 +1,650 @@
+mod decoder;
+mod listing;
+mod symbols;
+
+pub use self::decoder::decode;
+pub use self::symbols::{Symbol, SymbolMap};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Cursor, Lines, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use byteorder::{BigEndian, ByteOrder};
+use tempfile::NamedTempFile;
+
+use sections::dol::DOLHeader;
+use sections::dol::segment::Segment;
+
+// What `find_objdump` tries, in order
+const OBJDUMP_NAMES: &[&str] = &["powerpc-eabi-objdump", "objdump", "llvm-objdump"];
+
+// A run of code on the ROM, and where it's loaded in memory
+#[derive(Clone, Debug)]
+pub struct CodeSection {
+    pub name: String,
+    pub offset: u64,
+    pub size: usize,
+    pub load_address: u64,
+}
+
+impl From<&Segment> for CodeSection {
+    fn from(segment: &Segment) -> CodeSection {
+        CodeSection {
+            name: segment.to_string(),
+            offset: segment.offset,
+            size: segment.size,
+            load_address: segment.loading_address,
+        }
+    }
+}
+
+// What turns the machine code into text
+#[derive(Clone, Debug)]
+pub enum Backend {
+    // The built in decoder, which doesn't need anything installed
+    Native,
+    // GNU objdump, built with PowerPC support
+    Objdump(PathBuf),
+    // llvm-objdump, which only reads object files, so the code gets wrapped
+    // in an ELF first. It doesn't know about paired singles, so those are
+    // decoded natively.
+    LlvmObjdump(PathBuf),
+}
+
+pub struct Disassembler {
+    pub backend: Backend,
+}
+
+impl Default for Disassembler {
+    fn default() -> Disassembler {
+        Disassembler { backend: Backend::Native }
+    }
+}
+
+impl Disassembler {
+    pub fn new() -> Disassembler {
+        Disassembler::default()
+    }
+
+    // Uses the objdump at `objdump_path`, whichever kind it is
+    pub fn with_objdump(objdump_path: impl Into<PathBuf>) -> io::Result<Disassembler> {
+        let backend = Disassembler::check_objdump_version(objdump_path.into())?;
+        Ok(Disassembler { backend })
+    }
+
+    // Looks for an objdump that can handle PowerPC code on the PATH,
+    // preferring GNU's
+    pub fn find_objdump() -> io::Result<Disassembler> {
+        OBJDUMP_NAMES.iter()
+            .find_map(|name| Disassembler::with_objdump(name).ok())
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("couldn't find an objdump with PowerPC support (tried {})", OBJDUMP_NAMES.join(", ")),
+            ))
+    }
+
+    // Makes sure `objdump_path` is a GNU objdump or llvm-objdump that can
+    // handle PowerPC code, and returns the backend for it
+    pub fn check_objdump_version(objdump_path: impl Into<PathBuf>) -> io::Result<Backend> {
+        let objdump_path = objdump_path.into();
+        let version = Command::new(&objdump_path).arg("--version").output()?;
+        let version = String::from_utf8_lossy(&version.stdout);
+
+        let (targets, backend) = if version.starts_with("GNU objdump") {
+            let targets = Command::new(&objdump_path).arg("-i").output()?;
+            (String::from_utf8_lossy(&targets.stdout).into_owned(), Backend::Objdump(objdump_path.clone()))
+        } else if version.contains("LLVM version") {
+            // llvm-objdump lists its targets in the version
+            (version.to_string(), Backend::LlvmObjdump(objdump_path.clone()))
+        } else {
+            return Err(io::Error::other(format!(
+                "{} isn't GNU objdump or llvm-objdump", objdump_path.display(),
+            )));
+        };
+
+        if !targets.contains("powerpc") && !targets.contains("ppc32") {
+            return Err(io::Error::other(format!(
+                "{} wasn't built with PowerPC support", objdump_path.display(),
+            )));
+        }
+        Ok(backend)
+    }
+
+    // If `symbols` is given, branches to addresses in it use the symbol's name
+    // instead of the address.
+    pub fn disasm<'a>(
+        &self,
+        iso: impl Read + Seek,
+        section: impl Into<CodeSection>,
+        symbols: Option<&'a SymbolMap>,
+    ) -> io::Result<DisasmIter<'a>> {
+        let section = section.into();
+        let mut instructions = self.disasm_range(iso, section.offset, section.size as u64, section.load_address)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("{} is cut off", section.name),
+                ),
+                _ => e,
+            })?;
+        instructions.symbols = symbols;
+        Ok(instructions)
+    }
+
+    // Disassembles `len` bytes of `data` starting at `start`, as if they were
+    // loaded at `load_address`. Useful for code that isn't in the DOL, like
+    // REL modules.
+    pub fn disasm_range<'a>(
+        &self,
+        mut data: impl Read + Seek,
+        start: u64,
+        len: u64,
+        load_address: u64,
+    ) -> io::Result<DisasmIter<'a>> {
+        data.seek(SeekFrom::Start(start))?;
+        let mut code = Vec::with_capacity(len as usize);
+        data.take(len).read_to_end(&mut code)?;
+        if code.len() as u64 != len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the code is cut off"));
+        }
+
+        let inner = match self.backend {
+            Backend::Native =>
+                IterInner::Native { code, pos: 0, address: load_address },
+            Backend::Objdump(ref objdump_path) =>
+                IterInner::objdump(objdump_path, code, load_address)?,
+            Backend::LlvmObjdump(ref objdump_path) =>
+                IterInner::llvm_objdump(objdump_path, code, load_address)?,
+        };
+        Ok(DisasmIter { inner, symbols: None, current: None })
+    }
+
+    // Writes a listing of `sections` with a `loc_80003100:` style label
+    // before every instruction that's branched to, and branches pointing at
+    // those labels instead of addresses. Branches that leave the sections
+    // being written keep their address. Names from `symbols` are used in
+    // place of generated labels. Returns how many of objdump's lines couldn't
+    // be parsed, like `DisasmIter::unparsed_lines`.
+    pub fn write_with_sections(
+        &self,
+        mut iso: impl Read + Seek,
+        sections: &[CodeSection],
+        symbols: Option<&SymbolMap>,
+        mut output: impl Write,
+    ) -> io::Result<usize> {
+        let in_sections = |address: u64| sections.iter().any(|s|
+            s.load_address <= address && address < s.load_address + s.size as u64
+        );
+
+        // The first pass just finds where the labels go
+        let mut labels = HashSet::new();
+        for section in sections {
+            for ins in self.disasm(&mut iso, section.clone(), None)? {
+                if let Some(target) = ins?.branch_target() {
+                    if in_sections(target) {
+                        labels.insert(target);
+                    }
+                }
+            }
+        }
+
+        let mut unparsed = 0;
+        for (i, section) in sections.iter().enumerate() {
+            if i > 0 { writeln!(output)?; }
+            writeln!(output, "{}:  # {:#010x}, {:#x} bytes", section.name, section.load_address, section.size)?;
+
+            let mut instructions = self.disasm(&mut iso, section.clone(), symbols)?;
+            while let Some(ins) = instructions.next() {
+                let mut ins = ins?;
+                if let Some(name) = instructions.current_symbol() {
+                    writeln!(output, "{}:", name)?;
+                } else if labels.contains(&ins.location) {
+                    writeln!(output, "loc_{:08x}:", ins.location)?;
+                }
+
+                // Symbol names have already been filled in by the iterator
+                let target = ins.branch_target()
+                    .filter(|t| labels.contains(t));
+                if let Some(target) = target {
+                    ins.text = ins.text.replace(&format!("{:#x}", target), &format!("loc_{:08x}", target));
+                }
+                writeln!(output, "{}", ins)?;
+            }
+            unparsed += instructions.unparsed_lines();
+        }
+        output.flush()?;
+        Ok(unparsed)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operand {
+    Gpr(u8),
+    Fpr(u8),
+    Spr(u16),
+    // A condition register field, like cr7
+    Cr(u8),
+    // A single condition register bit, 0 to 31
+    CrBit(u8),
+    Imm(i32),
+    Mem { offset: i16, base: u8 },
+    BranchTarget(u64),
+}
+
+impl Operand {
+    fn parse(text: &str, mnemonic: &str) -> Option<Operand> {
+        let register = |prefix: &str| text.strip_prefix(prefix).and_then(|n| n.parse().ok());
+        let cr_bit = |name: &str| ["lt", "gt", "eq", "so"].iter().position(|&b| b == name);
+
+        if let Some(n) = register("cr") {
+            Some(Operand::Cr(n))
+        } else if let Some(n) = register("r") {
+            Some(Operand::Gpr(n))
+        } else if let Some(n) = register("f") {
+            Some(Operand::Fpr(n))
+        } else if let Some(b) = cr_bit(text) {
+            Some(Operand::CrBit(b as u8))
+        } else if let Some(rest) = text.strip_prefix("4*cr") {
+            let (field, bit) = rest.split_once('+')?;
+            Some(Operand::CrBit(field.parse::<u8>().ok()? * 4 + cr_bit(bit)? as u8))
+        } else if let Some((offset, base)) = text.strip_suffix(')').and_then(|t| t.split_once('(')) {
+            let base = if base == "0" { 0 } else { base.strip_prefix('r')?.parse().ok()? };
+            Some(Operand::Mem { offset: offset.parse().ok()?, base })
+        } else if let Some(hex) = text.strip_prefix("0x") {
+            let n = u64::from_str_radix(hex, 16).ok()?;
+            Some(if mnemonic.starts_with('b') {
+                Operand::BranchTarget(n)
+            } else {
+                Operand::Imm(n as i32)
+            })
+        } else if mnemonic == "mfspr" || mnemonic == "mtspr" {
+            text.parse().ok().map(Operand::Spr)
+        } else {
+            text.parse().ok().map(Operand::Imm)
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Gpr(n) => write!(f, "r{}", n),
+            Operand::Fpr(n) => write!(f, "f{}", n),
+            Operand::Spr(n) => write!(f, "{}", n),
+            Operand::Cr(n) => write!(f, "cr{}", n),
+            Operand::CrBit(n) => {
+                let name = ["lt", "gt", "eq", "so"][(n % 4) as usize];
+                if n < 4 { write!(f, "{}", name) } else { write!(f, "4*cr{}+{}", n / 4, name) }
+            },
+            Operand::Imm(n) => write!(f, "{}", n),
+            // r0 as a base means 0
+            Operand::Mem { offset, base: 0 } => write!(f, "{}(0)", offset),
+            Operand::Mem { offset, base } => write!(f, "{}(r{})", offset, base),
+            Operand::BranchTarget(a) => write!(f, "{:#x}", a),
+        }
+    }
+}
+
+impl From<i16> for Operand {
+    fn from(n: i16) -> Operand {
+        Operand::Imm(n as i32)
+    }
+}
+
+impl From<i32> for Operand {
+    fn from(n: i32) -> Operand {
+        Operand::Imm(n)
+    }
+}
+
+impl From<u32> for Operand {
+    fn from(n: u32) -> Operand {
+        Operand::Imm(n as i32)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Instruction {
+    // For display, `mnemonic` and `operands` have the actual details
+    pub text: String,
+    pub mnemonic: String,
+    pub operands: Vec<Operand>,
+    pub opcode: u32,
+    // The address in memory
+    pub location: u64,
+    // What objdump printed after the operands, like `some_label+0x8` from
+    // `<some_label+0x8>`
+    pub annotation: Option<String>,
+}
+
+// A line of objdump's output
+#[derive(Clone, Debug)]
+pub enum ObjdumpLine {
+    Instruction(Instruction),
+    // `...`, which objdump prints in place of a run of zeros. How big it is
+    // depends on the addresses around it.
+    Gap,
+    // A line with an address that should have been an instruction, but
+    // couldn't be read
+    Unparsed,
+    // Headers, labels, blank lines, and anything else
+    Other,
+}
+
+// Splits off the opcode from the start of `s`, which objdump prints as either
+// four bytes or a single word
+fn split_opcode(s: &str) -> Option<(u32, &str)> {
+    let s = s.trim_start();
+    let (first, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+    if first.len() == 8 {
+        return u32::from_str_radix(first, 16).ok().map(|o| (o, rest));
+    }
+
+    let mut opcode = 0;
+    let mut rest = s;
+    for _ in 0..4 {
+        let (byte, r) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if byte.len() != 2 { return None }
+        opcode = (opcode << 8) | u8::from_str_radix(byte, 16).ok()? as u32;
+        rest = r.trim_start();
+    }
+    Some((opcode, rest))
+}
+
+impl Instruction {
+    // Parses a line of `objdump -D` output, like:
+    // 80003100:	94 21 ff f0 	stwu    r1,-16(r1)
+    pub fn from_objdump(line: &str) -> ObjdumpLine {
+        let line = line.trim();
+        if line == "..." {
+            return ObjdumpLine::Gap;
+        }
+        match Instruction::parse_objdump(line) {
+            Some(ins) => ObjdumpLine::Instruction(ins),
+            None => match line.split_once(':') {
+                Some((location, _)) if u64::from_str_radix(location.trim(), 16).is_ok() => ObjdumpLine::Unparsed,
+                _ => ObjdumpLine::Other,
+            },
+        }
+    }
+
+    fn parse_objdump(line: &str) -> Option<Instruction> {
+        let (location, rest) = line.split_once(':')?;
+        let location = u64::from_str_radix(location.trim(), 16).ok()?;
+        let (opcode, text) = split_opcode(rest)?;
+
+        let text = text.trim();
+        let (text, annotation) = match text.rfind(" <") {
+            Some(i) if text.ends_with('>') => (
+                text[..i].trim_end(),
+                Some(text[i + 2..text.len() - 1].to_string()),
+            ),
+            _ => (text, None),
+        };
+        if text.is_empty() { return None }
+
+        let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+            Some((m, o)) => (m, o.trim()),
+            None => (text, ""),
+        };
+        let operands = operands.split(',')
+            .filter(|o| !o.is_empty())
+            .filter_map(|o| Operand::parse(o.trim(), mnemonic))
+            .collect();
+
+        Some(Instruction {
+            text: text.to_string(),
+            mnemonic: mnemonic.to_string(),
+            operands,
+            opcode,
+            location,
+            annotation,
+        })
+    }
+
+    pub fn is_branch(&self) -> bool {
+        match self.opcode >> 26 {
+            16 | 18 => true,
+            // bclr and bcctr
+            19 => matches!((self.opcode >> 1) & 0x3ff, 16 | 528),
+            _ => false,
+        }
+    }
+
+    // Whether this is a branch that sets the link register, like bl or bctrl
+    pub fn is_call(&self) -> bool {
+        self.is_branch() && self.opcode & 1 != 0
+    }
+
+    // The address a direct branch goes to
+    pub fn branch_target(&self) -> Option<u64> {
+        self.operands.iter().find_map(|o| match *o {
+            Operand::BranchTarget(a) => Some(a),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08x}:  {:08x}  {}", self.location, self.opcode, self.text)
+    }
+}
+
+pub struct DisasmIter<'a> {
+    inner: IterInner,
+    symbols: Option<&'a SymbolMap>,
+    // The location of the last instruction returned
+    current: Option<u64>,
+}
+
+enum IterInner {
+    Native {
+        code: Vec<u8>,
+        pos: usize,
+        address: u64,
+    },
+    Objdump(Box<ObjdumpIter>),
+}
+
+struct ObjdumpIter {
+    child: Child,
+    lines: Lines<BufReader<ChildStdout>>,
+    // objdump skips runs of zeros, and there may be lines that can't be
+    // parsed, so anything it leaves out is decoded from here instead
+    code: Vec<u8>,
+    address: u64,
+    // Where the next instruction should be
+    next_address: u64,
+    // Whether this is llvm-objdump, whose lines need to be cleaned up
+    llvm: bool,
+    // An instruction that was read while filling in a gap before it
+    pending: Option<Instruction>,
+    // Whether all of objdump's output has been read
+    finished: bool,
+    // Lines that should have been instructions but couldn't be parsed. The
+    // code they were for is decoded natively instead.
+    unparsed: usize,
+    // objdump reads the code from here, it's deleted once this is dropped
+    _input: NamedTempFile,
+}
+
+impl<'a> DisasmIter<'a> {
+    // The symbol at the instruction that was just returned by `next`, so
+    // listings can print a label before it
+    pub fn current_symbol(&self) -> Option<&'a str> {
+        let location = self.current?;
+        self.symbols?.name(location)
+    }
+
+    // How many of objdump's lines couldn't be parsed so far. They're still in
+    // the listing, but decoded by the built in decoder instead.
+    pub fn unparsed_lines(&self) -> usize {
+        match self.inner {
+            IterInner::Native { .. } => 0,
+            IterInner::Objdump(ref objdump) => objdump.unparsed,
+        }
+    }
+
+    fn next_instruction(&mut self) -> Option<io::Result<Instruction>> {
+        match self.inner {
+            IterInner::Native { ref code, ref mut pos, address } => {
+                let bytes = code.get(*pos..*pos + 4)?;
+                let location = address + *pos as u64;
+                *pos += 4;
+                Some(Ok(decode(BigEndian::read_u32(bytes), location)))
+            },
+            IterInner::Objdump(ref mut objdump) => objdump.next_instruction(),
+        }
+    }
+}
+
+impl IterInner {
+    fn objdump(objdump_path: &Path, code: Vec<u8>, address: u64) -> io::Result<IterInner> {
+        let mut input = NamedTempFile::new()?;
+        input.write_all(&code)?;
+        input.flush()?;
+
+        let child = Command::new(objdump_path)
+            .args(["-D", "-b", "binary", "-m", "powerpc", "-M", "750cl", "-EB"])
+            .arg(format!("--adjust-vma={:#x}", address))
+            .arg(input.path())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        Ok(IterInner::from_child(child, code, address, input, false))
+    }
+
+    fn llvm_objdump(objdump_path: &Path, code: Vec<u8>, address: u64) -> io::Result<IterInner> {
+        let mut input = NamedTempFile::new()?;
+        DOLHeader::with_text_segment(code.len(), address)
+            .to_elf(Cursor::new(&code), &mut input)?;
+        input.flush()?;
+
+        let child = Command::new(objdump_path)
+            .args(["-d", "--triple=powerpc-none-eabi", "--mcpu=750"])
+            .arg(input.path())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        Ok(IterInner::from_child(child, code, address, input, true))
+    }
+
+    fn from_child(mut child: Child, code: Vec<u8>, address: u64, input: NamedTempFile, llvm: bool) -> IterInner {
+        let lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        IterInner::Objdump(Box::new(ObjdumpIter {
+            child,
+            lines,
+            code,
+            address,
+            next_address: address,
+            llvm,
+            pending: None,
+            finished: false,
+            unparsed: 0,
+            _input: input,
+        }))
+    }
+}
+
+// Rewrites a line of llvm-objdump's output, like:
+// 80003100: 94 21 ff f0  	stwu 1, -16(1)
+// to look like GNU objdump's. The registers are still plain numbers, since
+// llvm-objdump can't print their names.
+fn normalize_llvm_line(line: &str) -> String {
+    let (start, text) = match line.split_once('\t') {
+        Some(parts) => parts,
+        None => return line.to_string(),
+    };
+    let text = text.trim();
+    let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let operands = operands.trim();
+    let (operands, annotation) = match operands.rfind(" <") {
+        Some(i) => operands.split_at(i),
+        None => (operands, ""),
+    };
+    let operands = operands.split(',').map(str::trim).collect::<Vec<_>>().join(",");
+    format!("{}\t{:<7} {}{}", start.trim_end(), mnemonic, operands, annotation).trim_end().to_string()
+}
+
+impl ObjdumpIter {
+    fn next_instruction(&mut self) -> Option<io::Result<Instruction>> {
+        if self.pending.is_none() && !self.finished {
+            match self.read_instruction() {
+                Ok(Some(ins)) => self.pending = Some(ins),
+                Ok(None) => self.finished = true,
+                Err(e) => {
+                    // The listing stops here, instead of going on with the
+                    // rest decoded natively like nothing happened
+                    self.finished = true;
+                    self.next_address = self.address + self.code.len() as u64;
+                    return Some(Err(e));
+                },
+            }
+        }
+
+        let gap_end = self.pending.as_ref().map_or(self.address + self.code.len() as u64, |p| p.location);
+        if self.next_address + 4 <= gap_end {
+            let pos = (self.next_address - self.address) as usize;
+            let ins = decode(BigEndian::read_u32(self.code.get(pos..pos + 4)?), self.next_address);
+            self.next_address += 4;
+            return Some(Ok(ins));
+        }
+        let ins = self.pending.take()?;
+        self.next_address = ins.location + 4;
+        Some(Ok(ins))
+    }
+
+    // The next instruction objdump printed, skipping the headers and labels
+    // before the code. Gaps are handled by the addresses. Once the output
+    // runs out, this makes sure objdump didn't fail partway through.
+    fn read_instruction(&mut self) -> io::Result<Option<Instruction>> {
+        for line in self.lines.by_ref() {
+            let line = line?;
+            let line = if self.llvm { normalize_llvm_line(&line) } else { line };
+            match Instruction::from_objdump(&line) {
+                // llvm-objdump reads paired singles as AltiVec
+                ObjdumpLine::Instruction(ref ins) if self.llvm && ins.opcode >> 26 == 4 =>
+                    return Ok(Some(decode(ins.opcode, ins.location))),
+                ObjdumpLine::Instruction(ins) => return Ok(Some(ins)),
+                ObjdumpLine::Unparsed => self.unparsed += 1,
+                ObjdumpLine::Gap | ObjdumpLine::Other => {},
+            }
+        }
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("objdump stopped early ({})", status)));
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for ObjdumpIter {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Errors come from objdump, if it couldn't be read from or failed. There
+// aren't any more instructions after one.
+impl<'a> Iterator for DisasmIter<'a> {
+    type Item = io::Result<Instruction>;
+
+    fn next(&mut self) -> Option<io::Result<Instruction>> {
+        let mut ins = match self.next_instruction()? {
+            Ok(ins) => ins,
+            Err(e) => return Some(Err(e)),
+        };
+        self.current = Some(ins.location);
+
+        let target = ins.branch_target();
+        let name = target.and_then(|t| self.symbols?.name(t).map(|n| (t, n)));
+        if let Some((target, name)) = name {
+            ins.text = ins.text.replace(&format!("{:#x}", target), name);
+        }
+        Some(Ok(ins))
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub size: u64,
+}
+
+// Function and data names by address, loaded from a .map file
+#[derive(Clone, Debug, Default)]
+pub struct SymbolMap {
+    symbols: BTreeMap<u64, Symbol>,
+}
+
+impl SymbolMap {
+    // Loads either a map saved by Dolphin, where each line looks like:
+    //   80003100 00000034 80003100 0 __start
+    // or a CodeWarrior linker map, where it's:
+    //   00000000 000034 80003100 00000400  4 __start 	__start.o
+    // (older versions of CodeWarrior leave out the file offset column).
+    // Section headers, column headers, and anything else are skipped.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<SymbolMap> {
+        let file = BufReader::new(File::open(path)?);
+        let mut map = SymbolMap::default();
+        for line in file.lines() {
+            if let Some((address, symbol)) = parse_line(&line?) {
+                map.symbols.insert(address, symbol);
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn get(&self, address: u64) -> Option<&Symbol> {
+        self.symbols.get(&address)
+    }
+
+    pub fn name(&self, address: u64) -> Option<&str> {
+        self.get(address).map(|s| &s.name[..])
+    }
+
+    pub fn insert(&mut self, address: u64, symbol: Symbol) {
+        self.symbols.insert(address, symbol);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &Symbol)> {
+        self.symbols.iter().map(|(&a, s)| (a, s))
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    // Writes the map the way Dolphin saves them, so it can be loaded from
+    // its Maps folder. Dolphin won't load symbols with a size of 0, so
+    // those are left out.
+    pub fn write_dolphin_map(&self, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, ".text section layout")?;
+        for (address, symbol) in self.iter().filter(|(_, s)| s.size > 0) {
+            writeln!(output, "{:08x} {:08x} {:08x} 0 {}", address, symbol.size, address, symbol.name)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    if s.len() > 8 { return None }
+    u64::from_str_radix(s, 16).ok()
+}
+
+fn parse_line(line: &str) -> Option<(u64, Symbol)> {
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    if tokens.len() < 5 { return None }
+
+    let start = parse_hex(tokens[0])?;
+    let size = parse_hex(tokens[1])?;
+    let address = parse_hex(tokens[2])?;
+
+    let name = if start == address && tokens[3] == "0" {
+        // Dolphin's format, where the name is the rest of the line (it can
+        // have spaces, for demangled names)
+        let mut rest = line.trim_start();
+        for _ in 0..4 {
+            rest = rest.split_once(char::is_whitespace)?.1.trim_start();
+        }
+        rest.trim_end()
+    } else {
+        // CodeWarrior's, where the name is followed by the object file
+        let name_index = if tokens[3].len() == 8 && parse_hex(tokens[3]).is_some() { 5 } else { 4 };
+        tokens[name_index - 1].parse::<u32>().ok()?;
+        tokens.get(name_index)?
+    };
+
+    // CodeWarrior lists the sections themselves, and local labels like
+    // `...data.0`, which aren't useful here
+    if name.is_empty() || name.starts_with('.') {
+        return None;
+    }
+
+    Some((address, Symbol { name: name.to_string(), size }))
+}
@@ -0,0 +1,260 @@
+// Everything that changes how `Game::extract_with` extracts a ROM, so new
+// options don't mean changing every caller. Build it with the methods below,
+// then `build` checks that they make sense together:
+//
+//     let options = ExtractOptions::new()
+//         .overwrite(true)
+//         .system_data(false)
+//         .filter(|path, _| path.extension().is_some_and(|e| e == "thp"))
+//         .build()?;
+//     game.extract_with(&mut iso, "out", &options)?;
+//
+// It can be cloned and used for as many games as needed.
+
+use std::io::{self, BufRead, Seek};
+use std::path::Path;
+use std::sync::Arc;
+
+use compression::DecompressReport;
+use game::Game;
+use progress::Progress;
+use sections::fst::entry::{FileEntry, FileFilter};
+use ::{ExistingCheck, ExtractCount, ExtractMode, ExtractOrder, TruncatedTail};
+
+type Filter = dyn Fn(&Path, &FileEntry) -> bool + Send + Sync;
+type ProgressCallback = dyn Fn(usize, usize) + Send + Sync;
+
+#[derive(Clone)]
+pub struct ExtractOptions {
+    overwrite: bool,
+    merge: bool,
+    resume: Option<ExistingCheck>,
+    system_data: bool,
+    files: bool,
+    filter: Option<Arc<Filter>>,
+    truncated: TruncatedTail,
+    order: ExtractOrder,
+    decompress: bool,
+    decompressed_suffix: Option<String>,
+    progress: Option<Arc<ProgressCallback>>,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> ExtractOptions {
+        ExtractOptions {
+            overwrite: false,
+            merge: false,
+            resume: None,
+            system_data: true,
+            files: true,
+            filter: None,
+            truncated: TruncatedTail::default(),
+            order: ExtractOrder::default(),
+            decompress: false,
+            decompressed_suffix: None,
+            progress: None,
+        }
+    }
+}
+
+impl ExtractOptions {
+    // Everything gets extracted, to a directory that can't already exist
+    pub fn new() -> ExtractOptions {
+        ExtractOptions::default()
+    }
+
+    // Delete the output directory first if it's there
+    pub fn overwrite(mut self, overwrite: bool) -> ExtractOptions {
+        self.overwrite = overwrite;
+        self
+    }
+
+    // Extract into the output directory if it's there, replacing files
+    pub fn merge(mut self, merge: bool) -> ExtractOptions {
+        self.merge = merge;
+        self
+    }
+
+    // Extract into the output directory if it's there, leaving the files
+    // that `check` says were already extracted alone
+    pub fn resume(mut self, check: Option<ExistingCheck>) -> ExtractOptions {
+        self.resume = check;
+        self
+    }
+
+    // Whether to write `&&systemdata`
+    pub fn system_data(mut self, system_data: bool) -> ExtractOptions {
+        self.system_data = system_data;
+        self
+    }
+
+    // Whether to write the files in the FST
+    pub fn files(mut self, files: bool) -> ExtractOptions {
+        self.files = files;
+        self
+    }
+
+    // Only extract the files `filter` accepts, given their path on the ROM
+    pub fn filter(mut self, filter: impl Fn(&Path, &FileEntry) -> bool + Send + Sync + 'static) -> ExtractOptions {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    pub fn truncated(mut self, tail: TruncatedTail) -> ExtractOptions {
+        self.truncated = tail;
+        self
+    }
+
+    pub fn order(mut self, order: ExtractOrder) -> ExtractOptions {
+        self.order = order;
+        self
+    }
+
+    // Decompress Yaz0 and Yay0 files after they're extracted, like
+    // `Game::decompress_extracted`
+    pub fn decompress(mut self, decompress: bool) -> ExtractOptions {
+        self.decompress = decompress;
+        self
+    }
+
+    // What to add to the names of decompressed files, like
+    // `compression::DECOMPRESSED_SUFFIX`
+    pub fn decompressed_suffix(mut self, suffix: impl Into<String>) -> ExtractOptions {
+        self.decompressed_suffix = Some(suffix.into());
+        self
+    }
+
+    // Called with how many files have been done and how many there are in
+    // all, instead of printing a status line
+    pub fn progress(mut self, callback: impl Fn(usize, usize) + Send + Sync + 'static) -> ExtractOptions {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    // Fails if some of the options don't go together, or there's nothing to
+    // extract
+    pub fn build(self) -> io::Result<ExtractOptions> {
+        self.check()?;
+        Ok(self)
+    }
+
+    fn check(&self) -> io::Result<()> {
+        let conflict = if self.overwrite && self.merge {
+            Some("overwriting the output directory and merging into it can't both be done")
+        } else if self.overwrite && self.resume.is_some() {
+            Some("an extraction can't be resumed if the output directory is overwritten")
+        } else if !self.system_data && !self.files {
+            Some("without the system data or the files, there's nothing to extract")
+        } else if self.filter.is_some() && !self.files {
+            Some("a filter can't be used when the files aren't being extracted")
+        } else if self.decompress && !self.files {
+            Some("only files can be decompressed, so they have to be extracted too")
+        } else if self.decompress && self.resume.is_some() {
+            Some("files can't be decompressed when resuming, since the ones that are already there might be decompressed already")
+        } else if self.decompressed_suffix.is_some() && !self.decompress {
+            Some("a suffix for decompressed files was given, but they aren't being decompressed")
+        } else {
+            None
+        };
+        match conflict {
+            Some(message) => Err(io::Error::new(io::ErrorKind::InvalidInput, message)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn mode(&self) -> ExtractMode {
+        if self.overwrite {
+            ExtractMode::Overwrite
+        } else if let Some(check) = self.resume {
+            ExtractMode::Resume(check)
+        } else if self.merge {
+            ExtractMode::Merge
+        } else {
+            ExtractMode::FailIfExists
+        }
+    }
+
+    pub fn includes_system_data(&self) -> bool {
+        self.system_data
+    }
+
+    // Whether everything on the ROM is extracted
+    pub fn is_filtered(&self) -> bool {
+        !(self.system_data && self.files) || self.filter.is_some()
+    }
+
+    pub fn truncated_tail(&self) -> TruncatedTail {
+        self.truncated
+    }
+
+    pub fn extract_order(&self) -> ExtractOrder {
+        self.order
+    }
+
+    // `None` if files aren't decompressed, otherwise the suffix to give them,
+    // if there is one
+    pub fn decompression(&self) -> Option<Option<&str>> {
+        if !self.decompress { return None }
+        Some(self.decompressed_suffix.as_deref())
+    }
+}
+
+// What `Game::extract_with` did
+#[derive(Debug, Default)]
+pub struct ExtractReport {
+    pub count: ExtractCount,
+    // Only there if files were decompressed
+    pub decompressed: Option<DecompressReport>,
+}
+
+impl Game {
+    // Extracts the ROM to `path` the way `options` says to. Files cut off by
+    // the end of the image are padded with zeros unless `options` says not to.
+    pub fn extract_with(
+        &mut self,
+        mut iso: impl BufRead + Seek,
+        path: impl AsRef<Path>,
+        options: &ExtractOptions,
+    ) -> io::Result<ExtractReport> {
+        options.check()?;
+        let path = path.as_ref();
+        let mode = options.mode();
+        mode.prepare_dir(path)?;
+
+        let mut count = ExtractCount::default();
+        if options.system_data {
+            count += self.extract_system_data(&mut iso, path, mode)?;
+        }
+        if options.files {
+            info!("Extracting file system...");
+            let filter = options.filter.as_deref().map(|f| f as FileFilter);
+            // The system files count towards the progress too
+            let existing = count.total();
+            let order = options.order;
+            count += match options.progress {
+                Some(ref callback) => self.extract_files_reporting(&mut iso, path, mode, order, filter, |done, total| {
+                    callback(done + existing, total + existing);
+                }),
+                None => {
+                    let mut progress = Progress::default();
+                    let verb = if mode.existing_check().is_some() { "done" } else { "written" };
+                    self.extract_files_reporting(&mut iso, path, mode, order, filter, |done, total| {
+                        progress.files(done + existing, total + existing, verb);
+                    })
+                },
+            }?;
+        }
+        if options.truncated == TruncatedTail::ZeroFill {
+            self.fill_truncated_files(path)?;
+        }
+
+        let decompressed = match options.decompression() {
+            Some(suffix) => {
+                info!("Decompressing files...");
+                Some(self.decompress_extracted(path, suffix)?)
+            },
+            None => None,
+        };
+        Ok(ExtractReport { count, decompressed })
+    }
+}
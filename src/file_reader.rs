@@ -0,0 +1,77 @@
+use std::cmp::min;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+// Reads one file (or system file, or DOL segment) out of an image without
+// extracting it. Offsets are relative to the start of the file, seeking past
+// the end stops at the end, and reads stop there too.
+pub struct FstFileReader<'a, R: 'a> {
+    iso: &'a mut R,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<'a, R: Seek> FstFileReader<'a, R> {
+    pub fn new(iso: &'a mut R, start: u64, len: u64) -> io::Result<FstFileReader<'a, R>> {
+        iso.seek(SeekFrom::Start(start))?;
+        Ok(FstFileReader { iso, start, len, pos: 0 })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Where the file starts in the image
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    fn remaining(&self) -> u64 {
+        self.len - self.pos
+    }
+}
+
+impl<'a, R: Read + Seek> Read for FstFileReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = min(buf.len() as u64, self.remaining()) as usize;
+        if max == 0 { return Ok(0) }
+        let n = self.iso.read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a, R: BufRead + Seek> BufRead for FstFileReader<'a, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let remaining = self.remaining();
+        let buf = self.iso.fill_buf()?;
+        Ok(&buf[..min(buf.len() as u64, remaining) as usize])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = min(amt as u64, self.remaining());
+        self.iso.consume(amt as usize);
+        self.pos += amt;
+    }
+}
+
+impl<'a, R: Seek> Seek for FstFileReader<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(p) => self.len.checked_add_signed(p),
+            SeekFrom::Current(p) => self.pos.checked_add_signed(p),
+        };
+        let target = target.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Can't seek before the start of the file.",
+        ))?;
+        self.pos = min(target, self.len);
+        self.iso.seek(SeekFrom::Start(self.start + self.pos))?;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,182 @@
+// Working out what a file on a ROM is, mostly from the first few bytes of it,
+// since games don't always use the usual extensions. Formats without a magic
+// number are found by their extension instead.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use sections::fst::entry::FileEntry;
+use {read_fully, Game};
+
+// How much of a file `identify` needs to see
+pub const HEADER_LEN: usize = 16;
+
+// The magic numbers that files start with, and what they mean. salvage uses
+// these too, to find where files start on a ROM without an FST.
+const MAGIC: &[(&[u8], FileKind)] = &[
+    (b"THP\0", FileKind::Thp),
+    (b"RARC", FileKind::Rarc),
+    (b"Yaz0", FileKind::Yaz0),
+    (b"Yay0", FileKind::Yay0),
+    (b"BNR1", FileKind::Banner),
+    (b"BNR2", FileKind::Banner),
+    (&[0x00, 0x20, 0xAF, 0x30], FileKind::Tpl),
+];
+
+// DSP files have a header with the sample rate, but no magic number, so these
+// are the ones that count
+const DSP_SAMPLE_RATES: [u32; 6] = [16000, 22050, 24000, 32000, 44100, 48000];
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    // Video
+    Thp,
+    // Audio: DSP files are sound effects and music with a header, ADP files
+    // are streamed music without one
+    Dsp,
+    Adp,
+    // Archives of other files
+    Rarc,
+    // Compressed files, which are usually archives or textures
+    Yaz0,
+    Yay0,
+    // Textures
+    Bti,
+    Tpl,
+    // Code that's loaded after the DOL
+    Rel,
+    // opening.bnr, with the game's title and icon
+    Banner,
+    Unknown,
+}
+
+impl FileKind {
+    pub fn is_texture(self) -> bool {
+        self == FileKind::Bti || self == FileKind::Tpl
+    }
+
+    pub fn is_audio(self) -> bool {
+        self == FileKind::Dsp || self == FileKind::Adp
+    }
+
+    pub fn is_compressed(self) -> bool {
+        self == FileKind::Yaz0 || self == FileKind::Yay0
+    }
+
+    // The extension for files of this kind that don't have a name, which
+    // `from_extension` turns back into the same kind
+    pub fn extension(self) -> &'static str {
+        match self {
+            FileKind::Thp => "thp",
+            FileKind::Dsp => "dsp",
+            FileKind::Adp => "adp",
+            FileKind::Rarc => "rarc",
+            FileKind::Yaz0 => "yaz0",
+            FileKind::Yay0 => "yay0",
+            FileKind::Bti => "bti",
+            FileKind::Tpl => "tpl",
+            FileKind::Rel => "rel",
+            FileKind::Banner => "bnr",
+            FileKind::Unknown => "bin",
+        }
+    }
+
+    fn from_extension(ext: &str) -> FileKind {
+        match &*ext.to_lowercase() {
+            "thp" => FileKind::Thp,
+            "dsp" => FileKind::Dsp,
+            "adp" => FileKind::Adp,
+            "arc" | "rarc" => FileKind::Rarc,
+            "szs" | "yaz0" => FileKind::Yaz0,
+            "szp" => FileKind::Yay0,
+            "bti" => FileKind::Bti,
+            "tpl" => FileKind::Tpl,
+            "rel" => FileKind::Rel,
+            "bnr" => FileKind::Banner,
+            _ => FileKind::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FileKind::Thp => "THP",
+            FileKind::Dsp => "DSP",
+            FileKind::Adp => "ADP",
+            FileKind::Rarc => "RARC",
+            FileKind::Yaz0 => "Yaz0",
+            FileKind::Yay0 => "Yay0",
+            FileKind::Bti => "BTI",
+            FileKind::Tpl => "TPL",
+            FileKind::Rel => "REL",
+            FileKind::Banner => "banner",
+            FileKind::Unknown => "unknown",
+        };
+        // So it can be padded in columns
+        f.pad(name)
+    }
+}
+
+// What the file called `name` that starts with `header` is. `header` can be
+// shorter than `HEADER_LEN` for small files.
+pub fn identify(header: &[u8], name: &Path) -> FileKind {
+    if let Some(kind) = identify_magic(header) {
+        return kind;
+    }
+    if looks_like_dsp(header) {
+        return FileKind::Dsp;
+    }
+    name.extension()
+        .map_or(FileKind::Unknown, |e| FileKind::from_extension(&e.to_string_lossy()))
+}
+
+// What the file that starts with `header` is, going only by its magic number
+pub fn identify_magic(header: &[u8]) -> Option<FileKind> {
+    MAGIC.iter()
+        .find(|&&(magic, _)| header.starts_with(magic))
+        .map(|&(_, kind)| kind)
+}
+
+// A DSP header starts with the number of samples and nibbles, then the sample
+// rate, whether it loops, and the format, which is always 0 for ADPCM
+fn looks_like_dsp(header: &[u8]) -> bool {
+    if header.len() < HEADER_LEN { return false }
+    let samples = BigEndian::read_u32(&header[0..]);
+    let nibbles = BigEndian::read_u32(&header[4..]);
+    let rate = BigEndian::read_u32(&header[8..]);
+    let looped = BigEndian::read_u16(&header[12..]);
+    let format = BigEndian::read_u16(&header[14..]);
+    samples > 0 && nibbles >= samples && DSP_SAMPLE_RATES.contains(&rate) && looped <= 1 && format == 0
+}
+
+impl Game {
+    pub fn identify_file(&self, mut iso: impl Read + Seek, file: &FileEntry) -> io::Result<FileKind> {
+        self.require_image("Identifying files")?;
+        let mut header = [0; HEADER_LEN];
+        let len = file.size.min(HEADER_LEN);
+        iso.seek(SeekFrom::Start(file.file_offset))?;
+        // Files past the end of a truncated image just have less to go by
+        let len = read_fully(&mut iso, &mut header[..len])?;
+        Ok(identify(&header[..len], Path::new(&file.info.name)))
+    }
+
+    // The kind of each of `files`, by their index in the FST. They're read
+    // in the order they're on the ROM, to keep the seeks short.
+    pub fn identify_files<'a>(
+        &self,
+        mut iso: impl Read + Seek,
+        files: impl IntoIterator<Item = &'a FileEntry>,
+    ) -> io::Result<HashMap<usize, FileKind>> {
+        let mut files = files.into_iter().collect::<Vec<_>>();
+        files.sort_by_key(|f| f.file_offset);
+        files.into_iter()
+            .map(|f| Ok((f.info.index, self.identify_file(&mut iso, f)?)))
+            .collect()
+    }
+}
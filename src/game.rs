@@ -1,32 +1,326 @@
-use std::collections::BTreeMap;
-use std::fs::{create_dir, File};
-use std::io::{self, BufRead, Seek};
-use std::path::Path;
-
-use sections::apploader::{Apploader, APPLOADER_OFFSET};
-use sections::dol::DOLHeader;
-use sections::dol::segment::Segment;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{self, BufRead, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Index;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use glob::{MatchOptions, Pattern};
+
+#[cfg(feature = "archive")]
+use archive::{ArchiveFormat, ArchiveWriter, TarWriter, ZipWriter};
+use filetype::FileKind;
+use handles::HandleBudget;
+use hash::{hash_range, hash_reader_with_progress, Digest, HashAlgo, HashReport, HashWriter, ManifestEntry};
+use progress::Progress;
+use source;
+
+use sections::apploader::{Apploader, APPLOADER_CODE_OFFSET, APPLOADER_OFFSET};
+use sections::dol::{DOLHeader, DOL_HEADER_LEN};
+use sections::dol::segment::{Segment, SegmentType};
 use sections::fst::{
-    entry::DirectoryEntry,
+    entry::{DirectoryEntry, Entry, FileEntry, FileFilter, ENTRY_SIZE},
+    FstIssue,
     FST,
 };
-use sections::header::{GAME_HEADER_SIZE, Header};
+use sections::header::{DiscVariant, GAME_HEADER_SIZE, Header, UnsupportedVariant};
+use sections::rel::RelHeader;
 use ::{
+    ExtractCount,
+    ExtractMode,
+    ExtractOptions,
+    ExtractOrder,
+    extract_section,
+    FstFileReader,
     format_u64,
     NumberStyle,
     paths::*,
+    read_fully,
 };
 
-use sections::Section;
+use sections::{ExtractOutcome, ResolvedSection, Section, SectionType, UniqueSectionType};
 
 pub const ROM_SIZE: usize = 0x57058000;
 
+// Everything `info` prints about a ROM, for tools that want it as data
+#[derive(Debug, Serialize)]
+pub struct GameInfo {
+    pub title: String,
+    pub game_code: String,
+    pub maker_code: String,
+    pub disk_id: u8,
+    pub version: u8,
+    pub region: String,
+    pub video_mode: String,
+    pub audio_streaming: bool,
+    pub stream_buffer_size: u8,
+    pub variant: DiscVariant,
+    pub apploader_offset: u64,
+    pub apploader_size: usize,
+    pub apploader_date: String,
+    pub apploader_entry_point: u64,
+    pub dol_offset: u64,
+    pub dol_size: usize,
+    pub entry_point: u64,
+    pub fst_offset: u64,
+    pub fst_size: usize,
+    pub segments: Vec<SegmentInfo>,
+    pub layout: Vec<LayoutEntry>,
+    // Only there if they're asked for, since they mean reading the DOL
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hashes: Vec<SectionHashes>,
+}
+
+// Everything that was read from a ROM's system data, for tools that use gcmod
+// as a library and want all of it in one go. It's only what was parsed, like
+// offsets, sizes, and header fields, not any of the data itself.
+#[derive(Debug, Serialize)]
+pub struct GameMetadata<'a> {
+    pub variant: DiscVariant,
+    pub image_size: u64,
+    pub header: &'a Header,
+    pub apploader: &'a Apploader,
+    pub dol: &'a DOLHeader,
+    pub fst: &'a FST,
+}
+
+// The hashes of a system file, like people use to tell builds of a game
+// apart. They're the same as the extracted file's.
+#[derive(Debug, Serialize)]
+pub struct SectionHashes {
+    pub name: String,
+    pub crc32: String,
+    pub sha1: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SegmentInfo {
+    pub name: String,
+    pub offset: u64,
+    pub size: usize,
+    pub load_address: u64,
+}
+
+// What `Game::print_layout` lists besides the system files. Without `files`,
+// all of the files are summed up in one row.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LayoutOptions {
+    pub files: bool,
+    pub segments: bool,
+}
+
+// A section from `Game::rom_layout`. `end` is one past the last byte, so empty
+// files have the same start and end.
+#[derive(Debug, Serialize)]
+pub struct LayoutEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub section_type: &'static str,
+    pub start: u64,
+    pub end: u64,
+    pub size: usize,
+}
+
+// Two sections on the ROM that share some bytes, from `Game::check_overlaps`.
+// `first` is the one that starts first, and `end` is one past the last byte
+// they share.
+#[derive(Debug, Serialize)]
+pub struct Overlap {
+    pub first: String,
+    pub second: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+// A file that goes past the end of the image. `path` is where it's extracted
+// to, and `missing` is how many bytes of it aren't on the image.
+#[derive(Debug)]
+pub struct TruncatedFile {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub size: u64,
+    pub missing: u64,
+}
+
+// What `Game::verify_directory` or `Game::verify_manifest` found. The paths
+// are relative to the directory that was checked, and a manifest's treated
+// like a directory with the files it lists.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub matching: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+    pub missing: Vec<PathBuf>,
+    // Files that aren't on the ROM at all
+    pub extra: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    pub fn is_unchanged(&self) -> bool {
+        self.modified.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+// What `Game::diff` found. "a" is the first ROM and "b" is the second. Paths
+// are the ones the files would have if the ROM were extracted.
+#[derive(Debug, Default, Serialize)]
+pub struct DiffReport {
+    pub header: Vec<HeaderChange>,
+    pub only_in_a: Vec<PathBuf>,
+    pub only_in_b: Vec<PathBuf>,
+    pub changed: Vec<FileDiff>,
+    pub unchanged: usize,
+}
+
+impl DiffReport {
+    pub fn is_identical(&self) -> bool {
+        self.header.is_empty() &&
+            self.only_in_a.is_empty() &&
+            self.only_in_b.is_empty() &&
+            self.changed.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeaderChange {
+    pub field: &'static str,
+    pub a: String,
+    pub b: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileDiff {
+    pub path: PathBuf,
+    pub size_a: usize,
+    pub size_b: usize,
+    // How many bytes are different, counting the bytes one file has past the
+    // end of the other. `None` if the diff didn't count them.
+    pub differing_bytes: Option<u64>,
+}
+
+struct ExtractedFile<'a> {
+    path: PathBuf,
+    section: NamedSection<'a>,
+    offset: u64,
+    size: usize,
+}
+
+// The number of bytes that differ between `a` and `b`, including any extra
+// bytes on the end of the longer one. With `stop_early`, it stops at the
+// first difference, so it only says whether they're the same.
+fn count_differences(mut a: impl Read, mut b: impl Read, stop_early: bool) -> io::Result<u64> {
+    let mut buf_a = vec![0; 0x10000];
+    let mut buf_b = vec![0; 0x10000];
+    let mut differences = 0;
+    loop {
+        let read_a = read_fully(&mut a, &mut buf_a)?;
+        let read_b = read_fully(&mut b, &mut buf_b)?;
+        differences += buf_a[..read_a].iter().zip(&buf_b[..read_b])
+            .filter(|(x, y)| x != y)
+            .count() as u64;
+        differences += (read_a as i64 - read_b as i64).unsigned_abs();
+        if stop_early && differences > 0 {
+            return Ok(differences);
+        }
+        if read_a < buf_a.len() || read_b < buf_b.len() {
+            break;
+        }
+    }
+    // Whatever's left of the longer one is all different
+    differences += io::copy(&mut a, &mut io::sink())? + io::copy(&mut b, &mut io::sink())?;
+    Ok(differences)
+}
+
+// Checks what's written to it against a file, as it's written
+struct Comparer<R> {
+    file: R,
+    differs: bool,
+}
+
+impl<R: Read> Write for Comparer<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut actual = vec![0; buf.len()];
+        let read = read_fully(&mut self.file, &mut actual)?;
+        if read < buf.len() || actual != buf {
+            self.differs = true;
+            return Err(io::Error::other("the file is different"));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Every file under `dir`, relative to the directory the walk started in
+fn list_files(dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            list_files(&entry.path(), &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct Game {
     pub header: Header,
     pub apploader: Apploader,
     pub fst: FST,
     pub dol: DOLHeader,
+    // How big the image actually is, which isn't always ROM_SIZE, like for
+    // images made for emulators
+    pub image_size: u64,
+    pub variant: DiscVariant,
+    // Where the ROM was extracted to, if it was opened from there with
+    // `open_dir` instead of from an image
+    pub extracted_root: Option<PathBuf>,
+}
+
+// Triforce discs have this in their root for the loader
+const TRIFORCE_BOOT_ID: &str = "boot.id";
+
+// The header can only tell NR discs apart, Triforce ones are found by their
+// files
+fn detect_variant(header: &Header, fst: &FST) -> DiscVariant {
+    let has_boot_id = fst.root().iter_contents(&fst.entries)
+        .any(|e| e.is_file() && e.info().name.eq_ignore_ascii_case(TRIFORCE_BOOT_ID));
+    if has_boot_id {
+        DiscVariant::Triforce
+    } else {
+        header.variant
+    }
+}
+
+// Discs without a GameCube apploader get everything between where it'd
+// start and whatever comes next instead
+fn read_apploader(
+    iso: impl Read + Seek,
+    offset: u64,
+    header: &Header,
+    fst: &FST,
+    variant: DiscVariant,
+) -> io::Result<Apploader> {
+    if variant.has_apploader() {
+        return Apploader::new(iso, offset + APPLOADER_OFFSET);
+    }
+    let end = fst.entries.iter()
+        .filter_map(|e| e.as_file())
+        .map(|f| f.file_offset)
+        .chain(vec![header.dol_offset, header.fst_offset])
+        .filter(|&o| o > APPLOADER_OFFSET)
+        .min()
+        .unwrap_or(APPLOADER_OFFSET);
+    // Rounded down, since the size is aligned to 32 bytes
+    Ok(Apploader::unparsed(((end - APPLOADER_OFFSET) & !31) as usize))
 }
 
 impl Game {
@@ -36,18 +330,325 @@ impl Game {
         R: BufRead + Seek,
     {
         let header = Header::new(&mut iso, offset)?;
-        let apploader = Apploader::new(&mut iso, offset + APPLOADER_OFFSET)?;
         let dol = DOLHeader::new(&mut iso, offset + header.dol_offset)?;
-        let fst = FST::new(&mut iso, offset + header.fst_offset)?;
+        let fst = FST::with_size(&mut iso, offset + header.fst_offset, header.fst_size)?;
+        // The FST says what kind of disc it is, which says whether there's
+        // an apploader to read
+        let variant = detect_variant(&header, &fst);
+        let apploader = read_apploader(&mut iso, offset, &header, &fst, variant)?;
+        let image_size = iso.seek(SeekFrom::End(0))?.saturating_sub(offset);
 
         Ok(Game {
             header,
             apploader,
             fst,
             dol,
+            image_size,
+            variant,
+            extracted_root: None,
         })
     }
 
+    // Opens a ROM that was extracted to `root` from its &&systemdata, so it
+    // can be looked at without the image. Anything that needs the files, like
+    // extracting them, fails, but the system data can still be read from the
+    // `source::dir::SystemDataImage` for `root`.
+    pub fn open_dir(root: impl AsRef<Path>) -> io::Result<Game> {
+        let root = root.as_ref();
+        let mut game = Game::open(source::dir::SystemDataImage::open(root)?, 0)?;
+        game.extracted_root = Some(root.to_path_buf());
+        Ok(game)
+    }
+
+    // Fails for games opened with `open_dir`, which only have the system data
+    pub(crate) fn require_image(&self, what: &str) -> io::Result<()> {
+        match &self.extracted_root {
+            Some(root) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} needs a ROM, but this was opened from {}, which only has the system data", what, root.display()),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // Like `open`, but fails if `validate` finds anything wrong with the FST
+    pub fn open_strict<R>(mut iso: R, offset: u64) -> io::Result<Game>
+    where
+        R: BufRead + Seek,
+    {
+        let game = Game::open(&mut iso, offset)?;
+        let issues = game.validate();
+        match issues.first() {
+            None => Ok(game),
+            Some(first) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} ({} problems found in total)", first, issues.len()),
+            )),
+        }
+    }
+
+    // Everything `FST::validate` checks, plus whether any files are on top of
+    // the apploader or DOL, whether the FST's names fit in its size from the
+    // header, and whether the apploader runs into the DOL or FST
+    pub fn validate(&self) -> Vec<FstIssue> {
+        let mut issues = self.fst.validate(self.image_size);
+        issues.extend(self.fst.overlapping_files(
+            "apploader",
+            APPLOADER_OFFSET,
+            APPLOADER_OFFSET + self.apploader.total_size() as u64,
+        ));
+        issues.extend(self.fst.overlapping_files(
+            "DOL",
+            self.dol.offset,
+            self.dol.offset + self.dol.dol_size as u64,
+        ));
+        let names_end = self.fst.offset + self.fst.size as u64;
+        let fst_end = self.fst.offset + self.header.fst_size as u64;
+        if names_end > fst_end {
+            issues.push(FstIssue::NamesOutOfRange { end: names_end, fst_end });
+        }
+        if self.variant.has_apploader() {
+            let apploader_end = APPLOADER_CODE_OFFSET + (self.apploader.code_size + self.apploader.trailer_size) as u64;
+            for (section, start) in [("DOL", self.dol.offset), ("FST", self.fst.offset)] {
+                if self.apploader.overlaps(start) {
+                    issues.push(FstIssue::ApploaderOverlap { end: apploader_end, section, start });
+                }
+            }
+        }
+        issues
+    }
+
+    // Hashes a whole image with each of `algos` in one pass. This doesn't
+    // need to seek, so `iso` can be a pipe.
+    pub fn hash_image(iso: impl Read, algos: &[HashAlgo]) -> io::Result<HashReport> {
+        Game::hash_image_with_progress(iso, algos, |_| {})
+    }
+
+    // Like `hash_image`, but calls `progress` with the number of bytes read
+    // so far as it goes
+    pub fn hash_image_with_progress(
+        iso: impl Read,
+        algos: &[HashAlgo],
+        progress: impl FnMut(u64),
+    ) -> io::Result<HashReport> {
+        let start = Instant::now();
+        let mut bytes_read = 0;
+        let mut progress = progress;
+        let digests = hash_reader_with_progress(algos, iso, |n| {
+            bytes_read = n;
+            progress(n);
+        })?;
+        Ok(HashReport { digests, bytes_read, elapsed: start.elapsed() })
+    }
+
+    // Hashes the system files and every file in the FST straight from the
+    // ROM. The paths are the ones they'd have in an extracted ROM, like
+    // `&&systemdata/Start.dol` and `audio/title.dsp`.
+    pub fn hash_files(&self, mut iso: impl BufRead + Seek, algo: HashAlgo) -> io::Result<Vec<ManifestEntry>> {
+        self.require_image("Hashing the files")?;
+        let mut hashes = Vec::with_capacity(self.fst.file_count + 4);
+        for file in self.extracted_files() {
+            let digest = self.hash_named_section(file.section, algo, &mut iso)?;
+            hashes.push(ManifestEntry { digest, path: file.path });
+        }
+        Ok(hashes)
+    }
+
+    // Checks the files on the ROM against a manifest. Each file's hashed with
+    // whichever algorithm its entry used, so a manifest with a mix of them
+    // works.
+    pub fn verify_manifest(&self, mut iso: impl BufRead + Seek, manifest: &[ManifestEntry]) -> io::Result<VerifyReport> {
+        self.require_image("Verifying the files")?;
+        let mut report = VerifyReport::default();
+        let files = self.extracted_files()
+            .map(|f| (f.path, f.section))
+            .collect::<HashMap<_, _>>();
+        let mut listed = BTreeSet::new();
+
+        for entry in manifest {
+            let section = match files.get(&entry.path) {
+                Some(&s) => s,
+                None => {
+                    report.extra.push(entry.path.clone());
+                    continue;
+                },
+            };
+            if self.hash_named_section(section, entry.digest.algo, &mut iso)? == entry.digest {
+                report.matching.push(entry.path.clone());
+            } else {
+                report.modified.push(entry.path.clone());
+            }
+            listed.insert(entry.path.as_path());
+        }
+
+        report.missing = self.extracted_files()
+            .map(|f| f.path)
+            .filter(|p| !listed.contains(p.as_path()))
+            .collect();
+        Ok(report)
+    }
+
+    fn hash_named_section(&self, section: NamedSection, algo: HashAlgo, iso: impl BufRead + Seek) -> io::Result<Digest> {
+        let mut hasher = HashWriter::new(algo)?;
+        self.extract_named_section(section, &mut hasher, iso)?;
+        Ok(Digest { algo, hex: hasher.finish() })
+    }
+
+    // Compares a directory made by `extract` against the ROM, to find out
+    // which files were changed. Sizes are checked first, so only files with
+    // the same size have their contents read.
+    pub fn verify_directory(&self, mut iso: impl BufRead + Seek, root: &Path) -> io::Result<VerifyReport> {
+        self.require_image("Verifying the files")?;
+        let mut report = VerifyReport::default();
+        let mut expected = BTreeSet::new();
+
+        for ExtractedFile { path, section, size, .. } in self.extracted_files() {
+            let file = match File::open(root.join(&path)) {
+                Ok(f) => f,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                    report.missing.push(path.clone());
+                    expected.insert(path);
+                    continue;
+                },
+                Err(e) => return Err(e),
+            };
+            let same = file.metadata()?.len() == size as u64 &&
+                self.section_matches_file(section, file, &mut iso)?;
+            if same {
+                report.matching.push(path.clone());
+            } else {
+                report.modified.push(path.clone());
+            }
+            expected.insert(path);
+        }
+
+        let mut on_disk = Vec::new();
+        list_files(root, Path::new(""), &mut on_disk)?;
+        report.extra = on_disk.into_iter().filter(|p| !expected.contains(p)).collect();
+        Ok(report)
+    }
+
+    fn section_matches_file(
+        &self,
+        section: NamedSection,
+        file: File,
+        iso: impl BufRead + Seek,
+    ) -> io::Result<bool> {
+        let mut comparer = Comparer { file: io::BufReader::new(file), differs: false };
+        match self.extract_named_section(section, &mut comparer, iso) {
+            // Returning an error is how `Comparer` stops the extraction early
+            Err(_) if comparer.differs => Ok(false),
+            Err(e) => Err(e),
+            // Anything left in the file means it's longer than the section
+            Ok(()) => Ok(comparer.file.fill_buf()?.is_empty()),
+        }
+    }
+
+    // The system files and every file in the FST, with the path each one gets
+    // when the ROM's extracted
+    fn extracted_files(&self) -> impl Iterator<Item = ExtractedFile<'_>> {
+        let system_files = vec![
+            (HEADER_PATH, NamedSection::Header, 0, GAME_HEADER_SIZE),
+            (APPLOADER_PATH, NamedSection::Apploader, APPLOADER_OFFSET, self.apploader.total_size()),
+            (DOL_PATH, NamedSection::Dol, self.dol.offset, self.dol.dol_size),
+            (FST_PATH, NamedSection::Fst, self.fst.offset, self.fst.size),
+        ];
+        let files = self.fst.entries.iter().filter_map(|e| e.as_file()).map(move |f| {
+            ExtractedFile {
+                path: self.fst.disk_path(&f.info),
+                section: NamedSection::File(f),
+                offset: f.file_offset,
+                size: f.size,
+            }
+        });
+        system_files.into_iter()
+            .map(|(path, section, offset, size)|
+                ExtractedFile { path: PathBuf::from(path), section, offset, size }
+            )
+            .chain(files)
+    }
+
+    // Compares two ROMs file by file, including the system files, to see
+    // what a mod changed. Every changed file is read in full to count how
+    // many bytes are different.
+    pub fn diff(iso_a: impl BufRead + Seek, iso_b: impl BufRead + Seek) -> io::Result<DiffReport> {
+        Game::diff_images(iso_a, iso_b, true)
+    }
+
+    // Like `diff`, but stops reading a file at the first difference, so
+    // `FileDiff::differing_bytes` is never filled in
+    pub fn diff_quick(iso_a: impl BufRead + Seek, iso_b: impl BufRead + Seek) -> io::Result<DiffReport> {
+        Game::diff_images(iso_a, iso_b, false)
+    }
+
+    fn diff_images(
+        mut iso_a: impl BufRead + Seek,
+        mut iso_b: impl BufRead + Seek,
+        count_bytes: bool,
+    ) -> io::Result<DiffReport> {
+        let a = Game::open(&mut iso_a, 0)?;
+        let b = Game::open(&mut iso_b, 0)?;
+        let mut report = DiffReport {
+            header: a.header_changes(&b),
+            ..DiffReport::default()
+        };
+
+        let mut files_b = b.extracted_files()
+            .map(|f| (f.path.clone(), f))
+            .collect::<BTreeMap<_, _>>();
+        for file_a in a.extracted_files() {
+            let file_b = match files_b.remove(&file_a.path) {
+                Some(f) => f,
+                None => {
+                    report.only_in_a.push(file_a.path);
+                    continue;
+                },
+            };
+
+            let differing_bytes = if !count_bytes && file_a.size != file_b.size {
+                1
+            } else {
+                iso_a.seek(SeekFrom::Start(file_a.offset))?;
+                iso_b.seek(SeekFrom::Start(file_b.offset))?;
+                count_differences(
+                    (&mut iso_a).take(file_a.size as u64),
+                    (&mut iso_b).take(file_b.size as u64),
+                    !count_bytes,
+                )?
+            };
+            if differing_bytes == 0 {
+                report.unchanged += 1;
+            } else {
+                report.changed.push(FileDiff {
+                    path: file_a.path,
+                    size_a: file_a.size,
+                    size_b: file_b.size,
+                    differing_bytes: if count_bytes { Some(differing_bytes) } else { None },
+                });
+            }
+        }
+        report.only_in_b = files_b.into_keys().collect();
+        Ok(report)
+    }
+
+    fn header_changes(&self, other: &Game) -> Vec<HeaderChange> {
+        let fields = [
+            ("title", self.header.title.clone(), other.header.title.clone()),
+            ("game code", self.header.game_code.clone(), other.header.game_code.clone()),
+            ("maker code", self.header.maker_code.clone(), other.header.maker_code.clone()),
+            ("disk ID", self.header.disk_id.to_string(), other.header.disk_id.to_string()),
+            ("version", self.header.version.to_string(), other.header.version.to_string()),
+            ("DOL offset", format!("{:#x}", self.dol.offset), format!("{:#x}", other.dol.offset)),
+            ("entry point", format!("{:#x}", self.dol.entry_point), format!("{:#x}", other.dol.entry_point)),
+            ("FST offset", format!("{:#x}", self.fst.offset), format!("{:#x}", other.fst.offset)),
+            ("FST size", format!("{:#x}", self.fst.size), format!("{:#x}", other.fst.size)),
+        ];
+        fields.iter()
+            .filter(|&(_, a, b)| a != b)
+            .map(|(field, a, b)| HeaderChange { field, a: a.clone(), b: b.clone() })
+            .collect()
+    }
+
     pub fn rom_layout(&self) -> ROMLayout {
         let size = 5
             + self.dol.iter_segments().count()
@@ -65,40 +666,86 @@ impl Game {
             layout.push(f);
         }
 
-        layout.sort_unstable_by_key(|info| info.start());
+        // Empty files go before whatever starts at the same place, so they
+        // don't get in the way of `find_offset`'s binary search
+        layout.sort_unstable_by_key(|info| (info.start(), info.size()));
 
         ROMLayout(layout)
     }
 
-    pub fn extract<R, P>(&mut self, mut iso: R, path: P) -> io::Result<()>
+    // Returns how many files were written, and how many were skipped when
+    // resuming, including the system files. See `extract_with` for more
+    // options.
+    pub fn extract<R, P>(&mut self, iso: R, path: P, mode: ExtractMode) -> io::Result<ExtractCount>
     where
         R: BufRead + Seek,
         P: AsRef<Path>,
     {
-        // Not using `create_dir_all` here so it fails if `path` already exists.
-        create_dir(path.as_ref())?;
-        let sys_data_path = path.as_ref().join("&&systemdata");
-        let sys_data_path: &Path = sys_data_path.as_ref();
-        create_dir(sys_data_path)?;
-
-        println!("Extracting system data...");
+        let options = ExtractOptions::new()
+            .overwrite(mode == ExtractMode::Overwrite)
+            .merge(mode == ExtractMode::Merge)
+            .resume(mode.existing_check());
+        self.extract_with(iso, path, &options).map(|report| report.count)
+    }
 
-        let header_file = File::create(sys_data_path.join("ISO.hdr"))?;
-        Header::extract(&mut iso, header_file)?;
+    // Writes ISO.hdr, Game.toc, Apploader.ldr, and Start.dol to
+    // `path/&&systemdata`, without touching the rest of the file system.
+    // When resuming, the ones that are already there are left alone.
+    pub fn extract_system_data(
+        &mut self,
+        mut iso: impl BufRead + Seek,
+        path: impl AsRef<Path>,
+        mode: ExtractMode,
+    ) -> io::Result<ExtractCount> {
+        self.require_image("Extracting the system data")?;
+        let path = path.as_ref();
+        create_dir_all(path.join("&&systemdata"))?;
 
-        let fst_file = File::create(sys_data_path.join("Game.toc"))?;
-        FST::extract(&mut iso, fst_file, self.fst.offset)?;
+        info!("Extracting system data...");
 
-        let apploader_file = File::create(sys_data_path.join("Apploader.ldr"))?;
-        Apploader::extract(&mut iso, apploader_file)?;
+        // The apploader's size is from `self.apploader`, like in
+        // `extract_apploader`
+        let system_files = [
+            (HEADER_PATH, 0, GAME_HEADER_SIZE),
+            (FST_PATH, self.fst.offset, self.header.fst_size),
+            (APPLOADER_PATH, APPLOADER_OFFSET, self.apploader.total_size()),
+            (DOL_PATH, self.dol.offset, self.dol.dol_size),
+        ];
+        let mut count = ExtractCount::default();
+        for &(name, offset, size) in &system_files {
+            let output = path.join(name);
+            if let Some(check) = mode.existing_check() {
+                if check.is_extracted(&output, &mut iso, offset, size as u64)? {
+                    debug!("Skipping {}, it's already there", name);
+                    count.skipped += 1;
+                    continue;
+                }
+            }
+            iso.seek(SeekFrom::Start(offset))?;
+            extract_section(&mut iso, size, File::create(output)?)?;
+            count.written += 1;
+        }
 
-        let mut dol_file = File::create(sys_data_path.join("Start.dol"))?;
-        DOLHeader::extract(&mut iso, &mut dol_file, self.dol.offset)?;
+        if self.fst.has_renamed_entries() {
+            self.fst.write_name_map(File::create(path.join(NAME_MAP_PATH))?)?;
+        }
+        Ok(count)
+    }
 
-        println!("Extracting file system...");
+    // This goes by `self.apploader` instead of the sizes on the ROM, which
+    // aren't there on discs without a GameCube apploader
+    fn extract_apploader(&self, mut iso: impl Read + Seek, output: impl Write) -> io::Result<()> {
+        iso.seek(SeekFrom::Start(APPLOADER_OFFSET))?;
+        extract_section(iso, self.apploader.total_size(), output)
+    }
 
-        self.extract_file_system(&mut iso, path.as_ref(), 4)?;
-        Ok(())
+    // The apploader, if the disc has a GameCube one
+    pub fn gamecube_apploader(&self) -> io::Result<&Apploader> {
+        if self.variant.has_apploader() {
+            Ok(&self.apploader)
+        } else {
+            Err(UnsupportedVariant::error(self.variant, "Reading the apploader"))
+        }
     }
 
     pub fn extract_file_system(
@@ -106,112 +753,1021 @@ impl Game {
         iso: impl BufRead + Seek,
         path: impl AsRef<Path>,
         existing_files: usize,
-    ) -> io::Result<usize> {
-        let total = self.fst.file_count + existing_files;
-        let mut count = existing_files;
-        let res = self.fst.extract_file_system(path, iso, |_| {
+        mode: ExtractMode,
+        filter: Option<FileFilter>,
+    ) -> io::Result<ExtractCount> {
+        let mut progress = Progress::default();
+        let verb = if mode.existing_check().is_some() { "done" } else { "written" };
+        self.extract_files_reporting(iso, path, mode, ExtractOrder::default(), filter, |count, total| {
+            progress.files(count + existing_files, total + existing_files, verb);
+        })
+    }
+
+    // Like `extract_file_system`, but `report` is called with how many files
+    // have been done and how many there are after each one, instead of
+    // printing the progress
+    pub(crate) fn extract_files_reporting(
+        &mut self,
+        iso: impl BufRead + Seek,
+        path: impl AsRef<Path>,
+        mode: ExtractMode,
+        order: ExtractOrder,
+        filter: Option<FileFilter>,
+        mut report: impl FnMut(usize, usize),
+    ) -> io::Result<ExtractCount> {
+        self.require_image("Extracting files")?;
+        let files = match filter {
+            Some(filter) => self.fst.entries.iter()
+                .filter_map(|e| e.as_file())
+                .filter(|f| filter(&f.info.full_path, f))
+                .count(),
+            None => self.fst.file_count,
+        };
+        let mut count = 0;
+        self.fst.extract_file_system(path, iso, mode, order, filter, |_| {
             count += 1;
-            print!("\r{}/{} files written.", count, total)
+            report(count, files);
+        })
+    }
+
+    // Extracts every file in the FST to `path`, like `extract_file_system`,
+    // but with `threads` threads at once, or one per CPU if it's 0. A reader
+    // can't be shared between threads, so each one opens the image at
+    // `iso_path` itself. Every open file is reserved from `handles` first, and
+    // when there aren't enough free, a thread closes its reader and waits for
+    // room to open it again. `callback` is called with the number of files
+    // written so far after each one, from whichever thread wrote it, but
+    // never by two at once, so the counts always go up. Returns how many
+    // files were written.
+    pub fn extract_files_parallel(
+        &self,
+        iso_path: &Path,
+        path: &Path,
+        threads: usize,
+        handles: &Arc<HandleBudget>,
+        callback: impl Fn(usize) + Sync,
+    ) -> io::Result<usize> {
+        self.require_image("Extracting files")?;
+        // Reading the files in the order they're on the ROM keeps the reads
+        // close together
+        let files = self.fst.files_by_offset(path, None)?;
+
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }.min(files.len());
+        // The image, and the file being written
+        let per_thread = source::handles_needed(iso_path) + 1;
+        handles.warn_if_short(threads * per_thread, &format!("Extracting with {} threads", threads));
+        let next = AtomicUsize::new(0);
+        let written = Mutex::new(0);
+        let error = Mutex::new(None);
+
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    let mut iso = None;
+                    let mut held = handles.reserve(0);
+                    let result = (|| loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let (file, output) = match files.get(i) {
+                            Some(f) => f,
+                            None => return Ok(()),
+                        };
+                        // A thread only waits while it has nothing open, so
+                        // it can't hold up the ones it's waiting on
+                        if iso.is_some() && !held.try_grow(1) {
+                            iso = None;
+                            held.release(per_thread - 1);
+                            handles.note_reopened();
+                        }
+                        let iso = match iso {
+                            Some(ref mut iso) => iso,
+                            None => {
+                                held.grow(per_thread);
+                                iso.insert(source::open(iso_path)?)
+                            },
+                        };
+                        file.extract(iso, File::create(output)?)?;
+                        held.release(1);
+                        let mut written = written.lock().unwrap();
+                        *written += 1;
+                        callback(*written);
+                    })();
+                    if let Err(e) = result {
+                        // Stop the other threads from starting anything new
+                        next.store(files.len(), Ordering::Relaxed);
+                        error.lock().unwrap().get_or_insert(e);
+                    }
+                });
+            }
         });
-        println!();
-        res
+        handles.warn_if_reopened();
+
+        match error.into_inner().unwrap() {
+            Some(e) => Err(e),
+            None => Ok(written.into_inner().unwrap()),
+        }
+    }
+
+    // Writes everything `extract` would to an archive instead of a directory,
+    // with the same layout
+    #[cfg(feature = "archive")]
+    pub fn extract_to_archive(
+        &mut self,
+        iso: impl BufRead + Seek,
+        output: impl Write + Seek,
+        format: ArchiveFormat,
+    ) -> io::Result<()> {
+        self.require_image("Writing an archive")?;
+        match format {
+            ArchiveFormat::Tar => self.write_archive(iso, TarWriter::new(output)),
+            ArchiveFormat::Zip => self.write_archive(iso, ZipWriter::new(output)),
+        }
+    }
+
+    #[cfg(feature = "archive")]
+    fn write_archive(&self, mut iso: impl BufRead + Seek, mut archive: impl ArchiveWriter) -> io::Result<()> {
+        info!("Extracting system data...");
+
+        archive.add_directory("&&systemdata")?;
+        for &path in &[HEADER_PATH, FST_PATH, APPLOADER_PATH, DOL_PATH] {
+            let file = self.open_file(&mut iso, path)?;
+            let size = file.len();
+            archive.add_file(path, size, file)?;
+        }
+        if self.fst.has_renamed_entries() {
+            let mut map = Vec::new();
+            self.fst.write_name_map(&mut map)?;
+            archive.add_file(NAME_MAP_PATH, map.len() as u64, &map[..])?;
+        }
+
+        info!("Extracting file system...");
+
+        let total = self.fst.file_count + 4;
+        let mut count = 4;
+        let mut progress = Progress::default();
+        for e in &self.fst.entries[1..] {
+            let path = self.fst.disk_path(e.info()).iter()
+                .map(|c| c.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            match e {
+                Entry::Directory(_) => archive.add_directory(&path)?,
+                Entry::File(f) => {
+                    // Archives need to know how big each file is up front, so
+                    // anything past the end of the image is always zeros
+                    let file = FstFileReader::new(&mut iso, f.file_offset, f.size as u64)?
+                        .chain(io::repeat(0))
+                        .take(f.size as u64);
+                    archive.add_file(&path, f.size as u64, file)?;
+                    count += 1;
+                    progress.files(count, total, "written");
+                },
+            }
+        }
+        archive.finish()
+    }
+
+    // Like `extract`, but only reads `iso` once, from start to end, so it
+    // can be stdin or a decompressor. The system data is kept in memory
+    // until all of it has been read, then the files are extracted in the
+    // order they're in on the ROM. Files that overlap the ones before them
+    // can't be extracted this way, since that would mean going back.
+    pub fn extract_streaming(
+        iso: impl BufRead,
+        path: impl AsRef<Path>,
+        mode: ExtractMode,
+    ) -> io::Result<Game> {
+        let path = path.as_ref();
+        let mut start = StreamStart { iso, data: Vec::new() };
+
+        let header = Header::new(start.read_to(GAME_HEADER_SIZE as u64)?, 0)?;
+        let dol = DOLHeader::new(start.read_to(header.dol_offset + DOL_HEADER_LEN as u64)?, header.dol_offset)?;
+        start.read_to(dol.offset + dol.dol_size as u64)?;
+        let fst = FST::with_size(start.read_to(header.fst_offset + header.fst_size as u64)?, header.fst_offset, header.fst_size)?;
+        let variant = detect_variant(&header, &fst);
+        let apploader = read_apploader(start.read_to(APPLOADER_OFFSET + 0x20)?, 0, &header, &fst, variant)?;
+        start.read_to(APPLOADER_OFFSET + apploader.total_size() as u64)?;
+        // The rest of the stream isn't read, so this is only as much as there
+        // has to be
+        let image_size = fst.entries.iter()
+            .filter_map(|e| e.as_file())
+            .map(|f| f.file_offset + f.size as u64)
+            .fold(start.data.len() as u64, cmp::max);
+        let mut game = Game { header, apploader, fst, dol, image_size, variant, extracted_root: None };
+
+        mode.prepare_dir(path)?;
+        game.extract_system_data(Cursor::new(&start.data), path, mode)?;
+
+        info!("Extracting file system...");
+
+        let files = game.fst.files_by_offset(path, None)?;
+
+        let StreamStart { mut iso, data } = start;
+        let mut position = data.len() as u64;
+        // Whether the stream ended before the files did. The files after that
+        // are left empty, and `image_size` is set to where it ended so they
+        // show up in `truncated_files`.
+        let mut ended = false;
+        let total = files.len() + 4;
+        let mut progress = Progress::default();
+        for (i, (f, output)) in files.iter().enumerate() {
+            let mut output = File::create(output)?;
+            let end = f.file_offset + f.size as u64;
+            if f.size == 0 || ended {
+                // Nothing to read
+            } else if end <= data.len() as u64 {
+                output.write_all(&data[f.file_offset as usize..end as usize])?;
+            } else if f.file_offset < position {
+                return Err(io::Error::other(format!(
+                    "{} starts at {:#x}, but everything up to {:#x} has already been read. \
+                    It probably overlaps another file, so the ROM has to be a file to extract it.",
+                    f.info.full_path.display(), f.file_offset, position,
+                )));
+            } else {
+                let gap = f.file_offset - position;
+                position += io::copy(&mut (&mut iso).take(gap), &mut io::sink())?;
+                if position == f.file_offset {
+                    position += io::copy(&mut (&mut iso).take(f.size as u64), &mut output)?;
+                }
+                ended = position < end;
+            }
+            progress.files(i + 5, total, "written");
+        }
+        drop(progress);
+        if ended {
+            game.image_size = position;
+        }
+        Ok(game)
+    }
+
+    // Extracts every file whose path on the ROM matches the glob `pattern`,
+    // like `**/*.thp` or `stages/*/data`, to the same path under
+    // `output_dir`. Returns the paths written, or a `NotFound` error if
+    // nothing matched.
+    pub fn extract_matching(
+        &mut self,
+        mut iso: impl BufRead + Seek,
+        pattern: &str,
+        output_dir: &Path,
+    ) -> io::Result<Vec<PathBuf>> {
+        self.require_image("Extracting files")?;
+        let glob = Pattern::new(pattern).map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid pattern {}: {}", pattern, e),
+        ))?;
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+
+        let mut written = Vec::new();
+        for f in self.fst.entries.iter().filter_map(|e| e.as_file()) {
+            let rom_path = f.info.full_path.strip_prefix("/").unwrap_or(&f.info.full_path);
+            if !glob.matches_path_with(rom_path, options) { continue }
+
+            let output = output_dir.join(self.fst.disk_path(&f.info));
+            if let Some(parent) = output.parent() {
+                create_dir_all(parent)?;
+            }
+            f.extract(&mut iso, File::create(&output)?)?;
+            written.push(output);
+        }
+
+        if written.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no files on the ROM match {}", pattern),
+            ));
+        }
+        Ok(written)
     }
 
+    // Extracts whatever `filename` names to `output`, which is a directory if
+    // it's a directory in the FST. See `named_section` for what names work.
     pub fn extract_section_with_name(
         &self,
         filename: impl AsRef<Path>,
         output: impl AsRef<Path>,
         iso: impl BufRead + Seek,
-    ) -> io::Result<bool> {
+    ) -> io::Result<ExtractOutcome> {
         let output = output.as_ref();
-        let filename = &*filename.as_ref().to_string_lossy();
-        match filename {
-            HEADER_PATH =>
-                Header::extract(iso, &mut File::create(output)?).map(|_| true),
-            APPLOADER_PATH =>
-                Apploader::extract(iso, &mut File::create(output)?)
-                    .map(|_| true),
-            DOL_PATH =>
-                DOLHeader::extract(
-                    iso,
-                    &mut File::create(output)?,
-                    self.dol.offset,
-                ).map(|_| true),
-            FST_PATH =>
-                FST::extract(iso, &mut File::create(output)?, self.fst.offset)
-                    .map(|_| true),
+        let section = match self.named_section(filename.as_ref()) {
+            Some(s) => s,
+            None => return Ok(ExtractOutcome::NotFound),
+        };
+        let section_type = match section {
+            NamedSection::Directory(d) => SectionType::Directory {
+                path: d.info.full_path.clone(),
+                files: self.fst.extract_directory(&d.info.full_path.to_string_lossy(), output, iso, |_| {})?,
+            },
             _ => {
-                if let Some(e) = self.fst.entry_for_path(filename) {
-                    e.extract_with_name(
-                        output, &self.fst.entries,
-                        iso,
-                        &|_| {},
-                    ).map(|_| true)
-                } else if let Some((t, n)) =
-                    Segment::parse_segment_name(filename)
-                {
-                    if let Some(s) = self.dol.find_segment(t, n) {
-                        s.extract(iso, &mut File::create(output)?).map(|_| true)
-                    } else {
-                        Ok(false)
-                    }
-                } else {
-                    Ok(false)
-                }
+                let section_type = section.section_type();
+                self.extract_named_section(section, File::create(output)?, iso)?;
+                section_type
             },
+        };
+        Ok(ExtractOutcome::Extracted(section_type))
+    }
+
+    // Like `extract_section_with_name`, but writes to `output` instead of a
+    // file, so directories can't be extracted this way
+    pub fn extract_section_to(
+        &self,
+        filename: impl AsRef<Path>,
+        output: impl Write,
+        iso: impl BufRead + Seek,
+    ) -> io::Result<ExtractOutcome> {
+        self.require_image("Extracting a section")?;
+        let filename = filename.as_ref();
+        match self.named_section(filename) {
+            Some(NamedSection::Directory(_)) => Err(io::Error::other(
+                format!("{} is a directory", filename.display()),
+            )),
+            Some(section) => {
+                let section_type = section.section_type();
+                self.extract_named_section(section, output, iso)?;
+                Ok(ExtractOutcome::Extracted(section_type))
+            },
+            None => Ok(ExtractOutcome::NotFound),
+        }
+    }
+
+    // A reader for just the file called `filename`, which can also be one of
+    // the system files or a DOL segment, like `extract_section_to`
+    pub fn open_file<'a, R: BufRead + Seek>(
+        &self,
+        iso: &'a mut R,
+        filename: impl AsRef<Path>,
+    ) -> io::Result<FstFileReader<'a, R>> {
+        self.require_image("Reading a file")?;
+        let filename = filename.as_ref();
+        let section: &dyn Section = match self.named_section(filename) {
+            Some(NamedSection::Header) => &self.header,
+            Some(NamedSection::Apploader) => &self.apploader,
+            // The DOL's section is just its header, since the segments are
+            // listed separately
+            Some(NamedSection::Dol) =>
+                return FstFileReader::new(iso, self.dol.offset, self.dol.dol_size as u64),
+            // Extracting Game.toc uses the size from the header, which can
+            // include padding after the names
+            Some(NamedSection::Fst) =>
+                return FstFileReader::new(iso, self.fst.offset, self.header.fst_size as u64),
+            Some(NamedSection::File(f)) => f,
+            Some(NamedSection::Segment(s)) => s,
+            Some(NamedSection::RelSection(f, index)) => {
+                let (start, size) = self.rel_section_range(&mut *iso, f, index)?;
+                return FstFileReader::new(iso, start, size);
+            },
+            Some(NamedSection::Directory(_)) => return Err(io::Error::other(
+                format!("{} is a directory", filename.display()),
+            )),
+            None => return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("There isn't anything called {} in the ROM", filename.display()),
+            )),
+        };
+        FstFileReader::new(iso, section.start(), section.size() as u64)
+    }
+
+    // The section called `filename`, like in `open_file`, but Start.dol is
+    // just the DOL's header here, like it is in `rom_layout`
+    pub fn section_with_name(&self, filename: impl AsRef<Path>) -> io::Result<&dyn Section> {
+        let filename = filename.as_ref();
+        Ok(match self.named_section(filename) {
+            Some(NamedSection::Header) => &self.header,
+            Some(NamedSection::Apploader) => &self.apploader,
+            Some(NamedSection::Dol) => &self.dol,
+            Some(NamedSection::Fst) => &self.fst,
+            Some(NamedSection::File(f)) => f,
+            Some(NamedSection::Segment(s)) => s,
+            // Where these are depends on the REL's header, which has to be
+            // read from the ROM
+            Some(NamedSection::RelSection(..)) => return Err(io::Error::other(
+                format!("{} is a section of a REL, which only works when extracting", filename.display()),
+            )),
+            Some(NamedSection::Directory(_)) => return Err(io::Error::other(
+                format!("{} is a directory", filename.display()),
+            )),
+            None => return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("There isn't anything called {} in the ROM", filename.display()),
+            )),
+        })
+    }
+
+    // The header of the REL called `filename`
+    pub fn rel_header<R: BufRead + Seek>(&self, iso: &mut R, filename: impl AsRef<Path>) -> io::Result<RelHeader> {
+        RelHeader::new(self.open_file(iso, filename)?)
+    }
+
+    // Where section `index` of the REL `f` is on the ROM, and how big it is
+    fn rel_section_range(&self, mut iso: impl Read + Seek, f: &FileEntry, index: usize) -> io::Result<(u64, u64)> {
+        let rel = RelHeader::new(FstFileReader::new(&mut iso, f.file_offset, f.size as u64)?)?;
+        match rel.section(index) {
+            Some(s) if s.is_bss() => Err(io::Error::other(format!(
+                "Section {} of {} is the BSS, which isn't in the file",
+                index,
+                f.info.full_path.display(),
+            ))),
+            Some(s) => Ok((f.file_offset + s.offset, s.size as u64)),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!(
+                "{} only has {} sections",
+                f.info.full_path.display(),
+                rel.sections.len(),
+            ))),
+        }
+    }
+
+    // Names are tried as one of the system files' names first, like
+    // `Start.dol` or `sys/main.dol`, then as a path in the FST, then as a
+    // section of a REL in the FST, like `rels/stage.rel:1`, then as a DOL
+    // segment, like `.text0`. A path that starts with `/` is always in the
+    // FST, for files with the same name as a system file.
+    fn named_section(&self, filename: &Path) -> Option<NamedSection<'_>> {
+        let filename = &*filename.to_string_lossy();
+        if let Ok(t) = filename.parse::<UniqueSectionType>() {
+            return Some(match t {
+                UniqueSectionType::Header => NamedSection::Header,
+                UniqueSectionType::Apploader => NamedSection::Apploader,
+                UniqueSectionType::Dol => NamedSection::Dol,
+                UniqueSectionType::Fst => NamedSection::Fst,
+            });
+        }
+        Some(match self.fst.entry_for_path(filename) {
+            Some(Entry::File(f)) => NamedSection::File(f),
+            Some(Entry::Directory(d)) => NamedSection::Directory(d),
+            None => match self.rel_section(filename) {
+                Some(s) => s,
+                None => {
+                    let (t, n) = Segment::parse_segment_name(filename)?;
+                    NamedSection::Segment(self.dol.find_segment(t, n)?)
+                },
+            },
+        })
+    }
+
+    // `path.rel:N`, for section N of a REL. Whether it has that section
+    // isn't known until its header's read.
+    fn rel_section(&self, filename: &str) -> Option<NamedSection<'_>> {
+        let (path, index) = filename.rsplit_once(':')?;
+        let is_rel = Path::new(path).extension().is_some_and(|e| e.eq_ignore_ascii_case("rel"));
+        if !is_rel { return None }
+        let index = index.parse().ok()?;
+        match self.fst.entry_for_path(path)? {
+            Entry::File(f) => Some(NamedSection::RelSection(f, index)),
+            Entry::Directory(_) => None,
+        }
+    }
+
+    fn extract_named_section(
+        &self,
+        section: NamedSection,
+        mut output: impl Write,
+        mut iso: impl BufRead + Seek,
+    ) -> io::Result<()> {
+        match section {
+            NamedSection::Header => Header::extract(iso, &mut output),
+            NamedSection::Apploader => self.extract_apploader(iso, &mut output),
+            NamedSection::Dol => DOLHeader::extract(iso, &mut output, self.dol.offset),
+            NamedSection::Fst => FST::extract(iso, &mut output, self.fst.offset),
+            NamedSection::File(f) => f.extract(iso, &mut output),
+            NamedSection::Segment(s) => iso.seek(SeekFrom::Start(s.offset))
+                .and_then(|_| extract_section(iso, s.size, &mut output)),
+            NamedSection::RelSection(f, index) => self.rel_section_range(&mut iso, f, index)
+                .and_then(|(start, size)| iso.seek(SeekFrom::Start(start)).map(|_| size))
+                .and_then(|size| extract_section(iso, size as usize, &mut output)),
+            NamedSection::Directory(d) => Err(io::Error::other(
+                format!("{} is a directory", d.info.full_path.display()),
+            )),
+        }?;
+        output.flush()
+    }
+
+    // Replaces a segment in the DOL with `len` bytes from `source`. If the DOL
+    // gets bigger, it has to fit in the space before whatever comes after it
+    // on the ROM.
+    pub fn replace_dol_segment(
+        &mut self,
+        iso: impl Read + Write + Seek,
+        seg_type: SegmentType,
+        seg_num: u64,
+        source: impl Read,
+        len: u64,
+    ) -> io::Result<()> {
+        self.require_image("Replacing a DOL segment")?;
+        let new_size = self.dol.size_with_segment(seg_type, seg_num, len)
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("the DOL doesn't have a {} segment", seg_type.to_string(seg_num)),
+            ))?;
+        let dol_end = self.dol.offset + self.dol.dol_size as u64;
+        let available = self.rom_layout().0.iter()
+            .filter(|s| s.size() > 0)
+            .map(|s| s.start())
+            .filter(|&s| s >= dol_end)
+            .min()
+            .unwrap_or(self.image_size) - self.dol.offset;
+        if new_size as u64 > available {
+            return Err(io::Error::other(format!(
+                "the new segment makes the DOL {} bytes too large to fit before the next section ({} bytes are available)",
+                new_size as u64 - available,
+                available,
+            )));
+        }
+        self.dol.replace_segment(iso, seg_type, seg_num, source, len)
+    }
+
+    // Overwrites a file on the ROM with `len` bytes from `source`, without
+    // moving anything. The new file has to fit in the space between the old
+    // one and whatever comes after it. The Game.toc on the ROM is updated with
+    // the new size.
+    pub fn replace_file(
+        &mut self,
+        mut iso: impl Read + Write + Seek,
+        fst_path: impl AsRef<Path>,
+        source: impl Read,
+        len: u64,
+    ) -> io::Result<()> {
+        self.require_image("Replacing a file")?;
+        let fst_path = fst_path.as_ref();
+        let (index, offset, old_size) = match self.fst.entry_for_path(fst_path) {
+            Some(e) => match e.as_file() {
+                Some(f) => (f.info.index, f.file_offset, f.size as u64),
+                None => return Err(io::Error::other(
+                    format!("{} is a directory", fst_path.display()),
+                )),
+            },
+            None => return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} doesn't exist on the ROM", fst_path.display()),
+            )),
+        };
+
+        // Empty files don't have any space of their own
+        let available = if old_size == 0 {
+            0
+        } else {
+            self.rom_layout().0.iter()
+                .filter(|s| s.size() > 0)
+                .map(|s| s.start())
+                .find(|&s| s > offset)
+                .unwrap_or(self.image_size) - offset
+        };
+        if len > available {
+            return Err(io::Error::other(format!(
+                "the new file is {} bytes too large to fit in the space for {} ({} bytes are available)",
+                len - available,
+                fst_path.display(),
+                available,
+            )));
+        }
+
+        iso.seek(SeekFrom::Start(offset))?;
+        let written = io::copy(&mut source.take(len), &mut iso)?;
+        if written != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("expected {} bytes for the new file, but only got {}", len, written),
+            ));
+        }
+        if old_size > len {
+            io::copy(&mut io::repeat(0).take(old_size - len), &mut iso)?;
+        }
+
+        {
+            let f = self.fst.entries[index].as_file_mut().unwrap();
+            f.size = len as usize;
+        }
+        self.fst.total_file_system_size =
+            self.fst.total_file_system_size - old_size as usize + len as usize;
+
+        iso.seek(SeekFrom::Start(self.fst.offset + (index * ENTRY_SIZE) as u64))?;
+        self.fst.entries[index].write(&mut iso)?;
+        iso.flush()
+    }
+
+    pub fn metadata(&self) -> GameMetadata<'_> {
+        GameMetadata {
+            variant: self.variant,
+            image_size: self.image_size,
+            header: &self.header,
+            apploader: &self.apploader,
+            dol: &self.dol,
+            fst: &self.fst,
+        }
+    }
+
+    pub fn info(&self) -> GameInfo {
+        GameInfo {
+            title: self.header.title.clone(),
+            game_code: self.header.game_code.clone(),
+            maker_code: self.header.maker_code.clone(),
+            disk_id: self.header.disk_id,
+            version: self.header.version,
+            region: self.header.region().to_string(),
+            video_mode: self.header.video_mode().to_string(),
+            audio_streaming: self.header.streams_audio(),
+            stream_buffer_size: self.header.stream_buffer_size,
+            variant: self.variant,
+            apploader_offset: APPLOADER_OFFSET,
+            apploader_size: self.apploader.total_size(),
+            apploader_date: self.apploader.date.clone(),
+            apploader_entry_point: self.apploader.entry_point,
+            dol_offset: self.header.dol_offset,
+            dol_size: self.dol.dol_size,
+            entry_point: self.dol.entry_point,
+            fst_offset: self.header.fst_offset,
+            fst_size: self.fst.size,
+            segments: self.dol.iter_segments().map(|s| SegmentInfo {
+                name: s.to_string(),
+                offset: s.offset,
+                size: s.size,
+                load_address: s.loading_address,
+            }).collect(),
+            layout: self.rom_layout().0.iter().map(|s| LayoutEntry {
+                name: s.name(),
+                section_type: s.section_type(),
+                start: s.start(),
+                end: s.start() + s.size() as u64,
+                size: s.size(),
+            }).collect(),
+            hashes: Vec::new(),
         }
     }
 
-    pub fn print_info(&self, style: NumberStyle) {
-        println!("Title: {}", self.header.title);
-        println!("GameID: {}{}", self.header.game_code, self.header.maker_code);
-        println!("Version: {}", format_u64(self.header.version as u64, style));
+    // See `SectionHashes`. This needs the crc32 and sha1 features.
+    pub fn system_file_hashes(&self, mut iso: impl Read + Seek) -> io::Result<Vec<SectionHashes>> {
+        let sections = vec![
+            (self.dol.name(), self.dol.offset, self.dol.dol_size),
+            (self.apploader.name(), APPLOADER_OFFSET, self.apploader.total_size()),
+        ];
+        sections.into_iter().map(|(name, start, size)| {
+            let mut digests = hash_range(&[HashAlgo::Crc32, HashAlgo::Sha1], &mut iso, start, size as u64)?;
+            let sha1 = digests.pop().unwrap().hex;
+            let crc32 = digests.pop().unwrap().hex;
+            Ok(SectionHashes { name, crc32, sha1 })
+        }).collect()
+    }
+
+    // `hashes` are from `system_file_hashes`, if they should be printed
+    pub fn print_info(&self, style: NumberStyle, hashes: &[SectionHashes], layout: LayoutOptions) {
+        let info = self.info();
+        println!("Title: {}", info.title);
+        println!("GameID: {}{}", info.game_code, info.maker_code);
+        println!("Version: {}", format_u64(info.version as u64, style));
+        println!("Region: {}", info.region);
+        println!("Video mode: {}", info.video_mode);
+        if self.variant != DiscVariant::Retail {
+            println!("Disc variant: {}", self.variant);
+        }
+        if info.audio_streaming {
+            println!(
+                "Audio streaming: on (buffer size: {})",
+                format_u64(info.stream_buffer_size as u64, style),
+            );
+        } else {
+            println!("Audio streaming: off");
+        }
+        if !self.variant.has_apploader() {
+            println!("Apploader: none, this disc has its own loader");
+        } else {
+            match self.apploader.sdk_version_guess() {
+                Some(sdk) => println!("Apploader date: {} ({})", info.apploader_date, sdk),
+                None => println!("Apploader date: {}", info.apploader_date),
+            }
+            println!("Apploader entry point: {}", format_u64(info.apploader_entry_point, style));
+        }
+        if self.apploader.overlaps_dol(self.dol.offset) {
+            println!("Warning: the apploader runs into the DOL, so this ROM is probably corrupt.");
+        }
+        for h in hashes {
+            println!("{} CRC32: {}", h.name, h.crc32);
+            println!("{} SHA-1: {}", h.name, h.sha1);
+        }
 
         println!("\nROM Layout:");
-        self.print_layout();
+        self.print_layout(style, layout);
     }
 
-    pub fn print_layout(&self) {
-        let mut regions = BTreeMap::new();
+    // Finds sections that start before the ones before them end, like files
+    // on top of the DOL. Bad dumps and some obfuscated discs have these, and
+    // rebuilding them as-is would write one section over the other.
+    pub fn check_overlaps(&self) -> Vec<Overlap> {
+        let layout = self.rom_layout();
+        let mut overlaps = Vec::new();
+        // The section that reaches the furthest so far
+        let mut previous: Option<&dyn Section> = None;
+        for s in layout.iter().filter(|s| s.size() > 0) {
+            let end = s.start() + s.size() as u64;
+            if let Some(p) = previous {
+                let p_end = p.start() + p.size() as u64;
+                if s.start() < p_end {
+                    overlaps.push(Overlap {
+                        first: p.name(),
+                        second: s.name(),
+                        start: s.start(),
+                        end: cmp::min(p_end, end),
+                    });
+                }
+                if end <= p_end { continue }
+            }
+            previous = Some(s);
+        }
+        overlaps
+    }
 
-        // Format: regions.insert(start, (size, name));
-        regions.insert(0, (GAME_HEADER_SIZE, "ISO.hdr"));
-        regions.insert(
-            APPLOADER_OFFSET,
-            (self.apploader.total_size(), "Apploader.ldr")
-        );
-        regions.insert(
-            self.header.dol_offset,
-            (self.dol.dol_size, "Start.dol")
-        );
-        regions.insert(self.header.fst_offset, (self.fst.size, "Game.toc"));
+    // Every file that doesn't fit on the image, in the order they're on it.
+    // Lots of trimmed images are just cut off after the last file, which is
+    // fine, but bad copies can stop partway through one.
+    pub fn truncated_files(&self) -> Vec<TruncatedFile> {
+        let mut truncated = self.fst.entries.iter()
+            .filter_map(|e| e.as_file())
+            .filter(|f| f.size > 0 && f.file_offset + f.size as u64 > self.image_size)
+            .map(|f| TruncatedFile {
+                path: self.fst.disk_path(&f.info),
+                offset: f.file_offset,
+                size: f.size as u64,
+                missing: f.file_offset + f.size as u64 - cmp::max(f.file_offset, self.image_size),
+            })
+            .collect::<Vec<_>>();
+        truncated.sort_by_key(|t| t.offset);
+        truncated
+    }
+
+    // The file that ends furthest into the image without going past the end
+    // of it
+    pub fn last_complete_file(&self) -> Option<&FileEntry> {
+        self.fst.entries.iter()
+            .filter_map(|e| e.as_file())
+            .filter(|f| f.size > 0 && f.file_offset + f.size as u64 <= self.image_size)
+            .max_by_key(|f| f.file_offset + f.size as u64)
+    }
+
+    // Pads each truncated file that was extracted to `path` with zeros, up to
+    // the size it's supposed to be. Ones that weren't extracted are left
+    // alone.
+    pub fn fill_truncated_files(&self, path: &Path) -> io::Result<()> {
+        for t in self.truncated_files() {
+            let file = match OpenOptions::new().write(true).open(path.join(&t.path)) {
+                Ok(f) => f,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            if file.metadata()?.len() < t.size {
+                file.set_len(t.size)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Prints where each section is, then the free space after the last one,
+    // up to the end of a full size ROM or of the image if it's bigger
+    pub fn print_layout(&self, style: NumberStyle, options: LayoutOptions) {
+        let layout = self.rom_layout();
+        let row = |start: u64, end: u64, size: u64, kind: &str, name: &str| {
+            println!(
+                "{}-{} {:>10} {:<9} {}",
+                format_offset(start, style), format_offset(end, style), format_u64(size, style), kind, name,
+            );
+        };
+
+        let files = layout.iter().filter(|s| s.section_type() == "file").collect::<Vec<_>>();
+        let mut summarized = false;
+        for s in layout.iter() {
+            match s.section_type() {
+                "file" if !options.files => {
+                    if summarized || files.is_empty() { continue }
+                    summarized = true;
+                    // Empty files can be anywhere, like at the very end of
+                    // the image, so they don't count towards where files are
+                    let sized = files.iter().filter(|f| f.size() > 0);
+                    let start = sized.clone().map(|f| f.start()).min()
+                        .unwrap_or_else(|| files.iter().map(|f| f.start()).min().unwrap());
+                    let end = sized.map(|f| f.start() + f.size() as u64).max().unwrap_or(start);
+                    let size = files.iter().map(|f| f.size() as u64).sum::<u64>();
+                    let name = format!("{} files, {} bytes", files.len(), format_u64(size, style));
+                    row(start, end, size, "files", &name);
+                },
+                "segment" if !options.segments => {},
+                kind => row(s.start(), s.start() + s.size() as u64, s.size() as u64, kind, &s.name()),
+            }
+        }
 
-        for (start, &(end, name)) in &regions {
-            println!("{:#010x}-{:#010x}: {}", start, start + end as u64, name);
+        let rom_size = cmp::max(ROM_SIZE as u64, self.image_size);
+        if let Some(g) = layout.gaps(rom_size).pop().filter(|g| g.after.is_none()) {
+            row(g.start, g.end, g.size, "free", "(unused)");
         }
     }
 
-    pub fn print_directory(&self, dir: &DirectoryEntry, long_format: bool) {
+    // Prints every section on the ROM, with the unused space between them.
+    // Gaps smaller than `min_gap` are left out of the listing, since they're
+    // usually just alignment, but they still count as unused in the total.
+    pub fn print_layout_with_gaps(&self, min_gap: u64, style: NumberStyle) {
+        let rom_size = self.image_size;
+        let layout = self.rom_layout();
+        let gaps = layout.gaps(rom_size);
+        let mut shown_gaps = gaps.iter().filter(|g| g.size >= min_gap).peekable();
+
+        for s in &layout.0 {
+            while let Some(g) = shown_gaps.next_if(|g| g.start < s.start()) {
+                println!("{:#010x}-{:#010x}: (unused, {} bytes)", g.start, g.end, format_u64(g.size, style));
+            }
+            println!("{:#010x}-{:#010x}: {}", s.start(), s.start() + s.size() as u64, s.name());
+        }
+        for g in shown_gaps {
+            println!("{:#010x}-{:#010x}: (unused, {} bytes)", g.start, g.end, format_u64(g.size, style));
+        }
+
+        let unused = gaps.iter().map(|g| g.size).sum::<u64>();
+        println!(
+            "\n{} bytes used, {} bytes unused ({:.1}%)",
+            format_u64(rom_size.saturating_sub(unused), style),
+            format_u64(unused, style),
+            unused as f64 / rom_size as f64 * 100.0,
+        );
+    }
+
+    // If `kinds` isn't empty, each file's kind from it goes before its path,
+    // like from `identify_files`
+    pub fn print_directory(&self, dir: &DirectoryEntry, long_format: bool, kinds: &HashMap<usize, FileKind>) {
         for e in dir.iter_contents(&self.fst.entries) {
+            let kind = match kinds.get(&e.info().index) {
+                Some(k) => format!("{:<7} ", k),
+                None if !kinds.is_empty() => format!("{:<7} ", "-"),
+                None => String::new(),
+            };
             if long_format {
-                println!("{}", e.format_long());
+                println!("{}{}", kind, e.format_long());
             } else {
-                println!("{}", e.info().full_path.to_string_lossy());
+                println!("{}{}", kind, e.info().full_path.to_string_lossy());
             }
         }
     }
 }
 
+// Offsets are padded so the rows line up
+fn format_offset(offset: u64, style: NumberStyle) -> String {
+    match style {
+        NumberStyle::Hexadecimal => format!("{:#010x}", offset),
+        NumberStyle::Decimal => format!("{:>10}", offset),
+    }
+}
+
+// Something `extract_section_with_name` can extract
+#[derive(Clone, Copy)]
+enum NamedSection<'a> {
+    Header,
+    Apploader,
+    Dol,
+    Fst,
+    File(&'a FileEntry),
+    Directory(&'a DirectoryEntry),
+    Segment(&'a Segment),
+    // A REL in the FST, and which of its sections
+    RelSection(&'a FileEntry, usize),
+}
+
+impl<'a> NamedSection<'a> {
+    // Directories don't know how many files are in them until they're
+    // extracted, so that's left at 0
+    fn section_type(&self) -> SectionType {
+        match self {
+            NamedSection::Header => SectionType::System(UniqueSectionType::Header),
+            NamedSection::Apploader => SectionType::System(UniqueSectionType::Apploader),
+            NamedSection::Dol => SectionType::System(UniqueSectionType::Dol),
+            NamedSection::Fst => SectionType::System(UniqueSectionType::Fst),
+            NamedSection::File(f) => SectionType::File(f.info.full_path.clone()),
+            NamedSection::Directory(d) => SectionType::Directory { path: d.info.full_path.clone(), files: 0 },
+            NamedSection::Segment(s) => SectionType::Segment(s.seg_type, s.seg_num),
+            NamedSection::RelSection(f, index) =>
+                SectionType::RelSection { path: f.info.full_path.clone(), index: *index },
+        }
+    }
+}
+
+// The start of an image that's being streamed, which is kept so the system
+// data can be parsed from it
+struct StreamStart<R> {
+    iso: R,
+    data: Vec<u8>,
+}
+
+// More than any real game's system data. A DOL or FST past this is probably
+// after the files, which would mean buffering all of them.
+const MAX_STREAMED_SYSTEM_DATA: u64 = 64 * 1024 * 1024;
+
+impl<R: Read> StreamStart<R> {
+    // Reads the image up to `end`, if it hasn't been already
+    fn read_to(&mut self, end: u64) -> io::Result<Cursor<&[u8]>> {
+        if end > MAX_STREAMED_SYSTEM_DATA {
+            return Err(io::Error::other(format!(
+                "The system data goes up to {:#x}, which is too far to read from a stream.", end,
+            )));
+        }
+        let len = self.data.len() as u64;
+        if end > len {
+            (&mut self.iso).take(end - len).read_to_end(&mut self.data)?;
+            if (self.data.len() as u64) < end {
+                return Err(stream_ended());
+            }
+        }
+        Ok(Cursor::new(&self.data))
+    }
+}
+
+fn stream_ended() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "The image ended before everything on it was read.")
+}
+
 pub struct ROMLayout<'a>(Vec<&'a dyn Section>);
 
+impl<'a> Index<usize> for ROMLayout<'a> {
+    type Output = dyn Section + 'a;
+
+    fn index(&self, index: usize) -> &(dyn Section + 'a) {
+        self.0[index]
+    }
+}
+
+// Space on the ROM that no section uses, from `ROMLayout::gaps`
+pub struct Gap<'a> {
+    pub start: u64,
+    pub end: u64,
+    pub size: u64,
+    // The sections on either side. There's nothing after a gap at the end of
+    // the ROM.
+    pub before: Option<&'a dyn Section>,
+    pub after: Option<&'a dyn Section>,
+}
+
 impl<'a> ROMLayout<'a> {
     pub fn find_offset(&'a self, offset: u64) -> Option<&'a dyn Section> {
-        self.0.binary_search_by(|s| s.compare_offset(offset))
-            .ok()
-            .map(|i| self.0[i])
+        self.index_of_offset(offset).map(|i| self.0[i])
+    }
+
+    // Like `find_offset`, but as the section's own type
+    pub fn resolve(&'a self, offset: u64) -> Option<ResolvedSection<'a>> {
+        self.find_offset(offset).map(|s| s.resolve())
+    }
+
+    pub fn file_at(&'a self, offset: u64) -> Option<&'a FileEntry> {
+        match self.resolve(offset) {
+            Some(ResolvedSection::File(f)) => Some(f),
+            _ => None,
+        }
+    }
+
+    pub fn segment_at(&'a self, offset: u64) -> Option<&'a Segment> {
+        match self.resolve(offset) {
+            Some(ResolvedSection::Segment(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    // Like `find_offset`, but gives the section's index, so the sections
+    // around it can be looked at too
+    pub fn index_of_offset(&self, offset: u64) -> Option<usize> {
+        self.0.binary_search_by(|s| s.compare_offset(offset)).ok()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'a dyn Section> + '_ {
+        self.0.iter().cloned()
     }
 
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // The unused space between sections, and after the last one up to
+    // `rom_size`. Empty files don't take up any space, so they're ignored.
+    pub fn gaps(&self, rom_size: u64) -> Vec<Gap<'a>> {
+        let mut gaps = Vec::new();
+        let mut end = 0;
+        let mut before = None;
+        for &s in self.0.iter().filter(|s| s.size() > 0) {
+            if s.start() > end {
+                gaps.push(Gap { start: end, end: s.start(), size: s.start() - end, before, after: Some(s) });
+            }
+            let s_end = s.start() + s.size() as u64;
+            if s_end >= end {
+                end = s_end;
+                before = Some(s);
+            }
+        }
+        if rom_size > end {
+            gaps.push(Gap { start: end, end: rom_size, size: rom_size - end, before, after: None });
+        }
+        gaps
+    }
+
+    // The unused space `offset` is in, if it isn't in any section
+    pub fn gap_at(&self, offset: u64, rom_size: u64) -> Option<Gap<'a>> {
+        self.gaps(rom_size).into_iter().find(|g| g.start <= offset && offset < g.end)
+    }
 }
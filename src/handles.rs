@@ -0,0 +1,230 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Kept free of the budget for everything that isn't counted, like stdio, the
+// lock file, and the ROM being written
+const HEADROOM: usize = 64;
+// What the budget is if the real limit can't be found
+const FALLBACK_LIMIT: usize = 512;
+
+lazy_static! {
+    static ref SHARED: Arc<HandleBudget> = Arc::new(HandleBudget::from_rlimit());
+}
+
+// How many files can be open at once between everything that opens them
+// (the threads extracting files, the reader in a rebuild, and split files),
+// so together they don't run into the process's open file limit and fail
+// with EMFILE partway through. Anything that needs a file open reserves a
+// handle for it first, and either waits for one to be released, or closes
+// something it has open and opens it again later.
+#[derive(Debug)]
+pub struct HandleBudget {
+    limit: usize,
+    open: Mutex<usize>,
+    released: Condvar,
+    reopened: AtomicUsize,
+}
+
+impl HandleBudget {
+    pub fn new(limit: usize) -> HandleBudget {
+        HandleBudget {
+            limit: limit.max(1),
+            open: Mutex::new(0),
+            released: Condvar::new(),
+            reopened: AtomicUsize::new(0),
+        }
+    }
+
+    // The soft open file limit, less some headroom
+    pub fn from_rlimit() -> HandleBudget {
+        HandleBudget::new(soft_limit().map_or(FALLBACK_LIMIT, |l| l.saturating_sub(HEADROOM)))
+    }
+
+    // The budget that's used if one isn't given
+    pub fn shared() -> Arc<HandleBudget> {
+        SHARED.clone()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    // How many handles are reserved right now
+    pub fn open(&self) -> usize {
+        *self.open.lock().unwrap()
+    }
+
+    // How many times something had to close a file to stay in the budget
+    pub fn reopened(&self) -> usize {
+        self.reopened.load(Ordering::Relaxed)
+    }
+
+    pub fn note_reopened(&self) {
+        self.reopened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Waits until there are `count` handles free, and reserves them. If
+    // `count` is more than the limit, it waits until nothing else is open
+    // instead of waiting forever.
+    pub fn reserve(self: &Arc<Self>, count: usize) -> Handles {
+        let mut open = self.open.lock().unwrap();
+        while !self.fits(*open, count) {
+            open = self.released.wait(open).unwrap();
+        }
+        *open += count;
+        Handles { budget: self.clone(), count }
+    }
+
+    // Like `reserve`, but gives up right away if there isn't room
+    pub fn try_reserve(self: &Arc<Self>, count: usize) -> Option<Handles> {
+        let mut open = self.open.lock().unwrap();
+        if !self.fits(*open, count) { return None }
+        *open += count;
+        Some(Handles { budget: self.clone(), count })
+    }
+
+    // Warns if there isn't room for `wanted` handles at once, which is how
+    // many something would need to not have to wait or reopen anything
+    pub fn warn_if_short(&self, wanted: usize, what: &str) {
+        if wanted > self.limit {
+            warn!(
+                "{} would need {} files open at once, but only {} can be, so it'll be slower. \
+                 Raise the open file limit (like with `ulimit -n`), or use fewer threads.",
+                what, wanted, self.limit,
+            );
+        }
+    }
+
+    // Warns if files had to be closed and opened again
+    pub fn warn_if_reopened(&self) {
+        let reopened = self.reopened();
+        if reopened > 0 {
+            warn!(
+                "Files had to be closed and opened again {} times to stay under the open file limit, \
+                 which slows things down. Raise it (like with `ulimit -n`) to avoid that.",
+                reopened,
+            );
+        }
+    }
+
+    fn fits(&self, open: usize, count: usize) -> bool {
+        open + count <= self.limit || open == 0
+    }
+
+    fn release(&self, count: usize) {
+        if count == 0 { return }
+        *self.open.lock().unwrap() -= count;
+        self.released.notify_all();
+    }
+}
+
+// Handles reserved from a budget, which are released when this is dropped
+#[derive(Debug)]
+pub struct Handles {
+    budget: Arc<HandleBudget>,
+    count: usize,
+}
+
+impl Handles {
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn budget(&self) -> &Arc<HandleBudget> {
+        &self.budget
+    }
+
+    // Reserves `count` more, if there's room
+    pub fn try_grow(&mut self, count: usize) -> bool {
+        match self.budget.try_reserve(count) {
+            Some(mut more) => {
+                self.count += more.count;
+                more.count = 0;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Reserves `count` more, waiting until there's room
+    pub fn grow(&mut self, count: usize) {
+        let mut more = self.budget.reserve(count);
+        self.count += more.count;
+        more.count = 0;
+    }
+
+    // Reserves `count` more even if that goes over the limit, for something
+    // that can't wait without holding up whatever it's waiting for
+    pub fn force_grow(&mut self, count: usize) {
+        *self.budget.open.lock().unwrap() += count;
+        self.count += count;
+    }
+
+    pub fn release(&mut self, count: usize) {
+        let count = count.min(self.count);
+        self.count -= count;
+        self.budget.release(count);
+    }
+}
+
+impl Drop for Handles {
+    fn drop(&mut self) {
+        self.budget.release(self.count);
+    }
+}
+
+#[cfg(unix)]
+fn soft_limit() -> Option<usize> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        return Some(usize::MAX);
+    }
+    Some(limit.rlim_cur as usize)
+}
+
+// Windows doesn't have a limit like this for files opened through the C
+// runtime, but there's no point going that high
+#[cfg(not(unix))]
+fn soft_limit() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn reserving_past_the_limit_waits() {
+        let budget = Arc::new(HandleBudget::new(3));
+        let mut held = budget.reserve(2);
+        assert!(budget.try_reserve(2).is_none());
+        assert!(held.try_grow(1));
+        assert_eq!(budget.open(), 3);
+
+        let waiting = {
+            let budget = budget.clone();
+            thread::spawn(move || budget.reserve(2).count())
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(budget.open(), 3);
+        held.release(2);
+        assert_eq!(waiting.join().unwrap(), 2);
+        drop(held);
+        assert_eq!(budget.open(), 0);
+    }
+
+    #[test]
+    fn too_much_for_the_limit_waits_for_everything() {
+        let budget = Arc::new(HandleBudget::new(2));
+        let held = budget.reserve(5);
+        assert_eq!(held.count(), 5);
+        assert!(budget.try_reserve(1).is_none());
+        drop(held);
+        assert!(budget.try_reserve(1).is_some());
+    }
+}
@@ -0,0 +1,438 @@
+use std::fmt;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(feature = "sha1")]
+use sha1::Digest as Sha1Digest;
+#[cfg(feature = "sha256")]
+use sha2::Digest as Sha2Digest;
+
+use ::{AppError, WRITE_CHUNK_SIZE};
+use util::ZEROS;
+
+// This is what should be used when the hashes are only used to check
+// whether or not something changed, rather than to compare against hashes
+// from somewhere else, like Redump.
+pub const CHANGE_DETECTION_ALGO: HashAlgo = HashAlgo::Xxh3;
+
+// What manifests use unless another algorithm's asked for, so they can be
+// checked with sha1sum
+pub const MANIFEST_ALGO: HashAlgo = HashAlgo::Sha1;
+
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgo {
+    Crc32,
+    Md5,
+    Sha1,
+    Sha256,
+    Xxh3,
+}
+
+impl HashAlgo {
+    pub const ALL: [HashAlgo; 5] = [
+        HashAlgo::Crc32,
+        HashAlgo::Md5,
+        HashAlgo::Sha1,
+        HashAlgo::Sha256,
+        HashAlgo::Xxh3,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgo::Crc32 => "crc32",
+            HashAlgo::Md5 => "md5",
+            HashAlgo::Sha1 => "sha1",
+            HashAlgo::Sha256 => "sha256",
+            HashAlgo::Xxh3 => "xxh3",
+        }
+    }
+
+    // The length of a digest from this algorithm, in hex digits
+    pub fn digest_len(self) -> usize {
+        match self {
+            HashAlgo::Crc32 => 8,
+            HashAlgo::Md5 => 32,
+            HashAlgo::Sha1 => 40,
+            HashAlgo::Sha256 => 64,
+            HashAlgo::Xxh3 => 16,
+        }
+    }
+
+    // Whether or not coreutils has a tool for this algorithm, like sha1sum.
+    // Manifest lines for these leave out the `algo:` prefix, so the tool can
+    // check them.
+    pub fn has_sum_tool(self) -> bool {
+        match self {
+            HashAlgo::Md5 | HashAlgo::Sha1 | HashAlgo::Sha256 => true,
+            HashAlgo::Crc32 | HashAlgo::Xxh3 => false,
+        }
+    }
+
+    // Whether or not support for this algorithm was compiled in
+    pub fn is_supported(self) -> bool {
+        match self {
+            HashAlgo::Crc32 => cfg!(feature = "crc32"),
+            HashAlgo::Md5 => cfg!(feature = "md5"),
+            HashAlgo::Sha1 => cfg!(feature = "sha1"),
+            HashAlgo::Sha256 => cfg!(feature = "sha256"),
+            HashAlgo::Xxh3 => cfg!(feature = "xxh3"),
+        }
+    }
+
+    pub fn hasher(self) -> io::Result<Box<dyn Hasher>> {
+        let hasher: Option<Box<dyn Hasher>> = match self {
+            #[cfg(feature = "crc32")]
+            HashAlgo::Crc32 => Some(Box::new(crc32fast::Hasher::new())),
+            #[cfg(feature = "md5")]
+            HashAlgo::Md5 => Some(Box::new(md5::Context::new())),
+            #[cfg(feature = "sha1")]
+            HashAlgo::Sha1 => Some(Box::new(sha1::Sha1::new())),
+            #[cfg(feature = "sha256")]
+            HashAlgo::Sha256 => Some(Box::new(sha2::Sha256::new())),
+            #[cfg(feature = "xxh3")]
+            HashAlgo::Xxh3 => Some(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        };
+        hasher.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("gcmod was built without support for {}", self),
+        ))
+    }
+}
+
+impl fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for HashAlgo {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<HashAlgo, AppError> {
+        let s = s.to_lowercase();
+        HashAlgo::ALL.iter()
+            .find(|a| a.name() == s || (s == "sha-1" && **a == HashAlgo::Sha1))
+            .cloned()
+            .ok_or_else(|| AppError::new(format!("Unknown hash algorithm: {}", s)))
+    }
+}
+
+pub trait Hasher {
+    fn update(&mut self, data: &[u8]);
+
+    // Returns the digest as lowercase hex
+    fn finish(self: Box<Self>) -> String;
+}
+
+#[cfg(feature = "crc32")]
+impl Hasher for crc32fast::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        crc32fast::Hasher::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> String {
+        format!("{:08x}", self.finalize())
+    }
+}
+
+#[cfg(feature = "md5")]
+impl Hasher for md5::Context {
+    fn update(&mut self, data: &[u8]) {
+        self.consume(data);
+    }
+
+    fn finish(self: Box<Self>) -> String {
+        format!("{:x}", self.compute())
+    }
+}
+
+#[cfg(feature = "sha1")]
+impl Hasher for sha1::Sha1 {
+    fn update(&mut self, data: &[u8]) {
+        Sha1Digest::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> String {
+        to_hex(&self.finalize())
+    }
+}
+
+#[cfg(feature = "sha256")]
+impl Hasher for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Sha2Digest::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> String {
+        to_hex(&self.finalize())
+    }
+}
+
+#[cfg(feature = "xxh3")]
+impl Hasher for xxhash_rust::xxh3::Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        xxhash_rust::xxh3::Xxh3::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> String {
+        format!("{:016x}", self.digest())
+    }
+}
+
+// Hashes everything written to it, so anything that can write a section out
+// can hash it instead
+pub struct HashWriter {
+    hasher: Box<dyn Hasher>,
+}
+
+impl HashWriter {
+    pub fn new(algo: HashAlgo) -> io::Result<HashWriter> {
+        algo.hasher().map(|hasher| HashWriter { hasher })
+    }
+
+    pub fn finish(self) -> String {
+        self.hasher.finish()
+    }
+}
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Hashes data with several algorithms at once as it's fed in, for things
+// that write the data themselves, like rebuilding a ROM
+pub struct MultiHasher {
+    hashers: Vec<(HashAlgo, Box<dyn Hasher>)>,
+}
+
+impl MultiHasher {
+    pub fn new(algos: &[HashAlgo]) -> io::Result<MultiHasher> {
+        let hashers = algos.iter()
+            .map(|&a| Ok((a, a.hasher()?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(MultiHasher { hashers })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for (_, h) in &mut self.hashers {
+            h.update(data);
+        }
+    }
+
+    // Hashes `count` zeros, like padding that was skipped over instead of
+    // being written
+    pub fn update_zeros(&mut self, count: u64) {
+        if self.hashers.is_empty() { return }
+        let mut left = count;
+        while left > 0 {
+            let n = left.min(WRITE_CHUNK_SIZE as u64) as usize;
+            self.update(&ZEROS[..n]);
+            left -= n as u64;
+        }
+    }
+
+    pub fn finish(self) -> Vec<Digest> {
+        self.hashers.into_iter()
+            .map(|(algo, h)| Digest { algo, hex: h.finish() })
+            .collect()
+    }
+}
+
+#[cfg(any(feature = "sha1", feature = "sha256"))]
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// A digest along with the algorithm that produced it. This is written as
+// `algo:hex`, so lists of digests can use a mix of different algorithms.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Digest {
+    pub algo: HashAlgo,
+    pub hex: String,
+}
+
+impl Digest {
+    pub fn of(algo: HashAlgo, reader: impl Read) -> io::Result<Digest> {
+        let mut digests = hash_reader(&[algo], reader)?;
+        Ok(digests.remove(0))
+    }
+
+    // Hashes `reader` with the same algorithm as this digest, and checks if the
+    // results match.
+    pub fn verify(&self, reader: impl Read) -> io::Result<bool> {
+        Digest::of(self.algo, reader).map(|d| d == *self)
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.algo, self.hex)
+    }
+}
+
+impl FromStr for Digest {
+    type Err = AppError;
+
+    // Digests without an `algo:` prefix are accepted too, as long as the
+    // algorithm can be figured out from their length.
+    fn from_str(s: &str) -> Result<Digest, AppError> {
+        let (algo, hex) = match s.find(':') {
+            Some(i) => (s[..i].parse()?, &s[i + 1..]),
+            None => {
+                let algo = HashAlgo::ALL.iter()
+                    .find(|a| a.digest_len() == s.len())
+                    .cloned()
+                    .ok_or_else(|| AppError::new(format!("Unrecognized digest: {}", s)))?;
+                (algo, s)
+            },
+        };
+        if hex.len() != algo.digest_len() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(AppError::new(format!("Invalid {} digest: {}", algo, hex)));
+        }
+        Ok(Digest { algo, hex: hex.to_lowercase() })
+    }
+}
+
+// A line of a manifest, like `xxh3:9f86d081884c7d65  audio/title.dsp`. Since
+// each line says which algorithm it used, a manifest can mix them, like one
+// that's been partly rehashed with a faster algorithm. Lines without an
+// algorithm, the way sha1sum writes them, are read too, and that's how md5,
+// sha1, and sha256 lines are written unless they're formatted with `{:#}`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ManifestEntry {
+    pub digest: Digest,
+    pub path: PathBuf,
+}
+
+impl fmt::Display for ManifestEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.digest.algo.has_sum_tool() && !f.alternate() {
+            write!(f, "{}  {}", self.digest.hex, self.path.display())
+        } else {
+            write!(f, "{}  {}", self.digest, self.path.display())
+        }
+    }
+}
+
+// Blank lines are skipped
+pub fn read_manifest(input: impl BufRead) -> io::Result<Vec<ManifestEntry>> {
+    let mut entries = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() { continue }
+
+        let invalid = |message: String| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", i + 1, message),
+        );
+        // sha1sum puts a `*` before the path for files it read in binary mode
+        let (digest, path) = match line.find(char::is_whitespace) {
+            Some(n) => (&line[..n], line[n..].trim_start().trim_start_matches('*')),
+            None => return Err(invalid("expected a digest and a path".to_string())),
+        };
+        if path.is_empty() {
+            return Err(invalid("expected a digest and a path".to_string()));
+        }
+        let digest = digest.parse::<Digest>().map_err(|e| invalid(format!("{:?}", e)))?;
+        entries.push(ManifestEntry { digest, path: PathBuf::from(path) });
+    }
+    Ok(entries)
+}
+
+// The digests of a whole image, and how long it took to read
+#[derive(Clone, Debug)]
+pub struct HashReport {
+    pub digests: Vec<Digest>,
+    pub bytes_read: u64,
+    pub elapsed: Duration,
+}
+
+impl HashReport {
+    pub fn digest(&self, algo: HashAlgo) -> Option<&Digest> {
+        self.digests.iter().find(|d| d.algo == algo)
+    }
+
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes_read as f64 / self.elapsed.as_secs_f64().max(0.001)
+    }
+}
+
+// Hashes everything in `reader` with each of `algos` in a single pass.
+pub fn hash_reader(algos: &[HashAlgo], reader: impl Read) -> io::Result<Vec<Digest>> {
+    hash_reader_with_progress(algos, reader, |_| {})
+}
+
+// Hashes `size` bytes of `reader` from `start`, like one section of a ROM
+pub fn hash_range(algos: &[HashAlgo], mut reader: impl Read + Seek, start: u64, size: u64) -> io::Result<Vec<Digest>> {
+    reader.seek(SeekFrom::Start(start))?;
+    let mut section = reader.take(size);
+    let digests = hash_reader(algos, &mut section)?;
+    if section.limit() > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("The image ends {} bytes before {:#x}.", section.limit(), start + size),
+        ));
+    }
+    Ok(digests)
+}
+
+// The SHA-1 of `size` bytes of `reader` from `start`
+#[cfg(feature = "sha1")]
+pub fn sha1_range(mut reader: impl Read + Seek, start: u64, size: u64) -> io::Result<[u8; 20]> {
+    reader.seek(SeekFrom::Start(start))?;
+    let mut hasher = sha1::Sha1::new();
+    let copied = io::copy(&mut reader.take(size), &mut hasher)?;
+    if copied < size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("The image ends {} bytes before {:#x}.", size - copied, start + size),
+        ));
+    }
+    Ok(hasher.finalize().into())
+}
+
+// Like `hash_reader`, but calls `progress` with the total number of bytes
+// read so far after every chunk.
+pub fn hash_reader_with_progress(
+    algos: &[HashAlgo],
+    mut reader: impl Read,
+    mut progress: impl FnMut(u64),
+) -> io::Result<Vec<Digest>> {
+    let mut hashers = algos.iter()
+        .map(|a| a.hasher())
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut buf = vec![0; WRITE_CHUNK_SIZE];
+    let mut total = 0;
+
+    loop {
+        let bytes_read = match reader.read(&mut buf) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if bytes_read == 0 { break }
+        for h in &mut hashers {
+            h.update(&buf[..bytes_read]);
+        }
+        total += bytes_read as u64;
+        progress(total);
+    }
+
+    Ok(algos.iter().zip(hashers).map(|(&algo, h)| Digest {
+        algo,
+        hex: h.finish(),
+    }).collect())
+}
@@ -0,0 +1,42 @@
+use std::io::{self, Read, Write};
+
+use ::{read_fully, NumberStyle};
+
+const BYTES_PER_ROW: usize = 16;
+
+// Writes everything in `input` like `hexdump -C` does, with the offset, 16
+// bytes in hex, and then the same bytes as ASCII, with `.` for anything that
+// can't be printed. The first byte is at `offset`.
+pub fn hexdump(
+    mut input: impl Read,
+    mut output: impl Write,
+    mut offset: u64,
+    style: NumberStyle,
+) -> io::Result<()> {
+    let mut row = [0; BYTES_PER_ROW];
+    loop {
+        let len = read_fully(&mut input, &mut row)?;
+        if len == 0 { break }
+
+        match style {
+            NumberStyle::Hexadecimal => write!(output, "{:08x} ", offset)?,
+            NumberStyle::Decimal => write!(output, "{:010} ", offset)?,
+        }
+        for i in 0..BYTES_PER_ROW {
+            // An extra space between the two halves
+            if i % 8 == 0 { write!(output, " ")? }
+            match row[..len].get(i) {
+                Some(b) => write!(output, "{:02x} ", b)?,
+                None => write!(output, "   ")?,
+            }
+        }
+        let ascii = row[..len].iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect::<String>();
+        writeln!(output, " |{}|", ascii)?;
+
+        offset += len as u64;
+        if len < BYTES_PER_ROW { break }
+    }
+    Ok(())
+}
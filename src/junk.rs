@@ -0,0 +1,358 @@
+// The junk data Nintendo's mastering tool fills the unused space on a disc
+// with. It comes from a lagged Fibonacci generator that's seeded again at
+// the start of every 32 KiB block, from the game code, the disc number, and
+// which block it is, so any byte of it can be worked out from where it is on
+// the disc. Filling the gaps with it, instead of zeros, is what lets a
+// rebuilt ROM match the original byte for byte.
+//
+// A ROM's gaps are checked against it when it's extracted, and what was found
+// is saved to `JUNK_CHECK_PATH`, so rebuilds know if it's safe to use.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use paths::JUNK_CHECK_PATH;
+use sections::header::Header;
+use Game;
+
+// The generator's state is this many words
+const LFG_K: usize = 521;
+const LFG_J: usize = 32;
+const SEED_SIZE: usize = 17;
+// It's seeded again at the start of each block
+pub const JUNK_BLOCK_SIZE: u64 = 0x8000;
+
+// How much of each gap is compared when checking a ROM, and how many gaps
+const SAMPLE_SIZE: u64 = 0x1000;
+const MAX_SAMPLED_GAPS: usize = 16;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct JunkSeed {
+    pub game_code: [u8; 4],
+    pub disc_number: u8,
+}
+
+impl JunkSeed {
+    pub fn from_header(header: &Header) -> JunkSeed {
+        let mut game_code = [0; 4];
+        for (c, b) in game_code.iter_mut().zip(header.game_code.bytes()) {
+            *c = b;
+        }
+        JunkSeed { game_code, disc_number: header.disk_id }
+    }
+
+    // The 17 words the generator starts from for the block at `block`
+    pub fn block_seed(&self, block: u32) -> [u32; SEED_SIZE] {
+        let id = self.game_code;
+        let seed = u32::from_be_bytes([
+            id[2],
+            id[1],
+            id[3].wrapping_add(id[2]),
+            id[0].wrapping_add(id[1]),
+        ]) ^ self.disc_number as u32;
+
+        let mut n = seed.wrapping_mul(0x0260_bcd5) ^ block.wrapping_mul(0x1ef2_9123);
+        let mut words = [0; SEED_SIZE];
+        for w in words.iter_mut() {
+            for _ in 0..32 {
+                n = n.wrapping_mul(0x5d58_8b65).wrapping_add(1);
+                *w = (*w >> 1) | (n & 0x8000_0000);
+            }
+        }
+        words[16] ^= (words[0] >> 9) ^ (words[16] << 23);
+        words
+    }
+
+    // Fills `buf` with the junk that goes at `offset` on the disc
+    pub fn fill(&self, offset: u64, buf: &mut [u8]) {
+        let mut offset = offset;
+        let mut done = 0;
+        while done < buf.len() {
+            let block = offset / JUNK_BLOCK_SIZE;
+            let in_block = (offset % JUNK_BLOCK_SIZE) as usize;
+            let len = (buf.len() - done).min(JUNK_BLOCK_SIZE as usize - in_block);
+
+            let mut generator = Generator::new(&self.block_seed(block as u32));
+            generator.skip(in_block);
+            generator.read(&mut buf[done..done + len]);
+
+            done += len;
+            offset += len as u64;
+        }
+    }
+}
+
+struct Generator {
+    buffer: [u32; LFG_K],
+    // Where the next byte comes from in `buffer`
+    position: usize,
+}
+
+impl Generator {
+    fn new(seed: &[u32; SEED_SIZE]) -> Generator {
+        let mut buffer = [0; LFG_K];
+        buffer[..SEED_SIZE].copy_from_slice(seed);
+        for i in SEED_SIZE..LFG_K {
+            buffer[i] = (buffer[i - 17] << 23) ^ (buffer[i - 16] >> 9) ^ buffer[i - 1];
+        }
+        // The bytes that come out skip around a bit, which is easier to do
+        // once here than every time one's read
+        for x in buffer.iter_mut() {
+            *x = (*x & 0xff00_ffff) | ((*x >> 2) & 0x00ff_0000);
+        }
+        let mut generator = Generator { buffer, position: 0 };
+        for _ in 0..4 {
+            generator.forward();
+        }
+        generator
+    }
+
+    fn forward(&mut self) {
+        for i in 0..LFG_J {
+            self.buffer[i] ^= self.buffer[i + LFG_K - LFG_J];
+        }
+        for i in LFG_J..LFG_K {
+            self.buffer[i] ^= self.buffer[i - LFG_J];
+        }
+    }
+
+    fn skip(&mut self, mut count: usize) {
+        while count > 0 {
+            let len = count.min(LFG_K * 4 - self.position);
+            self.advance(len);
+            count -= len;
+        }
+    }
+
+    fn read(&mut self, out: &mut [u8]) {
+        let mut done = 0;
+        while done < out.len() {
+            let len = (out.len() - done).min(LFG_K * 4 - self.position);
+            for (i, b) in out[done..done + len].iter_mut().enumerate() {
+                let p = self.position + i;
+                *b = self.buffer[p / 4].to_be_bytes()[p % 4];
+            }
+            self.advance(len);
+            done += len;
+        }
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.position += len;
+        if self.position == LFG_K * 4 {
+            self.forward();
+            self.position = 0;
+        }
+    }
+}
+
+// The junk that should be at `offset` on the ROM, next to what's actually
+// there. If the ROM ends first, `actual` is cut short.
+#[derive(Clone, Debug)]
+pub struct JunkSample {
+    pub offset: u64,
+    pub generated: Vec<u8>,
+    pub actual: Vec<u8>,
+}
+
+impl JunkSample {
+    pub fn read(mut iso: impl Read + Seek, seed: &JunkSeed, offset: u64, len: usize) -> io::Result<JunkSample> {
+        let mut generated = vec![0; len];
+        seed.fill(offset, &mut generated);
+        iso.seek(SeekFrom::Start(offset))?;
+        let mut actual = Vec::with_capacity(len);
+        iso.take(len as u64).read_to_end(&mut actual)?;
+        Ok(JunkSample { offset, generated, actual })
+    }
+
+    // Where the first byte that doesn't match is
+    pub fn first_mismatch(&self) -> Option<u64> {
+        let i = self.generated.iter().zip(&self.actual).position(|(g, a)| g != a)
+            .or((self.actual.len() < self.generated.len()).then_some(self.actual.len()))?;
+        Some(self.offset + i as u64)
+    }
+
+    pub fn matches(&self) -> bool {
+        self.first_mismatch().is_none()
+    }
+}
+
+// What was found when a ROM's gaps were compared with the junk that should
+// be there
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct JunkCheck {
+    // How many bytes were compared. If it's 0, there weren't any gaps to
+    // check, so it's not known what the ROM used.
+    pub sampled: u64,
+    // The first byte that wasn't junk
+    pub mismatch: Option<u64>,
+    // The ROM that was checked, so its gaps can be copied instead if they
+    // aren't junk
+    pub source: Option<PathBuf>,
+}
+
+impl JunkCheck {
+    pub fn matches(&self) -> bool {
+        self.sampled > 0 && self.mismatch.is_none()
+    }
+
+    // Saves this to `JUNK_CHECK_PATH` in the extracted ROM at `root`
+    pub fn save(&self, root: &Path) -> io::Result<()> {
+        let mut file = File::create(root.join(JUNK_CHECK_PATH))?;
+        writeln!(file, "# Whether the gaps between files on the ROM this was extracted from were")?;
+        writeln!(file, "# filled with the standard junk data, which rebuild --gap-fill junk goes by.")?;
+        writeln!(file, "sampled\t{}", self.sampled)?;
+        if let Some(offset) = self.mismatch {
+            writeln!(file, "mismatch\t{:#x}", offset)?;
+        }
+        if let Some(ref source) = self.source {
+            writeln!(file, "source\t{}", source.display())?;
+        }
+        Ok(())
+    }
+
+    // What was saved in the extracted ROM at `root`, if anything was
+    pub fn load(root: &Path) -> io::Result<Option<JunkCheck>> {
+        let file = match File::open(root.join(JUNK_CHECK_PATH)) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut check = JunkCheck::default();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') { continue }
+            let bad_line = || io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has a bad line: {}", JUNK_CHECK_PATH, line),
+            );
+            let (key, value) = line.split_once('\t').ok_or_else(bad_line)?;
+            match key {
+                "sampled" => check.sampled = value.parse().map_err(|_| bad_line())?,
+                "mismatch" => check.mismatch = Some(::parse_as_u64(value).map_err(|_| bad_line())?),
+                "source" => check.source = Some(PathBuf::from(value)),
+                _ => return Err(bad_line()),
+            }
+        }
+        Ok(Some(check))
+    }
+}
+
+impl Game {
+    // Compares the start of some of the gaps between files with the junk
+    // that'd be there if the ROM was mastered the usual way. Gaps before the
+    // end of the FST aren't checked, since that's all system data. The first
+    // few bytes of a gap, up to a multiple of 4, are left out too, since
+    // they're zeros.
+    pub fn check_junk(&self, mut iso: impl Read + Seek) -> io::Result<JunkCheck> {
+        let seed = JunkSeed::from_header(&self.header);
+        let fst_end = self.fst.offset + self.fst.size as u64;
+        let layout = self.rom_layout();
+        let gaps = layout.gaps(self.image_size).into_iter()
+            .map(|g| (::align(g.start, 4), g.end))
+            .filter(|&(start, end)| start >= fst_end && start < end)
+            .collect::<Vec<_>>();
+        // Spread out over the whole ROM, instead of only the first few
+        let step = gaps.len().div_ceil(MAX_SAMPLED_GAPS).max(1);
+
+        let mut check = JunkCheck::default();
+        for &(start, end) in gaps.iter().step_by(step) {
+            let len = (end - start).min(SAMPLE_SIZE);
+            let sample = JunkSample::read(&mut iso, &seed, start, len as usize)?;
+            check.sampled += len;
+            if let Some(offset) = sample.first_mismatch() {
+                check.mismatch = Some(offset);
+                break;
+            }
+        }
+        Ok(check)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed() -> JunkSeed {
+        JunkSeed { game_code: *b"GTST", disc_number: 0 }
+    }
+
+    #[test]
+    fn blocks_are_seeded_differently() {
+        let s = seed();
+        assert_ne!(s.block_seed(0), s.block_seed(1));
+        assert_ne!(s.block_seed(0), JunkSeed { disc_number: 1, ..s }.block_seed(0));
+        assert_ne!(s.block_seed(0), JunkSeed { game_code: *b"GTSP", ..s }.block_seed(0));
+    }
+
+    // Junk only depends on where it is, so any piece of it is the same as
+    // that part of a bigger piece
+    #[test]
+    fn fill_depends_on_offset() {
+        let s = seed();
+        let mut whole = vec![0; 3 * JUNK_BLOCK_SIZE as usize];
+        s.fill(0, &mut whole);
+        for &(offset, len) in &[(0, 1), (3, 100), (2083, 2090), (0x7ff0, 0x20), (0x8000, 0x8000), (0x7fff, 0x10001)] {
+            let mut part = vec![0; len];
+            s.fill(offset as u64, &mut part);
+            assert_eq!(&part[..], &whole[offset..offset + len], "at {:#x}", offset);
+        }
+    }
+
+    // Fixed bytes for a few places on a couple of discs, so a change to the
+    // generator can't go unnoticed just because it still agrees with itself.
+    // The one at 0x7ff8 runs over into the next block, where it's seeded
+    // again, and the one at 0x1000 is after the generator's first refill.
+    #[test]
+    fn known_junk() {
+        let known: [(&[u8; 4], u8, u64, [u8; 16]); 4] = [
+            (b"GALE", 0, 0, [
+                0x94, 0x21, 0x5a, 0xda, 0x27, 0xf1, 0x5c, 0x2d, 0x8b, 0xc8, 0x34, 0xab, 0xfa, 0x88, 0x4b, 0x9d,
+            ]),
+            (b"GALE", 0, 0x7ff8, [
+                0x92, 0x97, 0x81, 0x50, 0x5d, 0x74, 0x0d, 0x38, 0x73, 0xf4, 0xff, 0xc1, 0x29, 0x57, 0x63, 0x5e,
+            ]),
+            (b"GALE", 0, 0x1000, [
+                0x8b, 0xe2, 0x54, 0xc6, 0x64, 0x23, 0xb3, 0x00, 0xe4, 0x1a, 0x8d, 0x72, 0xc6, 0xbf, 0x2f, 0x23,
+            ]),
+            (b"GM4E", 1, 0x1234_5678, [
+                0x85, 0x6f, 0x56, 0xbf, 0x43, 0xef, 0x40, 0x87, 0xc6, 0x99, 0x4b, 0x2a, 0xbc, 0x39, 0x82, 0xe0,
+            ]),
+        ];
+        for &(game_code, disc_number, offset, expected) in &known {
+            let s = JunkSeed { game_code: *game_code, disc_number };
+            let mut junk = [0; 16];
+            s.fill(offset, &mut junk);
+            assert_eq!(junk, expected, "{} disc {} at {:#x}", String::from_utf8_lossy(game_code), disc_number, offset);
+        }
+    }
+
+    #[test]
+    fn fill_starts_again_each_block() {
+        let s = seed();
+        let mut a = vec![0; 0x40];
+        let mut b = vec![0; 0x40];
+        s.fill(0, &mut a);
+        s.fill(JUNK_BLOCK_SIZE, &mut b);
+        assert_ne!(a, b);
+        assert!(a.iter().any(|&x| x != 0));
+    }
+
+    #[test]
+    fn samples_find_mismatches() {
+        let s = seed();
+        let mut disc = vec![0; 0x200];
+        s.fill(0x100, &mut disc[0x100..]);
+        let sample = JunkSample::read(io::Cursor::new(&disc), &s, 0x100, 0x100).unwrap();
+        assert!(sample.matches());
+
+        disc[0x180] ^= 1;
+        let sample = JunkSample::read(io::Cursor::new(&disc), &s, 0x100, 0x100).unwrap();
+        assert_eq!(sample.first_mismatch(), Some(0x180));
+
+        // Past the end of the ROM
+        let sample = JunkSample::read(io::Cursor::new(&disc), &s, 0x1c0, 0x80).unwrap();
+        assert_eq!(sample.first_mismatch(), Some(0x200));
+    }
+}
@@ -1,22 +1,119 @@
 extern crate byteorder;
+#[cfg(any(feature = "crc32", feature = "patch", feature = "archive"))]
+extern crate crc32fast;
+extern crate encoding_rs;
+#[cfg(feature = "gcz")]
+extern crate flate2;
+extern crate glob;
 #[macro_use]
 extern crate lazy_static;
+#[cfg(unix)]
+extern crate libc;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "md5")]
+extern crate md5;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
 extern crate regex;
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "sha1")]
+extern crate sha1;
+#[cfg(feature = "sha256")]
+extern crate sha2;
+extern crate tempfile;
+#[cfg(windows)]
+extern crate winapi;
+#[cfg(feature = "xxh3")]
+extern crate xxhash_rust;
 
 use std::borrow::Cow;
 use std::cmp::min;
 use std::fmt;
-use std::io::{self, Read, Write};
+use std::fs::{create_dir, create_dir_all, remove_dir_all, remove_file, File};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
 use std::num::ParseIntError;
+use std::ops::AddAssign;
+use std::path::Path;
 
 mod game;
-pub use game::Game;
+pub use game::{
+    DiffReport,
+    FileDiff,
+    Game,
+    GameInfo,
+    GameMetadata,
+    Gap,
+    HeaderChange,
+    LayoutEntry,
+    LayoutOptions,
+    Overlap,
+    SectionHashes,
+    SegmentInfo,
+    TruncatedFile,
+    VerifyReport,
+};
 pub use game::ROM_SIZE;
 
+mod extract;
+pub use extract::{ExtractOptions, ExtractReport};
+
+mod file_reader;
+pub use file_reader::FstFileReader;
+
 pub mod sections;
 
+pub mod analysis;
+
+#[cfg(feature = "archive")]
+pub mod archive;
+
+pub mod compression;
+
+pub mod disassembler;
+
+pub mod filetype;
+
+pub mod handles;
+
+pub mod hash;
+
+pub mod hexdump;
+
+pub mod junk;
+
+pub mod lock;
+
+#[cfg(feature = "patch")]
+pub mod patch;
+
+pub mod progress;
+
 mod rom_rebuilder;
-pub use rom_rebuilder::ROMRebuilder;
+pub use rom_rebuilder::{
+    CreateOptions,
+    DiscSize,
+    GapFill,
+    OrderStrategy,
+    PlannedFile,
+    RebuildPlan,
+    RebuildReport,
+    RelocatedFile,
+    ROMRebuilder,
+    SystemFileOverrides,
+    TreeMismatch,
+};
+
+pub mod salvage;
+
+pub mod search;
+
+pub mod source;
+
+pub mod stats;
+
+mod util;
 
 // 1048576 = 2^20 = 1MiB, there's no real good reason behind this choice
 pub const WRITE_CHUNK_SIZE: usize = 1048576; 
@@ -30,6 +127,13 @@ pub mod paths {
     pub const DOL_PATH: &'static str = "&&systemdata/Start.dol";
     pub const FST_PATH: &'static str = "&&systemdata/Game.toc";
     pub const HEADER_PATH: &'static str = "&&systemdata/ISO.hdr";
+    // Only there if some files had to be renamed when they were extracted
+    pub const NAME_MAP_PATH: &str = "&&systemdata/filename_map.txt";
+    // Only there if some files were decompressed when they were extracted
+    pub const COMPRESSED_LIST_PATH: &str = "&&systemdata/compressed_files.txt";
+    // Whether the ROM's gaps were filled with junk, checked when it was
+    // extracted
+    pub const JUNK_CHECK_PATH: &str = "&&systemdata/junk_check.txt";
 }
 
 pub fn extract_section(
@@ -53,11 +157,181 @@ pub fn extract_section(
     Ok(())
 }
 
+// Like `extract_section`, but if `iso` already has the whole section in its
+// buffer, it's written all at once. A memory-mapped image always does, and so
+// does a `BufReader` for files smaller than its buffer.
+pub fn extract_buffered_section(
+    mut iso: impl BufRead,
+    bytes: usize,
+    mut file: impl Write,
+) -> io::Result<()> {
+    let buf = iso.fill_buf()?;
+    if buf.len() >= bytes {
+        file.write_all(&buf[..bytes])?;
+        iso.consume(bytes);
+        return Ok(());
+    }
+    extract_section(iso, bytes, file)
+}
+
 pub fn align(n: u64, m: u64) -> u64 {
     let extra = if n % m == 0 { 0 } else { 1 };
     ((n / m) + extra) * m
 }
 
+// Like `read_exact`, but running out of data early isn't an error
+fn read_fully(mut reader: impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match reader.read(&mut buf[total..]) {
+            Ok(0) => break,
+            Ok(n) => total += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(total)
+}
+
+// What to do when the directory a ROM is being extracted to already exists
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExtractMode {
+    FailIfExists,
+    // Delete it and start over
+    Overwrite,
+    // Write into it, replacing files that are already there
+    Merge,
+    // Write into it, but leave the files that `ExistingCheck` says were
+    // already extracted alone, so an interrupted extraction can pick up where
+    // it left off
+    Resume(ExistingCheck),
+}
+
+impl ExtractMode {
+    // Gets `path` ready to extract a ROM to
+    pub fn prepare_dir(self, path: &Path) -> io::Result<()> {
+        let exists = path.symlink_metadata().is_ok();
+        match self {
+            // Not using `create_dir_all` here so it fails if `path` already exists.
+            ExtractMode::FailIfExists => create_dir(path).map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists", path.display()),
+                ),
+                _ => e,
+            }),
+            ExtractMode::Overwrite if exists => {
+                if path.is_dir() {
+                    remove_dir_all(path)
+                } else {
+                    remove_file(path)
+                }.map_err(|e| io::Error::new(e.kind(), format!("couldn't remove {}: {}", path.display(), e)))?;
+                create_dir(path)
+            },
+            ExtractMode::Merge | ExtractMode::Resume(_) if exists && !path.is_dir() => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists and isn't a directory", path.display()),
+            )),
+            _ => create_dir_all(path),
+        }
+    }
+
+    pub fn existing_check(self) -> Option<ExistingCheck> {
+        match self {
+            ExtractMode::Resume(check) => Some(check),
+            _ => None,
+        }
+    }
+}
+
+// How much of each end of a file `ExistingCheck::Ends` compares
+pub const RESUME_CHECK_SIZE: u64 = 4 * 1024;
+
+// How to tell that a file that's already there when resuming an extraction
+// doesn't need to be written again
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExistingCheck {
+    // It's the right size
+    Size,
+    // It's the right size, and its first and last `RESUME_CHECK_SIZE` bytes
+    // are the same as on the ROM, which catches files that were cut off by a
+    // crash after they were already as big as they should be
+    Ends,
+}
+
+impl ExistingCheck {
+    // Whether the file at `path` already has the `size` bytes at `offset`
+    // in `iso`
+    pub fn is_extracted(self, path: &Path, mut iso: impl Read + Seek, offset: u64, size: u64) -> io::Result<bool> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(_) => return Ok(false),
+        };
+        let m = file.metadata()?;
+        if !m.is_file() || m.len() != size { return Ok(false) }
+        if self == ExistingCheck::Size { return Ok(true) }
+
+        let len = size.min(RESUME_CHECK_SIZE);
+        let mut expected = vec![0; len as usize];
+        let mut actual = vec![0; len as usize];
+        for start in [0, size - len] {
+            iso.seek(SeekFrom::Start(offset + start))?;
+            let n = read_fully(&mut iso, &mut expected)?;
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(&mut actual)?;
+            // Anything past the end of a truncated image would've been
+            // filled in with zeros
+            expected[n..].fill(0);
+            if expected != actual { return Ok(false) }
+        }
+        Ok(true)
+    }
+}
+
+// How many files an extraction wrote, and how many were left alone since
+// they were already there
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ExtractCount {
+    pub written: usize,
+    pub skipped: usize,
+}
+
+impl ExtractCount {
+    pub fn total(self) -> usize {
+        self.written + self.skipped
+    }
+}
+
+impl AddAssign for ExtractCount {
+    fn add_assign(&mut self, other: ExtractCount) {
+        self.written += other.written;
+        self.skipped += other.skipped;
+    }
+}
+
+// What to do with the part of a file that's past the end of the image, on
+// images that were cut off partway through a file
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum TruncatedTail {
+    // Fill it in with zeros, so the file is the size the FST says
+    #[default]
+    ZeroFill,
+    // Leave it off, so the file is only what's on the image
+    Skip,
+}
+
+// The order files are extracted in
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ExtractOrder {
+    // The order they're in on the disc, so the image is read from start to
+    // end instead of jumping around. Directories are all made first.
+    #[default]
+    OffsetOrder,
+    // The order they're in in the FST, which is how it used to be done, and
+    // is the order the progress goes in
+    FstOrder,
+}
+
 #[derive(Copy, Clone)]
 pub enum NumberStyle {
     Hexadecimal,
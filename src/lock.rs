@@ -0,0 +1,198 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+// An advisory lock on a ROM or a directory, held for as long as this is alive.
+// The lock is a file next to the locked path, so it works for paths that
+// don't exist yet (like the output of a rebuild), and doesn't end up inside
+// an extracted ROM.
+//
+// The file's locked with flock on Unix and LockFileEx on Windows, so the lock
+// goes away with the process that held it, even if it's killed before it can
+// clean up after itself.
+pub struct Lock {
+    path: PathBuf,
+    // Kept open so the OS lock lasts as long as this does
+    file: File,
+}
+
+#[derive(Debug)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub command: String,
+    pub started_at: u64,
+}
+
+impl Lock {
+    // `command` is used to describe this operation to anything else that tries
+    // to take the lock. If `wait` is given, this keeps trying until that much
+    // time has passed, otherwise it fails right away if the lock is held.
+    pub fn acquire(
+        target: impl AsRef<Path>,
+        command: &str,
+        wait: Option<Duration>,
+    ) -> io::Result<Lock> {
+        let target = target.as_ref();
+        let path = lock_path(target);
+        let start = Instant::now();
+
+        loop {
+            if let Some(file) = try_lock(&path, command)? {
+                return Ok(Lock { path, file });
+            }
+            if wait.is_some_and(|w| start.elapsed() < w) {
+                sleep(RETRY_INTERVAL);
+                continue;
+            }
+            // The holder could still be writing its details, in which case
+            // all that's known is that there is one
+            let holder = match read_holder(&path)? {
+                Some(h) => format!("pid {}, `{}`, started {}", h.pid, h.command, describe_time(h.started_at)),
+                None => "it just started".to_string(),
+            };
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, format!(
+                "another gcmod operation ({}) holds the lock on {}",
+                holder,
+                target.display(),
+            )));
+        }
+    }
+
+    pub fn holder(target: impl AsRef<Path>) -> io::Result<Option<LockHolder>> {
+        read_holder(&lock_path(target.as_ref()))
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        release(&self.path, &self.file);
+    }
+}
+
+fn lock_path(target: &Path) -> PathBuf {
+    let name = target.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{}.gcmod-lock", name))
+}
+
+fn write_holder(mut file: &File, command: &str) -> io::Result<()> {
+    let started_at = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    writeln!(file, "{}\n{}\n{}", process::id(), started_at, command)?;
+    file.flush()
+}
+
+// Returns the locked file, or None if something else holds the lock
+#[cfg(unix)]
+fn try_lock(path: &Path, command: &str) -> io::Result<Option<File>> {
+    use std::fs::metadata;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            return if e.kind() == io::ErrorKind::WouldBlock { Ok(None) } else { Err(e) };
+        }
+        // If the last holder removed the file between it being opened here
+        // and locked, this has a lock on a file nobody else can see, so it
+        // has to start over with the one that's there now
+        let locked = file.metadata()?;
+        match metadata(path) {
+            Ok(m) if m.dev() == locked.dev() && m.ino() == locked.ino() => {},
+            Ok(_) => continue,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+        // Anything in it is from a holder that's gone
+        file.set_len(0)?;
+        write_holder(&file, command)?;
+        return Ok(Some(file));
+    }
+}
+
+// The file's removed before it's unlocked, so nothing can lock it after it's
+// gone
+#[cfg(unix)]
+fn release(path: &Path, _file: &File) {
+    let _ = std::fs::remove_file(path);
+}
+
+// Windows won't let a file be removed while something else has it open, so
+// the file stays, and the lock is taken on a byte far past its contents so
+// that it can still be read while it's held. Since the file's never removed,
+// there's no need to check that the locked file is still the one at the path.
+#[cfg(windows)]
+const LOCKED_BYTE: u32 = 0x8000_0000;
+
+#[cfg(windows)]
+fn overlapped() -> winapi::um::minwinbase::OVERLAPPED {
+    let mut overlapped: winapi::um::minwinbase::OVERLAPPED = unsafe { std::mem::zeroed() };
+    unsafe { overlapped.u.s_mut().OffsetHigh = LOCKED_BYTE; }
+    overlapped
+}
+
+#[cfg(windows)]
+fn try_lock(path: &Path, command: &str) -> io::Result<Option<File>> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::shared::winerror::ERROR_LOCK_VIOLATION;
+    use winapi::um::fileapi::LockFileEx;
+    use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY};
+
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+    let flags = LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY;
+    if unsafe { LockFileEx(file.as_raw_handle() as _, flags, 0, 1, 0, &mut overlapped()) } == 0 {
+        let e = io::Error::last_os_error();
+        return if e.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) { Ok(None) } else { Err(e) };
+    }
+    // Anything in it is from a holder that's gone
+    file.set_len(0)?;
+    write_holder(&file, command)?;
+    Ok(Some(file))
+}
+
+// The details are cleared before it's unlocked, so an unheld lock doesn't
+// look like it's held
+#[cfg(windows)]
+fn release(_path: &Path, file: &File) {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::UnlockFileEx;
+
+    let _ = file.set_len(0);
+    unsafe { UnlockFileEx(file.as_raw_handle() as _, 0, 1, 0, &mut overlapped()); }
+}
+
+fn read_holder(path: &Path) -> io::Result<Option<LockHolder>> {
+    let mut contents = String::new();
+    match File::open(path) {
+        Ok(mut f) => { f.read_to_string(&mut contents)?; },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut lines = contents.lines();
+    let pid = lines.next().and_then(|l| l.parse().ok());
+    let started_at = lines.next().and_then(|l| l.parse().ok());
+    let command = lines.next().map(|l| l.to_string());
+
+    Ok(match (pid, started_at, command) {
+        (Some(pid), Some(started_at), Some(command)) =>
+            Some(LockHolder { pid, command, started_at }),
+        _ => None,
+    })
+}
+
+fn describe_time(started_at: u64) -> String {
+    SystemTime::now().duration_since(UNIX_EPOCH).ok()
+        .and_then(|now| now.as_secs().checked_sub(started_at))
+        .map(|secs| format!("{}s ago", secs))
+        .unwrap_or_else(|| "at an unknown time".to_string())
+}
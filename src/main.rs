@@ -1,46 +1,179 @@
 #[macro_use]
 extern crate clap;
 extern crate gcmod;
+extern crate glob;
+#[macro_use]
+extern crate log;
+extern crate serde_json;
 extern crate tempfile;
 
-use std::fs::{remove_file, File};
-use std::io::BufReader;
-use std::path::Path;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, remove_file, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use clap::AppSettings;
+use clap::{AppSettings, ArgMatches};
+use glob::{MatchOptions, Pattern};
 
 use gcmod::{
+    analysis::{call_graph, generate_map},
     AppError,
     AppResult,
+    compression::{decompress_file, Compression, DecompressReport, DECOMPRESSED_SUFFIX},
     DEFAULT_ALIGNMENT,
+    ExistingCheck,
+    ExtractMode,
+    ExtractOptions,
+    ExtractOrder,
+    disassembler::{decode, CodeSection, Disassembler, SymbolMap},
+    filetype::FileKind,
     Game,
     format_u64,
     format_usize,
+    handles::HandleBudget,
+    hash::{hash_reader, read_manifest, Digest, HashAlgo, MANIFEST_ALGO},
+    hexdump::hexdump,
+    junk::{JunkSample, JunkSeed},
+    LayoutOptions,
+    lock::Lock,
     MIN_ALIGNMENT,
     NumberStyle,
     parse_as_u64,
+    progress::Progress,
     ROM_SIZE,
+    salvage::salvage,
+    search::SearchPattern,
+    SectionHashes,
     sections::{
         apploader::Apploader,
-        dol::DOLHeader,
-        fst::FST,
+        dol::{
+            AddressOffset,
+            DOLHeader,
+            patch::apply_gecko,
+            segment::{Segment, SegmentType},
+        },
+        fst::{
+            entry::FileEntry,
+            gcr::GcrFileList,
+            json::FstJson,
+            PathMatch,
+            TreeOptions,
+            TreeOrder,
+            FST,
+        },
         header::Header,
+        rel::RelHeader,
+        ExtractOutcome,
+        ResolvedSection,
         Section,
+        SectionType,
+        UniqueSectionType,
     },
+    source::{self, ImageSource, split::{self, SplitFileWriter}, wbfs::WbfsFile},
+    TruncatedTail,
+};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use gcmod::{
+    CreateOptions,
+    DiscSize,
+    OrderStrategy,
+    RebuildPlan,
+    RebuildReport,
+    RelocatedFile,
+    ROMRebuilder,
+    SystemFileOverrides,
 };
-use gcmod::ROMRebuilder;
+#[cfg(feature = "archive")]
+use gcmod::archive::ArchiveFormat;
+#[cfg(feature = "gcz")]
+use gcmod::source::gcz;
+#[cfg(feature = "patch")]
+use gcmod::patch;
+
+// Log messages go to stderr, so they stay out of anything written to stdout
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) { return }
+        match record.level() {
+            Level::Error => eprintln!("Error: {}", record.args()),
+            Level::Warn => eprintln!("Warning: {}", record.args()),
+            _ => eprintln!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
 
 fn main() -> AppResult {
     let app = clap_app!(app =>
+        (@arg quiet: -q --quiet global(true) conflicts_with[verbose]
+            "Only print warnings and errors, not progress or what's being done.")
+        (@arg verbose: -v --verbose ... global(true)
+            "Print more about what's being done. Pass it twice for even more.")
         (@subcommand extract =>
             (about: "Extract a ROM's contents to disk.")
+            (@arg rom_path: +required "The ROM, or - to read it from stdin. Reading from stdin only works for a whole ROM.")
+            (@arg output: +required "Where to extract the ROM to. If it ends in .tar or .zip, it's written to an archive instead of a directory.")
+            (@arg rom_section: -s --section +takes_value "Specify a single section to extract from the ROM, rather than everything. If the output is -, it's written to stdout.")
+            (@arg overwrite: --overwrite conflicts_with[merge rom_section]
+                "If the output directory already exists, delete it first.")
+            (@arg merge: --merge conflicts_with[rom_section]
+                "If the output directory already exists, extract into it, replacing any files that are already there.")
+            (@arg skip_existing: --("skip-existing") requires[merge]
+                "With --merge, leave files that already exist with the right size alone, to resume an extraction.")
+            (@arg resume: --resume conflicts_with[overwrite merge rom_section]
+                "Pick up where an interrupted extraction left off: extract into the output directory if it exists, and skip files that are already there with the right size.")
+            (@arg check_ends: --("check-ends") requires[resume]
+                "With --resume, only skip files whose first and last 4 KiB are the same as on the ROM too.")
+            (@arg system_only: --("system-only") conflicts_with[files_only rom_section]
+                "Only extract the system data (&&systemdata), not the files.")
+            (@arg files_only: --("files-only") conflicts_with[rom_section]
+                "Only extract the files, not the system data.")
+            (@arg only_ext: --("only-ext") +takes_value conflicts_with[skip_ext rom_section system_only]
+                "Only extract files with these extensions, separated by commas, like \"dsp,thp\".")
+            (@arg skip_ext: --("skip-ext") +takes_value conflicts_with[rom_section system_only]
+                "Don't extract files with these extensions, separated by commas.")
+            (@arg threads: -j --threads +takes_value conflicts_with[rom_section skip_existing resume system_only only_ext skip_ext]
+                "Extract this many files at once, or pass 0 to use one thread per CPU. This helps most on SSDs.")
+            (@arg max_open_files: --("max-open-files") +takes_value requires[threads]
+                "With --threads, keep at most this many files open at once. The default is the open file limit (ulimit -n) less some headroom.")
+            (@arg truncated: --truncated +takes_value possible_values(&["zero-fill", "skip"])
+                "What to do with files that are cut off by the end of the ROM: zero-fill pads them to their full size with zeros (the default), and skip only writes what's there.")
+            (@arg order: --order +takes_value possible_values(&["offset-order", "fst-order"])
+                "The order to extract the files in: offset-order goes in the order they're on the disc, which keeps the reads together (the default), and fst-order goes in the order they're listed in the FST.")
+            (@arg decompress: --decompress conflicts_with[resume skip_existing]
+                "Decompress Yaz0 and Yay0 files as they're extracted. They're listed in &&systemdata/compressed_files.txt, so rebuild --compress can compress them again.")
+            (@arg dec_suffix: --("dec-suffix") requires[decompress]
+                "With --decompress, add .dec to the names of decompressed files, like stage.szs.dec. Rebuilding still gives them their original names.")
+            (@arg wait: --wait +takes_value
+                "If another gcmod operation is using the same files, wait up to this many seconds for it to finish.")
+        )
+        (@subcommand extract_files =>
+            (name: "extract-files")
+            (about: "Extract every file on the ROM matching a glob pattern.")
             (@arg rom_path: +required)
-            (@arg output: +required)
-            (@arg rom_section: -s --section +takes_value "Specify a single section to extract from the ROM, rather than everything.")
+            (@arg pattern: -p --pattern +takes_value +required
+                "A glob pattern matched against the full paths on the ROM, like \"**/*.thp\" or \"stages/*/data\".")
+            (@arg output: -o --output +takes_value +required "The directory to extract the files to.")
+            (@arg decompress: --decompress "Decompress Yaz0 and Yay0 files as they're extracted.")
+            (@arg dec_suffix: --("dec-suffix") requires[decompress]
+                "With --decompress, add .dec to the names of decompressed files, like stage.szs.dec.")
         )
         (@subcommand info =>
             (about: "Display information about the ROM.")
-            (@arg rom_path: +required)
+            (@arg rom_path: +required "The ROM, or a directory it was extracted to.")
             (@arg hex_output: -h --hex "Displays numbers in hexadecimal.")
             (@arg type: -t --type +takes_value +case_insensitive
                 possible_value[header dol fst apploader layout]
@@ -51,6 +184,22 @@ fn main() -> AppResult {
             (@arg mem_addr: -m --("mem-addr") +takes_value
                 conflicts_with[type offset]
                 "Print information about the DOL segment that will be loaded into a given address in memory.")
+            (@arg json: --json conflicts_with[type offset mem_addr hex_output]
+                "Print everything about the ROM, including its full layout, as a JSON object.")
+            (@arg memory_map: --("memory-map") conflicts_with[type offset mem_addr json]
+                "Print where each of the DOL's segments and its BSS are loaded in memory.")
+            (@arg show_gaps: --("show-gaps") conflicts_with[offset mem_addr json memory_map]
+                "Print every section on the ROM and the unused space between them.")
+            (@arg min_gap: --("min-gap") +takes_value requires[show_gaps]
+                "Leave out gaps smaller than this many bytes. The default is 32, so alignment padding isn't listed.")
+            (@arg hashes: --hashes conflicts_with[type offset mem_addr memory_map show_gaps]
+                "Also print the CRC32 and SHA-1 of Start.dol and Apploader.ldr, which tell builds of a game apart. This reads the whole DOL.")
+            (@arg files: --files conflicts_with[offset mem_addr json memory_map show_gaps]
+                "List every file in the layout, instead of summing them up in one row.")
+            (@arg segments: --segments conflicts_with[offset mem_addr json memory_map show_gaps]
+                "List the DOL's segments in the layout.")
+            (@arg disc: --disc +takes_value conflicts_with[type offset mem_addr memory_map show_gaps]
+                "For a WBFS file, which disc in it to show, starting at 1. Without this, a WBFS file with more than one disc lists them.")
         )
         // TODO: add flags for searching and crap
         // Add more `ls` style flags (LS_COLORS!)
@@ -60,30 +209,419 @@ fn main() -> AppResult {
             (@arg rom_path: +required)
             (@arg dir: "The name or path of the directory in the ROM to list.")
             (@arg long: -l --long "List the files in an `ls -l`-style format.")
+            (@arg json: --json conflicts_with[long table]
+                "Print everything under the directory as a JSON array, with each file's offset and size.")
+            (@arg table: --table conflicts_with[long]
+                "Print everything under the directory as a table of offsets, sizes, and paths.")
+            (@arg order: --order +takes_value possible_value[fst offset]
+                "The order to list things in with --json or --table: the order they're in the FST (the default), or the order they're on the ROM.")
+            (@arg hex_output: --hex requires[table] "Display numbers in hexadecimal in the table.")
+            (@arg identify: --identify
+                "Read the start of each file to show what kind it is, like THP or Yaz0. This takes a seek per file.")
+        )
+        (@subcommand stats =>
+            (about: "Show what's taking up the space on a ROM: the biggest files, the space each file extension takes up, and how much is unused.")
+            (@arg rom_path: +required "The ROM, or a directory it was extracted to.")
+            (@arg json: --json conflicts_with[hex_output] "Print the stats as JSON.")
+            (@arg hex_output: -h --hex "Displays numbers in hexadecimal.")
+        )
+        (@subcommand rel_info =>
+            (name: "rel-info")
+            (about: "Print the header of a REL: its sections, and the modules it imports from.")
+            (@arg rom_path: +required "The ROM, a directory it was extracted to, or a REL.")
+            (@arg rel: "The path to the REL in the ROM, like rels/stage.rel. It's required unless rom_path is a REL.")
+            (@arg json: --json conflicts_with[hex_output] "Print the header as JSON.")
+            (@arg hex_output: -h --hex "Displays numbers in hexadecimal.")
+        )
+        (@subcommand tree =>
+            (about: "Print the files on the ROM as a tree.")
+            (@arg rom_path: +required)
+            (@arg dir: "The name or path of the directory in the ROM to start from.")
+            (@arg sizes: -s --sizes "Show the size of each file, and the total size of each directory.")
+            (@arg offsets: -o --offsets "Show the offset of each file.")
+            (@arg depth: -d --depth +takes_value "Only go this many directories deep.")
+            (@arg sort_name: --("sort-name") conflicts_with[sort_size]
+                "Sort each directory by name, instead of the order they're in the FST.")
+            (@arg sort_size: --("sort-size") "Sort each directory by size, largest first.")
+            (@arg identify: --identify
+                "Read the start of each file to show what kind it is, like THP or Yaz0. This takes a seek per file.")
         )
         (@subcommand rebuild =>
             (about: "Rebuilds a ROM.")
             (@arg root_path: +required)
             (@arg output: +required)
             (@arg no_rebuild_fst: --("no-rebuild-fst") "It this flag is passed, the existing file system table will be used, rather than creating a new one.")
+            (@arg preserve_offsets: --("preserve-offsets") conflicts_with[no_rebuild_fst]
+                "Keep every file at its offset from the existing file system table, only moving files that no longer fit.")
+            (@arg no_relocate: --("no-relocate") requires[preserve_offsets]
+                "With --preserve-offsets, fail instead of moving files that no longer fit.")
+            (@arg allow_missing: --("allow-missing")
+                "When the existing file system table is used, go on even if some of its files aren't in the root. With --no-rebuild-fst their space is left as zeros, and otherwise they're left out.")
+            (@arg allow_extra: --("allow-extra") requires[no_rebuild_fst]
+                "With --no-rebuild-fst, go on even if the root has files that aren't in the file system table. They're left off the ROM.")
+            (@arg incremental: --incremental
+                conflicts_with[no_rebuild_fst preserve_offsets force split_size file_order file_order_list disc_size exclude hash format]
+                "Update the output ROM in place, only rewriting the parts that changed. It has to have been made from this root, and every file has to fit where it was on it.")
             (@arg alignment: -a --alignment +takes_value
                 "Specifies the alignment in bytes for the files in the filesystem. The default is 32768 bytes (32KiB) and the minimum is 2 bytes.")
+            (@arg json_report: --("json-report") +takes_value
+                "Write a JSON report of everything that was written to the given path.")
+            (@arg hash: --hash +takes_value +multiple number_of_values(1)
+                possible_value[crc32 md5 sha1 sha256 xxh3]
+                "Hash the ROM with this algorithm as it's written, so it doesn't have to be read again. Can be passed multiple times.")
+            (@arg exclude: --exclude +takes_value +multiple number_of_values(1) conflicts_with[no_rebuild_fst]
+                "Leave files and directories matching a glob pattern, like \"movies/*.thp\", out of the ROM. Can be passed multiple times.")
+            (@arg filelist: --filelist +takes_value
+                conflicts_with[no_rebuild_fst preserve_offsets incremental exclude file_order file_order_list]
+                "Use the existing file system table, but put every file at the offset this list gives it, like one from export-filelist or GameCube Rebuilder. Every file has to be listed, with its size in the file system table.")
+            (@arg force: --force conflicts_with[preserve_offsets]
+                "With --no-rebuild-fst or --filelist, rebuild even if files in the file system table overlap each other or the system files.")
+            (@arg split_size: --("split-size") +takes_value conflicts_with[preserve_offsets]
+                "Split the ROM into parts of this many bytes, like game.part0.iso, game.part1.iso, and so on.")
+            (@arg max_open_files: --("max-open-files") +takes_value conflicts_with[preserve_offsets incremental]
+                "Keep at most this many files open at once, closing parts of a split ROM and opening them again if there are too many. The default is the open file limit (ulimit -n) less some headroom.")
+            (@arg format: --format +takes_value possible_value[iso gcz] conflicts_with[split_size]
+                "What to write the ROM as: a plain ISO, or a GCZ file, Dolphin's compressed format. The default is GCZ if the output ends in .gcz.")
+            (@arg file_order: --("file-order") +takes_value possible_values(&["fst", "original", "alphabetical"])
+                conflicts_with[no_rebuild_fst preserve_offsets file_order_list]
+                "The order to put the files' data in: the order they're in in the new file system table (the default), the order they were in on the original ROM, or sorted by path. This can affect load times on a real console.")
+            (@arg file_order_list: --("file-order-list") +takes_value conflicts_with[no_rebuild_fst preserve_offsets]
+                "Put the files listed in this file, one path per line, first, and the rest after them in their original order.")
+            (@arg dol: --dol +takes_value
+                "Use this DOL instead of &&systemdata/Start.dol, like one from a build directory. It isn't copied into the extracted ROM, and the ROM isn't written if it won't load.")
+            (@arg apploader: --apploader +takes_value "Use this apploader instead of &&systemdata/Apploader.ldr.")
+            (@arg header: --header +takes_value
+                "Use this header instead of &&systemdata/ISO.hdr. If the header has to change, the new one is based on this and written to &&systemdata/ISO.hdr.")
+            (@arg compress: --compress
+                "Compress the files that were decompressed by extract --decompress again, the way they were on the ROM.")
+            (@arg gap_fill: --("gap-fill") +takes_value possible_value[zeros junk] conflicts_with[incremental]
+                "What to fill the space between files with: zeros (the default), or the junk data Nintendo's discs have, so a ROM with the same layout can match the original exactly. If extract found the original ROM's gaps weren't junk, they're copied from it instead, if it's still there.")
+            (@arg no_stream_alignment: --("no-stream-alignment")
+                "Don't keep ADP and DSP files 32 KiB aligned when the header says the game streams audio. Streamed audio that isn't aligned might not play on a console.")
+            (@arg disc_size: --("disc-size") +takes_value
+                "How big the ROM is, in bytes. The default is the size of a retail disc. \"auto\" ends it right after the last file, which only works on emulators.")
+            (@arg dry_run: --("dry-run")
+                "Print where every file would go, and anything that would stop the rebuild, without writing anything. With --json-report, the plan is written there instead.")
+            (@arg wait: --wait +takes_value
+                "If another gcmod operation is using the same files, wait up to this many seconds for it to finish.")
+        )
+        (@subcommand create =>
+            (about: "Make a new ROM from a directory of files, a DOL, and an apploader, like for homebrew. It doesn't need a &&systemdata directory.")
+            (@arg root_path: +required)
+            (@arg output: +required)
+            (@arg title: -t --title +takes_value +required "The game's title.")
+            (@arg game_code: -g --("game-code") +takes_value +required
+                "The 4 character game code, like GALE. The last letter is the region.")
+            (@arg maker_code: -m --("maker-code") +takes_value
+                "The 2 character maker code. The default is 00.")
+            (@arg dol: --dol +takes_value "The DOL to boot. The default is &&systemdata/Start.dol in the root.")
+            (@arg apploader: --apploader +takes_value
+                "The apploader to use. The default is &&systemdata/Apploader.ldr in the root.")
+            (@arg disc_size: --("disc-size") +takes_value
+                "How big the ROM is, in bytes. The default is the size of a retail disc. \"auto\" ends it right after the last file, which only works on emulators.")
+            (@arg alignment: -a --alignment +takes_value
+                "Specifies the alignment in bytes for the files in the filesystem. The default is 32768 bytes (32KiB) and the minimum is 2 bytes.")
+            (@arg json_report: --("json-report") +takes_value
+                "Write a JSON report of everything that was written to the given path.")
+            (@arg dry_run: --("dry-run")
+                "Print where every file would go without writing anything. With --json-report, the plan is written there instead.")
+            (@arg wait: --wait +takes_value
+                "If another gcmod operation is using the same files, wait up to this many seconds for it to finish.")
+        )
+        (@subcommand salvage =>
+            (about: "Recover the files from a ROM whose file system table is damaged.")
+            (@arg rom_path: +required)
+            (@arg output: +required)
+            (@arg alignment: -a --alignment +takes_value
+                "The alignment in bytes that files on the ROM are expected to have. The default is 32768 bytes (32KiB).")
+            (@arg wait: --wait +takes_value
+                "If another gcmod operation is using the same files, wait up to this many seconds for it to finish.")
+        )
+        (@subcommand set_info =>
+            (name: "set-info")
+            (about: "Change a ROM's title, game ID, or version in place.")
+            (@arg path: +required "A ROM, or the &&systemdata/ISO.hdr from an extracted ROM.")
+            (@arg title: --title +takes_value)
+            (@arg game_code: --("game-code") +takes_value "Four uppercase letters or numbers.")
+            (@arg maker_code: --("maker-code") +takes_value "Two uppercase letters or numbers.")
+            (@arg disc_id: --("disc-id") +takes_value "Which disc this is, starting at 0.")
+            (@arg version: --version +takes_value)
+            (@arg wait: --wait +takes_value
+                "If another gcmod operation is using the same files, wait up to this many seconds for it to finish.")
+        )
+        (@subcommand inject =>
+            (about: "Replace a file on a ROM in place, without extracting and rebuilding it.")
+            (@arg rom_path: +required)
+            (@arg file_in_rom: +required "The path of the file on the ROM to replace.")
+            (@arg file: +required "The file to replace it with.")
+            (@arg wait: --wait +takes_value
+                "If another gcmod operation is using the same files, wait up to this many seconds for it to finish.")
+        )
+        (@subcommand replace_segment =>
+            (name: "replace-segment")
+            (about: "Replace a segment of the DOL, either in a DOL file or in place on a ROM.")
+            (@arg path: +required "A DOL file or a ROM.")
+            (@arg segment: +required "The segment to replace, like .text1 or .data0.")
+            (@arg file: +required "The new segment data.")
+            (@arg wait: --wait +takes_value
+                "If another gcmod operation is using the same files, wait up to this many seconds for it to finish.")
+        )
+        (@subcommand dol2elf =>
+            (about: "Convert the DOL from a ROM, or a standalone .dol file, to an ELF.")
+            (@arg input: +required "A ROM, or a file ending in .dol")
+            (@arg output: +required)
+        )
+        (@subcommand apply_codes =>
+            (name: "apply-codes")
+            (about: "Apply Gecko codes to a DOL, so they're always on.")
+            (@arg dol: +required "The DOL to patch, like &&systemdata/Start.dol in an extracted ROM.")
+            (@arg codes: +required "A text file with the codes, in the usual two-column format.")
+            (@arg output: -o --output +takes_value "Where to write the patched DOL. By default, the DOL is patched in place.")
+        )
+        (@subcommand elf2dol =>
+            (about: "Convert an ELF to a DOL, such as a Start.dol for rebuilding.")
+            (@arg input: +required)
+            (@arg output: +required)
+        )
+        (@subcommand segments =>
+            (about: "List the segments in the DOL, and extract them.")
+            (@arg path: +required "A ROM, a directory it was extracted to, or a DOL file.")
+            (@arg extract: --extract +takes_value "Write each segment to this directory, named like text0.bin.")
+            (@arg all: --all "List the slots in the DOL's header without a segment too.")
+            (@arg hex_output: -h --hex "Print numbers in hexadecimal.")
+        )
+        (@subcommand disasm =>
+            (about: "Disassemble the code in the DOL.")
+            (@arg rom_path: +required "The ROM, a directory it was extracted to, or a DOL like &&systemdata/Start.dol.")
+            (@arg section: -s --section +takes_value
+                "The section to disassemble: a DOL segment like .text1, apploader if it's a ROM, or a REL on the ROM like rels/stage.rel (its code sections) or rels/stage.rel:1 (just section 1). By default every text segment is disassembled.")
+            (@arg offset: --offset +takes_value conflicts_with[section] requires[length]
+                "Disassemble the code at this offset in the file instead, which doesn't have to be a ROM.")
+            (@arg length: --length +takes_value requires[offset] "How many bytes to disassemble from --offset.")
+            (@arg load_address: --("load-address") +takes_value
+                "The address the code at --offset, or the REL given with --section, is loaded to in memory. The default is 0.")
+            (@arg objdump: --objdump +takes_value
+                "Use this GNU objdump or llvm-objdump (it has to support PowerPC) instead of the built in disassembler. \"auto\" uses the first one found on the PATH.")
+            (@arg map: --map +takes_value
+                "A symbol map (from Dolphin or a CodeWarrior linker map) to name functions and branch targets with.")
+            (@arg labels: --labels "Add labels for branch targets, and use them in the branches.")
+            (@arg all: --all conflicts_with[section] conflicts_with[offset]
+                "List the whole DOL: every text segment with function and branch labels, then the data segments, with anything that looks like a string pointed out. Without --map, functions are found the same way as gen-map does.")
+            (@arg output: -o --output +takes_value "Write the disassembly to a file instead of printing it.")
+        )
+        (@subcommand callgraph =>
+            (about: "Find the functions in the DOL and which ones call each other.")
+            (@arg rom_path: +required)
+            (@arg output: -o --output +takes_value "Write the call graph to this file in Graphviz's DOT format.")
+        )
+        (@subcommand gen_map =>
+            (name: "gen-map")
+            (about: "Make a symbol map of the functions in the DOL that Dolphin can load.")
+            (@arg rom_path: +required)
+            (@arg output: -o --output +takes_value "Where to save the map. Defaults to the game's ID, like GALE01.map, which is what Dolphin looks for in its Maps folder.")
+        )
+        (@subcommand check =>
+            (about: "Check a ROM's FST for files that are out of bounds or on top of other data, and directories that don't fit together, and its DOL for segments that won't load.")
+            (@arg rom_path: +required)
+        )
+        (@subcommand fst_export =>
+            (name: "fst-export")
+            (about: "Write a ROM's FST as a JSON tree of its directories and files, which fst-import can turn back into a Game.toc.")
+            (@arg rom_path: +required)
+            (@arg output: +required "Where to write the JSON.")
+        )
+        (@subcommand fst_import =>
+            (name: "fst-import")
+            (about: "Make a Game.toc from JSON written by fst-export. Entries are numbered in the order they're listed, and names without a name_offset go at the end of the string table.")
+            (@arg json_path: +required)
+            (@arg output: -o --output +takes_value +required "Where to write the Game.toc.")
+        )
+        (@subcommand export_filelist =>
+            (name: "export-filelist")
+            (about: "Write the path, offset, and size of every file on a ROM, separated by tabs, like the file lists GameCube Rebuilder exports. rebuild --filelist can put the files back where a list says.")
+            (@arg rom_path: +required)
+            (@arg output: -o --output +takes_value +required "Where to write the list.")
+        )
+        (@subcommand locate_address =>
+            (name: "locate-address")
+            (about: "Find where the data at an address in memory comes from, like the address of a crash.")
+            (@arg path: +required "A ROM or a DOL.")
+            (@arg address: +required "The address in memory, like 0x80045abc.")
+            (@arg hex_output: -h --hex "Print offsets in hexadecimal.")
+        )
+        (@subcommand at_offset =>
+            (name: "at-offset")
+            (about: "Find which section is at an offset on the ROM, like one from a hex editor, and optionally extract it.")
+            (@arg rom_path: +required)
+            (@arg offset: +required "The offset on the ROM, like 0x2453800.")
+            (@arg extract: -e --extract +takes_value "Write the whole section to this file.")
+            (@arg hex_output: -h --hex "Print offsets in hexadecimal.")
+        )
+        (@subcommand dump =>
+            (about: "Print a hex dump of a section of the ROM, or of some bytes at an offset on it.")
+            (@arg rom_path: +required)
+            (@arg section: -s --section +takes_value
+                "The section to dump, like &&systemdata/ISO.hdr, .text0, or a file's path.")
+            (@arg offset: -o --offset +takes_value required_unless[section]
+                "Where to start. It's from the start of the section with --section, and the start of the ROM otherwise.")
+            (@arg length: -l --length +takes_value
+                "How many bytes to dump. The default is the rest of the section, or 256 bytes without --section.")
+            (@arg hex_output: -h --hex "Print offsets in hexadecimal.")
+        )
+        (@subcommand search =>
+            (about: "Search a ROM for a string or some bytes, and find which files they're in.")
+            (@arg rom_path: +required)
+            (@arg string: -s --string +takes_value conflicts_with[hex] required_unless[hex]
+                "The text to look for.")
+            (@arg hex: -x --hex +takes_value
+                "The bytes to look for in hex, like \"3C 60 80 ?? 38 63\". ?? matches any byte.")
+            (@arg limit: -n --limit +takes_value
+                "Print at most this many hits. The default is 100, and 0 prints all of them.")
+            (@arg skip: --skip +takes_value "Leave out this many hits first, to see the ones after --limit.")
+            (@arg hex_output: -h --("hex-output") "Print offsets in hexadecimal.")
+        )
+        (@subcommand verify =>
+            (about: "Hash a whole ROM with CRC32, MD5, and SHA-1, to check it against known hashes like Redump's.")
+            (@arg rom_path: +required "The ROM, or - to read it from stdin.")
+            (@arg expect_crc32: --("expect-crc32") +takes_value "Fail if the CRC32 isn't this.")
+            (@arg expect_md5: --("expect-md5") +takes_value "Fail if the MD5 isn't this.")
+            (@arg expect_sha1: --("expect-sha1") +takes_value "Fail if the SHA-1 isn't this.")
+            (@arg algo: --algo +takes_value +multiple number_of_values(1)
+                possible_value[crc32 md5 sha1 sha256 xxh3]
+                "Hash the ROM with this algorithm too. Can be passed multiple times.")
+            (@arg manifest: --manifest +takes_value
+                conflicts_with[expect_crc32 expect_md5 expect_sha1 algo]
+                "Check each file on the ROM against a manifest made by the manifest command, \
+                instead of hashing the whole ROM. Each file's hashed with whichever algorithm \
+                the manifest used for it.")
+            (@arg list: -l --list requires[manifest] "List every file that doesn't match the manifest.")
+        )
+        (@subcommand diff =>
+            (about: "Compare two ROMs to see which files are different.")
+            (@arg rom_a: +required)
+            (@arg rom_b: +required)
+            (@arg json: --json "Print the differences as JSON.")
+            (@arg quick: --quick "Stop reading a file at its first difference instead of counting the bytes that are different.")
+        )
+        (@subcommand make_patch =>
+            (name: "make-patch")
+            (about: "Make a BPS patch that turns one ROM into another.")
+            (@arg original: +required "The unmodified ROM.")
+            (@arg modified: +required "The modified ROM.")
+            (@arg output: +required "Where to write the patch.")
+        )
+        (@subcommand apply_patch =>
+            (name: "apply-patch")
+            (about: "Apply a BPS or IPS patch to a ROM.")
+            (@arg rom_path: +required)
+            (@arg patch: +required)
+            (@arg output: -o --output +takes_value +required "Where to write the patched ROM.")
+            (@arg force: -f --force "Apply the patch even if the ROM isn't the one it was made for.")
+        )
+        (@subcommand verify_dir =>
+            (name: "verify-dir")
+            (about: "Compare an extracted ROM to the original to see which files were changed.")
+            (@arg rom_path: +required "The original ROM.")
+            (@arg dir: +required "The directory the ROM was extracted to.")
+            (@arg list: -l --list "List every file that was changed, not just how many there were.")
+        )
+        (@subcommand manifest =>
+            (about: "Hash every file on a ROM without extracting it. With md5, sha1, or sha256, it's \
+                written the way sha1sum and the others write it, so they can check it. With crc32 or \
+                xxh3, each digest starts with its algorithm, like \"xxh3:\", which verify --manifest \
+                reads, along with manifests that use a mix of them.")
+            (@arg rom_path: +required)
+            (@arg output: -o --output +takes_value "Write the manifest to this file instead of printing it.")
+            (@arg algo: --algo +takes_value possible_value[crc32 md5 sha1 sha256 xxh3]
+                "The hash algorithm to use. The default is sha1. xxh3 is much faster than the others, \
+                but is only meant for finding changes.")
+            (@arg prefix: --prefix "Start every digest with its algorithm, even with md5, sha1, or sha256.")
+        )
+        (@subcommand junk =>
+            (about: "Work with the junk data that fills the space between files on a disc.")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand sample =>
+                (about: "Print the junk that should be at an offset on a ROM next to what's actually there, to check if it used the standard junk.")
+                (@arg rom_path: +required)
+                (@arg offset: --offset +takes_value +required "Where to start.")
+                (@arg length: --len +takes_value "How many bytes to compare. The default is 256.")
+            )
+        )
+        (@subcommand hash =>
+            (about: "Hash a file, such as a ROM or a file from an extracted ROM.")
+            (@arg path: +required)
+            (@arg algo: --algo +takes_value +multiple number_of_values(1)
+                possible_value[crc32 md5 sha1 sha256 xxh3]
+                "The hash algorithm to use. Can be passed multiple times. The default is sha1.")
         )
     ).setting(AppSettings::SubcommandRequired);
 
-    match app.get_matches().subcommand() {
+    let matches = app.get_matches();
+    let level = match (matches.is_present("quiet"), matches.occurrences_of("verbose")) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+
+    match matches.subcommand() {
+        ("extract", Some(cmd)) if cmd.value_of("rom_path") == Some("-") =>
+            extract_iso_streaming(
+                cmd.value_of("output").unwrap(),
+                extract_options(cmd),
+                STREAMING_UNSUPPORTED.iter().find(|&&(arg, _)| cmd.is_present(arg)).map(|&(_, flag)| flag),
+                cmd.value_of("wait"),
+            ),
+        ("extract", Some(cmd)) if cmd.is_present("threads") =>
+            extract_iso_parallel(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("output").unwrap(),
+                extract_options(cmd),
+                (cmd.value_of("threads").unwrap(), cmd.value_of("max_open_files")),
+                cmd.value_of("wait"),
+            ),
         ("extract", Some(cmd)) => 
             extract_iso(
                 cmd.value_of("rom_path").unwrap(),
                 cmd.value_of("output").unwrap(),
                 cmd.value_of("rom_section"),
+                extract_options(cmd),
+                cmd.value_of("wait"),
+            ),
+        ("extract-files", Some(cmd)) =>
+            extract_matching_files(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("pattern").unwrap(),
+                cmd.value_of("output").unwrap(),
+                decompress_suffix(cmd),
             ),
         ("info", Some(cmd)) => 
             get_info(
-                cmd.value_of("rom_path").unwrap(),
-                cmd.value_of("type"),
+                (cmd.value_of("rom_path").unwrap(), cmd.value_of("disc")),
+                if cmd.is_present("memory_map") {
+                    Some("memory-map")
+                } else if cmd.is_present("show_gaps") {
+                    Some("layout")
+                } else {
+                    cmd.value_of("type")
+                },
                 cmd.value_of("offset"),
                 cmd.value_of("mem_addr"),
+                (cmd.is_present("json"), cmd.is_present("hashes")),
+                (
+                    if cmd.is_present("show_gaps") {
+                        Some(cmd.value_of("min_gap").unwrap_or("32"))
+                    } else {
+                        None
+                    },
+                    LayoutOptions {
+                        files: cmd.is_present("files"),
+                        segments: cmd.is_present("segments"),
+                    },
+                ),
                 if cmd.is_present("hex_output") {
                     NumberStyle::Hexadecimal
                 } else {
@@ -91,95 +629,1851 @@ fn main() -> AppResult {
                 },
             ),
         ("ls", Some(cmd)) =>
-            ls_files(
+            if cmd.is_present("json") || cmd.is_present("table") {
+                list_files(
+                    cmd.value_of("rom_path").unwrap(),
+                    cmd.value_of("dir"),
+                    cmd.value_of("order") == Some("offset"),
+                    if cmd.is_present("json") {
+                        None
+                    } else if cmd.is_present("hex_output") {
+                        Some(NumberStyle::Hexadecimal)
+                    } else {
+                        Some(NumberStyle::Decimal)
+                    },
+                    cmd.is_present("identify"),
+                )
+            } else {
+                ls_files(
+                    cmd.value_of("rom_path").unwrap(),
+                    cmd.value_of("dir"),
+                    cmd.is_present("long"),
+                    cmd.is_present("identify"),
+                )
+            },
+        ("tree", Some(cmd)) =>
+            print_tree(
                 cmd.value_of("rom_path").unwrap(),
                 cmd.value_of("dir"),
-                cmd.is_present("long"),
+                TreeOptions {
+                    sizes: cmd.is_present("sizes"),
+                    offsets: cmd.is_present("offsets"),
+                    depth: cmd.value_of("depth").map(|d| parse_as_u64(d)
+                        .map(|d| d as usize)
+                        .map_err(|_| AppError::new("Invalid depth. Must be an integer."))
+                    ).transpose()?,
+                    order: if cmd.is_present("sort_name") {
+                        TreeOrder::Name
+                    } else if cmd.is_present("sort_size") {
+                        TreeOrder::Size
+                    } else {
+                        TreeOrder::Fst
+                    },
+                },
+                cmd.is_present("identify"),
             ),
-        ("rebuild", Some(cmd)) =>
-            rebuild_iso(
+        ("rebuild", Some(cmd)) => {
+            let gcz = match cmd.value_of("format") {
+                Some(format) => format == "gcz",
+                None => source::is_gcz(Path::new(cmd.value_of("output").unwrap())),
+            };
+            if cmd.is_present("incremental") {
+                rebuild_iso_incremental(
+                    cmd.value_of("root_path").unwrap(),
+                    cmd.value_of("output").unwrap(),
+                    (cmd.value_of("alignment"), cmd.is_present("dry_run")),
+                    system_file_overrides(cmd),
+                    cmd.value_of("json_report"),
+                    cmd.value_of("wait"),
+                )
+            } else if cmd.is_present("preserve_offsets") {
+                rebuild_iso_preserving_offsets(
+                    cmd.value_of("root_path").unwrap(),
+                    (cmd.value_of("output").unwrap(), gcz),
+                    (cmd.value_of("alignment"), cmd.value_of("disc_size")),
+                    (!cmd.is_present("no_relocate"), cmd.is_present("dry_run")),
+                    (cmd.values_of("exclude").map(|p| p.collect()).unwrap_or_default(), system_file_overrides(cmd)),
+                    (
+                        cmd.value_of("json_report"),
+                        cmd.values_of("hash").map(|a| a.collect()).unwrap_or_default(),
+                        cmd.value_of("gap_fill") == Some("junk"),
+                    ),
+                    cmd.value_of("wait"),
+                )
+            } else {
+                rebuild_iso(
+                    cmd.value_of("root_path").unwrap(),
+                    (cmd.value_of("output").unwrap(), gcz, cmd.value_of("split_size"), cmd.value_of("disc_size")),
+                    (cmd.value_of("alignment"), cmd.value_of("file_order"), cmd.value_of("file_order_list"), cmd.value_of("filelist")),
+                    (!cmd.is_present("no_rebuild_fst"), cmd.is_present("force"), cmd.is_present("dry_run")),
+                    (cmd.values_of("exclude").map(|p| p.collect()).unwrap_or_default(), system_file_overrides(cmd)),
+                    (
+                        cmd.value_of("json_report"),
+                        cmd.values_of("hash").map(|a| a.collect()).unwrap_or_default(),
+                        cmd.value_of("gap_fill") == Some("junk"),
+                    ),
+                    (cmd.value_of("wait"), cmd.value_of("max_open_files")),
+                )
+            }
+        },
+        ("create", Some(cmd)) =>
+            create_iso(
                 cmd.value_of("root_path").unwrap(),
                 cmd.value_of("output").unwrap(),
+                CreateOptions {
+                    title: cmd.value_of("title").unwrap(),
+                    game_code: cmd.value_of("game_code").unwrap(),
+                    maker_code: cmd.value_of("maker_code").unwrap_or("00"),
+                    dol: cmd.value_of("dol").map(Path::new),
+                    apploader: cmd.value_of("apploader").map(Path::new),
+                },
+                (cmd.value_of("alignment"), cmd.value_of("disc_size"), cmd.is_present("dry_run")),
+                cmd.value_of("json_report"),
+                cmd.value_of("wait"),
+            ),
+        ("salvage", Some(cmd)) =>
+            salvage_iso(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("output").unwrap(),
                 cmd.value_of("alignment"),
-                !cmd.is_present("no_rebuild_fst"),
+                cmd.value_of("wait"),
+            ),
+        ("set-info", Some(cmd)) =>
+            set_info(
+                cmd.value_of("path").unwrap(),
+                HeaderChanges {
+                    title: cmd.value_of("title"),
+                    game_code: cmd.value_of("game_code"),
+                    maker_code: cmd.value_of("maker_code"),
+                    disc_id: cmd.value_of("disc_id"),
+                    version: cmd.value_of("version"),
+                },
+                cmd.value_of("wait"),
+            ),
+        ("inject", Some(cmd)) =>
+            inject_file(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("file_in_rom").unwrap(),
+                cmd.value_of("file").unwrap(),
+                cmd.value_of("wait"),
+            ),
+        ("replace-segment", Some(cmd)) =>
+            replace_segment(
+                cmd.value_of("path").unwrap(),
+                cmd.value_of("segment").unwrap(),
+                cmd.value_of("file").unwrap(),
+                cmd.value_of("wait"),
+            ),
+        ("dol2elf", Some(cmd)) =>
+            dol_to_elf(
+                cmd.value_of("input").unwrap(),
+                cmd.value_of("output").unwrap(),
+            ),
+        ("apply-codes", Some(cmd)) =>
+            apply_codes(
+                cmd.value_of("dol").unwrap(),
+                cmd.value_of("codes").unwrap(),
+                cmd.value_of("output"),
+            ),
+        ("elf2dol", Some(cmd)) =>
+            elf_to_dol(
+                cmd.value_of("input").unwrap(),
+                cmd.value_of("output").unwrap(),
+            ),
+        ("segments", Some(cmd)) =>
+            list_segments(
+                cmd.value_of("path").unwrap(),
+                cmd.value_of("extract"),
+                cmd.is_present("all"),
+                if cmd.is_present("hex_output") {
+                    NumberStyle::Hexadecimal
+                } else {
+                    NumberStyle::Decimal
+                },
+            ),
+        ("disasm", Some(cmd)) => {
+            let load_address = cmd.value_of("load_address").map(|a| parse_as_u64(a)
+                .map_err(|_| AppError::new(format!("Invalid load address: {}", a)))
+            ).transpose()?;
+            disassemble(
+                cmd.value_of("rom_path").unwrap(),
+                (cmd.value_of("section"), load_address),
+                parse_disasm_range(cmd.value_of("offset"), cmd.value_of("length"), load_address)?,
+                cmd.value_of("objdump"),
+                cmd.value_of("map"),
+                (cmd.is_present("labels"), cmd.is_present("all")),
+                cmd.value_of("output"),
+            )
+        },
+        ("callgraph", Some(cmd)) =>
+            write_call_graph(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("output"),
+            ),
+        ("gen-map", Some(cmd)) =>
+            write_symbol_map(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("output"),
+            ),
+        ("stats", Some(cmd)) =>
+            print_stats(
+                cmd.value_of("rom_path").unwrap(),
+                if cmd.is_present("json") {
+                    None
+                } else if cmd.is_present("hex_output") {
+                    Some(NumberStyle::Hexadecimal)
+                } else {
+                    Some(NumberStyle::Decimal)
+                },
+            ),
+        ("rel-info", Some(cmd)) =>
+            print_rel_info(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("rel"),
+                if cmd.is_present("json") {
+                    None
+                } else if cmd.is_present("hex_output") {
+                    Some(NumberStyle::Hexadecimal)
+                } else {
+                    Some(NumberStyle::Decimal)
+                },
+            ),
+        ("check", Some(cmd)) => check_rom(cmd.value_of("rom_path").unwrap()),
+        ("fst-export", Some(cmd)) =>
+            export_fst(cmd.value_of("rom_path").unwrap(), cmd.value_of("output").unwrap()),
+        ("fst-import", Some(cmd)) =>
+            import_fst(cmd.value_of("json_path").unwrap(), cmd.value_of("output").unwrap()),
+        ("export-filelist", Some(cmd)) =>
+            export_filelist(cmd.value_of("rom_path").unwrap(), cmd.value_of("output").unwrap()),
+        ("locate-address", Some(cmd)) =>
+            locate_address(
+                cmd.value_of("path").unwrap(),
+                cmd.value_of("address").unwrap(),
+                if cmd.is_present("hex_output") {
+                    NumberStyle::Hexadecimal
+                } else {
+                    NumberStyle::Decimal
+                },
+            ),
+        ("at-offset", Some(cmd)) =>
+            at_offset(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("offset").unwrap(),
+                cmd.value_of("extract"),
+                if cmd.is_present("hex_output") {
+                    NumberStyle::Hexadecimal
+                } else {
+                    NumberStyle::Decimal
+                },
+            ),
+        ("dump", Some(cmd)) =>
+            dump(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("section"),
+                (cmd.value_of("offset"), cmd.value_of("length")),
+                if cmd.is_present("hex_output") {
+                    NumberStyle::Hexadecimal
+                } else {
+                    NumberStyle::Decimal
+                },
+            ),
+        ("search", Some(cmd)) =>
+            search(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("string"),
+                cmd.value_of("hex"),
+                (cmd.value_of("limit"), cmd.value_of("skip")),
+                if cmd.is_present("hex_output") {
+                    NumberStyle::Hexadecimal
+                } else {
+                    NumberStyle::Decimal
+                },
+            ),
+        ("verify", Some(cmd)) => match cmd.value_of("manifest") {
+            Some(manifest) => verify_manifest(
+                cmd.value_of("rom_path").unwrap(),
+                manifest,
+                cmd.is_present("list"),
+            ),
+            None => verify_image(
+                cmd.value_of("rom_path").unwrap(),
+                &[
+                    (HashAlgo::Crc32, cmd.value_of("expect_crc32")),
+                    (HashAlgo::Md5, cmd.value_of("expect_md5")),
+                    (HashAlgo::Sha1, cmd.value_of("expect_sha1")),
+                ],
+                cmd.values_of("algo").map(|a| a.collect()).unwrap_or_default(),
+            ),
+        },
+        ("diff", Some(cmd)) =>
+            diff_roms(
+                cmd.value_of("rom_a").unwrap(),
+                cmd.value_of("rom_b").unwrap(),
+                cmd.is_present("json"),
+                cmd.is_present("quick"),
+            ),
+        ("make-patch", Some(cmd)) =>
+            make_patch(
+                cmd.value_of("original").unwrap(),
+                cmd.value_of("modified").unwrap(),
+                cmd.value_of("output").unwrap(),
+            ),
+        ("apply-patch", Some(cmd)) =>
+            apply_patch(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("patch").unwrap(),
+                cmd.value_of("output").unwrap(),
+                cmd.is_present("force"),
+            ),
+        ("verify-dir", Some(cmd)) =>
+            verify_directory(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("dir").unwrap(),
+                cmd.is_present("list"),
+            ),
+        ("manifest", Some(cmd)) =>
+            write_manifest(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("output"),
+                cmd.value_of("algo"),
+                cmd.is_present("prefix"),
+            ),
+        ("junk", Some(cmd)) => match cmd.subcommand() {
+            ("sample", Some(cmd)) =>
+                sample_junk(
+                    cmd.value_of("rom_path").unwrap(),
+                    cmd.value_of("offset").unwrap(),
+                    cmd.value_of("length"),
+                ),
+            _ => unreachable!(),
+        },
+        ("hash", Some(cmd)) =>
+            hash_file(
+                cmd.value_of("path").unwrap(),
+                cmd.values_of("algo").map(|a| a.collect()).unwrap_or_default(),
             ),
         _ => unreachable!(),
     }
 }
 
-fn extract_iso(
-    input: impl AsRef<Path>,
-    output: impl AsRef<Path>,
-    file_in_iso: Option<impl AsRef<Path>>,
-) -> AppResult {
-    let output = output.as_ref();
+// The extract command's options. The arguments that can't be used together
+// are already kept apart by clap, so `build` shouldn't fail, but it's checked
+// when the options are used anyway.
+fn extract_options(cmd: &ArgMatches) -> ExtractOptions {
+    let mut options = ExtractOptions::new()
+        .overwrite(cmd.is_present("overwrite"))
+        .merge(cmd.is_present("merge"))
+        .resume(if cmd.is_present("resume") || cmd.is_present("skip_existing") {
+            Some(if cmd.is_present("check_ends") { ExistingCheck::Ends } else { ExistingCheck::Size })
+        } else {
+            None
+        })
+        .system_data(!cmd.is_present("files_only"))
+        .files(!cmd.is_present("system_only"))
+        .truncated(if cmd.value_of("truncated") == Some("skip") {
+            TruncatedTail::Skip
+        } else {
+            TruncatedTail::ZeroFill
+        })
+        .order(if cmd.value_of("order") == Some("fst-order") {
+            ExtractOrder::FstOrder
+        } else {
+            ExtractOrder::OffsetOrder
+        })
+        .decompress(cmd.is_present("decompress"));
+    if cmd.is_present("dec_suffix") {
+        options = options.decompressed_suffix(DECOMPRESSED_SUFFIX);
+    }
+
+    // (a list of extensions, whether they're the only ones to extract or the
+    // ones to skip)
+    let extensions = cmd.value_of("only_ext").map(|e| (e, true))
+        .or_else(|| cmd.value_of("skip_ext").map(|e| (e, false)));
+    if let Some((list, only)) = extensions {
+        let list = list.split(',')
+            .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+            .collect::<Vec<_>>();
+        options = options.filter(move |path: &Path, _: &FileEntry| {
+            let listed = path.extension()
+                .is_some_and(|e| list.contains(&e.to_string_lossy().to_lowercase()));
+            listed == only
+        });
+    }
+    options
+}
+
+fn extract_iso(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    file_in_iso: Option<impl AsRef<Path>>,
+    options: ExtractOptions,
+    wait: Option<&str>,
+) -> AppResult {
+    let options = options.build()?;
+    let mode = options.mode();
+    let decompress = options.decompression();
+    let output = output.as_ref();
+    if let Some(file) = file_in_iso {
+        if output == Path::new("-") {
+            return extract_section_to_stdout(input.as_ref(), file.as_ref(), decompress.is_some());
+        }
+        let _lock = lock_paths(&[output], wait)?;
+        extract_section(input.as_ref(), file.as_ref(), output)?;
+        if let Some(suffix) = decompress {
+            decompress_file(output, suffix)
+                .map_err(|e| AppError::new(format!("Couldn't decompress {}: {}", output.display(), e)))?;
+        }
+        return Ok(());
+    }
+
+    let _lock = lock_paths(&[output], wait)?;
+
+    if mode == ExtractMode::FailIfExists && output.exists() {
+        return Err(AppError::new(format!("Error: {} already exists.", output.display())));
+    }
+
+    #[cfg(feature = "archive")]
+    {
+        if let Some(format) = ArchiveFormat::from_path(output) {
+            if options.is_filtered() {
+                return Err(AppError::new("Archives always have everything on the ROM, so they can't be filtered."));
+            }
+            if options.truncated_tail() == TruncatedTail::Skip {
+                return Err(AppError::new("Archives need every file's full size, so --truncated skip can't be used with them."));
+            }
+            if decompress.is_some() {
+                return Err(AppError::new("--decompress can't be used when extracting to an archive."));
+            }
+            return extract_iso_to_archive(input.as_ref(), output, mode, format);
+        }
+    }
+
+    let (mut game, mut iso) = try_to_open_game(input.as_ref(), 0)?;
+    warn_about_overlaps(&game);
+    let report = game.extract_with(&mut iso, output, &options)
+        .map_err(|e| AppError::new(format!("Failed to write files: {}", e)))?;
+    if options.includes_system_data() {
+        save_junk_check(&game, &mut iso, input.as_ref(), output);
+    }
+    warn_about_truncated_files(&game, options.truncated_tail());
+    if let Some(ref decompressed) = report.decompressed {
+        warn_about_decompression(decompressed);
+    }
+    if let ExtractMode::Resume(_) = mode {
+        info!(
+            "Skipped {} files that were already there, and wrote {}.",
+            with_commas(report.count.skipped as u64), with_commas(report.count.written as u64),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(feature = "archive")]
+fn extract_iso_to_archive(input: &Path, output: &Path, mode: ExtractMode, format: ArchiveFormat) -> AppResult {
+    if let ExtractMode::Merge | ExtractMode::Resume(_) = mode {
+        return Err(AppError::new("--merge and --resume can't be used when extracting to an archive."));
+    }
+    let (mut game, mut iso) = try_to_open_game(input, 0)?;
+    warn_about_overlaps(&game);
+
+    let result = File::create(output)
+        .and_then(|f| game.extract_to_archive(&mut iso, io::BufWriter::new(f), format));
+    if result.is_err() {
+        let _ = remove_file(output);
+    } else {
+        warn_about_truncated_files(&game, TruncatedTail::ZeroFill);
+    }
+    result.map_err(|e| AppError::new(format!("Failed to write {}: {}", output.display(), e)))
+}
+
+// The extract arguments that need to seek, so they don't work with stdin,
+// and their flags
+const STREAMING_UNSUPPORTED: [(&str, &str); 8] = [
+    ("rom_section", "--section"),
+    ("skip_existing", "--skip-existing"),
+    ("resume", "--resume"),
+    ("system_only", "--system-only"),
+    ("files_only", "--files-only"),
+    ("only_ext", "--only-ext"),
+    ("skip_ext", "--skip-ext"),
+    ("threads", "--threads"),
+];
+
+fn extract_iso_streaming(
+    output: impl AsRef<Path>,
+    options: ExtractOptions,
+    unsupported_flag: Option<&str>,
+    wait: Option<&str>,
+) -> AppResult {
+    if let Some(flag) = unsupported_flag {
+        return Err(AppError::new(format!("{} can't be used when reading the ROM from stdin.", flag)));
+    }
+    let options = options.build()?;
+    if options.extract_order() == ExtractOrder::FstOrder {
+        return Err(AppError::new("Files can only be extracted in the order they're on the disc when reading the ROM from stdin."));
+    }
+    let (mode, tail) = (options.mode(), options.truncated_tail());
+    let output = output.as_ref();
+    let _lock = lock_paths(&[output], wait)?;
+
+    let stdin = io::stdin();
+    let mut game = Game::extract_streaming(stdin.lock(), output, mode)
+        .and_then(|game| handle_truncated_files(&game, output, tail).map(|_| game))
+        .map_err(|e| AppError::new(format!("Failed to write files: {}", e)))?;
+    match options.decompression() {
+        Some(suffix) => decompress_extracted(&mut game, output, suffix),
+        None => Ok(()),
+    }
+}
+
+fn extract_iso_parallel(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: ExtractOptions,
+    (threads, max_open_files): (&str, Option<&str>),
+    wait: Option<&str>,
+) -> AppResult {
+    let options = options.build()?;
+    if options.extract_order() == ExtractOrder::FstOrder {
+        return Err(AppError::new("Files are always extracted in the order they're on the disc with --threads."));
+    }
+    let (mode, tail) = (options.mode(), options.truncated_tail());
+    let threads = parse_as_u64(threads)
+        .map_err(|_| AppError::new("Invalid number of threads. Must be an integer."))?;
+    let handles = parse_max_open_files(max_open_files)?;
+    let input = input.as_ref();
+    let output = output.as_ref();
+    let _lock = lock_paths(&[output], wait)?;
+
+    let (mut game, mut iso) = try_to_open_game(input, 0)?;
+    warn_about_overlaps(&game);
+    let result = mode.prepare_dir(output).and_then(|_| {
+        let existing = if options.includes_system_data() {
+            game.extract_system_data(&mut iso, output, mode)?.total()
+        } else {
+            0
+        };
+        info!("Extracting file system...");
+        let total = game.fst.file_count + existing;
+        let progress = Mutex::new(Progress::default());
+        game.extract_files_parallel(input, output, threads as usize, &handles, |count| {
+            progress.lock().unwrap().files(count + existing, total, "written");
+        })?;
+        handle_truncated_files(&game, output, tail)
+    });
+    result.map_err(|e| AppError::new(format!("Failed to write files: {}", e)))?;
+    if options.includes_system_data() {
+        save_junk_check(&game, &mut iso, input, output);
+    }
+    match options.decompression() {
+        Some(suffix) => decompress_extracted(&mut game, output, suffix),
+        None => Ok(()),
+    }
+}
+
+// Checks if the ROM's gaps are junk, and saves what was found with the
+// extracted ROM for rebuild --gap-fill junk. The files are all there either
+// way, so it's only a warning if it can't be done.
+fn save_junk_check(game: &Game, iso: impl Read + Seek, rom_path: &Path, root: &Path) {
+    let result = game.check_junk(iso).and_then(|mut check| {
+        check.source = rom_path.canonicalize().ok();
+        check.save(root)
+    });
+    if let Err(e) = result {
+        warn!("Couldn't check if the ROM's gaps are junk: {}", e);
+    }
+}
+
+// What to do with compressed files when extracting: `None` to leave them
+// alone, or the suffix to add to their names when they're decompressed, if
+// there is one
+fn decompress_suffix(cmd: &ArgMatches) -> Option<Option<&'static str>> {
+    if !cmd.is_present("decompress") { return None }
+    Some(if cmd.is_present("dec_suffix") { Some(DECOMPRESSED_SUFFIX) } else { None })
+}
+
+fn decompress_extracted(game: &mut Game, output: &Path, suffix: Option<&str>) -> AppResult {
+    info!("Decompressing files...");
+    let report = game.decompress_extracted(output, suffix)
+        .map_err(|e| AppError::new(format!("Failed to decompress files: {}", e)))?;
+    warn_about_decompression(&report);
+    Ok(())
+}
+
+fn warn_about_decompression(report: &DecompressReport) {
+    for (path, e) in &report.failed {
+        warn!("{} couldn't be decompressed, so it was left as it was: {}", path.display(), e);
+    }
+    info!("Decompressed {} files.", with_commas(report.decompressed.len() as u64));
+}
+
+fn print_iso_info(
+    input: impl AsRef<Path>,
+    disc: Option<&str>,
+    hashes: bool,
+    layout: LayoutOptions,
+    style: NumberStyle,
+) -> AppResult {
+    let (game, mut iso) = try_to_open_disc(input.as_ref(), disc)?;
+    let hashes = if hashes { system_file_hashes(&game, &mut iso)? } else { Vec::new() };
+    game.print_info(style, &hashes, layout);
+    warn_about_overlaps(&game);
+    Ok(())
+}
+
+fn system_file_hashes(game: &Game, iso: &mut dyn ImageSource) -> Result<Vec<SectionHashes>, AppError> {
+    game.system_file_hashes(iso)
+        .map_err(|e| AppError::new(format!("Couldn't hash the system files: {}", e)))
+}
+
+fn warn_about_overlaps(game: &Game) {
+    for o in game.check_overlaps() {
+        warn!(
+            "{} overlaps {} from {:#010x} to {:#010x}.",
+            o.second, o.first, o.start, o.end,
+        );
+    }
+}
+
+// Lists the files that were cut off by the end of the image, if there are
+// any, and fills in what's missing from the ones extracted to `output` unless
+// `tail` says not to
+fn handle_truncated_files(game: &Game, output: &Path, tail: TruncatedTail) -> io::Result<()> {
+    warn_about_truncated_files(game, tail);
+    match tail {
+        TruncatedTail::ZeroFill => game.fill_truncated_files(output),
+        TruncatedTail::Skip => Ok(()),
+    }
+}
+
+fn warn_about_truncated_files(game: &Game, tail: TruncatedTail) {
+    let truncated = game.truncated_files();
+    if truncated.is_empty() { return }
+    warn!(
+        "The ROM ends at {:#010x}, before the end of {} file{}:",
+        game.image_size, truncated.len(), if truncated.len() == 1 { "" } else { "s" },
+    );
+    for t in &truncated {
+        warn!("    {} is missing {} of its {} bytes", t.path.display(), t.missing, t.size);
+    }
+    match tail {
+        TruncatedTail::ZeroFill => warn!("The missing bytes were filled in with zeros."),
+        TruncatedTail::Skip => warn!("Only the bytes that are there were written."),
+    }
+}
+
+fn parse_wait(wait: Option<&str>) -> Result<Option<Duration>, AppError> {
+    wait.map(|w| parse_as_u64(w)
+        .map(Duration::from_secs)
+        .map_err(|_| AppError::new("Invalid wait time. Must be a number of seconds."))
+    ).transpose()
+}
+
+// Keeps other gcmod processes from touching these paths until the locks are
+// dropped.
+fn lock_paths(paths: &[&Path], wait: Option<&str>) -> Result<Vec<Lock>, AppError> {
+    let wait = parse_wait(wait)?;
+    let command = env::args().collect::<Vec<_>>().join(" ");
+    paths.iter()
+        .map(|p| Lock::acquire(p, &command, wait).map_err(|e| AppError::new(e.to_string())))
+        .collect()
+}
+
+fn parse_alignment(alignment: Option<&str>) -> Result<u64, AppError> {
+    match alignment {
+        Some(a) => match parse_as_u64(a) {
+            Ok(a) if a >= MIN_ALIGNMENT => Ok(a),
+            _ => Err(AppError::new(format!("Invalid alignment. Must be an integer >= {}", MIN_ALIGNMENT))),
+        },
+        None => Ok(DEFAULT_ALIGNMENT),
+    }
+}
+
+fn parse_disc_size(disc_size: Option<&str>) -> Result<DiscSize, AppError> {
+    match disc_size {
+        Some("auto") => Ok(DiscSize::Fit),
+        Some(size) => match parse_as_u64(size) {
+            Ok(size) if size > 0 => Ok(DiscSize::Bytes(size)),
+            _ => Err(AppError::new("Invalid disc size. Must be an integer greater than 0 or \"auto\".")),
+        },
+        None => Ok(DiscSize::Retail),
+    }
+}
+
+fn parse_max_open_files(max_open_files: Option<&str>) -> Result<Arc<HandleBudget>, AppError> {
+    match max_open_files.map(parse_as_u64) {
+        Some(Ok(n)) if n > 0 => Ok(Arc::new(HandleBudget::new(n as usize))),
+        Some(_) => Err(AppError::new("Invalid number of open files. Must be an integer greater than 0.")),
+        None => Ok(HandleBudget::shared()),
+    }
+}
+
+fn check_rebuild_paths(root_path: &Path, iso_path: &Path) -> AppResult {
+    if iso_path.exists() {
+        return Err(AppError::new(format!("{} already exists.", iso_path.display())));
+    }
+    if !root_path.exists() {
+        return Err(AppError::new("Couldn't find root."));
+    }
+    Ok(())
+}
+
+// The directories in an extracted ROM that start with `&&`, and aren't part
+// of the FST
+const SPECIAL_DIRECTORIES: &[&str] = &["systemdata"];
+
+// Returns a function that checks if a path matches any of `patterns`
+fn parse_exclusions(patterns: &[&str]) -> Result<impl Fn(&Path) -> bool, AppError> {
+    for p in patterns {
+        let special = match p.strip_prefix("&&") {
+            Some(s) => s.split('/').next().unwrap_or(""),
+            None => continue,
+        };
+        if SPECIAL_DIRECTORIES.contains(&special) {
+            return Err(AppError::new(format!(
+                "Can't exclude {}, since the ROM won't boot without the system files.", p,
+            )));
+        }
+        return Err(AppError::new(format!(
+            "Invalid pattern {}: &&{} isn't one of the special directories ({}).",
+            p, special, SPECIAL_DIRECTORIES.iter().map(|d| format!("&&{}", d)).collect::<Vec<_>>().join(", "),
+        )));
+    }
+    let patterns = patterns.iter()
+        .map(|p| Pattern::new(p).map_err(|e| AppError::new(format!("Invalid pattern {}: {}", p, e))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    Ok(move |path: &Path| patterns.iter().any(|p| p.matches_path_with(path, options)))
+}
+
+fn rebuild_progress() -> impl FnMut(usize, usize) {
+    let mut progress = Progress::default();
+    move |count, total| progress.files(count, total, "added")
+}
+
+fn finish_rebuild(report: &RebuildReport, json_report: Option<&str>) -> AppResult {
+    println!(
+        "{:2}% of space filled ({}/{} bytes).",
+        report.percent_filled() as usize,
+        report.bytes_written - report.padding_bytes,
+        report.bytes_written,
+    );
+    for d in &report.digests {
+        println!("{}: {}", d.algo, d.hex);
+    }
+
+    if let Some(path) = json_report {
+        let file = File::create(path)
+            .map_err(|_| AppError::new(format!("Couldn't create {}.", path)))?;
+        serde_json::to_writer_pretty(file, report)
+            .map_err(|e| AppError::new(format!("Couldn't write the report: {}", e)))?;
+    }
+    Ok(())
+}
+
+// Prints where everything would go, and what's wrong if anything is
+fn print_rebuild_plan(root_path: &Path, plan: &RebuildPlan, json_report: Option<&str>) -> AppResult {
+    let padding = plan.padding();
+
+    if let Some(path) = json_report {
+        let file = File::create(path)
+            .map_err(|_| AppError::new(format!("Couldn't create {}.", path)))?;
+        let mut value = serde_json::to_value(plan)
+            .map_err(|e| AppError::new(format!("Couldn't write the plan: {}", e)))?;
+        value["padding"] = serde_json::to_value(&padding)
+            .map_err(|e| AppError::new(format!("Couldn't write the plan: {}", e)))?;
+        serde_json::to_writer_pretty(file, &value)
+            .map_err(|e| AppError::new(format!("Couldn't write the plan: {}", e)))?;
+    } else {
+        let files = plan.files.iter().map(|f| {
+            let path = f.path.strip_prefix(root_path).unwrap_or(&f.path);
+            (f.offset, f.size, path.display().to_string())
+        });
+        let gaps = padding.iter().map(|&(offset, size)| (offset, size, "(padding)".to_string()));
+        let mut sections = files.chain(gaps).collect::<Vec<_>>();
+        sections.sort_by_key(|&(offset, _, _)| offset);
+        for (offset, size, name) in sections {
+            println!("{:#010x} {:>10}  {}", offset, size, name);
+        }
+        print_offset_changes(&plan.relocated, &plan.added, "would be");
+    }
+
+    let padding_bytes = padding.iter().map(|&(_, size)| size).sum::<u64>();
+    println!();
+    println!("{} files would be written.", plan.files.len());
+    println!(
+        "{:2}% of space would be filled ({}/{} bytes).",
+        ((plan.disc_size - padding_bytes) as f64 / plan.disc_size as f64 * 100.0) as usize,
+        plan.disc_size - padding_bytes,
+        plan.disc_size,
+    );
+
+    if plan.problems.is_empty() {
+        println!("Nothing was written, since this was a dry run.");
+        Ok(())
+    } else {
+        Err(AppError::new(format!("The rebuild would fail:\n{}", plan.problems.join("\n"))))
+    }
+}
+
+// `verb` is "were" or "would be"
+fn print_offset_changes(relocated: &[RelocatedFile], added: &[(PathBuf, u64, u64)], verb: &str) {
+    if !relocated.is_empty() {
+        println!("{} file(s) no longer fit and {} moved:", relocated.len(), verb);
+        for f in relocated {
+            println!(
+                "{}: {:#010x} -> {:#010x} ({} bytes)",
+                f.path.display(), f.old_offset, f.new_offset, f.size,
+            );
+        }
+    }
+    if !added.is_empty() {
+        println!("{} new file(s) {} added:", added.len(), verb);
+        for (path, offset, size) in added {
+            println!("{}: {:#010x} ({} bytes)", path.display(), offset, size);
+        }
+    }
+}
+
+fn system_file_overrides<'a>(cmd: &'a ArgMatches) -> SystemFileOverrides<'a> {
+    SystemFileOverrides {
+        header: cmd.value_of("header").map(Path::new),
+        apploader: cmd.value_of("apploader").map(Path::new),
+        dol: cmd.value_of("dol").map(Path::new),
+        stream_alignment: if cmd.is_present("no_stream_alignment") { Some(false) } else { None },
+        compress: cmd.is_present("compress"),
+        allow_missing: cmd.is_present("allow_missing"),
+        allow_extra: cmd.is_present("allow_extra"),
+    }
+}
+
+// Streamed audio that isn't aligned still gets written, but it's worth
+// knowing about before testing on a console
+fn warn_about_plan(plan: &RebuildPlan) {
+    for w in &plan.warnings {
+        warn!("{}.", w);
+    }
+}
+
+fn rebuild_iso(
+    root_path: impl AsRef<Path>,
+    (iso_path, gcz, split_size, disc_size): (impl AsRef<Path>, bool, Option<&str>, Option<&str>),
+    (alignment, file_order, file_order_list, filelist): (Option<&str>, Option<&str>, Option<&str>, Option<&str>),
+    (rebuild_systemdata, allow_overlaps, dry_run): (bool, bool, bool),
+    (exclude, overrides): (Vec<&str>, SystemFileOverrides),
+    (json_report, hashes, junk): (Option<&str>, Vec<&str>, bool),
+    (wait, max_open_files): (Option<&str>, Option<&str>),
+) -> AppResult {
+    let alignment = parse_alignment(alignment)?;
+    let disc_size = parse_disc_size(disc_size)?;
+    let handles = parse_max_open_files(max_open_files)?;
+    let hashes = hashes.iter().map(|a| a.parse()).collect::<Result<Vec<HashAlgo>, _>>()?;
+    let file_order = match (file_order, file_order_list) {
+        _ if !rebuild_systemdata || filelist.is_some() => None,
+        (_, Some(list)) => Some(OrderStrategy::from_list_file(list)
+            .map_err(|e| AppError::new(format!("Couldn't read {}: {}", list, e)))?),
+        (Some("original"), _) => Some(OrderStrategy::Original),
+        (Some("alphabetical"), _) => Some(OrderStrategy::Alphabetical),
+        _ => Some(OrderStrategy::FstOrder),
+    };
+    let filelist = filelist.map(|path| File::open(path)
+        .and_then(|f| GcrFileList::read(io::BufReader::new(f)))
+        .map_err(|e| AppError::new(format!("Couldn't read {}: {}", path, e)))
+    ).transpose()?;
+    let exclude = parse_exclusions(&exclude)?;
+    let split_size = match split_size.map(parse_as_u64) {
+        Some(Ok(0)) | Some(Err(_)) =>
+            return Err(AppError::new("Invalid split size. Must be an integer greater than 0.")),
+        Some(Ok(_)) if gcz => return Err(AppError::new("GCZ files can't be split.")),
+        Some(Ok(size)) => Some(size),
+        None => None,
+    };
+
+    let iso_path = iso_path.as_ref();
+    let root_path = root_path.as_ref();
+    let _locks = lock_paths(&[root_path, iso_path], wait)?;
+    match split_size {
+        Some(_) => check_rebuild_paths(root_path, &split::output_part_path(iso_path, 0))?,
+        None => check_rebuild_paths(root_path, iso_path)?,
+    }
+
+    let plan = match filelist {
+        Some(ref list) => ROMRebuilder::plan_from_filelist(root_path, alignment, list, allow_overlaps, overrides, disc_size),
+        None => ROMRebuilder::plan(root_path, alignment, file_order.as_ref(), allow_overlaps, exclude, overrides, disc_size),
+    };
+    let mut plan = plan.map_err(|e| AppError::new(format!("Couldn't rebuild iso:\n{}", e)))?;
+    plan.hashes = hashes;
+    plan.handles = handles.clone();
+    if junk {
+        plan.fill_gaps_with_junk(root_path)
+            .map_err(|e| AppError::new(format!("Couldn't fill the gaps with junk: {}", e)))?;
+    }
+    warn_about_plan(&plan);
+    if dry_run {
+        return print_rebuild_plan(root_path, &plan, json_report);
+    }
+
+    let result = match split_size {
+        Some(size) => SplitFileWriter::create_with_handles(iso_path, size, &handles)
+            .and_then(|output| plan.write_to_split_files(output, rebuild_progress())),
+        None if gcz => write_gcz(&plan, iso_path),
+        None => plan.write_to_file(iso_path, rebuild_progress()),
+    };
+    handles.warn_if_reopened();
+    match result {
+        Ok(report) => finish_rebuild(&report, json_report),
+        Err(e) => Err(AppError::new(format!("Couldn't rebuild iso:\n{}", e))),
+    }
+}
+
+#[cfg(feature = "gcz")]
+fn write_gcz(plan: &RebuildPlan, path: &Path) -> io::Result<RebuildReport> {
+    plan.write_to_gcz(path, gcz::DEFAULT_BLOCK_SIZE, rebuild_progress())
+}
+
+#[cfg(not(feature = "gcz"))]
+fn write_gcz(_plan: &RebuildPlan, _path: &Path) -> io::Result<RebuildReport> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "gcmod was built without the gcz feature"))
+}
+
+fn rebuild_iso_preserving_offsets(
+    root_path: impl AsRef<Path>,
+    (iso_path, gcz): (impl AsRef<Path>, bool),
+    (alignment, disc_size): (Option<&str>, Option<&str>),
+    (allow_relocation, dry_run): (bool, bool),
+    (exclude, overrides): (Vec<&str>, SystemFileOverrides),
+    (json_report, hashes, junk): (Option<&str>, Vec<&str>, bool),
+    wait: Option<&str>,
+) -> AppResult {
+    let alignment = parse_alignment(alignment)?;
+    let disc_size = parse_disc_size(disc_size)?;
+    let hashes = hashes.iter().map(|a| a.parse()).collect::<Result<Vec<HashAlgo>, _>>()?;
+    let exclude = parse_exclusions(&exclude)?;
+
+    let iso_path = iso_path.as_ref();
+    let root_path = root_path.as_ref();
+    let _locks = lock_paths(&[root_path, iso_path], wait)?;
+    check_rebuild_paths(root_path, iso_path)?;
+
+    let mut plan = ROMRebuilder::plan_preserving_offsets(root_path, alignment, allow_relocation, exclude, overrides, disc_size)
+        .map_err(|e| AppError::new(format!("Couldn't rebuild iso:\n{}", e)))?;
+    plan.hashes = hashes;
+    if junk {
+        plan.fill_gaps_with_junk(root_path)
+            .map_err(|e| AppError::new(format!("Couldn't fill the gaps with junk: {}", e)))?;
+    }
+    warn_about_plan(&plan);
+    if dry_run {
+        return print_rebuild_plan(root_path, &plan, json_report);
+    }
+
+    let result = if gcz {
+        write_gcz(&plan, iso_path)
+    } else {
+        File::create(iso_path).and_then(|iso| plan.write_sparse(iso, rebuild_progress()))
+    };
+    match result {
+        Ok(report) => {
+            finish_rebuild(&report, json_report)?;
+            if report.relocated.is_empty() {
+                println!("Every file was kept at its original offset.");
+            }
+            print_offset_changes(&report.relocated, &report.added, "were");
+            Ok(())
+        },
+        Err(e) => {
+            let _ = remove_file(iso_path);
+            Err(AppError::new(format!("Couldn't rebuild iso:\n{}", e)))
+        },
+    }
+}
+
+fn rebuild_iso_incremental(
+    root_path: impl AsRef<Path>,
+    iso_path: impl AsRef<Path>,
+    (alignment, dry_run): (Option<&str>, bool),
+    overrides: SystemFileOverrides,
+    json_report: Option<&str>,
+    wait: Option<&str>,
+) -> AppResult {
+    let alignment = parse_alignment(alignment)?;
+
+    let iso_path = iso_path.as_ref();
+    let root_path = root_path.as_ref();
+    let _locks = lock_paths(&[root_path, iso_path], wait)?;
+    if !root_path.exists() {
+        return Err(AppError::new("Couldn't find root."));
+    }
+    if source::is_gcz(iso_path) {
+        return Err(AppError::new("GCZ files can't be updated in place."));
+    }
+    let mut iso = OpenOptions::new().read(true).write(!dry_run).open(iso_path)
+        .map_err(|e| AppError::new(format!("Couldn't open {}: {}", iso_path.display(), e)))?;
+
+    let plan = ROMRebuilder::plan_incremental(root_path, alignment, overrides, &mut iso)
+        .map_err(|e| AppError::new(format!("Couldn't rebuild iso:\n{}", e)))?;
+    warn_about_plan(&plan);
+    if dry_run {
+        return print_rebuild_plan(root_path, &plan, json_report);
+    }
+
+    let mut progress = Progress::default();
+    let report = plan.write_incremental(iso, |count, total| progress.files(count, total, "checked"))
+        .map_err(|e| AppError::new(format!("Couldn't rebuild iso:\n{}", e)))?;
+    drop(progress);
+    println!(
+        "Rewrote {} bytes in {} of {} files.",
+        with_commas(report.bytes_written),
+        report.files_written,
+        plan.files.len(),
+    );
+    for (path, _, _) in &report.files {
+        println!("{}", path.strip_prefix(root_path).unwrap_or(path).display());
+    }
+    print_offset_changes(&[], &report.added, "were");
+
+    if let Some(path) = json_report {
+        let file = File::create(path)
+            .map_err(|_| AppError::new(format!("Couldn't create {}.", path)))?;
+        serde_json::to_writer_pretty(file, &report)
+            .map_err(|e| AppError::new(format!("Couldn't write the report: {}", e)))?;
+    }
+    Ok(())
+}
+
+fn create_iso(
+    root_path: impl AsRef<Path>,
+    iso_path: impl AsRef<Path>,
+    options: CreateOptions,
+    (alignment, disc_size, dry_run): (Option<&str>, Option<&str>, bool),
+    json_report: Option<&str>,
+    wait: Option<&str>,
+) -> AppResult {
+    let alignment = parse_alignment(alignment)?;
+    let disc_size = parse_disc_size(disc_size)?;
+
+    let iso_path = iso_path.as_ref();
+    let root_path = root_path.as_ref();
+    let _locks = lock_paths(&[root_path, iso_path], wait)?;
+    check_rebuild_paths(root_path, iso_path)?;
+
+    let plan = ROMRebuilder::plan_create(root_path, alignment, &options, disc_size)
+        .map_err(|e| AppError::new(format!("Couldn't create iso:\n{}", e)))?;
+    if dry_run {
+        return print_rebuild_plan(root_path, &plan, json_report);
+    }
+
+    match plan.write_to_file(iso_path, rebuild_progress()) {
+        Ok(report) => finish_rebuild(&report, json_report),
+        Err(e) => Err(AppError::new(format!("Couldn't create iso:\n{}", e))),
+    }
+}
+
+fn salvage_iso(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    alignment: Option<&str>,
+    wait: Option<&str>,
+) -> AppResult {
+    let alignment = parse_alignment(alignment)?;
+    let output = output.as_ref();
+    let _lock = lock_paths(&[output], wait)?;
+
+    if output.exists() {
+        return Err(AppError::new(format!("Error: {} already exists.", output.display())));
+    }
+
+    let input = input.as_ref();
+    let iso = File::open(input)
+        .map(BufReader::new)
+        .map_err(|_| AppError::new(format!("Couldn't open {}.", input.display())))?;
+    let report = salvage(iso, output, alignment)
+        .map_err(|e| AppError::new(format!("Couldn't salvage {}: {}", input.display(), e)))?;
+
+    report.write(io::stdout())?;
+    if !report.is_rebuildable() {
+        println!("\nSome system files couldn't be recovered, so the result can't be rebuilt as is.");
+    }
+    Ok(())
+}
+
+struct HeaderChanges<'a> {
+    title: Option<&'a str>,
+    game_code: Option<&'a str>,
+    maker_code: Option<&'a str>,
+    disc_id: Option<&'a str>,
+    version: Option<&'a str>,
+}
+
+// The header's at the start of both ROMs and ISO.hdr files, so this works
+// the same way for either
+fn set_info(path: impl AsRef<Path>, changes: HeaderChanges, wait: Option<&str>) -> AppResult {
+    let path = path.as_ref();
+    let parse_byte = |name: &str, value: &str| parse_as_u64(value).ok()
+        .filter(|&n| n <= 0xff)
+        .map(|n| n as u8)
+        .ok_or_else(|| AppError::new(format!("Invalid {}: {}. It has to be from 0 to 255.", name, value)));
+    let disc_id = changes.disc_id.map(|d| parse_byte("disc ID", d)).transpose()?;
+    let version = changes.version.map(|v| parse_byte("version", v)).transpose()?;
+
+    let _lock = lock_paths(&[path], wait)?;
+    let mut file = OpenOptions::new().read(true).write(true).open(path)
+        .map_err(|_| AppError::new(format!("Couldn't open {} for writing.", path.display())))?;
+    let mut header = Header::new(BufReader::new(&file), 0)
+        .map_err(|_| AppError::new(format!("{} doesn't have a valid header.", path.display())))?;
+
+    let invalid = |e: io::Error| AppError::new(format!("Invalid value: {}.", e));
+    if let Some(title) = changes.title {
+        header.set_title(title).map_err(invalid)?;
+    }
+    if let Some(game_code) = changes.game_code {
+        header.set_game_code(game_code).map_err(invalid)?;
+    }
+    if let Some(maker_code) = changes.maker_code {
+        header.set_maker_code(maker_code).map_err(invalid)?;
+    }
+    if let Some(disc_id) = disc_id {
+        header.set_disc_id(disc_id);
+    }
+    if let Some(version) = version {
+        header.set_version(version);
+    }
+
+    file.seek(SeekFrom::Start(0))?;
+    header.write_ids_and_title(&mut file)
+        .map_err(|e| AppError::new(format!("Couldn't write the header: {}", e)))?;
+
+    let header = Header::new(BufReader::new(&file), 0)?;
+    header.print_info(NumberStyle::Hexadecimal);
+    println!("Disc ID: {}", header.disk_id);
+    println!("Version: {}", header.version);
+    Ok(())
+}
+
+fn inject_file(
+    rom_path: impl AsRef<Path>,
+    file_in_rom: impl AsRef<Path>,
+    file: impl AsRef<Path>,
+    wait: Option<&str>,
+) -> AppResult {
+    let rom_path = rom_path.as_ref();
+    let file = file.as_ref();
+    let _lock = lock_paths(&[rom_path], wait)?;
+
+    let (mut game, _) = try_to_open_game(rom_path, 0)?;
+    let source = File::open(file)
+        .map_err(|_| AppError::new(format!("Couldn't open {}.", file.display())))?;
+    let len = source.metadata()?.len();
+    let iso = OpenOptions::new().read(true).write(true).open(rom_path)
+        .map_err(|_| AppError::new(format!("Couldn't open {} for writing.", rom_path.display())))?;
+
+    game.replace_file(iso, file_in_rom, BufReader::new(source), len)
+        .map_err(|e| AppError::new(format!("Couldn't inject {}: {}", file.display(), e)))
+}
+
+fn replace_segment(
+    path: impl AsRef<Path>,
+    segment: &str,
+    file: impl AsRef<Path>,
+    wait: Option<&str>,
+) -> AppResult {
+    let path = path.as_ref();
+    let file = file.as_ref();
+    let (seg_type, seg_num) = Segment::parse_segment_name(segment)
+        .ok_or_else(|| AppError::new(format!("Invalid segment name: {}", segment)))?;
+    let _lock = lock_paths(&[path], wait)?;
+
+    let source = File::open(file)
+        .map_err(|_| AppError::new(format!("Couldn't open {}.", file.display())))?;
+    let len = source.metadata()?.len();
+    let mut target = OpenOptions::new().read(true).write(true).open(path)
+        .map_err(|_| AppError::new(format!("Couldn't open {} for writing.", path.display())))?;
+
+    if is_dol_path(path) {
+        let mut dol = DOLHeader::new(&mut target, 0)
+            .map_err(|_| AppError::new(format!("Invalid DOL: {}.", path.display())))?;
+        dol.replace_segment(&mut target, seg_type, seg_num, BufReader::new(source), len)
+    } else {
+        let (mut game, _) = try_to_open_game(path, 0)?;
+        game.replace_dol_segment(&mut target, seg_type, seg_num, BufReader::new(source), len)
+    }.map_err(|e| AppError::new(format!("Couldn't replace {}: {}", segment, e)))
+}
+
+fn is_dol_path(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("dol"))
+}
+
+// Opens `path` on its own if it's a DOL, or else as a ROM (or a directory one
+// was extracted to) to get to its DOL. The segments' offsets are from the
+// start of whichever it is.
+fn open_dol_or_rom(path: &Path) -> Result<(DOLHeader, Box<dyn ImageSource>), AppError> {
+    if is_dol_path(path) {
+        let mut f = File::open(path)
+            .map(BufReader::new)
+            .map_err(|_| AppError::new(format!("Couldn't open {}.", path.display())))?;
+        let dol = DOLHeader::new(&mut f, 0)
+            .map_err(|_| AppError::new(format!("Invalid DOL: {}.", path.display())))?;
+        Ok((dol, Box::new(f)))
+    } else {
+        let (game, iso) = try_to_open_game(path, 0)?;
+        Ok((game.dol, iso))
+    }
+}
+
+fn list_segments(path: impl AsRef<Path>, extract: Option<&str>, all: bool, style: NumberStyle) -> AppResult {
+    let path = path.as_ref();
+    let (dol, mut reader) = open_dol_or_rom(path)?;
+
+    dol.print_segments(style, all);
+    if let Some(output) = extract {
+        let written = dol.extract_all_segments(&mut reader, Path::new(output))
+            .map_err(|e| AppError::new(format!("Couldn't extract the segments: {}", e)))?;
+        info!("Wrote {} segments to {}.", written.len(), output);
+    }
+    Ok(())
+}
+
+fn dol_to_elf(input: impl AsRef<Path>, output: impl AsRef<Path>) -> AppResult {
+    let input = input.as_ref();
+    let output = output.as_ref();
+    let (dol, mut reader) = open_dol_or_rom(input)?;
+
+    let elf = File::create(output)
+        .map_err(|_| AppError::new(format!("Couldn't create {}.", output.display())))?;
+    dol.to_elf(&mut reader, io::BufWriter::new(elf))
+        .map_err(|e| AppError::new(format!("Couldn't convert the DOL: {}", e)))
+}
+
+fn apply_codes(dol_path: &str, codes_path: &str, output: Option<&str>) -> AppResult {
+    let mut dol_data = fs::read(dol_path)
+        .map_err(|e| AppError::new(format!("Couldn't read {}: {}", dol_path, e)))?;
+    let codes = fs::read_to_string(codes_path)
+        .map_err(|e| AppError::new(format!("Couldn't read {}: {}", codes_path, e)))?;
+    let dol = DOLHeader::new(io::Cursor::new(&dol_data), 0)
+        .map_err(|_| AppError::new(format!("Invalid DOL: {}.", dol_path)))?;
+
+    let report = apply_gecko(&dol, &mut dol_data, &codes)
+        .map_err(|e| AppError::new(format!("Couldn't apply the codes: {}", e)))?;
+    for name in &report.unsupported {
+        eprintln!("Skipped {}", name);
+    }
+    for address in &report.outside_segments {
+        eprintln!("Skipped a write to {:#010x}, which isn't in any of the DOL's segments", address);
+    }
+
+    let output = output.unwrap_or(dol_path);
+    fs::write(output, &dol_data)
+        .map_err(|e| AppError::new(format!("Couldn't write {}: {}", output, e)))?;
+    println!("Applied {} writes and inserts to {}.", report.applied, output);
+    Ok(())
+}
+
+fn elf_to_dol(input: impl AsRef<Path>, output: impl AsRef<Path>) -> AppResult {
+    let input = input.as_ref();
+    let output = output.as_ref();
+    let elf = File::open(input)
+        .map(BufReader::new)
+        .map_err(|_| AppError::new(format!("Couldn't open {}.", input.display())))?;
+    let dol = File::create(output)
+        .map_err(|_| AppError::new(format!("Couldn't create {}.", output.display())))?;
+    DOLHeader::from_elf(elf, io::BufWriter::new(dol))
+        .map_err(|e| AppError::new(format!("Couldn't convert the ELF: {}", e)))
+}
+
+// `load_address` is where the code's loaded with `range`, or where the REL
+// is if `section` is one
+fn disassemble(
+    rom_path: impl AsRef<Path>,
+    (section, load_address): (Option<&str>, Option<u64>),
+    range: Option<CodeSection>,
+    objdump: Option<&str>,
+    map: Option<&str>,
+    (labels, all): (bool, bool),
+    output: Option<&str>,
+) -> AppResult {
+    let rom_path = rom_path.as_ref();
+    let rel_section = section.filter(|s| is_rel_section_name(s));
+    if load_address.is_some() && range.is_none() && rel_section.is_none() {
+        return Err(AppError::new("--load-address only works with --offset or a REL section."));
+    }
+    let disassembler = match objdump {
+        Some("auto") => Disassembler::find_objdump()
+            .map_err(|e| AppError::new(format!("Can't disassemble with objdump: {}", e)))?,
+        Some(path) => Disassembler::with_objdump(path)
+            .map_err(|e| AppError::new(format!("Can't use {}: {}", path, e)))?,
+        None => Disassembler::new(),
+    };
+    let symbols = match map {
+        Some(path) => Some(SymbolMap::load(path)
+            .map_err(|e| AppError::new(format!("Couldn't load {}: {}", path, e)))?),
+        None => None,
+    };
+
+    let open_output = || -> Result<Box<dyn Write>, AppError> {
+        Ok(match output {
+            Some(path) => Box::new(io::BufWriter::new(File::create(path)
+                .map_err(|_| AppError::new(format!("Couldn't create {}.", path)))?)),
+            None => Box::new(io::BufWriter::new(io::stdout().lock())),
+        })
+    };
+
+    if all {
+        let (dol, mut iso) = open_dol_or_rom(rom_path)?;
+        let unparsed = disassembler.write_listing(&mut iso, &dol, symbols.as_ref(), open_output()?)?;
+        if unparsed > 0 {
+            warn!("{} line(s) from objdump couldn't be parsed, so the built in disassembler was used for them.", unparsed);
+        }
+        return Ok(());
+    }
+
+    let (sections, mut iso) = if let Some(range) = range {
+        let mut file = source::open(rom_path)
+            .map_err(|e| AppError::new(format!("Couldn't open {}: {}", rom_path.display(), e)))?;
+        // If it's a ROM, say what the code is part of
+        if let Ok(game) = Game::open(&mut file, 0) {
+            if let Some(r) = game.rom_layout().resolve(range.offset) {
+                println!("{} is in:", range.name);
+                r.section().print_info(NumberStyle::Hexadecimal);
+                print_offset_details(r, range.offset, NumberStyle::Hexadecimal);
+                println!();
+            }
+        }
+        (vec![range], file)
+    } else if is_dol_path(rom_path) {
+        if section.is_some_and(|s| s.eq_ignore_ascii_case("apploader")) {
+            return Err(AppError::new("A DOL doesn't have an apploader."));
+        }
+        let (dol, file) = open_dol_or_rom(rom_path)?;
+        (dol_code_sections(&dol, section)?, file)
+    } else {
+        let (game, mut iso) = try_to_open_game(rom_path, 0)?;
+        let sections = match rel_section {
+            Some(name) => rel_code_sections(&game, &mut iso, name, load_address.unwrap_or(0))?,
+            None => code_sections(&game, section)?,
+        };
+        (sections, iso)
+    };
+    let mut out = open_output()?;
+
+    let unparsed = if labels {
+        disassembler.write_with_sections(&mut iso, &sections, symbols.as_ref(), out)?
+    } else {
+        let mut unparsed = 0;
+        for (i, section) in sections.into_iter().enumerate() {
+            if i > 0 { writeln!(out)?; }
+            writeln!(out, "{}:", section.name)?;
+            let mut instructions = disassembler.disasm(&mut iso, section, symbols.as_ref())?;
+            while let Some(ins) = instructions.next() {
+                let ins = ins?;
+                if let Some(name) = instructions.current_symbol() {
+                    writeln!(out, "{}:", name)?;
+                }
+                writeln!(out, "{}", ins)?;
+            }
+            unparsed += instructions.unparsed_lines();
+        }
+        out.flush()?;
+        unparsed
+    };
+    if unparsed > 0 {
+        warn!("{} line(s) from objdump couldn't be parsed, so the built in disassembler was used for them.", unparsed);
+    }
+    Ok(())
+}
+
+fn write_call_graph(rom_path: impl AsRef<Path>, output: Option<&str>) -> AppResult {
+    let (game, mut iso) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let graph = call_graph(&game.dol, &mut iso)?;
+
+    println!(
+        "Found {} functions and {} calls, plus {} indirect calls.",
+        graph.functions().count(),
+        graph.calls().count(),
+        graph.indirect_calls,
+    );
+    if let Some(path) = output {
+        let file = File::create(path)
+            .map_err(|_| AppError::new(format!("Couldn't create {}.", path)))?;
+        graph.write_dot(io::BufWriter::new(file))?;
+    }
+    Ok(())
+}
+
+fn write_symbol_map(rom_path: impl AsRef<Path>, output: Option<&str>) -> AppResult {
+    let (game, mut iso) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let map = generate_map(&game.dol, &mut iso)?;
+
+    let path = output.map_or_else(
+        || format!("{}{}.map", game.header.game_code, game.header.maker_code),
+        String::from,
+    );
+    let file = File::create(&path)
+        .map_err(|_| AppError::new(format!("Couldn't create {}.", path)))?;
+    let mut file = io::BufWriter::new(file);
+    map.write_dolphin_map(&mut file)?;
+    file.flush()?;
+
+    let named = map.iter().filter(|(_, s)| !s.name.starts_with("fn_")).count();
+    println!("Wrote {} functions to {}, {} of them with names.", map.len(), path, named);
+    Ok(())
+}
+
+fn code_sections(game: &Game, section: Option<&str>) -> Result<Vec<CodeSection>, AppError> {
+    match section {
+        Some(name) if name.eq_ignore_ascii_case("apploader") => Ok(vec![game.gamecube_apploader()?.code_section()]),
+        _ => dol_code_sections(&game.dol, section),
+    }
+}
+
+// `name` is either a REL, for all of its code sections, or `path.rel:N` for
+// just section N
+fn rel_code_sections(
+    game: &Game,
+    iso: &mut Box<dyn ImageSource>,
+    name: &str,
+    base: u64,
+) -> Result<Vec<CodeSection>, AppError> {
+    let (path, index) = match name.rsplit_once(':') {
+        Some((path, index)) => (path, Some(index.parse::<usize>()
+            .map_err(|_| AppError::new(format!("Invalid section number: {}", index)))?)),
+        None => (name, None),
+    };
+    let mut file = game.open_file(iso, path)?;
+    let start = file.start();
+    let rel = RelHeader::new(&mut file)
+        .map_err(|e| AppError::new(format!("Couldn't read {}: {}", path, e)))?;
+    Ok(match index {
+        Some(i) => {
+            let s = rel.section(i)
+                .ok_or_else(|| AppError::new(format!("{} only has {} sections.", path, rel.sections.len())))?;
+            if s.is_bss() || s.size == 0 {
+                return Err(AppError::new(format!("Section {} of {} isn't in the file.", i, path)));
+            }
+            vec![rel.code_section(s, path, start, base)]
+        },
+        None => rel.code_sections(path, start, base),
+    })
+}
+
+fn is_rel_path(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("rel"))
+}
+
+// A REL, or one of its sections like `stage.rel:1`
+fn is_rel_section_name(name: &str) -> bool {
+    is_rel_path(Path::new(name.rsplit_once(':').map_or(name, |(path, _)| path)))
+}
+
+fn dol_code_sections(dol: &DOLHeader, section: Option<&str>) -> Result<Vec<CodeSection>, AppError> {
+    Ok(match section {
+        Some(name) => {
+            let (seg_type, num) = Segment::parse_segment_name(name)
+                .ok_or_else(|| AppError::new(format!("Invalid section name: {}", name)))?;
+            let seg = dol.find_segment(seg_type, num)
+                .ok_or_else(|| AppError::new(format!("The DOL doesn't have a {} segment.", name)))?;
+            vec![seg.into()]
+        },
+        None => dol.iter_segments()
+            .filter(|s| s.seg_type == SegmentType::Text)
+            .map(CodeSection::from)
+            .collect(),
+    })
+}
+
+fn parse_disasm_range(
+    offset: Option<&str>,
+    length: Option<&str>,
+    load_address: Option<u64>,
+) -> Result<Option<CodeSection>, AppError> {
+    let parse = |name: &str, n: &str| parse_as_u64(n)
+        .map_err(|_| AppError::new(format!("Invalid {}: {}", name, n)));
+    let offset = match offset {
+        Some(o) => parse("offset", o)?,
+        None => return Ok(None),
+    };
+    Ok(Some(CodeSection {
+        name: format!("{:#x}", offset),
+        offset,
+        size: parse("length", length.unwrap())? as usize,
+        load_address: load_address.unwrap_or(0),
+    }))
+}
+
+fn parse_hash_algos(names: &[&str]) -> Result<Vec<HashAlgo>, AppError> {
+    if names.is_empty() {
+        Ok(vec![HashAlgo::Sha1])
+    } else {
+        names.iter().map(|n| n.parse()).collect()
+    }
+}
+
+// Prints the junk that should be at `offset` next to what's there, 16 bytes
+// a line, with the lines that don't match marked
+fn sample_junk(rom_path: impl AsRef<Path>, offset: &str, len: Option<&str>) -> AppResult {
+    let offset = parse_as_u64(offset)
+        .map_err(|_| AppError::new(format!("Invalid offset: {}", offset)))?;
+    let len = match len {
+        Some(l) => parse_as_u64(l).map_err(|_| AppError::new(format!("Invalid length: {}", l)))? as usize,
+        None => 256,
+    };
+    let (game, mut iso) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let seed = JunkSeed::from_header(&game.header);
+    let sample = JunkSample::read(&mut iso, &seed, offset, len)?;
+
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+    println!("{:<10}  {:<47}  {:<47}", "Offset", "Generated", "Actual");
+    for (i, generated) in sample.generated.chunks(16).enumerate() {
+        let start = i * 16;
+        let actual = sample.actual.get(start..(start + 16).min(sample.actual.len())).unwrap_or(&[]);
+        println!(
+            "{:#010x}  {:<47}  {:<47}{}",
+            offset + start as u64, hex(generated), hex(actual),
+            if generated == actual { "" } else { "  *" },
+        );
+    }
+    println!();
+    match sample.first_mismatch() {
+        None => println!("All {} bytes match the standard junk.", len),
+        Some(at) => println!("The ROM stops matching the standard junk at {:#x}.", at),
+    }
+    Ok(())
+}
+
+fn hash_file(path: impl AsRef<Path>, algos: Vec<&str>) -> AppResult {
+    let algos = parse_hash_algos(&algos)?;
+    let path = path.as_ref();
+    let file = File::open(path)
+        .map_err(|_| AppError::new(format!("Couldn't open {}.", path.display())))?;
+
+    for d in hash_reader(&algos, BufReader::new(file))? {
+        println!("{}: {}", d.algo, d.hex);
+    }
+    Ok(())
+}
+
+fn diff_roms(rom_a: &str, rom_b: &str, json: bool, quick: bool) -> AppResult {
+    let open = |path: &str| source::open(path)
+        .map_err(|e| AppError::new(format!("Couldn't open {}: {}", path, e)));
+    let (iso_a, iso_b) = (open(rom_a)?, open(rom_b)?);
+    let report = if quick {
+        Game::diff_quick(iso_a, iso_b)
+    } else {
+        Game::diff(iso_a, iso_b)
+    }.map_err(|e| AppError::new(format!("Couldn't compare the ROMs: {}", e)))?;
+
+    if json {
+        let stdout = io::stdout();
+        serde_json::to_writer_pretty(stdout.lock(), &report)
+            .map_err(|e| AppError::new(format!("Couldn't write the JSON: {}", e)))?;
+        println!();
+        return Ok(());
+    }
+
+    if report.is_identical() {
+        println!("The ROMs have the same files.");
+        return Ok(());
+    }
+    for change in &report.header {
+        println!("{}: {} -> {}", change.field, change.a, change.b);
+    }
+    for path in &report.only_in_a {
+        println!("Only in {}: {}", rom_a, path.display());
+    }
+    for path in &report.only_in_b {
+        println!("Only in {}: {}", rom_b, path.display());
+    }
+    for file in &report.changed {
+        print!("Changed: {} ({} -> {} bytes", file.path.display(), file.size_a, file.size_b);
+        match file.differing_bytes {
+            Some(n) => println!(", {} different)", n),
+            None => println!(")"),
+        }
+    }
+    println!(
+        "{} changed, {} only in {}, {} only in {}, {} unchanged",
+        report.changed.len(),
+        report.only_in_a.len(), rom_a,
+        report.only_in_b.len(), rom_b,
+        report.unchanged,
+    );
+    Ok(())
+}
+
+#[cfg(feature = "patch")]
+fn make_patch(original: &str, modified: &str, output: &str) -> AppResult {
+    let open = |path: &str| File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| AppError::new(format!("Couldn't open {}: {}", path, e)));
+    let (original_file, modified_file) = (open(original)?, open(modified)?);
+    let patch = File::create(output)
+        .map_err(|e| AppError::new(format!("Couldn't create {}: {}", output, e)))?;
 
-    if let Some(file) = file_in_iso {
-        return extract_section(input.as_ref(), file.as_ref(), output);
-    }
+    patch::create_bps(original_file, modified_file, &patch)
+        .map_err(|e| AppError::new(format!("Couldn't make the patch: {}", e)))?;
+    println!("Wrote {} ({} bytes).", output, patch.metadata()?.len());
+    Ok(())
+}
 
-    if output.exists() {
-        return Err(AppError::new(format!("Error: {} already exists.", output.display())));
+#[cfg(not(feature = "patch"))]
+fn make_patch(_original: &str, _modified: &str, _output: &str) -> AppResult {
+    Err(AppError::new("This build of gcmod doesn't support patches."))
+}
+
+#[cfg(feature = "patch")]
+fn apply_patch(rom_path: &str, patch_path: &str, output: &str, force: bool) -> AppResult {
+    let open = |path: &str| File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| AppError::new(format!("Couldn't open {}: {}", path, e)));
+    let (rom, patch_file) = (open(rom_path)?, open(patch_path)?);
+    let patched = File::create(output)
+        .map_err(|e| AppError::new(format!("Couldn't create {}: {}", output, e)))?;
+
+    if force {
+        patch::apply_unchecked(patch_file, rom, &patched)
+    } else {
+        patch::apply(patch_file, rom, &patched)
+    }.map_err(|e| AppError::new(format!("Couldn't apply {}: {}", patch_path, e)))?;
+
+    // Make sure it's still a ROM gcmod can read
+    let (game, _) = try_to_open_game(output, 0)?;
+    println!(
+        "Wrote {}: {} ({}{})",
+        output, game.header.title, game.header.game_code, game.header.maker_code,
+    );
+    Ok(())
+}
+
+#[cfg(not(feature = "patch"))]
+fn apply_patch(_rom_path: &str, _patch_path: &str, _output: &str, _force: bool) -> AppResult {
+    Err(AppError::new("This build of gcmod doesn't support patches."))
+}
+
+fn verify_directory(rom_path: impl AsRef<Path>, dir: impl AsRef<Path>, list: bool) -> AppResult {
+    let dir = dir.as_ref();
+    let (game, mut iso) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let report = game.verify_directory(&mut iso, dir)
+        .map_err(|e| AppError::new(format!("Couldn't check {}: {}", dir.display(), e)))?;
+
+    println!("{} unchanged", report.matching.len());
+    let differences = [
+        ("modified", &report.modified),
+        ("missing", &report.missing),
+        ("not on the ROM", &report.extra),
+    ];
+    for (description, paths) in &differences {
+        println!("{} {}", paths.len(), description);
+        if list {
+            for path in paths.iter() {
+                println!("    {}", path.display());
+            }
+        }
     }
 
-    let (mut game, mut iso) = try_to_open_game(input.as_ref(), 0)?;
-    game.extract(&mut iso, output).map_err(|_| AppError::new("Failed to write files."))
+    if report.is_unchanged() {
+        Ok(())
+    } else {
+        Err(AppError::new(format!("{} doesn't match the ROM.", dir.display())))
+    }
 }
 
-fn print_iso_info(input: impl AsRef<Path>, offset: u64, style: NumberStyle) -> AppResult {
-    let (game, _) = try_to_open_game(input, offset)?;
-    game.print_info(style);
+fn write_manifest(rom_path: impl AsRef<Path>, output: Option<&str>, algo: Option<&str>, prefix: bool) -> AppResult {
+    let algo = algo.map_or(Ok(MANIFEST_ALGO), |a| a.parse())?;
+    let (game, mut iso) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let hashes = game.hash_files(&mut iso, algo)
+        .map_err(|e| AppError::new(format!("Couldn't hash the files: {}", e)))?;
+
+    let stdout = io::stdout();
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)
+            .map_err(|_| AppError::new(format!("Couldn't create {}.", path)))?)),
+        None => Box::new(io::BufWriter::new(stdout.lock())),
+    };
+    for entry in hashes {
+        if prefix {
+            writeln!(out, "{:#}", entry)?;
+        } else {
+            writeln!(out, "{}", entry)?;
+        }
+    }
     Ok(())
 }
 
-fn rebuild_iso(
-    root_path: impl AsRef<Path>,
-    iso_path: impl AsRef<Path>,
-    alignment: Option<&str>,
-    rebuild_systemdata: bool,
-) -> AppResult {
-    let alignment = match alignment {
-        Some(a) => match parse_as_u64(a) {
-            Ok(a) if a >= MIN_ALIGNMENT => a,
-            _ => return Err(AppError::new(format!("Invalid alignment. Must be an integer >= {}", MIN_ALIGNMENT))),
-        },
-        None => DEFAULT_ALIGNMENT,
-    };
+fn verify_manifest(rom_path: &str, manifest_path: &str, list: bool) -> AppResult {
+    let manifest = File::open(manifest_path)
+        .and_then(|f| read_manifest(BufReader::new(f)))
+        .map_err(|e| AppError::new(format!("Couldn't read {}: {}", manifest_path, e)))?;
+    let (game, mut iso) = try_to_open_game(rom_path, 0)?;
+    let report = game.verify_manifest(&mut iso, &manifest)
+        .map_err(|e| AppError::new(format!("Couldn't hash the files: {}", e)))?;
 
-    let iso_path = iso_path.as_ref();
-    let root_path = root_path.as_ref();
+    println!("{} unchanged", report.matching.len());
+    let differences = [
+        ("modified", &report.modified),
+        ("not in the manifest", &report.missing),
+        ("not on the ROM", &report.extra),
+    ];
+    for (description, paths) in &differences {
+        println!("{} {}", paths.len(), description);
+        if list {
+            for path in paths.iter() {
+                println!("    {}", path.display());
+            }
+        }
+    }
 
-    if iso_path.exists() {
-        return Err(AppError::new(format!("{} already exists.", iso_path.display())));
+    if report.is_unchanged() {
+        Ok(())
+    } else {
+        Err(AppError::new(format!("{} doesn't match {}.", rom_path, manifest_path)))
     }
-    if !root_path.exists() {
-        return Err(AppError::new("Couldn't find root."));
+}
+
+fn verify_image(rom_path: &str, expected: &[(HashAlgo, Option<&str>)], extra_algos: Vec<&str>) -> AppResult {
+    let mut expected = expected.iter()
+        .map(|&(algo, hex)| Ok((algo, hex.map(|h| format!("{}:{}", algo, h).parse::<Digest>()).transpose()?)))
+        .collect::<Result<Vec<_>, AppError>>()?;
+    for algo in extra_algos {
+        let algo = algo.parse::<HashAlgo>()?;
+        if expected.iter().all(|&(a, _)| a != algo) {
+            expected.push((algo, None));
+        }
     }
+    let algos = expected.iter()
+        .map(|&(algo, _)| algo)
+        .filter(|a| a.is_supported())
+        .collect::<Vec<_>>();
 
-    let iso = File::create(iso_path)?;
-    if let Err(_) = ROMRebuilder::rebuild(root_path, alignment, iso, rebuild_systemdata) {
-        remove_file(iso_path).unwrap();
-        Err(AppError::new("Couldn't rebuild iso."))
+    let progress = |total: Option<u64>| {
+        let mut progress = Progress::default();
+        move |n: u64| progress.update(total == Some(n), || match total {
+            Some(total) => format!("{:.1}% read.", n as f64 / total as f64 * 100.0),
+            None => format!("{} MiB read.", n / 1024 / 1024),
+        })
+    };
+    let report = if rom_path == "-" {
+        let stdin = io::stdin();
+        Game::hash_image_with_progress(stdin.lock(), &algos, progress(None))
     } else {
+        let mut file = source::open(rom_path)
+            .map_err(|e| AppError::new(format!("Couldn't open {}: {}", rom_path, e)))?;
+        let len = file.len()
+            .map_err(|e| AppError::new(format!("Couldn't read {}: {}", rom_path, e)))?;
+        Game::hash_image_with_progress(file, &algos, progress(Some(len)))
+    }.map_err(|e| AppError::new(format!("Couldn't read {}: {}", rom_path, e)))?;
+    println!(
+        "Read {} bytes in {:.1}s ({:.1} MiB/s).",
+        report.bytes_read,
+        report.elapsed.as_secs_f64(),
+        report.bytes_per_second() / 1024.0 / 1024.0,
+    );
+    if rom_path != "-" {
+        print_short_image(rom_path);
+    }
+
+    let mut mismatched = Vec::new();
+    // Expected digests that this build can't check
+    let mut unchecked = Vec::new();
+    for (algo, expected) in expected {
+        let digest = match report.digest(algo) {
+            Some(d) => d,
+            None => {
+                println!("{}: (not supported by this build)", algo);
+                if expected.is_some() {
+                    unchecked.push(algo.name());
+                }
+                continue;
+            },
+        };
+        match expected {
+            Some(ref e) if *e != *digest => {
+                println!("{}: {} (expected {})", algo, digest.hex, e.hex);
+                mismatched.push(algo.name());
+            },
+            Some(_) => println!("{}: {} (matches)", algo, digest.hex),
+            None => println!("{}: {}", algo, digest.hex),
+        }
+    }
+
+    let mut problems = Vec::new();
+    if !mismatched.is_empty() {
+        problems.push(format!("The {} didn't match.", mismatched.join(" and ")));
+    }
+    if !unchecked.is_empty() {
+        problems.push(format!("The {} couldn't be checked, since this build doesn't support it.", unchecked.join(" and ")));
+    }
+    if problems.is_empty() {
         Ok(())
+    } else {
+        Err(AppError::new(problems.join(" ")))
+    }
+}
+
+// Says how much smaller than a full disc the image is, if it is, and where
+// its files stop being complete. Images that aren't ROMs are left alone, since
+// verify can check those too.
+fn print_short_image(rom_path: &str) {
+    let game = match source::open(rom_path).and_then(|iso| Game::open(iso, 0)) {
+        Ok(game) => game,
+        Err(_) => return,
+    };
+    if game.image_size >= ROM_SIZE as u64 { return }
+    let last = game.last_complete_file()
+        .map_or_else(|| "none".to_string(), |f| game.fst.disk_path(&f.info).display().to_string());
+    println!(
+        "Image is {} bytes short of full size; last complete file: {}",
+        with_commas(ROM_SIZE as u64 - game.image_size), last,
+    );
+    for t in game.truncated_files() {
+        println!("    {} is missing {} of its {} bytes", t.path.display(), with_commas(t.missing), with_commas(t.size));
+    }
+}
+
+// 1234567 -> 1,234,567
+fn with_commas(n: u64) -> String {
+    let digits = n.to_string();
+    let mut result = String::with_capacity(digits.len() * 4 / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            result.push(',');
+        }
+        result.push(c);
     }
+    result
 }
 
 fn get_info(
-    path: impl AsRef<Path>,
+    (path, disc): (impl AsRef<Path>, Option<&str>),
     section_type: Option<&str>,
     offset: Option<&str>,
     mem_addr: Option<&str>,
+    (json, hashes): (bool, bool),
+    (min_gap, layout): (Option<&str>, LayoutOptions),
     style: NumberStyle,
 ) -> AppResult {
-    if let Some(offset) = offset {
+    if disc.is_none() && source::is_wbfs(path.as_ref()) && print_wbfs_discs(path.as_ref())? {
+        return Ok(());
+    }
+    if json {
+        let (game, mut iso) = try_to_open_disc(path.as_ref(), disc)?;
+        let mut info = game.info();
+        if hashes {
+            info.hashes = system_file_hashes(&game, &mut iso)?;
+        }
+        let stdout = io::stdout();
+        serde_json::to_writer_pretty(stdout.lock(), &info)
+            .map_err(|e| AppError::new(format!("Couldn't write the info: {}", e)))?;
+        println!();
+        Ok(())
+    } else if let Some(offset) = offset {
         find_offset(path.as_ref(), offset, style)
     } else if let Some(addr) = mem_addr {
         find_mem_addr(path.as_ref(), addr, style)
     } else {
-        let mut f = File::open(path.as_ref())
-            .map(BufReader::new)
-            .map_err(|_| AppError::new("Couldn't open file"))?;
+        let mut f = source::open(path.as_ref())
+            .map_err(|e| AppError::new(format!("Couldn't open file: {}", e)))?;
         let game = Game::open(&mut f, 0);
         match section_type {
             Some("header") => {
@@ -196,6 +2490,13 @@ fn get_info(
                     .map_err(|_| AppError::new("Invalid iso or DOL"))?
                     .print_info(style);
             },
+            Some("memory-map") => {
+                game
+                    .map(|g| g.dol)
+                    .or_else(|_| DOLHeader::new(f, 0))
+                    .map_err(|_| AppError::new("Invalid iso or DOL"))?
+                    .print_memory_map(style);
+            },
             Some("fst") => {
                 game
                     .map(|g| g.fst)
@@ -204,50 +2505,81 @@ fn get_info(
                     .print_info(style);
             },
             Some("apploader") | Some("app_loader") | Some("app-loader") => {
-                game
-                    .map(|g| g.apploader)
-                    .or_else(|_| Apploader::new(f, 0))
-                    .map_err(|_| AppError::new("Invalid iso or apploader"))?
-                    .print_info(style);
+                let apploader = match game {
+                    Ok(g) => {
+                        g.gamecube_apploader()?;
+                        g.apploader
+                    },
+                    Err(_) => Apploader::new(f, 0)
+                        .map_err(|_| AppError::new("Invalid iso or apploader"))?,
+                };
+                apploader.print_info(style);
             },
-            Some("layout") => { print_layout(path.as_ref())?; }
+            Some("layout") => { print_layout(path.as_ref(), min_gap, layout, style)?; }
             Some(_) => unreachable!(),
-            None => { print_iso_info(path.as_ref(), 0, style)? },
+            None => { print_iso_info(path.as_ref(), disc, hashes, layout, style)? },
         }
         Ok(())
     }
 }
 
-fn print_layout(path: impl AsRef<Path>) -> AppResult {
+fn print_layout(
+    path: impl AsRef<Path>,
+    min_gap: Option<&str>,
+    layout: LayoutOptions,
+    style: NumberStyle,
+) -> AppResult {
     let (game, _) = try_to_open_game(path.as_ref(), 0)?;
-    game.print_layout();
+    match min_gap {
+        Some(min_gap) => {
+            let min_gap = parse_as_u64(min_gap)
+                .map_err(|_| AppError::new("Invalid minimum gap size. Must be an integer."))?;
+            game.print_layout_with_gaps(min_gap, style);
+        },
+        None => game.print_layout(style, layout),
+    }
     Ok(())
 }
 
 fn find_offset(header_path: impl AsRef<Path>, offset: &str, style: NumberStyle) -> AppResult {
+    let (game, _) = try_to_open_game(header_path.as_ref(), 0)?;
     let offset = parse_as_u64(offset).ok()
-        .filter(|o| (*o as usize) < ROM_SIZE)
+        .filter(|o| *o < game.image_size)
         .ok_or_else(|| AppError::new(format!(
             "Invalid offset. Offset must be a number > 0 and < {}",
-            format_usize(ROM_SIZE, style),
+            format_u64(game.image_size, style),
         )))?;
 
-    let (game, _) = try_to_open_game(header_path.as_ref(), 0)?;
     let layout = game.rom_layout();
-    let section = layout.find_offset(offset)
+    let resolved = layout.resolve(offset)
         .ok_or_else(|| AppError::new("There isn't any data at this offset."))?;
 
-    section.print_info(style);
+    resolved.section().print_info(style);
+    print_offset_details(resolved, offset, style);
     Ok(())
 }
 
+// Where `offset` on the ROM is in the section it's in
+fn print_offset_details(resolved: ResolvedSection, offset: u64, style: NumberStyle) {
+    println!("Offset from start of section: {}", format_u64(resolved.section().relative_offset(offset), style));
+    match resolved.fst_index(offset) {
+        Some(i) => println!("FST entry: {}", i),
+        None => if let ResolvedSection::Fst(_) = resolved {
+            println!("In the FST's string table");
+        },
+    }
+    if let Some(address) = resolved.address(offset) {
+        println!("Loaded at: {}", format_u64(address, style));
+    }
+}
+
 fn find_mem_addr(path: impl AsRef<Path>, mem_addr: &str, style: NumberStyle) -> AppResult {
     let mem_addr = parse_as_u64(mem_addr)
         .map_err(|_| AppError::new("Invalid address. Must be an integer."))?;
 
     let (game, _) = try_to_open_game(path.as_ref(), 0)?;
 
-    let seg = game.dol.segment_at_addr(mem_addr)
+    let seg = game.dol.segment_at_address(mem_addr)
         .ok_or_else(|| AppError::new("No DOL segment will be loaded at this address."))?;
 
     let offset = mem_addr - seg.loading_address;
@@ -257,6 +2589,332 @@ fn find_mem_addr(path: impl AsRef<Path>, mem_addr: &str, style: NumberStyle) ->
     Ok(())
 }
 
+// Prints the stats as JSON if there's no `style`
+// `rel` is the REL's path in the ROM, unless `path` is a REL itself
+fn print_rel_info(path: impl AsRef<Path>, rel: Option<&str>, style: Option<NumberStyle>) -> AppResult {
+    let path = path.as_ref();
+    let header = match rel {
+        Some(rel) => {
+            let (game, mut iso) = try_to_open_game(path, 0)?;
+            game.rel_header(&mut iso, rel)
+                .map_err(|e| AppError::new(format!("Couldn't read {}: {}", rel, e)))?
+        },
+        None if is_rel_path(path) => {
+            let file = File::open(path)
+                .map_err(|_| AppError::new(format!("Couldn't open {}.", path.display())))?;
+            RelHeader::new(BufReader::new(file))
+                .map_err(|e| AppError::new(format!("Couldn't read {}: {}", path.display(), e)))?
+        },
+        None => return Err(AppError::new("Which REL? Give its path in the ROM, like rels/stage.rel.")),
+    };
+    match style {
+        Some(style) => header.print_info(style),
+        None => {
+            let stdout = io::stdout();
+            serde_json::to_writer_pretty(stdout.lock(), &header)
+                .map_err(|e| AppError::new(format!("Couldn't write the header: {}", e)))?;
+            println!();
+        },
+    }
+    Ok(())
+}
+
+fn print_stats(rom_path: impl AsRef<Path>, style: Option<NumberStyle>) -> AppResult {
+    let (game, _) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let stats = game.stats();
+    let style = match style {
+        Some(s) => s,
+        None => {
+            let stdout = io::stdout();
+            serde_json::to_writer_pretty(stdout.lock(), &stats)
+                .map_err(|e| AppError::new(format!("Couldn't write the stats: {}", e)))?;
+            println!();
+            return Ok(());
+        },
+    };
+
+    println!("Files: {} in {} directories", stats.files, stats.directories);
+    println!("File data: {} bytes", format_u64(stats.file_bytes, style));
+    println!("System data: {} bytes", format_u64(stats.system_bytes, style));
+    println!("Padding: {} bytes", format_u64(stats.padding_bytes, style));
+    println!("Free: {} bytes", format_u64(stats.free_bytes, style));
+    println!("Used: {:.1}% of a full size ROM", stats.used_percent);
+
+    println!("\nLargest files:");
+    for f in &stats.largest_files {
+        println!("{:>12} {}", format_u64(f.size, style), f.path.display());
+    }
+    println!("\nBy extension:");
+    for e in &stats.extensions {
+        let name = if e.extension.is_empty() { "(none)".to_string() } else { format!(".{}", e.extension) };
+        println!("{:>12} {:<10} {} files", format_u64(e.bytes, style), name, e.files);
+    }
+    Ok(())
+}
+
+fn check_rom(rom_path: impl AsRef<Path>) -> AppResult {
+    let (game, _) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let issues = game.validate().iter().map(|i| i.to_string())
+        .chain(game.dol.validate().iter().map(|i| i.to_string()))
+        .collect::<Vec<_>>();
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    if issues.is_empty() {
+        println!("No problems found.");
+        Ok(())
+    } else {
+        Err(AppError::new(format!("Found {} problems.", issues.len())))
+    }
+}
+
+fn export_fst(rom_path: impl AsRef<Path>, output: &str) -> AppResult {
+    let (game, _) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let file = File::create(output)
+        .map_err(|_| AppError::new(format!("Couldn't create {}.", output)))?;
+    serde_json::to_writer_pretty(io::BufWriter::new(file), &game.export_fst())
+        .map_err(|e| AppError::new(format!("Couldn't write the FST: {}", e)))
+}
+
+fn export_filelist(rom_path: impl AsRef<Path>, output: &str) -> AppResult {
+    let (game, _) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let mut file = io::BufWriter::new(File::create(output)
+        .map_err(|_| AppError::new(format!("Couldn't create {}.", output)))?);
+    game.fst.write_gcr_filelist(&mut file)
+        .and_then(|_| file.flush())
+        .map_err(|e| AppError::new(format!("Couldn't write the file list: {}", e)))
+}
+
+fn import_fst(json_path: &str, output: &str) -> AppResult {
+    let file = File::open(json_path)
+        .map_err(|e| AppError::new(format!("Couldn't open {}: {}", json_path, e)))?;
+    let json: FstJson = serde_json::from_reader(io::BufReader::new(file))
+        .map_err(|e| AppError::new(format!("Couldn't read {}: {}", json_path, e)))?;
+    let fst = json.to_fst()
+        .map_err(|e| AppError::new(format!("Couldn't make an FST from {}: {}", json_path, e)))?;
+
+    let issues = json.validate(&fst);
+    if !issues.is_empty() {
+        for issue in &issues {
+            println!("{}", issue);
+        }
+        return Err(AppError::new(format!("Found {} problems, so nothing was written.", issues.len())));
+    }
+
+    let file = File::create(output)
+        .map_err(|_| AppError::new(format!("Couldn't create {}.", output)))?;
+    json.write_fst(&fst, file)?;
+    Ok(())
+}
+
+fn at_offset(
+    rom_path: impl AsRef<Path>,
+    offset: &str,
+    output: Option<&str>,
+    style: NumberStyle,
+) -> AppResult {
+    let offset = parse_as_u64(offset)
+        .map_err(|_| AppError::new("Invalid offset. Must be an integer."))?;
+
+    let (game, mut iso) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let rom_size = game.image_size;
+    if offset >= rom_size {
+        return Err(AppError::new(format!(
+            "The offset is past the end of the ROM, which is {} bytes.",
+            format_u64(rom_size, style),
+        )));
+    }
+
+    let layout = game.rom_layout();
+    let resolved = match layout.resolve(offset) {
+        Some(r) => r,
+        None => {
+            // Always there, since the offset is on the ROM but not in a section
+            let gap = layout.gap_at(offset, rom_size).unwrap();
+            println!("Nothing is at this offset. It's in unused space:");
+            println!("Start: {}", format_u64(gap.start, style));
+            println!("End: {}", format_u64(gap.end - 1, style));
+            println!("Size: {}", format_u64(gap.size, style));
+            if let Some(s) = gap.before {
+                println!("Before it: {} ({}, ends at {})", s.name(), s.section_type(), format_u64(s.end(), style));
+            }
+            if let Some(s) = gap.after {
+                println!("After it: {} ({}, starts at {})", s.name(), s.section_type(), format_u64(s.start(), style));
+            }
+            return Err(AppError::new("There isn't any data at this offset."));
+        },
+    };
+
+    let section = resolved.section();
+    println!("Name: {}", section.name());
+    println!("Type: {}", section.section_type());
+    println!("Start: {}", format_u64(section.start(), style));
+    println!("End: {}", format_u64(section.end(), style));
+    print_offset_details(resolved, offset, style);
+
+    if let Some(output) = output {
+        let mut file = io::BufWriter::new(File::create(output)
+            .map_err(|e| AppError::new(format!("Couldn't create {}: {}", output, e)))?);
+        section.extract(&mut iso, &mut file)
+            .and_then(|_| file.flush())
+            .map_err(|e| AppError::new(format!("Couldn't extract {}: {}", section.name(), e)))?;
+        info!("Wrote {} to {}.", section.name(), output);
+    }
+
+    Ok(())
+}
+
+// How much `dump` prints from an offset on the ROM when there's no length
+const DEFAULT_DUMP_LENGTH: u64 = 0x100;
+
+fn dump(
+    rom_path: impl AsRef<Path>,
+    section_name: Option<&str>,
+    (offset, length): (Option<&str>, Option<&str>),
+    style: NumberStyle,
+) -> AppResult {
+    let offset = offset.map(|o| parse_as_u64(o)
+        .map_err(|_| AppError::new("Invalid offset. Must be an integer."))
+    ).transpose()?;
+    let length = length.map(|l| parse_as_u64(l)
+        .map_err(|_| AppError::new("Invalid length. Must be an integer."))
+    ).transpose()?;
+
+    let (game, mut iso) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let stdout = io::stdout();
+    let mut output = io::BufWriter::new(stdout.lock());
+
+    let result = match section_name {
+        Some(name) => {
+            let section = game.section_with_name(name)?;
+            let size = section.size() as u64;
+            let start = offset.unwrap_or(0);
+            let end = length.map_or(size, |l| start.saturating_add(l));
+            if end > size {
+                warn!(
+                    "{} is only {} bytes, so the dump stops at the end of it.",
+                    section.name(),
+                    format_u64(size, style),
+                );
+            }
+            section.hexdump(&mut iso, &mut output, Some(start..end), style)
+        },
+        None => {
+            // clap makes sure there's an offset if there isn't a section
+            let start = offset.unwrap();
+            let rom_size = game.image_size;
+            let end = start.saturating_add(length.unwrap_or(DEFAULT_DUMP_LENGTH));
+            if end > rom_size {
+                warn!(
+                    "The ROM is only {} bytes, so the dump stops at the end of it.",
+                    format_u64(rom_size, style),
+                );
+            }
+            let start = start.min(rom_size);
+            iso.seek(SeekFrom::Start(start))
+                .and_then(|_| hexdump((&mut iso).take(end.min(rom_size) - start), &mut output, start, style))
+        },
+    };
+    result
+        .and_then(|_| output.flush())
+        .map_err(|e| AppError::new(format!("Couldn't write the dump: {}", e)))
+}
+
+// How many hits `search` prints when there's no limit
+const DEFAULT_SEARCH_LIMIT: usize = 100;
+
+fn search(
+    rom_path: impl AsRef<Path>,
+    string: Option<&str>,
+    hex: Option<&str>,
+    (limit, skip): (Option<&str>, Option<&str>),
+    style: NumberStyle,
+) -> AppResult {
+    let pattern = match (string, hex) {
+        (Some(s), _) => SearchPattern::string(s),
+        // clap makes sure there's one or the other
+        (None, h) => SearchPattern::parse_hex(h.unwrap()),
+    }.map_err(|e| AppError::new(format!("Invalid search: {}", e)))?;
+    let limit = limit.map_or(Ok(DEFAULT_SEARCH_LIMIT), |l| parse_as_u64(l).map(|l| l as usize))
+        .map_err(|_| AppError::new("Invalid limit. Must be an integer."))?;
+    let skip = skip.map_or(Ok(0), |s| parse_as_u64(s).map(|s| s as usize))
+        .map_err(|_| AppError::new("Invalid number of hits to skip. Must be an integer."))?;
+
+    let (game, mut iso) = try_to_open_game(rom_path.as_ref(), 0)?;
+    info!("Searching...");
+    let hits = game.search(&mut iso, &pattern)
+        .map_err(|e| AppError::new(format!("Couldn't search the ROM: {}", e)))?;
+
+    let shown = hits.iter().skip(skip).take(if limit == 0 { usize::MAX } else { limit });
+    let mut last_shown = skip;
+    for hit in shown {
+        match &hit.section {
+            Some(s) => println!(
+                "{}: {} ({}, {} bytes in{}{})",
+                format_u64(hit.offset, style),
+                s.name,
+                s.section_type,
+                format_u64(s.offset, style),
+                s.fst_index.map_or(String::new(), |i| format!(", FST entry {}", i)),
+                s.address.map_or(String::new(), |a| format!(", loaded at {}", format_u64(a, style))),
+            ),
+            None => println!("{}: (unused)", format_u64(hit.offset, style)),
+        }
+        last_shown += 1;
+    }
+
+    match hits.len() {
+        0 => println!("No hits."),
+        1 => println!("1 hit."),
+        n => println!("{} hits.", n),
+    }
+    if last_shown < hits.len() {
+        println!("Use --skip {} to see the rest.", last_shown);
+    }
+    Ok(())
+}
+
+fn locate_address(path: impl AsRef<Path>, address: &str, style: NumberStyle) -> AppResult {
+    let address = parse_as_u64(address)
+        .map_err(|_| AppError::new("Invalid address. Must be an integer."))?;
+
+    let mut f = source::open(path.as_ref())
+        .map_err(|e| AppError::new(format!("Couldn't open file: {}", e)))?;
+    let dol = match Game::open(&mut f, 0) {
+        Ok(game) => game.dol,
+        Err(_) => DOLHeader::new(&mut f, 0)
+            .map_err(|_| AppError::new("Invalid iso or DOL"))?,
+    };
+
+    let offset = match dol.address_to_offset(address) {
+        Some(AddressOffset::File(offset)) => offset,
+        Some(AddressOffset::Bss) => {
+            println!("Segment: .bss");
+            println!("The BSS is cleared when the game boots, so this address isn't loaded from the file.");
+            return Ok(());
+        },
+        None => return Err(AppError::new("No DOL segment will be loaded at this address.")),
+    };
+    let seg = dol.segment_at_address(address).unwrap();
+
+    println!("Segment: {}", seg.to_string());
+    println!("Offset from start of segment: {}", format_u64(address - seg.loading_address, style));
+    println!("Offset in the DOL: {}", format_u64(offset - dol.offset, style));
+    if dol.offset != 0 {
+        println!("Offset on the ROM: {}", format_u64(offset, style));
+    }
+
+    if seg.seg_type == SegmentType::Text {
+        // Instructions are always word aligned
+        let ins_offset = offset - (address & 3);
+        let mut opcode = [0; 4];
+        f.seek(SeekFrom::Start(ins_offset))?;
+        f.read_exact(&mut opcode)?;
+        println!("Instruction: {}", decode(u32::from_be_bytes(opcode), address & !3));
+    }
+    Ok(())
+}
+
 fn extract_section(
     iso_path: impl AsRef<Path>,
     section_filename: impl AsRef<Path>,
@@ -264,6 +2922,8 @@ fn extract_section(
 ) -> AppResult {
     let (game, mut iso) = try_to_open_game(iso_path.as_ref(), 0)?;
 
+    warn_about_capitalization(&game, section_filename.as_ref());
+
     let result = game.extract_section_with_name(
         section_filename,
         output.as_ref(),
@@ -271,39 +2931,240 @@ fn extract_section(
     );
 
     match result {
-        Ok(true) => Ok(()),
-        Ok(false) => Err(AppError::new("Couldn't find a section with that name.")),
-        Err(_) => Err(AppError::new("Error extracting section.")),
+        Ok(ExtractOutcome::Extracted(SectionType::Directory { files, .. })) => {
+            println!("{} files written.", files);
+            Ok(())
+        },
+        Ok(ExtractOutcome::Extracted(_)) => Ok(()),
+        Ok(ExtractOutcome::NotFound) => Err(AppError::new("Couldn't find a section with that name.")),
+        Err(e) => Err(AppError::new(format!("Error extracting section: {}", e))),
+    }
+}
+
+// System files are found by their names before anything in the FST, so
+// there's nothing to warn about for those
+fn warn_about_capitalization(game: &Game, section_filename: &Path) {
+    if section_filename.to_string_lossy().parse::<UniqueSectionType>().is_ok() { return }
+    if let Some((e, PathMatch::IgnoringCase)) = game.fst.resolve_path(section_filename) {
+        warn!("using {}, which has different capitalization.", e.info().full_path.display());
+    }
+}
+
+// Everything besides the data goes to stderr here, so it can be piped into
+// something else. To decompress it, the whole section's read into memory
+// first.
+fn extract_section_to_stdout(
+    iso_path: impl AsRef<Path>,
+    section_filename: impl AsRef<Path>,
+    decompress: bool,
+) -> AppResult {
+    let (game, mut iso) = try_to_open_game(iso_path.as_ref(), 0)?;
+
+    warn_about_capitalization(&game, section_filename.as_ref());
+
+    let stdout = io::stdout();
+    let mut data = Vec::new();
+    let result = if decompress {
+        game.extract_section_to(section_filename, &mut data, &mut iso)
+    } else {
+        game.extract_section_to(section_filename, io::BufWriter::new(stdout.lock()), &mut iso)
+    };
+    match result {
+        Ok(ExtractOutcome::Extracted(_)) => {},
+        Ok(ExtractOutcome::NotFound) => return Err(AppError::new("Couldn't find a section with that name.")),
+        Err(e) => return Err(AppError::new(format!("Error extracting section: {}", e))),
     }
+    if decompress {
+        if let Some(c) = Compression::detect(&data) {
+            data = c.decompress(&data)
+                .map_err(|e| AppError::new(format!("Couldn't decompress the section: {}", e)))?;
+        }
+        stdout.lock().write_all(&data)?;
+    }
+    Ok(())
+}
+
+fn extract_matching_files(
+    rom_path: impl AsRef<Path>,
+    pattern: &str,
+    output: impl AsRef<Path>,
+    decompress: Option<Option<&str>>,
+) -> AppResult {
+    let (mut game, mut iso) = try_to_open_game(rom_path.as_ref(), 0)?;
+    let written = game.extract_matching(&mut iso, pattern, output.as_ref())
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => AppError::new(format!("No files match {}.", pattern)),
+            _ => AppError::new(format!("Error extracting files: {}", e)),
+        })?;
+
+    for path in &written {
+        let decompressed = match decompress.map(|suffix| decompress_file(path, suffix)) {
+            Some(Ok(d)) => d,
+            Some(Err(e)) => {
+                warn!("{} couldn't be decompressed, so it was left as it was: {}", path.display(), e);
+                None
+            },
+            None => None,
+        };
+        match decompressed {
+            Some((c, path)) => println!("{} (decompressed from {})", path.display(), c),
+            None => println!("{}", path.display()),
+        }
+    }
+    println!("{} files written.", written.len());
+    Ok(())
 }
 
-fn ls_files(rom_path: impl AsRef<Path>, dir: Option<impl AsRef<Path>>, long_format: bool) -> AppResult {
-    let (game, _) = try_to_open_game(rom_path, 0)?;
+fn ls_files(rom_path: impl AsRef<Path>, dir: Option<impl AsRef<Path>>, long_format: bool, identify: bool) -> AppResult {
+    let (game, mut iso) = try_to_open_game(rom_path, 0)?;
     let dir = match dir {
         Some(p) => game.fst.entry_for_path(p).and_then(|e| e.as_dir()),
         None => Some(game.fst.root()),
     };
 
     if let Some(d) = dir {
-        game.print_directory(d, long_format);
+        let files = d.iter_contents(&game.fst.entries).filter_map(|e| e.as_file());
+        let kinds = if identify { identify_files(&game, &mut iso, files)? } else { HashMap::new() };
+        game.print_directory(d, long_format, &kinds);
         Ok(())
     } else {
         Err(AppError::new("No directory with that name/path exists"))
     }
 }
 
-fn try_to_open_game<P>(path: P, offset: u64) -> Result<(Game, BufReader<File>), AppError>
+fn print_tree(rom_path: impl AsRef<Path>, dir: Option<impl AsRef<Path>>, opts: TreeOptions, identify: bool) -> AppResult {
+    let (game, mut iso) = try_to_open_game(rom_path, 0)?;
+    let dir = match dir {
+        Some(p) => game.fst.entry_for_path(p).and_then(|e| e.as_dir()),
+        None => Some(game.fst.root()),
+    }.ok_or_else(|| AppError::new("No directory with that name/path exists"))?;
+
+    let kinds = if identify {
+        let files = game.fst.entries.iter()
+            .filter_map(|e| e.as_file())
+            .filter(|f| f.info.full_path.starts_with(&dir.info.full_path));
+        identify_files(&game, &mut iso, files)?
+    } else {
+        HashMap::new()
+    };
+    let stdout = io::stdout();
+    game.fst.write_subtree(dir, io::BufWriter::new(stdout.lock()), opts, &kinds)?;
+    Ok(())
+}
+
+fn identify_files<'a>(
+    game: &Game,
+    iso: &mut dyn ImageSource,
+    files: impl IntoIterator<Item = &'a FileEntry>,
+) -> Result<HashMap<usize, FileKind>, AppError> {
+    game.identify_files(iso, files)
+        .map_err(|e| AppError::new(format!("Couldn't read the files to identify them: {}", e)))
+}
+
+// Lists everything under `dir`, as JSON if `style` is `None`, otherwise as a
+// table
+fn list_files(
+    rom_path: impl AsRef<Path>,
+    dir: Option<impl AsRef<Path>>,
+    by_offset: bool,
+    style: Option<NumberStyle>,
+    identify: bool,
+) -> AppResult {
+    let (game, mut iso) = try_to_open_game(rom_path, 0)?;
+    let dir = match dir {
+        Some(p) => game.fst.entry_for_path(p).and_then(|e| e.as_dir()),
+        None => Some(game.fst.root()),
+    }.ok_or_else(|| AppError::new("No directory with that name/path exists"))?;
+
+    let listing = if by_offset {
+        game.fst.to_listing_by_offset()
+    } else {
+        game.fst.to_listing()
+    };
+    let mut listing = listing.into_iter()
+        .filter(|l| l.path.starts_with(&dir.info.full_path) && l.path != dir.info.full_path)
+        .collect::<Vec<_>>();
+    if identify {
+        let files = listing.iter().filter_map(|l| game.fst.entries[l.index].as_file());
+        let kinds = identify_files(&game, &mut iso, files)?;
+        for l in &mut listing {
+            l.kind = kinds.get(&l.index).cloned();
+        }
+    }
+
+    let style = match style {
+        Some(s) => s,
+        None => {
+            let stdout = io::stdout();
+            serde_json::to_writer_pretty(stdout.lock(), &listing)
+                .map_err(|e| AppError::new(format!("Couldn't write the listing: {}", e)))?;
+            println!();
+            return Ok(());
+        },
+    };
+
+    let kind_column = |kind: String| if identify { format!("{:<7}  ", kind) } else { String::new() };
+    println!("{:>12} {:>12}  {}Path", "Offset", "Size", kind_column("Type".to_string()));
+    for l in &listing {
+        let offset = l.offset.map_or("-".to_string(), |o| format_u64(o, style));
+        let size = l.size.map_or("-".to_string(), |s| format_usize(s, style));
+        let kind = kind_column(l.kind.map_or("-".to_string(), |k| k.to_string()));
+        println!("{:>12} {:>12}  {}{}", offset, size, kind, l.path.display());
+    }
+    Ok(())
+}
+
+// Like `try_to_open_game`, but if `disc` is given, `path` is a WBFS file and
+// it's the number of the disc in it to open, starting at 1
+fn try_to_open_disc(path: &Path, disc: Option<&str>) -> Result<(Game, Box<dyn ImageSource>), AppError> {
+    let disc = match disc {
+        Some(d) => parse_as_u64(d).ok().filter(|&d| d > 0)
+            .ok_or_else(|| AppError::new("Invalid disc number. Discs are numbered starting at 1."))?,
+        None => return try_to_open_game(path, 0),
+    };
+    let mut iso: Box<dyn ImageSource> = WbfsFile::open(path)
+        .and_then(|wbfs| wbfs.into_disc(disc as usize - 1))
+        .map(Box::new)
+        .map_err(|e| AppError::new(format!("Couldn't open disc {} of {}: {}", disc, path.display(), e)))?;
+    Game::open(&mut iso, 0)
+        .map(|game| (game, iso))
+        .map_err(|e| AppError::new(format!("Invalid iso: disc {} of {}: {}.", disc, path.display(), e)))
+}
+
+// Lists the discs in a WBFS file if there's more than one, since it's not
+// clear which one to show. Returns whether it did.
+fn print_wbfs_discs(path: &Path) -> Result<bool, AppError> {
+    let wbfs = WbfsFile::open(path)
+        .map_err(|e| AppError::new(format!("Couldn't open {}: {}", path.display(), e)))?;
+    let discs = wbfs.discs();
+    if discs.len() < 2 { return Ok(false) }
+    println!("{} has {} discs:", path.display(), discs.len());
+    for (i, d) in discs.iter().enumerate() {
+        let kind = if d.is_gamecube { "" } else { " (Wii)" };
+        println!("{:>4}: {} {}{}", i + 1, d.game_id, d.title, kind);
+    }
+    println!("Use --disc to pick one.");
+    Ok(true)
+}
+
+fn try_to_open_game<P>(path: P, offset: u64) -> Result<(Game, Box<dyn ImageSource>), AppError>
 where
     P: AsRef<Path>,
 {
     let path = path.as_ref();
-    if !path.exists() {
-        return Err(AppError::new(format!("The iso {} doesn't exist.", path.display())));
+    let mut iso = source::open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound =>
+            AppError::new(format!("The iso {} doesn't exist.", path.display())),
+        _ => AppError::new(format!("Couldn't open {}: {}", path.display(), e)),
+    })?;
+    // An extracted ROM only has its system data, which `source::open` reads
+    // like an image
+    if path.is_dir() {
+        return Game::open_dir(path)
+            .map(|game| (game, iso))
+            .map_err(|e| AppError::new(format!("Invalid extracted ROM: {}: {}.", path.display(), e)));
     }
-
-    let iso = File::open(path).expect("Couldn't open file");
-    let mut iso = BufReader::new(iso);
     Game::open(&mut iso, offset)
         .map(|game| (game, iso))
-        .map_err(|_| AppError::new(format!("Invalid iso: {}.", path.display())))
+        .map_err(|e| AppError::new(format!("Invalid iso: {}: {}.", path.display(), e)))
 }
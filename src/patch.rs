@@ -0,0 +1,804 @@
+// BPS patches, the format most ROM hacks are distributed in, since sharing the
+// hacked ROM itself isn't legal. The format's documented here:
+// https://www.romhacking.net/documents/746/
+// Older IPS patches can be applied too, but not made.
+
+use std::cmp::max;
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+
+use crc32fast::Hasher as Crc32;
+
+use ::read_fully;
+
+pub const BPS_MAGIC: &[u8] = b"BPS1";
+pub const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+
+const SOURCE_READ: u64 = 0;
+const TARGET_READ: u64 = 1;
+const SOURCE_COPY: u64 = 2;
+const TARGET_COPY: u64 = 3;
+
+// The source is indexed in blocks this big to find data that moved. Smaller
+// blocks find more matches, but a full ROM has a lot of them.
+const BLOCK_SIZE: usize = 1024;
+// Shorter matches aren't worth interrupting a run of new bytes for
+const MIN_MATCH: u64 = 8;
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+// New bytes are written in pieces at most this big, so they don't all have
+// to be held in memory
+const MAX_TARGET_READ: usize = 1024 * 1024;
+// How much of the output is kept around while applying a patch, for actions
+// that copy from earlier in the output. Patches made by gcmod only copy the
+// previous byte, and most others don't reach back far either.
+const TARGET_HISTORY: usize = 64 * 1024 * 1024;
+
+const HASH_BASE: u32 = 0x0100_0193;
+// What the first byte in a block is multiplied by in its hash
+const FIRST_BYTE_FACTOR: u32 = {
+    let mut factor = 1u32;
+    let mut i = 1;
+    while i < BLOCK_SIZE {
+        factor = factor.wrapping_mul(HASH_BASE);
+        i += 1;
+    }
+    factor
+};
+
+// Writes a BPS patch that turns `original` into `modified`. Neither file is
+// read into memory, so this works on full ROMs.
+pub fn create_bps(
+    mut original: impl Read + Seek,
+    mut modified: impl Read + Seek,
+    output: impl Write,
+) -> io::Result<()> {
+    let source_size = original.seek(SeekFrom::End(0))?;
+    let target_size = modified.seek(SeekFrom::End(0))?;
+    original.seek(SeekFrom::Start(0))?;
+    modified.seek(SeekFrom::Start(0))?;
+
+    let (index, source_crc) = index_source(&mut original)?;
+    let mut source = SourceCache { reader: original, buf: Vec::new(), start: 0, size: source_size };
+    let mut target = TargetWindow {
+        reader: modified,
+        buf: Vec::new(),
+        start: 0,
+        floor: 0,
+        eof: false,
+        crc: Crc32::new(),
+    };
+    let mut encoder = Encoder {
+        output: CrcWriter { inner: BufWriter::new(output), crc: Crc32::new() },
+        new_bytes: Vec::new(),
+        source_relative: 0,
+        target_relative: 0,
+    };
+
+    encoder.output.write_all(BPS_MAGIC)?;
+    encoder.write_number(source_size)?;
+    encoder.write_number(target_size)?;
+    // No metadata
+    encoder.write_number(0)?;
+
+    let mut p = 0;
+    // The hash of the `BLOCK_SIZE` bytes at `p`, if it's been worked out
+    let mut hash = None;
+    while p < target_size {
+        target.discard_before(p.saturating_sub(1));
+
+        // The same bytes in the same place, which is most of a ROM hack
+        let len = match_length(&mut target, p, &mut source, p)?;
+        if len >= MIN_MATCH {
+            encoder.source_read(len)?;
+            p += len;
+            hash = None;
+            continue;
+        }
+
+        // Whatever comes after the last thing copied from the source
+        let next = encoder.source_relative;
+        if next != p {
+            let len = match_length(&mut target, p, &mut source, next)?;
+            if len >= MIN_MATCH {
+                encoder.source_copy(next, len)?;
+                p += len;
+                hash = None;
+                continue;
+            }
+        }
+
+        // The same byte over and over, like padding that isn't in the source
+        if p > 0 {
+            let len = run_length(&mut target, p)?;
+            if len >= MIN_MATCH {
+                encoder.target_copy(p - 1, len)?;
+                p += len;
+                hash = None;
+                continue;
+            }
+        }
+
+        // Something that moved somewhere else in the source
+        let (byte, next_byte) = {
+            let window = target.slice(p, BLOCK_SIZE + 1)?;
+            if window.len() >= BLOCK_SIZE {
+                hash = hash.or_else(|| Some(block_hash(&window[..BLOCK_SIZE])));
+            } else {
+                hash = None;
+            }
+            (window[0], window.get(BLOCK_SIZE).cloned())
+        };
+        if let Some(&from) = hash.and_then(|h| index.get(&h)) {
+            let len = match_length(&mut target, p, &mut source, from)?;
+            if len >= BLOCK_SIZE as u64 {
+                encoder.source_copy(from, len)?;
+                p += len;
+                hash = None;
+                continue;
+            }
+        }
+
+        encoder.target_read(byte)?;
+        hash = match (hash, next_byte) {
+            (Some(h), Some(next)) => Some(roll_hash(h, byte, next)),
+            _ => None,
+        };
+        p += 1;
+    }
+    encoder.flush_target_read()?;
+
+    let target_crc = target.finish()?;
+    encoder.output.write_all(&source_crc.to_le_bytes())?;
+    encoder.output.write_all(&target_crc.to_le_bytes())?;
+    let CrcWriter { mut inner, crc } = encoder.output;
+    inner.write_all(&crc.finalize().to_le_bytes())?;
+    inner.flush()
+}
+
+// Maps the hash of every `BLOCK_SIZE`-aligned block in the source to where
+// it is, and works out the source's CRC32 along the way
+fn index_source(mut source: impl Read) -> io::Result<(HashMap<u32, u64>, u32)> {
+    let mut index = HashMap::new();
+    let mut crc = Crc32::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut offset = 0;
+    loop {
+        let read = read_fully(&mut source, &mut buf)?;
+        crc.update(&buf[..read]);
+        for block in buf[..read].chunks(BLOCK_SIZE).filter(|b| b.len() == BLOCK_SIZE) {
+            index.entry(block_hash(block)).or_insert(offset);
+            offset += BLOCK_SIZE as u64;
+        }
+        if read < buf.len() { break }
+    }
+    Ok((index, crc.finalize()))
+}
+
+fn block_hash(block: &[u8]) -> u32 {
+    block.iter().fold(0u32, |h, &b| h.wrapping_mul(HASH_BASE).wrapping_add(b as u32))
+}
+
+// Slides the hash forward a byte, dropping `old` off the front and adding
+// `new` to the end
+fn roll_hash(hash: u32, old: u8, new: u8) -> u32 {
+    hash.wrapping_sub((old as u32).wrapping_mul(FIRST_BYTE_FACTOR))
+        .wrapping_mul(HASH_BASE)
+        .wrapping_add(new as u32)
+}
+
+// How many bytes starting at `target_offset` in the target are the same as
+// the ones starting at `source_offset` in the source
+fn match_length<T: Read, S: Read + Seek>(
+    target: &mut TargetWindow<T>,
+    target_offset: u64,
+    source: &mut SourceCache<S>,
+    source_offset: u64,
+) -> io::Result<u64> {
+    let mut len = 0;
+    loop {
+        let t = target.slice(target_offset + len, 1)?;
+        if t.is_empty() { break }
+        let s = source.read_at(source_offset + len, 1)?;
+        let same = t.iter().zip(s).take_while(|&(a, b)| a == b).count();
+        len += same as u64;
+        if same < t.len() { break }
+        // Every caller takes a match this long, so there's no going back
+        if len >= BLOCK_SIZE as u64 {
+            target.discard_before(target_offset + len - 1);
+        }
+    }
+    Ok(len)
+}
+
+// How many times the byte before `offset` repeats from `offset` on
+fn run_length<T: Read>(target: &mut TargetWindow<T>, offset: u64) -> io::Result<u64> {
+    let byte = target.slice(offset - 1, 1)?[0];
+    let mut len = 0;
+    loop {
+        let t = target.slice(offset + len, 1)?;
+        if t.is_empty() { break }
+        let same = t.iter().take_while(|&&b| b == byte).count();
+        len += same as u64;
+        if same < t.len() { break }
+        if len >= BLOCK_SIZE as u64 {
+            target.discard_before(offset + len - 1);
+        }
+    }
+    Ok(len)
+}
+
+// Reads the source in big pieces, since most reads are near the last one
+struct SourceCache<R> {
+    reader: R,
+    buf: Vec<u8>,
+    start: u64,
+    size: u64,
+}
+
+impl<R: Read + Seek> SourceCache<R> {
+    // At least `min` bytes starting at `offset`, unless the source ends first
+    fn read_at(&mut self, offset: u64, min: usize) -> io::Result<&[u8]> {
+        if offset >= self.size {
+            return Ok(&[]);
+        }
+        let end = self.start + self.buf.len() as u64;
+        let cached = offset >= self.start && offset < end &&
+            (offset + min as u64 <= end || end == self.size);
+        if !cached {
+            self.reader.seek(SeekFrom::Start(offset))?;
+            self.buf.resize(max(min, CHUNK_SIZE), 0);
+            let read = read_fully(&mut self.reader, &mut self.buf)?;
+            self.buf.truncate(read);
+            self.start = offset;
+        }
+        Ok(&self.buf[(offset - self.start) as usize..])
+    }
+}
+
+// The part of the target that's being looked at. It's read in order, once,
+// and bytes are only dropped once they're before the floor.
+struct TargetWindow<R> {
+    reader: R,
+    buf: Vec<u8>,
+    start: u64,
+    floor: u64,
+    eof: bool,
+    crc: Crc32,
+}
+
+impl<R: Read> TargetWindow<R> {
+    // Everything that's been read from `offset` on, which is at least `min`
+    // bytes unless the target ends first
+    fn slice(&mut self, offset: u64, min: usize) -> io::Result<&[u8]> {
+        while !self.eof && self.start + (self.buf.len() as u64) < offset + min as u64 {
+            let unneeded = (self.floor.min(offset) - self.start) as usize;
+            self.buf.drain(..unneeded);
+            self.start += unneeded as u64;
+
+            let old_len = self.buf.len();
+            self.buf.resize(old_len + CHUNK_SIZE, 0);
+            let read = read_fully(&mut self.reader, &mut self.buf[old_len..])?;
+            self.buf.truncate(old_len + read);
+            self.crc.update(&self.buf[old_len..]);
+            self.eof = read < CHUNK_SIZE;
+        }
+        let i = ((offset - self.start) as usize).min(self.buf.len());
+        Ok(&self.buf[i..])
+    }
+
+    fn discard_before(&mut self, offset: u64) {
+        self.floor = max(self.floor, offset);
+    }
+
+    // The CRC32 of the whole target
+    fn finish(mut self) -> io::Result<u32> {
+        let mut rest = CrcWriter { inner: io::sink(), crc: self.crc };
+        io::copy(&mut self.reader, &mut rest)?;
+        Ok(rest.crc.finalize())
+    }
+}
+
+struct Encoder<W: Write> {
+    output: CrcWriter<W>,
+    // Bytes that weren't found anywhere, waiting to be written
+    new_bytes: Vec<u8>,
+    source_relative: u64,
+    target_relative: u64,
+}
+
+impl<W: Write> Encoder<W> {
+    fn write_number(&mut self, mut n: u64) -> io::Result<()> {
+        loop {
+            let low = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                return self.output.write_all(&[0x80 | low]);
+            }
+            self.output.write_all(&[low])?;
+            n -= 1;
+        }
+    }
+
+    fn write_offset(&mut self, from: u64, relative: u64) -> io::Result<()> {
+        let delta = from as i64 - relative as i64;
+        self.write_number((delta.unsigned_abs() << 1) | (delta < 0) as u64)
+    }
+
+    fn action(&mut self, kind: u64, len: u64) -> io::Result<()> {
+        self.flush_target_read()?;
+        self.write_number(((len - 1) << 2) | kind)
+    }
+
+    fn source_read(&mut self, len: u64) -> io::Result<()> {
+        self.action(SOURCE_READ, len)
+    }
+
+    fn source_copy(&mut self, from: u64, len: u64) -> io::Result<()> {
+        self.action(SOURCE_COPY, len)?;
+        let relative = self.source_relative;
+        self.write_offset(from, relative)?;
+        self.source_relative = from + len;
+        Ok(())
+    }
+
+    fn target_copy(&mut self, from: u64, len: u64) -> io::Result<()> {
+        self.action(TARGET_COPY, len)?;
+        let relative = self.target_relative;
+        self.write_offset(from, relative)?;
+        self.target_relative = from + len;
+        Ok(())
+    }
+
+    fn target_read(&mut self, byte: u8) -> io::Result<()> {
+        self.new_bytes.push(byte);
+        if self.new_bytes.len() >= MAX_TARGET_READ {
+            self.flush_target_read()?;
+        }
+        Ok(())
+    }
+
+    fn flush_target_read(&mut self) -> io::Result<()> {
+        if self.new_bytes.is_empty() {
+            return Ok(());
+        }
+        let bytes = std::mem::take(&mut self.new_bytes);
+        self.write_number(((bytes.len() as u64 - 1) << 2) | TARGET_READ)?;
+        self.output.write_all(&bytes)
+    }
+}
+
+// Keeps a CRC32 of everything written through it
+struct CrcWriter<W> {
+    inner: W,
+    crc: Crc32,
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Applies a BPS or IPS patch to `source`. A BPS patch has checksums of the
+// file it's for and the file it makes, and it's an error if either one
+// doesn't match.
+pub fn apply(patch: impl Read, source: impl Read + Seek, output: impl Write) -> io::Result<()> {
+    apply_patch(patch, source, output, true)
+}
+
+// Like `apply`, but it's not an error if the source or the result aren't what
+// the patch expects, for patches made from a slightly different dump
+pub fn apply_unchecked(patch: impl Read, source: impl Read + Seek, output: impl Write) -> io::Result<()> {
+    apply_patch(patch, source, output, false)
+}
+
+fn apply_patch(
+    mut patch: impl Read,
+    source: impl Read + Seek,
+    output: impl Write,
+    check: bool,
+) -> io::Result<()> {
+    let mut data = Vec::new();
+    patch.read_to_end(&mut data)?;
+    if data.starts_with(BPS_MAGIC) {
+        apply_bps(&data, source, output, check)
+    } else if data.starts_with(IPS_MAGIC) {
+        apply_ips(&data, source, output)
+    } else {
+        Err(invalid_patch("it isn't a BPS or IPS patch"))
+    }
+}
+
+fn apply_bps(
+    patch: &[u8],
+    mut source: impl Read + Seek,
+    output: impl Write,
+    check: bool,
+) -> io::Result<()> {
+    if patch.len() < BPS_MAGIC.len() + 3 + 12 {
+        return Err(invalid_patch("it's too short"));
+    }
+    let (body, footer) = patch.split_at(patch.len() - 12);
+    let footer_crc = |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+    let (source_crc, target_crc, patch_crc) = (footer_crc(0), footer_crc(4), footer_crc(8));
+    check_crc("patch", crc32(&patch[..patch.len() - 4]), patch_crc)?;
+
+    let mut actions = &body[BPS_MAGIC.len()..];
+    let source_size = read_number(&mut actions)?;
+    let target_size = read_number(&mut actions)?;
+    let metadata_size = read_number(&mut actions)?;
+    if metadata_size > actions.len() as u64 {
+        return Err(invalid_patch("its metadata is cut off"));
+    }
+    actions = &actions[metadata_size as usize..];
+
+    let actual_source_size = source.seek(SeekFrom::End(0))?;
+    source.seek(SeekFrom::Start(0))?;
+    if check {
+        if actual_source_size != source_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "The patch is for a {} byte file, but this one is {} bytes.",
+                source_size, actual_source_size,
+            )));
+        }
+        let mut crc = CrcWriter { inner: io::sink(), crc: Crc32::new() };
+        io::copy(&mut source, &mut crc)?;
+        check_crc("source", crc.crc.finalize(), source_crc)?;
+    }
+
+    let mut source = SourceCache { reader: source, buf: Vec::new(), start: 0, size: actual_source_size };
+    let mut target = TargetWriter {
+        output: CrcWriter { inner: BufWriter::new(output), crc: Crc32::new() },
+        history: Vec::new(),
+        history_start: 0,
+        written: 0,
+        max_history: TARGET_HISTORY,
+    };
+    let mut source_relative = 0;
+    let mut target_relative = 0;
+    while !actions.is_empty() {
+        let action = read_number(&mut actions)?;
+        let len = (action >> 2) + 1;
+        match action & 3 {
+            SOURCE_READ => {
+                let from = target.written;
+                copy_from_source(&mut source, from, len, &mut target)?;
+            },
+            TARGET_READ => {
+                if len > actions.len() as u64 {
+                    return Err(invalid_patch("it ends in the middle of an action"));
+                }
+                let (bytes, rest) = actions.split_at(len as usize);
+                target.write(bytes)?;
+                actions = rest;
+            },
+            SOURCE_COPY => {
+                source_relative = add_offset(source_relative, read_number(&mut actions)?)?;
+                copy_from_source(&mut source, source_relative, len, &mut target)?;
+                source_relative += len;
+            },
+            _ => {
+                target_relative = add_offset(target_relative, read_number(&mut actions)?)?;
+                target.copy_from_self(target_relative, len)?;
+                target_relative += len;
+            },
+        }
+    }
+
+    let TargetWriter { mut output, written, .. } = target;
+    output.flush()?;
+    if check {
+        if written != target_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "The patch made a {} byte file, but it should have made a {} byte one.",
+                written, target_size,
+            )));
+        }
+        check_crc("result", output.crc.finalize(), target_crc)?;
+    }
+    Ok(())
+}
+
+// IPS patches are a list of bytes to write at different offsets. They're
+// limited to 16MiB files, but the bytes past that are just copied over.
+fn apply_ips(patch: &[u8], mut source: impl Read + Seek, output: impl Write) -> io::Result<()> {
+    let mut records = Vec::new();
+    let mut data = &patch[IPS_MAGIC.len()..];
+    let truncate_to = loop {
+        if data.starts_with(IPS_EOF) {
+            let rest = &data[IPS_EOF.len()..];
+            break if rest.len() >= 3 { Some(read_u24(rest)) } else { None };
+        }
+        if data.len() < 5 {
+            return Err(invalid_patch("it doesn't end with EOF"));
+        }
+        let offset = read_u24(data);
+        let size = ((data[3] as usize) << 8) | data[4] as usize;
+        data = &data[5..];
+        let bytes = if size == 0 {
+            // Run-length encoded: a 16-bit count and the byte to repeat
+            if data.len() < 3 {
+                return Err(invalid_patch("it ends in the middle of a record"));
+            }
+            let count = ((data[0] as usize) << 8) | data[1] as usize;
+            let bytes = vec![data[2]; count];
+            data = &data[3..];
+            bytes
+        } else {
+            if data.len() < size {
+                return Err(invalid_patch("it ends in the middle of a record"));
+            }
+            let (bytes, rest) = data.split_at(size);
+            data = rest;
+            bytes.to_vec()
+        };
+        records.push((offset, bytes));
+    };
+
+    let source_size = source.seek(SeekFrom::End(0))?;
+    source.seek(SeekFrom::Start(0))?;
+    let patched_size = records.iter()
+        .map(|(offset, bytes)| offset + bytes.len() as u64)
+        .fold(source_size, max);
+    let size = truncate_to.unwrap_or(patched_size);
+
+    let mut output = BufWriter::new(output);
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut offset = 0;
+    while offset < size {
+        let len = (size - offset).min(CHUNK_SIZE as u64) as usize;
+        let chunk = &mut buf[..len];
+        let read = read_fully(&mut source, chunk)?;
+        // Anything past the end of the source only comes from the patch
+        for b in &mut chunk[read..] {
+            *b = 0;
+        }
+        let chunk_end = offset + len as u64;
+        for (start, bytes) in &records {
+            let end = start + bytes.len() as u64;
+            if end <= offset || *start >= chunk_end { continue }
+            let from = offset.max(*start);
+            let to = chunk_end.min(end);
+            chunk[(from - offset) as usize..(to - offset) as usize]
+                .copy_from_slice(&bytes[(from - start) as usize..(to - start) as usize]);
+        }
+        output.write_all(chunk)?;
+        offset = chunk_end;
+    }
+    output.flush()
+}
+
+// Writes the patched file, and keeps the end of it around for actions that
+// copy from it
+struct TargetWriter<W: Write> {
+    output: CrcWriter<W>,
+    history: Vec<u8>,
+    history_start: u64,
+    written: u64,
+    // How far back a copy can reach. The history's allowed to grow to twice
+    // this before it's trimmed, so it isn't trimmed after every write.
+    max_history: usize,
+}
+
+impl<W: Write> TargetWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.output.write_all(bytes)?;
+        self.history.extend_from_slice(bytes);
+        self.written += bytes.len() as u64;
+        if self.history.len() > 2 * self.max_history {
+            let unneeded = self.history.len() - self.max_history;
+            self.history.drain(..unneeded);
+            self.history_start += unneeded as u64;
+        }
+        Ok(())
+    }
+
+    // The copy can overlap what it's writing, which is how runs of a byte
+    // are written
+    fn copy_from_self(&mut self, from: u64, len: u64) -> io::Result<()> {
+        let too_far_back = || invalid_patch(
+            "it copies from too far back in the file it's making, which isn't supported"
+        );
+        // How much of the history there is depends on when it was last
+        // trimmed, so the distance is checked against the most it's trimmed
+        // to instead, for copies that work no matter when they happen. The
+        // distance stays the same as the copy goes, since what's copied is
+        // written right away.
+        if from >= self.written || self.written - from > self.max_history as u64 {
+            return Err(too_far_back());
+        }
+        let mut copied = 0;
+        while copied < len {
+            let start = (from + copied).checked_sub(self.history_start).ok_or_else(too_far_back)? as usize;
+            let available = self.history.len() - start;
+            let n = ((len - copied) as usize).min(available).min(CHUNK_SIZE);
+            let bytes = self.history[start..start + n].to_vec();
+            self.write(&bytes)?;
+            copied += n as u64;
+        }
+        Ok(())
+    }
+}
+
+fn copy_from_source<R: Read + Seek, W: Write>(
+    source: &mut SourceCache<R>,
+    from: u64,
+    len: u64,
+    target: &mut TargetWriter<W>,
+) -> io::Result<()> {
+    let mut copied = 0;
+    while copied < len {
+        let bytes = source.read_at(from + copied, 1)?;
+        if bytes.is_empty() {
+            return Err(invalid_patch("it reads past the end of the source"));
+        }
+        let n = ((len - copied) as usize).min(bytes.len());
+        target.write(&bytes[..n])?;
+        copied += n as u64;
+    }
+    Ok(())
+}
+
+fn read_number(data: &mut &[u8]) -> io::Result<u64> {
+    let mut n = 0u64;
+    let mut shift = 1u64;
+    loop {
+        let (&byte, rest) = data.split_first()
+            .ok_or_else(|| invalid_patch("it ends in the middle of a number"))?;
+        *data = rest;
+        n = ((byte & 0x7f) as u64).checked_mul(shift)
+            .and_then(|b| n.checked_add(b))
+            .ok_or_else(|| invalid_patch("it has a number that's too big"))?;
+        if byte & 0x80 != 0 {
+            return Ok(n);
+        }
+        shift = shift.checked_mul(0x80)
+            .ok_or_else(|| invalid_patch("it has a number that's too big"))?;
+        n = n.checked_add(shift)
+            .ok_or_else(|| invalid_patch("it has a number that's too big"))?;
+    }
+}
+
+// Offsets are stored as a magnitude with the sign in the lowest bit
+fn add_offset(base: u64, encoded: u64) -> io::Result<u64> {
+    let delta = encoded >> 1;
+    let result = if encoded & 1 == 1 {
+        base.checked_sub(delta)
+    } else {
+        base.checked_add(delta)
+    };
+    result.ok_or_else(|| invalid_patch("it has an offset that's out of range"))
+}
+
+fn read_u24(data: &[u8]) -> u64 {
+    ((data[0] as u64) << 16) | ((data[1] as u64) << 8) | data[2] as u64
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finalize()
+}
+
+fn check_crc(what: &str, actual: u32, expected: u32) -> io::Result<()> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "The {}'s CRC32 is {:08x}, but the patch expects {:08x}.", what, actual, expected,
+        )))
+    }
+}
+
+fn invalid_patch(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid patch: {}.", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn target_writer(max_history: usize) -> TargetWriter<Vec<u8>> {
+        TargetWriter {
+            output: CrcWriter { inner: Vec::new(), crc: Crc32::new() },
+            history: Vec::new(),
+            history_start: 0,
+            written: 0,
+            max_history,
+        }
+    }
+
+    // A BPS patch for an empty source, with the actions `write` adds
+    fn bps_patch(target_size: u64, write: impl FnOnce(&mut Encoder<&mut Vec<u8>>) -> io::Result<()>) -> Vec<u8> {
+        let mut patch = Vec::new();
+        let mut encoder = Encoder {
+            output: CrcWriter { inner: &mut patch, crc: Crc32::new() },
+            new_bytes: Vec::new(),
+            source_relative: 0,
+            target_relative: 0,
+        };
+        encoder.output.write_all(BPS_MAGIC).unwrap();
+        encoder.write_number(0).unwrap();
+        encoder.write_number(target_size).unwrap();
+        encoder.write_number(0).unwrap();
+        write(&mut encoder).unwrap();
+        encoder.flush_target_read().unwrap();
+        encoder.output.write_all(&crc32(&[]).to_le_bytes()).unwrap();
+        // The result's CRC32 isn't checked by `apply_unchecked`
+        encoder.output.write_all(&[0; 4]).unwrap();
+        let crc = encoder.output.crc.finalize();
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    fn bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+    }
+
+    #[test]
+    fn copies_keep_working_as_the_history_is_trimmed() {
+        let mut target = target_writer(16);
+        let start = bytes(20);
+        target.write(&start).unwrap();
+        // This trims the history several times while it's being copied from
+        target.copy_from_self(4, 100).unwrap();
+
+        let mut expected = start.clone();
+        for i in 0..100 {
+            let b = expected[4 + i];
+            expected.push(b);
+        }
+        assert_eq!(target.output.inner, expected);
+    }
+
+    #[test]
+    fn copies_from_further_back_than_the_history_are_errors() {
+        let mut target = target_writer(16);
+        target.write(&bytes(30)).unwrap();
+        // Everything's still there, but the first write would trim what the
+        // rest of the copy needs
+        let e = target.copy_from_self(2, 40).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(target.copy_from_self(30, 1).is_err());
+    }
+
+    #[test]
+    fn long_distance_target_copies() {
+        let data = bytes(MAX_TARGET_READ);
+        let run = TARGET_HISTORY as u64;
+        let target_size = data.len() as u64 + run + 10;
+
+        // Repeats the new bytes until the history's been trimmed, then copies
+        // the most it can reach
+        let patch = bps_patch(target_size, |e| {
+            for &b in &data {
+                e.target_read(b)?;
+            }
+            e.target_copy(0, run)?;
+            e.target_copy(data.len() as u64 + run - TARGET_HISTORY as u64, 10)
+        });
+        let mut output = Vec::new();
+        apply_unchecked(Cursor::new(&patch), Cursor::new(Vec::new()), &mut output).unwrap();
+        assert_eq!(output.len() as u64, target_size);
+        assert!(output.chunks(data.len()).all(|c| c == &data[..c.len()]));
+
+        // One byte further back than that
+        let patch = bps_patch(target_size, |e| {
+            for &b in &data {
+                e.target_read(b)?;
+            }
+            e.target_copy(0, run)?;
+            e.target_copy(data.len() as u64 + run - TARGET_HISTORY as u64 - 1, 10)
+        });
+        let e = apply_unchecked(Cursor::new(&patch), Cursor::new(Vec::new()), io::sink()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,44 @@
+use std::time::{Duration, Instant};
+
+use log::Level;
+
+// Printing after every file slows extraction down a lot when there are
+// thousands of tiny ones, so the line is only redrawn this often
+const MIN_REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+// A status line like "12/345 files written." that's redrawn in place on
+// stderr, so it stays out of anything written to stdout. It's only printed
+// when info messages are logged but debug messages aren't, since those say
+// what's happening to each file anyway and would end up on the same line.
+// The line is ended when this is dropped.
+#[derive(Default)]
+pub struct Progress {
+    last_redraw: Option<Instant>,
+    drawn: bool,
+}
+
+impl Progress {
+    // Redraws the line, unless it was redrawn too recently. `status` is only
+    // called if it is. `done` always redraws it, so the final count is shown.
+    pub fn update(&mut self, done: bool, status: impl FnOnce() -> String) {
+        if !log_enabled!(Level::Info) || log_enabled!(Level::Debug) { return }
+        let now = Instant::now();
+        if !done && self.last_redraw.is_some_and(|t| now - t < MIN_REDRAW_INTERVAL) { return }
+        self.last_redraw = Some(now);
+        self.drawn = true;
+        eprint!("\r{}", status());
+    }
+
+    // `verb` is what's being done to the files, like "written"
+    pub fn files(&mut self, count: usize, total: usize, verb: &str) {
+        self.update(count >= total, || format!("{}/{} files {}.", count, total, verb));
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.drawn {
+            eprintln!();
+        }
+    }
+}
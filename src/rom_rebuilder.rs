@@ -1,35 +1,420 @@
 use std::cmp;
-use std::fs::{File, read_dir};
-use std::io::{self, BufReader, Write};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File, OpenOptions, metadata, read_dir, remove_file};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{self, Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::Arc;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
 
+use compression::{read_compressed_list, Compression};
+use game::Game;
+use handles::{HandleBudget, Handles};
+use hash::{Digest, HashAlgo, MultiHasher};
+use junk::{JunkCheck, JunkSeed};
 use sections::apploader::APPLOADER_OFFSET;
+use sections::dol::DOLHeader;
 use sections::fst::{
     FST,
-    entry::{DirectoryEntry, Entry, EntryInfo, FileEntry},
+    entry::{encode_name, DirectoryEntry, Entry, EntryInfo, FileEntry, ENTRY_SIZE},
+    gcr::GcrFileList,
+    names::read_name_map,
 };
 use sections::header::Header;
+#[cfg(feature = "gcz")]
+use source::gcz::GczWriter;
+use source::split::SplitFileWriter;
+use tempfile::NamedTempFile;
+use util::{write_zeros, ZEROS};
 use ::{
     align,
     DEFAULT_ALIGNMENT,
-    extract_section,
-    paths::*,
     WRITE_CHUNK_SIZE,
+    paths::*,
 };
 
 pub const ROM_SIZE: usize = 0x57058000;
 
 // TODO: modify the config struct to include stuff like whether the system data should be rebuilt
-// and the paths for stuff like the dol, apploader, fst, and so on...
 
 // Header -> apploader -> fst -> dol -> fs
 
 struct ROMConfig<'a> {
     alignment: u64,
+    // Whether streamed audio files have to be aligned to STREAM_ALIGNMENT,
+    // which they do if the game streams audio
+    align_streams: bool,
     root_path: &'a Path,
     files: Vec<(u64, PathBuf)>,
-    space_used: Option<usize>,
+    // Whether or not a file or directory (given relative to the root) should
+    // be left out of the ROM
+    exclude: &'a dyn Fn(&Path) -> bool,
+    // The names on the ROM of files that were renamed when they were
+    // extracted, by where they are relative to the root
+    original_names: HashMap<PathBuf, String>,
+    // Files that are compressed again as they're added to the ROM, by their
+    // full path, with the temporary file that has what to add instead
+    compressed: HashMap<PathBuf, NamedTempFile>,
+    // How many files in `COMPRESSED_LIST_PATH` are going on the ROM
+    // decompressed, since they aren't being compressed again
+    left_decompressed: usize,
+    // Whether to go on when the existing Game.toc and the root don't match,
+    // and files from it are missing, or there are files it doesn't have
+    allow_missing: bool,
+    allow_extra: bool,
+    // The full paths of files in the existing Game.toc that aren't in the
+    // root, when that's allowed
+    missing: HashSet<PathBuf>,
+    // The mismatches that were allowed, to warn about
+    mismatch_warnings: Vec<String>,
+    // Where to read the system files from, which is `&&systemdata` unless
+    // they've been overridden
+    header_path: PathBuf,
+    apploader_path: PathBuf,
+    dol_path: PathBuf,
+    disc_size: DiscSize,
+}
+
+impl<'a> ROMConfig<'a> {
+    fn new(
+        root_path: &'a Path,
+        alignment: u64,
+        exclude: &'a dyn Fn(&Path) -> bool,
+        overrides: SystemFileOverrides,
+        disc_size: DiscSize,
+    ) -> io::Result<ROMConfig<'a>> {
+        // Overrides are easy to get wrong, so say which one's missing
+        for p in [overrides.header, overrides.apploader, overrides.dol].iter().flatten() {
+            metadata(p).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", p.display(), e)))?;
+        }
+        // A DOL that was built somewhere else might not boot, which is better
+        // to find out before the whole ROM's written
+        if let Some(dol) = overrides.dol {
+            check_dol(dol)?;
+        }
+        let path = |p: Option<&Path>, default| p.map_or_else(|| root_path.join(default), Path::to_path_buf);
+        let header_path = path(overrides.header, HEADER_PATH);
+        let align_streams = match overrides.stream_alignment {
+            Some(align) => align,
+            None => match File::open(&header_path) {
+                Ok(f) => Header::new(BufReader::new(f), 0)?.streams_audio(),
+                // A new ROM gets a header that doesn't stream audio
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => false,
+                Err(e) => return Err(e),
+            },
+        };
+        let (compressed, left_decompressed) = if overrides.compress {
+            (compress_listed_files(root_path)?, 0)
+        } else {
+            (HashMap::new(), read_compressed_list(root_path)?.len())
+        };
+        Ok(ROMConfig {
+            alignment,
+            align_streams,
+            root_path,
+            files: vec![],
+            exclude,
+            original_names: read_name_map(root_path)?,
+            compressed,
+            left_decompressed,
+            allow_missing: overrides.allow_missing,
+            allow_extra: overrides.allow_extra,
+            missing: HashSet::new(),
+            mismatch_warnings: Vec::new(),
+            header_path,
+            apploader_path: path(overrides.apploader, APPLOADER_PATH),
+            dol_path: path(overrides.dol, DOL_PATH),
+            disc_size,
+        })
+    }
+
+    // The name to give the file at `fs_path` on the ROM
+    fn original_name(&self, fs_path: &Path, disk_name: &str) -> String {
+        fs_path.strip_prefix(self.root_path).ok()
+            .and_then(|p| self.original_names.get(p))
+            .cloned()
+            .unwrap_or_else(|| disk_name.to_string())
+    }
+
+    // How big the file at `fs_path` will be on the ROM
+    fn file_size(&self, fs_path: &Path) -> io::Result<u64> {
+        match self.compressed.get(fs_path) {
+            Some(temp) => Ok(metadata(temp.path())?.len()),
+            None => Ok(metadata(fs_path)?.len()),
+        }
+    }
+
+    // Checks the existing FST against the files in the root, and fails if
+    // they don't match in a way that isn't allowed. With `used_as_is`, the
+    // FST isn't going to change, so the files in it have to be the same size,
+    // and files that aren't in it are left out. Otherwise, those are added to
+    // it, so only missing files matter.
+    fn reconcile(&mut self, fst: &FST, used_as_is: bool) -> io::Result<()> {
+        let mut mismatches = Vec::new();
+        let mut expected = HashSet::new();
+        for e in &fst.entries[1..] {
+            let disk_path = fst.disk_path(e.info());
+            expected.insert(disk_path.clone());
+            let f = match e.as_file() {
+                Some(f) if !self.is_excluded(&f.info.full_path) => f,
+                _ => continue,
+            };
+            let full_path = self.root_path.join(&disk_path);
+            match self.file_size(&full_path) {
+                Ok(size) if used_as_is && size != f.size as u64 => mismatches.push(TreeMismatch::SizeMismatch {
+                    fst_path: f.info.full_path.clone(),
+                    disk_path,
+                    fst_size: f.size,
+                    size,
+                }),
+                Ok(_) => {},
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => mismatches.push(TreeMismatch::Missing {
+                    fst_path: f.info.full_path.clone(),
+                    disk_path,
+                    size: f.size,
+                }),
+                Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", full_path.display(), e))),
+            }
+        }
+        if used_as_is {
+            find_extra_files(self.root_path, Path::new(""), &expected, &mut mismatches)?;
+        }
+
+        let (allowed, problems): (Vec<_>, Vec<_>) = mismatches.into_iter().partition(|m| match m {
+            TreeMismatch::Missing { .. } => self.allow_missing,
+            TreeMismatch::Extra { .. } => self.allow_extra,
+            TreeMismatch::SizeMismatch { .. } => false,
+        });
+        if !problems.is_empty() {
+            let problems = problems.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+            return Err(io::Error::other(format!(
+                "The files in the root don't match {}:\n{}",
+                FST_PATH,
+                problems.join("\n"),
+            )));
+        }
+        for m in allowed {
+            if let TreeMismatch::Missing { ref disk_path, .. } = m {
+                self.missing.insert(self.root_path.join(disk_path));
+            }
+            self.mismatch_warnings.push(match m {
+                TreeMismatch::Missing { .. } if used_as_is => format!("{}, so it's left as zeros", m),
+                TreeMismatch::Missing { .. } => format!("{}, so it's left off the ROM", m),
+                _ => m.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // `path` is the full path in the FST, starting with "/"
+    fn is_excluded(&self, path: &Path) -> bool {
+        (self.exclude)(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    // Where the file called `name` can go after `offset`
+    fn align_file(&self, offset: u64, name: &str) -> u64 {
+        if self.align_streams && is_streamed_audio(name) {
+            align(offset, cmp::max(self.alignment, STREAM_ALIGNMENT))
+        } else {
+            align(offset, self.alignment)
+        }
+    }
+}
+
+fn check_dol(path: &Path) -> io::Result<()> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let issues = DOLHeader::new(BufReader::new(file), 0)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?
+        .validate_file(len);
+    if issues.is_empty() {
+        return Ok(());
+    }
+    let issues = issues.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+    Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+        "{} won't boot:\n{}",
+        path.display(),
+        issues.join("\n"),
+    )))
+}
+
+// A difference between the existing Game.toc and the files in the root
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TreeMismatch {
+    // The FST has a file that isn't where it would've been extracted to
+    Missing { fst_path: PathBuf, disk_path: PathBuf, size: usize },
+    // There's a file in the root that the FST doesn't have, so it isn't going
+    // on the ROM
+    Extra { disk_path: PathBuf },
+    SizeMismatch { fst_path: PathBuf, disk_path: PathBuf, fst_size: usize, size: u64 },
+}
+
+impl fmt::Display for TreeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TreeMismatch::Missing { fst_path, disk_path, size } => write!(
+                f, "{} ({} bytes) is in the FST, but {} isn't there",
+                fst_path.display(), size, disk_path.display(),
+            ),
+            TreeMismatch::Extra { disk_path } => write!(
+                f, "{} isn't in the FST, so it won't be on the ROM",
+                disk_path.display(),
+            ),
+            TreeMismatch::SizeMismatch { fst_path, disk_path, fst_size, size } => write!(
+                f, "{} is {} bytes in the FST, but {} is {} bytes",
+                fst_path.display(), fst_size, disk_path.display(), size,
+            ),
+        }
+    }
+}
+
+// Adds the files under `dir` (`relative` to the root) that aren't in
+// `expected` to `mismatches`
+fn find_extra_files(
+    dir: &Path,
+    relative: &Path,
+    expected: &HashSet<PathBuf>,
+    mismatches: &mut Vec<TreeMismatch>,
+) -> io::Result<()> {
+    let contents = read_dir(dir).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?;
+    let mut entries = contents.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for e in entries {
+        let name = e.file_name();
+        if FSTRebuilder::is_file_ignored(&name.to_string_lossy()) { continue }
+        let relative = relative.join(&name);
+        if e.file_type()?.is_dir() {
+            find_extra_files(&e.path(), &relative, expected, mismatches)?;
+        } else if !expected.contains(&relative) {
+            mismatches.push(TreeMismatch::Extra { disk_path: relative });
+        }
+    }
+    Ok(())
+}
+
+// Compresses the files in `COMPRESSED_LIST_PATH` the way they were before
+// they were extracted. Ones that have been removed since are skipped, and so
+// are ones that are already compressed. Each one's written to a temporary
+// file as soon as it's compressed, so only one is in memory at a time.
+fn compress_listed_files(root: &Path) -> io::Result<HashMap<PathBuf, NamedTempFile>> {
+    let mut compressed = HashMap::new();
+    for (path, compression) in read_compressed_list(root)? {
+        let path = root.join(path);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+        };
+        if Compression::detect(&data).is_some() { continue }
+        debug!("Compressing {} with {}", path.display(), compression);
+        let mut temp = NamedTempFile::new()?;
+        temp.write_all(&compression.compress(&data)?)?;
+        temp.flush()?;
+        compressed.insert(path, temp);
+    }
+    Ok(compressed)
+}
+
+// Streamed audio has to start on a 32 KiB boundary, or it doesn't play on a
+// console
+const STREAM_ALIGNMENT: u64 = 0x8000;
+
+fn is_streamed_audio(name: &str) -> bool {
+    Path::new(name).extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("adp") || e.eq_ignore_ascii_case("dsp"))
+}
+
+// Files to use instead of the ones in `&&systemdata`, like a DOL that's just
+// been compiled somewhere else. They're never written to. If the header needs
+// to change, like when a new FST is made, the new one is based on the
+// override, and is written to `&&systemdata` like it usually is.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemFileOverrides<'a> {
+    pub header: Option<&'a Path>,
+    pub apploader: Option<&'a Path>,
+    pub dol: Option<&'a Path>,
+    // Whether to keep streamed audio files 32 KiB aligned, instead of going
+    // by the header's audio streaming flag
+    pub stream_alignment: Option<bool>,
+    // Whether to compress the files that were decompressed when the ROM was
+    // extracted again, so they're the way the game expects
+    pub compress: bool,
+    // When the existing Game.toc is used, whether to go on when some of its
+    // files aren't in the root, or the root has files it doesn't
+    pub allow_missing: bool,
+    pub allow_extra: bool,
+}
+
+// How big a rebuilt ROM is. Everything after the last file is padding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DiscSize {
+    // ROM_SIZE, which is how much a retail disc holds
+    #[default]
+    Retail,
+    // Some discs hold less, and emulators can load images that are bigger
+    // than any disc
+    Bytes(u64),
+    // Ends right after the last file, rounded up to `FIT_ALIGNMENT`
+    Fit,
+}
+
+const FIT_ALIGNMENT: u64 = 0x8000;
+
+impl DiscSize {
+    // How much space there is for files, if there's a limit
+    fn limit(self) -> Option<u64> {
+        match self {
+            DiscSize::Retail => Some(ROM_SIZE as u64),
+            DiscSize::Bytes(size) => Some(size),
+            DiscSize::Fit => None,
+        }
+    }
+}
+
+// What goes in the header of a ROM that's made from scratch, and where its
+// DOL and apploader are. If they aren't given, the ones in `&&systemdata` are
+// used, if it's there.
+#[derive(Clone, Copy, Debug)]
+pub struct CreateOptions<'a> {
+    pub title: &'a str,
+    pub game_code: &'a str,
+    pub maker_code: &'a str,
+    pub dol: Option<&'a Path>,
+    pub apploader: Option<&'a Path>,
+}
+
+// The order the files' data goes in when a new FST is made. The FST itself
+// stays the same, this is just where each file ends up on the disc, which can
+// make a difference to load times on a real console.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OrderStrategy {
+    // The order they were in on the ROM they were extracted from, going by
+    // the existing Game.toc. Files that weren't on it go last.
+    Original,
+    // The order they're in in the FST
+    FstOrder,
+    // By their full path
+    Alphabetical,
+    // These paths, relative to the root (like `audio/title.dsp`), go first,
+    // then everything else goes in its original order
+    List(Vec<PathBuf>),
+}
+
+impl OrderStrategy {
+    // Reads a `List` from a file with one path per line. Blank lines and lines
+    // starting with `#` are skipped.
+    pub fn from_list_file(path: impl AsRef<Path>) -> io::Result<OrderStrategy> {
+        let file = File::open(path)?;
+        let mut paths = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue }
+            paths.push(PathBuf::from(line.trim_start_matches('/')));
+        }
+        Ok(OrderStrategy::List(paths))
+    }
 }
 
 struct FSTRebuilderInfo {
@@ -55,38 +440,34 @@ impl FSTRebuilderInfo {
 struct FSTRebuilder<'a> {
     apploader_size: usize,
     dol_size: usize,
+    order: &'a OrderStrategy,
     config: ROMConfig<'a>,
 }
 
 impl<'a> FSTRebuilder<'a> {
-    fn new<P: ?Sized>(root: &'a P, alignment: u64) -> io::Result<FSTRebuilder<'a>>
-    where
-        P: AsRef<Path>,
-    {
-        let apploader = File::open(root.as_ref().join(APPLOADER_PATH))?;
+    fn new(config: ROMConfig<'a>, order: &'a OrderStrategy) -> io::Result<FSTRebuilder<'a>> {
+        let apploader = File::open(&config.apploader_path)?;
         let apploader_size = apploader.metadata()?.len() as usize;
 
-        let dol = File::open(root.as_ref().join(DOL_PATH))?;
+        let dol = File::open(&config.dol_path)?;
         let dol_size = dol.metadata()?.len() as usize;
 
         Ok(FSTRebuilder {
             apploader_size,
             dol_size,
-            config: ROMConfig {
-                alignment,
-                root_path: root.as_ref(),
-                files: vec![],
-                space_used: None,
-            },
+            order,
+            config,
         })
     }
 
-    fn rebuild(mut self) -> io::Result<HeaderRebuilder<'a>> {
+    fn rebuild(self) -> io::Result<HeaderRebuilder<'a>> {
         let root_entry = Entry::Directory(DirectoryEntry {
             info: EntryInfo {
                 index: 0,
                 name: path::MAIN_SEPARATOR.to_string(),
+                raw_name: Vec::new(),
                 filename_offset: 0,
+                disk_name: String::new(),
                 directory_index: None,
                 full_path: "/".into(),
             },
@@ -111,15 +492,7 @@ impl<'a> FSTRebuilder<'a> {
 
         let dol_offset = align(offset + size as u64, self.config.alignment);
         let file_system_offset = align(dol_offset + self.dol_size as u64, self.config.alignment);
-
-        // Move this loop/don't iteratate over all these again?
-        let mut max_eof = 0;
-        for e in &mut rb_info.entries {
-            if let Some(ref mut f) = e.as_file_mut() {
-                f.file_offset += file_system_offset;
-                max_eof = cmp::max(max_eof, f.file_offset as usize + f.size);
-            }
-        }
+        self.place_files(&mut rb_info.entries, file_system_offset)?;
 
         let fst = FST {
             offset,
@@ -128,14 +501,13 @@ impl<'a> FSTRebuilder<'a> {
             total_file_system_size: rb_info.file_system_size as usize,
             size,
         };
-        let fst_path = self.config.root_path.join(FST_PATH);
-        fst.write(File::create(&fst_path)?)?;
-
-        self.config.space_used = Some(max_eof);
+        let mut fst_data = Vec::new();
+        fst.write(&mut fst_data)?;
 
         Ok(HeaderRebuilder {
             dol_offset,
             fst,
+            system_files: vec![(self.config.root_path.join(FST_PATH), fst_data)],
             config: self.config,
         })
     }
@@ -177,21 +549,23 @@ impl<'a> FSTRebuilder<'a> {
             let e = e?;
             let filename = e.file_name();
             let filename = filename.to_string_lossy();
+            if FSTRebuilder::is_file_ignored(&*filename) { continue }
 
-            if FSTRebuilder::is_file_ignored(&*filename) {
-                continue
-            }
+            let name = self.config.original_name(&e.path(), &filename);
+            if self.config.is_excluded(&rb_info.current_path.join(&name)) { continue }
 
             let index = rb_info.entries.len() as usize;
             let info = EntryInfo {
                 index,
-                name: filename.clone().into_owned(),
+                raw_name: encode_name(&name),
                 filename_offset: rb_info.filename_offset,
+                disk_name: filename.into_owned(),
                 directory_index: rb_info.parent_index,
-                full_path: rb_info.current_path.join(&*filename),
+                full_path: rb_info.current_path.join(&name),
+                name,
             };
             // plus 1 for the null byte
-            rb_info.filename_offset += info.name.chars().count() as u64 + 1;
+            rb_info.filename_offset += info.raw_name.len() as u64 + 1;
 
             if e.file_type()?.is_dir() {
                 let parent_index = info.directory_index.unwrap_or(0);
@@ -208,7 +582,7 @@ impl<'a> FSTRebuilder<'a> {
                 let entry = Entry::File(FileEntry {
                     info,
                     file_offset: rb_info.file_system_size,
-                    size: e.metadata()?.len() as usize,
+                    size: self.config.file_size(&e.path())? as usize,
                 });
                 rb_info.add_entry(entry);
             }
@@ -217,23 +591,412 @@ impl<'a> FSTRebuilder<'a> {
         Ok(immediate_children_added)
     }
 
+    // Gives each file its offset, starting at `file_system_offset`, in the
+    // order `self.order` says to
+    fn place_files(&self, entries: &mut [Entry], file_system_offset: u64) -> io::Result<()> {
+        let mut files = entries.iter()
+            .filter_map(|e| e.as_file())
+            .map(|f| f.info.index)
+            .collect::<Vec<_>>();
+
+        match *self.order {
+            OrderStrategy::Original => self.sort_by_original_offset(&mut files, entries)?,
+            OrderStrategy::FstOrder => {},
+            OrderStrategy::Alphabetical =>
+                files.sort_by(|&a, &b| entries[a].info().full_path.cmp(&entries[b].info().full_path)),
+            OrderStrategy::List(ref list) => {
+                let mut positions = HashMap::new();
+                for (i, path) in list.iter().enumerate() {
+                    positions.entry(Path::new("/").join(path)).or_insert(i);
+                }
+                let unknown = positions.keys()
+                    .filter(|p| !self.config.is_excluded(p))
+                    .filter(|&p| !files.iter().any(|&i| entries[i].info().full_path == *p))
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>();
+                if !unknown.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, format!(
+                        "These files from the order list aren't in the ROM: {}",
+                        unknown.join(", "),
+                    )));
+                }
+
+                self.sort_by_original_offset(&mut files, entries)?;
+                // The sort is stable, so the rest stay in their original order
+                files.sort_by_key(|&i| positions.get(&entries[i].info().full_path).copied().unwrap_or(list.len()));
+            },
+        }
+
+        let mut offset = file_system_offset;
+        for i in files {
+            let f = entries[i].as_file_mut().unwrap();
+            f.file_offset = self.config.align_file(offset, &f.info.name);
+            offset = align(f.file_offset + f.size as u64, self.config.alignment);
+        }
+        Ok(())
+    }
+
+    // Sorts `files` by where they were in the existing Game.toc, if there is
+    // one. This has to happen before it's replaced.
+    fn sort_by_original_offset(&self, files: &mut [usize], entries: &[Entry]) -> io::Result<()> {
+        let old_fst = match File::open(self.config.root_path.join(FST_PATH)) {
+            Ok(f) => FST::new(BufReader::new(f), 0)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let offsets = old_fst.entries.iter()
+            .filter_map(|e| e.as_file())
+            .map(|f| (f.info.full_path.as_path(), f.file_offset))
+            .collect::<HashMap<_, _>>();
+        files.sort_by_key(|&i| offsets.get(entries[i].info().full_path.as_path()).copied().unwrap_or(u64::MAX));
+        Ok(())
+    }
+
     fn is_file_ignored(name: &str) -> bool {
         name.starts_with(".") || name == "&&systemdata"
     }
 }
 
+#[derive(Clone, Serialize)]
+pub struct RelocatedFile {
+    pub path: PathBuf,
+    pub old_offset: u64,
+    pub new_offset: u64,
+    pub size: usize,
+}
+
+// Files that couldn't be kept at their original offset
+struct OffsetChanges {
+    relocated: Vec<RelocatedFile>,
+    added: Vec<(PathBuf, u64, u64)>,
+    // Files that don't fit, and can't be moved
+    errors: Vec<String>,
+}
+
+// Used while adding new files and directories to an existing FST
+struct MergeInfo {
+    // The entries from the existing FST, taken as they're added to `entries`
+    old_entries: Vec<Option<Entry>>,
+    // The indices of the immediate children of each directory in `old_entries`
+    old_children: Vec<Vec<usize>>,
+    entries: Vec<Entry>,
+    filename_offset: u64,
+    // The indices of the files that weren't in the existing FST
+    added: Vec<usize>,
+}
+
+// Keeps every file from the existing Game.toc at its original offset, as long
+// as it still fits in the space between it and the next section. Files that
+// have outgrown their slot, and files that are new, are put after the end of
+// the existing data.
+struct OffsetRebuilder<'a> {
+    fst: FST,
+    header: Header,
+    allow_relocation: bool,
+    config: ROMConfig<'a>,
+}
+
+impl<'a> OffsetRebuilder<'a> {
+    fn new(mut config: ROMConfig<'a>, allow_relocation: bool) -> io::Result<OffsetRebuilder<'a>> {
+        let (fst, header) = read_fst_and_header(&config)?;
+        config.reconcile(&fst, false)?;
+        Ok(OffsetRebuilder {
+            fst,
+            header,
+            allow_relocation,
+            config,
+        })
+    }
+
+    fn rebuild(mut self) -> io::Result<(FileSystemRebuilder<'a>, OffsetChanges)> {
+        let root = self.config.root_path;
+        let apploader_size = metadata(&self.config.apploader_path)?.len();
+        let dol_size = metadata(&self.config.dol_path)?.len();
+
+        // The start of every non-empty section in the original layout.
+        // A section's slot ends where the next one starts.
+        let mut starts = vec![0, APPLOADER_OFFSET, self.header.dol_offset, self.fst.offset];
+        starts.extend(
+            self.fst.entries.iter()
+                .filter_map(|e| e.as_file())
+                .filter(|f| f.size > 0)
+                .map(|f| f.file_offset)
+        );
+        starts.sort_unstable();
+        starts.dedup();
+        let disc_end = self.config.disc_size.limit().unwrap_or(u64::MAX);
+        let slot_size = |start: u64| {
+            starts.iter().find(|&&s| s > start).cloned().unwrap_or(disc_end).saturating_sub(start)
+        };
+
+        let added_indices = self.add_new_entries()?;
+
+        let mut errors = Vec::new();
+
+        // There's nowhere to move these to, so they always have to fit.
+        let system_files = [
+            (&self.config.apploader_path, APPLOADER_OFFSET, apploader_size),
+            (&self.config.dol_path, self.header.dol_offset, dol_size),
+            (&root.join(FST_PATH), self.fst.offset, self.fst.size as u64),
+        ];
+        for &(path, start, size) in &system_files {
+            let available = slot_size(start);
+            if size > available {
+                errors.push(format!(
+                    "{} is {} bytes, which is {} bytes too large for its original slot",
+                    path.display(),
+                    size,
+                    size - available,
+                ));
+            }
+        }
+
+        let mut end_of_data = cmp::max(
+            self.header.dol_offset + dol_size,
+            self.fst.offset + self.fst.size as u64,
+        );
+        for f in self.fst.entries.iter().filter_map(|e| e.as_file()) {
+            end_of_data = cmp::max(end_of_data, f.file_offset + f.size as u64);
+        }
+
+        let disk_paths = self.fst.entries.iter().map(|e| self.fst.disk_path(e.info())).collect::<Vec<_>>();
+        let mut relocated = Vec::new();
+        for f in self.fst.entries.iter_mut().filter_map(|e| e.as_file_mut()) {
+            if added_indices.contains(&f.info.index) { continue }
+
+            // Skip the leading "/"
+            let path: PathBuf = f.info.full_path.iter().skip(1).collect();
+            let size = self.config.file_size(&root.join(&disk_paths[f.info.index]))?;
+
+            // Files that used to be empty don't have a slot of their own.
+            let available = if f.size == 0 { 0 } else { slot_size(f.file_offset) };
+
+            if size > available {
+                if !self.allow_relocation {
+                    errors.push(format!(
+                        "{} is {} bytes too large for its original slot",
+                        path.display(),
+                        size - available,
+                    ));
+                    continue;
+                }
+                let new_offset = self.config.align_file(end_of_data, &f.info.name);
+                end_of_data = new_offset + size;
+                relocated.push(RelocatedFile {
+                    path,
+                    old_offset: f.file_offset,
+                    new_offset,
+                    size: size as usize,
+                });
+                f.file_offset = new_offset;
+            }
+            f.size = size as usize;
+        }
+
+        let mut added = Vec::with_capacity(added_indices.len());
+        for &i in &added_indices {
+            let f = self.fst.entries[i].as_file_mut().unwrap();
+            let path: PathBuf = f.info.full_path.iter().skip(1).collect();
+            f.size = self.config.file_size(&root.join(&disk_paths[i]))? as usize;
+            f.file_offset = self.config.align_file(end_of_data, &f.info.name);
+            end_of_data = f.file_offset + f.size as u64;
+            added.push((path, f.file_offset, f.size as u64));
+        }
+        self.fst.total_file_system_size = self.fst.entries.iter()
+            .filter_map(|e| e.as_file())
+            .map(|f| f.size)
+            .sum();
+
+        if let Some(limit) = self.config.disc_size.limit().filter(|&l| end_of_data > l) {
+            errors.push(format!(
+                "relocated and new files need {} more bytes than are available",
+                end_of_data - limit,
+            ));
+        }
+        let mut system_files = Vec::new();
+        let mut fst_data = Vec::new();
+        self.fst.write(&mut fst_data)?;
+        system_files.push((root.join(FST_PATH), fst_data));
+        if self.header.fst_size != self.fst.size {
+            self.header.fst_size = self.fst.size;
+            self.header.max_fst_size = cmp::max(self.header.max_fst_size, self.fst.size);
+            let mut header_data = Vec::new();
+            self.header.write(&mut header_data)?;
+            self.config.header_path = root.join(HEADER_PATH);
+            system_files.push((self.config.header_path.clone(), header_data));
+        }
+
+        Ok((
+            FileSystemRebuilder {
+                fst: self.fst,
+                header: self.header,
+                config: self.config,
+                system_files,
+            },
+            OffsetChanges { relocated, added, errors },
+        ))
+    }
+
+    // Adds entries for the files and directories in the root that aren't in
+    // the FST yet. Existing entries keep their order and names, but their
+    // indices change if anything is added before them. New files are given an
+    // offset and size of 0 for now. Returns the indices of the new files.
+    fn add_new_entries(&mut self) -> io::Result<Vec<usize>> {
+        let old_entries = std::mem::take(&mut self.fst.entries);
+
+        let mut old_children = vec![Vec::new(); old_entries.len()];
+        let mut filename_offset = 0;
+        for e in old_entries.iter().skip(1) {
+            let info = e.info();
+            old_children[info.directory_index.unwrap_or(0)].push(info.index);
+            // The new names go after the end of the existing string table
+            let name_len = info.raw_name.len() as u64;
+            filename_offset = cmp::max(filename_offset, info.filename_offset + name_len + 1);
+        }
+
+        let mut info = MergeInfo {
+            entries: Vec::with_capacity(old_entries.len()),
+            old_entries: old_entries.into_iter().map(Some).collect(),
+            old_children,
+            filename_offset,
+            added: Vec::new(),
+        };
+
+        let root = match info.old_entries[0].take() {
+            Some(Entry::Directory(d)) => d,
+            _ => return Err(io::Error::other("the root of the FST isn't a directory")),
+        };
+        self.merge_dir(self.config.root_path, root, Some(0), &mut info)?;
+
+        // FST::write packs the names together, so if any entries were
+        // excluded, the names after them need to be moved back.
+        let mut by_name_offset = info.entries.iter_mut().skip(1).collect::<Vec<_>>();
+        by_name_offset.sort_by_key(|e| e.info().filename_offset);
+        info.filename_offset = 0;
+        for e in by_name_offset {
+            let name_len = e.info().raw_name.len() as u64;
+            e.info_mut().filename_offset = info.filename_offset;
+            info.filename_offset += name_len + 1;
+        }
+
+        self.fst.file_count = info.entries.iter().filter(|e| e.is_file()).count();
+        self.fst.size = info.entries.len() * ENTRY_SIZE + info.filename_offset as usize;
+        self.fst.entries = info.entries;
+        Ok(info.added)
+    }
+
+    // `old_index` is the index of `dir` in the existing FST, if it was in it.
+    fn merge_dir(
+        &self,
+        fs_path: &Path,
+        mut dir: DirectoryEntry,
+        old_index: Option<usize>,
+        info: &mut MergeInfo,
+    ) -> io::Result<()> {
+        let dir_index = info.entries.len();
+        dir.info.index = dir_index;
+        let full_path = dir.info.full_path.clone();
+        info.entries.push(Entry::Directory(dir));
+
+        let mut on_disk = Vec::new();
+        let dir_contents = read_dir(fs_path).map_err(|e|
+            io::Error::new(e.kind(), format!("{}: {}", fs_path.display(), e))
+        )?;
+        for e in dir_contents {
+            let e = e?;
+            let name = e.file_name().to_string_lossy().into_owned();
+            if !FSTRebuilder::is_file_ignored(&name) {
+                on_disk.push((name, e.file_type()?.is_dir()));
+            }
+        }
+        on_disk.sort();
+
+        let old_children = old_index
+            .map(|i| std::mem::take(&mut info.old_children[i]))
+            .unwrap_or_default();
+        let mut child_count = 0;
+
+        for old_child in old_children {
+            let mut entry = info.old_entries[old_child].take().unwrap();
+            let disk_name = entry.info().disk_name.clone();
+            on_disk.retain(|(n, _)| *n != disk_name);
+            let name = entry.info().name.trim_end_matches(path::MAIN_SEPARATOR);
+            if self.config.is_excluded(&full_path.join(name)) { continue }
+            if self.config.missing.contains(&fs_path.join(&disk_name)) { continue }
+
+            entry.info_mut().directory_index = Some(dir_index);
+            match entry {
+                Entry::Directory(mut d) => {
+                    d.parent_index = dir_index;
+                    self.merge_dir(&fs_path.join(&disk_name), d, Some(old_child), info)?;
+                },
+                Entry::File(mut f) => {
+                    f.info.index = info.entries.len();
+                    info.entries.push(Entry::File(f));
+                },
+            }
+            child_count += 1;
+        }
+
+        for (disk_name, is_dir) in on_disk {
+            let name = self.config.original_name(&fs_path.join(&disk_name), &disk_name);
+            if self.config.is_excluded(&full_path.join(&name)) { continue }
+
+            let entry_info = EntryInfo {
+                index: info.entries.len(),
+                raw_name: encode_name(&name),
+                filename_offset: info.filename_offset,
+                disk_name: disk_name.clone(),
+                directory_index: Some(dir_index),
+                full_path: full_path.join(&name),
+                name,
+            };
+            // plus 1 for the null byte
+            info.filename_offset += entry_info.raw_name.len() as u64 + 1;
+
+            if is_dir {
+                let d = DirectoryEntry {
+                    info: entry_info,
+                    parent_index: dir_index,
+                    next_index: 0,
+                    file_count: 0,
+                };
+                self.merge_dir(&fs_path.join(&disk_name), d, None, info)?;
+            } else {
+                info.added.push(entry_info.index);
+                info.entries.push(Entry::File(FileEntry {
+                    info: entry_info,
+                    file_offset: 0,
+                    size: 0,
+                }));
+            }
+            child_count += 1;
+        }
+
+        let next_index = info.entries.len();
+        let dir = info.entries[dir_index].as_dir_mut().unwrap();
+        dir.next_index = next_index;
+        dir.file_count = child_count;
+        Ok(())
+    }
+}
+
 struct HeaderRebuilder<'a> {
     dol_offset: u64,
     fst: FST,
     config: ROMConfig<'a>,
+    system_files: Vec<(PathBuf, Vec<u8>)>,
 }
 
 impl<'a> HeaderRebuilder<'a> {
    fn rebuild(self) -> io::Result<FileSystemRebuilder<'a>> {
-        let header_path = self.config.root_path.join(HEADER_PATH);
-        let header_buf = BufReader::new(File::open(&header_path)?);
-        let mut header = Header::new(header_buf, 0)?;
+        let header_buf = BufReader::new(File::open(&self.config.header_path)?);
+        let header = Header::new(header_buf, 0)?;
+        self.rebuild_with(header)
+    }
 
+    // Like `rebuild`, but starting from `header` instead of the existing one
+    fn rebuild_with(mut self, mut header: Header) -> io::Result<FileSystemRebuilder<'a>> {
         header.dol_offset = self.dol_offset as u64;
         header.fst_offset = self.fst.offset as u64;
         header.fst_size = self.fst.size;
@@ -241,12 +1004,16 @@ impl<'a> HeaderRebuilder<'a> {
         // TODO: Is this okay to assume?
         header.max_fst_size = self.fst.size;
 
-        header.write(File::create(&header_path)?)?;
+        let mut header_data = Vec::new();
+        header.write(&mut header_data)?;
+        self.config.header_path = self.config.root_path.join(HEADER_PATH);
+        self.system_files.push((self.config.header_path.clone(), header_data));
 
         Ok(FileSystemRebuilder {
             fst: self.fst,
             header,
             config: self.config,
+            system_files: self.system_files,
         })
     }
 }
@@ -255,14 +1022,16 @@ struct FileSystemRebuilder<'a> {
     fst: FST,
     header: Header,
     config: ROMConfig<'a>,
+    // What to write to the root before the ROM
+    system_files: Vec<(PathBuf, Vec<u8>)>,
 }
 
 impl<'a> FileSystemRebuilder<'a> {
-    fn rebuild(mut self) -> io::Result<ROMRebuilder> {
-        let apploader_path = self.config.root_path.join(APPLOADER_PATH);
-        let dol_path = self.config.root_path.join(DOL_PATH);
+    fn rebuild(mut self, allow_overlaps: bool) -> io::Result<RebuildPlan> {
+        let apploader_path = self.config.apploader_path.clone();
+        let dol_path = self.config.dol_path.clone();
         let fst_path = self.config.root_path.join(FST_PATH);
-        let header_path = self.config.root_path.join(HEADER_PATH);
+        let header_path = self.config.header_path.clone();
 
         self.config.files.push((APPLOADER_OFFSET, apploader_path));
         self.config.files.push((self.header.dol_offset, dol_path));
@@ -270,13 +1039,45 @@ impl<'a> FileSystemRebuilder<'a> {
         self.config.files.push((0, header_path));
 
         FileSystemRebuilder::fill_files(&mut self.config.files, self.fst.entries[0].as_dir().unwrap(), self.config.root_path, &self.fst);
+        // Their space is left as zeros
+        let missing = &self.config.missing;
+        self.config.files.retain(|(_, path)| !missing.contains(path));
 
         self.config.files.sort();
 
-        Ok(ROMRebuilder {
-            files: self.config.files,
-            space_used: self.config.space_used,
-        })
+        let mut warnings = std::mem::take(&mut self.config.mismatch_warnings);
+        if self.header.streams_audio() {
+            warnings.extend(self.unaligned_streams());
+        }
+        if self.config.left_decompressed > 0 {
+            warnings.push(format!(
+                "{} files were decompressed when they were extracted, and are going on the ROM that way, which the game might not expect",
+                self.config.left_decompressed,
+            ));
+        }
+        let mut plan = RebuildPlan::new(
+            self.config.files,
+            self.system_files,
+            self.config.compressed,
+            allow_overlaps,
+            self.config.disc_size,
+        )?;
+        plan.warnings = warnings;
+        Ok(plan)
+    }
+
+    // Streamed audio files that won't be aligned, like when that's been
+    // turned off, or they weren't to begin with
+    fn unaligned_streams(&self) -> Vec<String> {
+        self.fst.entries.iter()
+            .filter_map(|e| e.as_file())
+            .filter(|f| f.size > 0 && is_streamed_audio(&f.info.name) && f.file_offset % STREAM_ALIGNMENT != 0)
+            .map(|f| format!(
+                "{} would be at {:#x}, which isn't 32 KiB aligned, so it might not play on a console",
+                f.info.full_path.display(),
+                f.file_offset,
+            ))
+            .collect()
     }
 
     fn fill_files(
@@ -292,14 +1093,14 @@ impl<'a> FileSystemRebuilder<'a> {
                     files.push((
                         // offset,
                         file.file_offset,
-                        prefix.as_ref().join(&file.info.name),
+                        prefix.as_ref().join(&file.info.disk_name),
                     ));
                 },
                 Entry::Directory(ref sub_dir) => {
                     FileSystemRebuilder::fill_files(
                         files,
                         sub_dir,
-                        prefix.as_ref().join(&sub_dir.info.name),
+                        prefix.as_ref().join(&sub_dir.info.disk_name),
                         fst,
                     );
                 },
@@ -308,95 +1109,994 @@ impl<'a> FileSystemRebuilder<'a> {
     }
 }
 
-pub struct ROMRebuilder {
-    files: Vec<(u64, PathBuf)>,
-    space_used: Option<usize>,
+// Only used as a namespace for the ways to rebuild a ROM
+pub struct ROMRebuilder;
+
+// What a rebuild is going to do, worked out before anything is written. The
+// files can be changed before it's written, as long as they still fit.
+#[derive(Serialize)]
+pub struct RebuildPlan {
+    // Everything that'll be written to the ROM, sorted by offset
+    pub files: Vec<PlannedFile>,
+    // Anything that would stop the rebuild, like files that don't fit. The
+    // plan can't be written until these are fixed.
+    pub problems: Vec<String>,
+    // Things that won't stop the rebuild, but might stop the ROM from
+    // working, like streamed audio that isn't aligned
+    pub warnings: Vec<String>,
+    // Only used when preserving offsets
+    pub relocated: Vec<RelocatedFile>,
+    // (path, offset, size) for files that weren't in the existing FST, only
+    // used when preserving offsets
+    pub added: Vec<(PathBuf, u64, u64)>,
+    // Write files that overlap instead of failing. Whichever one starts first
+    // gets the bytes they share.
+    pub allow_overlaps: bool,
+    // How big the ROM will be, including the padding at the end
+    pub disc_size: u64,
+    // The new Game.toc and ISO.hdr, if they've changed, which are written to
+    // the ROM from here instead of the root
+    #[serde(skip)]
+    system_files: Vec<(PathBuf, Vec<u8>)>,
+    // Files that were compressed again, which are read from temporary files
+    // instead of the root, and never saved
+    #[serde(skip)]
+    compressed: HashMap<PathBuf, NamedTempFile>,
+    // Whether to save the new system files to the root right before the ROM
+    // is written, so it can be rebuilt again the same way
+    #[serde(skip)]
+    save_system_files: bool,
+    // What to hash the ROM with as it's written, padding and all, so it
+    // doesn't have to be read again to get its checksums
+    #[serde(skip)]
+    pub hashes: Vec<HashAlgo>,
+    // What goes in the gaps between files. It's only used when the whole ROM
+    // is written, not by `write_incremental`.
+    #[serde(skip)]
+    pub gap_fill: GapFill,
+    // Files are only opened to read them when there's a handle free
+    #[serde(skip)]
+    pub handles: Arc<HandleBudget>,
+}
+
+// What to fill the space between files with
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum GapFill {
+    #[default]
+    Zeros,
+    // The junk the official mastering tool uses, so a ROM laid out the same
+    // way as the original can match it exactly
+    Junk(JunkSeed),
+    // Whatever's at the same place on another ROM, like the one that was
+    // extracted. Anything past its end is zeros.
+    CopyFromSource(PathBuf),
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlannedFile {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Serialize)]
+pub struct RebuildReport {
+    pub files_written: usize,
+    pub bytes_written: u64,
+    pub padding_bytes: u64,
+    // (path, offset, size) for every file that was written
+    pub files: Vec<(PathBuf, u64, u64)>,
+    // Only used when preserving offsets
+    pub relocated: Vec<RelocatedFile>,
+    // (path, offset, size) for files that weren't in the existing FST, only
+    // used when preserving offsets
+    pub added: Vec<(PathBuf, u64, u64)>,
+    // The digests of the whole ROM, from each of the plan's `hashes`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub digests: Vec<Digest>,
+}
+
+impl RebuildReport {
+    pub fn percent_filled(&self) -> f64 {
+        (self.bytes_written - self.padding_bytes) as f64 / self.bytes_written as f64 * 100.0
+    }
 }
 
 impl ROMRebuilder {
-    pub fn rebuild(root: impl AsRef<Path>, alignment: u64, output: impl Write, rebuild_systemdata: bool) -> io::Result<()> {
-        let root = root.as_ref();
-        if rebuild_systemdata {
-            FSTRebuilder::new(root, alignment)?
-                .rebuild()?
+    // `exclude` is called with the path of every file and directory, relative
+    // to the root, and the ones it returns true for are left out of the ROM.
+    // `progress` is called with the amount of files added so far and the
+    // total amount of files, after each file is added. Files can only overlap
+    // if the existing FST is used, and then the rebuild fails unless
+    // `allow_overlaps` is set. `file_order` is the order to put the files in
+    // when making a new FST, or `None` to use the existing one.
+    pub fn rebuild(
+        root: impl AsRef<Path>,
+        alignment: u64,
+        output: impl Write,
+        file_order: Option<&OrderStrategy>,
+        allow_overlaps: bool,
+        exclude: impl Fn(&Path) -> bool,
+        progress: impl FnMut(usize, usize),
+    ) -> io::Result<RebuildReport> {
+        ROMRebuilder::plan(root, alignment, file_order, allow_overlaps, exclude, SystemFileOverrides::default(), DiscSize::Retail)?
+            .write(output, progress)
+    }
+
+    // Like `rebuild`, but the padding between files is skipped over instead of
+    // being written, so it's a lot faster and the ROM can be a sparse file.
+    // The file is removed if the rebuild fails.
+    pub fn rebuild_to_file(
+        root: impl AsRef<Path>,
+        alignment: u64,
+        path: impl AsRef<Path>,
+        file_order: Option<&OrderStrategy>,
+        allow_overlaps: bool,
+        exclude: impl Fn(&Path) -> bool,
+        progress: impl FnMut(usize, usize),
+    ) -> io::Result<RebuildReport> {
+        ROMRebuilder::plan(root, alignment, file_order, allow_overlaps, exclude, SystemFileOverrides::default(), DiscSize::Retail)?
+            .write_to_file(path, progress)
+    }
+
+    // Like `rebuild_to_file`, but the ROM is split into parts. They're all
+    // removed if the rebuild fails.
+    pub fn rebuild_to_split_files(
+        root: impl AsRef<Path>,
+        alignment: u64,
+        output: SplitFileWriter,
+        file_order: Option<&OrderStrategy>,
+        allow_overlaps: bool,
+        exclude: impl Fn(&Path) -> bool,
+        progress: impl FnMut(usize, usize),
+    ) -> io::Result<RebuildReport> {
+        match ROMRebuilder::plan(root, alignment, file_order, allow_overlaps, exclude, SystemFileOverrides::default(), DiscSize::Retail) {
+            Ok(plan) => plan.write_to_split_files(output, progress),
+            Err(e) => {
+                let _ = output.remove();
+                Err(e)
+            },
+        }
+    }
+
+    // `output` has to be empty, since the padding between files is skipped
+    // over rather than written.
+    pub fn rebuild_preserving_offsets(
+        root: impl AsRef<Path>,
+        alignment: u64,
+        output: impl Write + Seek,
+        allow_relocation: bool,
+        exclude: impl Fn(&Path) -> bool,
+        progress: impl FnMut(usize, usize),
+    ) -> io::Result<RebuildReport> {
+        ROMRebuilder::plan_preserving_offsets(
+            root,
+            alignment,
+            allow_relocation,
+            exclude,
+            SystemFileOverrides::default(),
+            DiscSize::Retail,
+        )?
+            .write_sparse(output, progress)
+    }
+
+    // Works out where everything goes without writing anything, not even the
+    // new Game.toc and ISO.hdr. Takes the same arguments as `rebuild`, along
+    // with files to use in place of the ones in `&&systemdata`, and how big
+    // the ROM should be.
+    pub fn plan(
+        root: impl AsRef<Path>,
+        alignment: u64,
+        file_order: Option<&OrderStrategy>,
+        allow_overlaps: bool,
+        exclude: impl Fn(&Path) -> bool,
+        overrides: SystemFileOverrides,
+        disc_size: DiscSize,
+    ) -> io::Result<RebuildPlan> {
+        check_exclusions(&exclude)?;
+        let config = ROMConfig::new(root.as_ref(), alignment, &exclude, overrides, disc_size)?;
+        let rebuilder = if let Some(order) = file_order {
+            FSTRebuilder::new(config, order)?
                 .rebuild()?
                 .rebuild()?
-                .write(output)
         } else {
-            let fst_file = File::open(root.join(FST_PATH))?;
-            let header_file = File::open(root.join(HEADER_PATH))?;
-
-            let mut fst = FST::new(BufReader::new(fst_file), 0)?;
-            let header = Header::new(BufReader::new(header_file), 0)?;
-            fst.offset = header.fst_offset;
+            let (fst, header) = read_fst_and_header(&config)?;
+            let mut config = config;
+            config.reconcile(&fst, true)?;
+            // Leaving files out would mean changing the FST
+            if let Some(e) = fst.entries.iter().find(|e| config.is_excluded(&e.info().full_path)) {
+                return Err(io::Error::other(format!(
+                    "{} can't be excluded without rebuilding the file system table",
+                    e.info().full_path.display(),
+                )));
+            }
 
             FileSystemRebuilder {
                 fst,
                 header,
-                config: ROMConfig {
-                    alignment,
-                    root_path: root,
-                    files: vec![],
-                    space_used: None,
+                config,
+                system_files: Vec::new(),
+            }
+        };
+        rebuilder.rebuild(allow_overlaps)
+    }
+
+    // Like `plan`, but for `rebuild_preserving_offsets`
+    pub fn plan_preserving_offsets(
+        root: impl AsRef<Path>,
+        alignment: u64,
+        allow_relocation: bool,
+        exclude: impl Fn(&Path) -> bool,
+        overrides: SystemFileOverrides,
+        disc_size: DiscSize,
+    ) -> io::Result<RebuildPlan> {
+        check_exclusions(&exclude)?;
+        let config = ROMConfig::new(root.as_ref(), alignment, &exclude, overrides, disc_size)?;
+        let (rebuilder, changes) = OffsetRebuilder::new(config, allow_relocation)?.rebuild()?;
+        let mut plan = rebuilder.rebuild(false)?;
+        // Files that don't fit run into the next one, so the layout's problems
+        // would just be these again, but less clearly
+        if !changes.errors.is_empty() {
+            plan.problems = changes.errors;
+        }
+        plan.relocated = changes.relocated;
+        plan.added = changes.added;
+        Ok(plan)
+    }
+
+    // Like `plan` with the existing Game.toc, but every file is put where
+    // `list` says, like a list from GameCube Rebuilder. The list has to have
+    // every file in the FST, with the same sizes.
+    pub fn plan_from_filelist(
+        root: impl AsRef<Path>,
+        alignment: u64,
+        list: &GcrFileList,
+        allow_overlaps: bool,
+        overrides: SystemFileOverrides,
+        disc_size: DiscSize,
+    ) -> io::Result<RebuildPlan> {
+        let exclude = |_: &Path| false;
+        let mut config = ROMConfig::new(root.as_ref(), alignment, &exclude, overrides, disc_size)?;
+        let (mut fst, header) = read_fst_and_header(&config)?;
+        config.reconcile(&fst, true)?;
+
+        let mismatches = fst.apply_gcr_filelist(list);
+        if !mismatches.is_empty() {
+            let mismatches = mismatches.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "The file list doesn't match {}:\n{}",
+                FST_PATH,
+                mismatches.join("\n"),
+            )));
+        }
+        let mut fst_data = Vec::new();
+        fst.write(&mut fst_data)?;
+
+        FileSystemRebuilder {
+            fst,
+            header,
+            system_files: vec![(config.root_path.join(FST_PATH), fst_data)],
+            config,
+        }.rebuild(allow_overlaps)
+    }
+
+    // Updates the ROM at `iso_path` in place from the files in `root`, only
+    // rewriting the parts of it that changed, which is a lot faster than a
+    // full rebuild when only a few files did. The ROM has to have the same
+    // layout as `root`'s Game.toc, like the ROM it was extracted from or the
+    // last one it was rebuilt to. Nothing on the ROM is moved, so every file
+    // has to fit in its original slot, and new files go after the last one.
+    pub fn rebuild_incremental(
+        root: impl AsRef<Path>,
+        alignment: u64,
+        iso_path: impl AsRef<Path>,
+        progress: impl FnMut(usize, usize),
+    ) -> io::Result<RebuildReport> {
+        let mut iso = OpenOptions::new().read(true).write(true).open(iso_path)?;
+        ROMRebuilder::plan_incremental(root, alignment, SystemFileOverrides::default(), &mut iso)?
+            .write_incremental(iso, progress)
+    }
+
+    // Like `plan`, but for `rebuild_incremental`. `iso` is the ROM that's
+    // going to be updated.
+    pub fn plan_incremental(
+        root: impl AsRef<Path>,
+        alignment: u64,
+        overrides: SystemFileOverrides,
+        mut iso: impl Read + Seek,
+    ) -> io::Result<RebuildPlan> {
+        let root = root.as_ref();
+        // If the FST on the ROM is the same as the one in the root, so is
+        // where every file is
+        let fst_data = fs::read(root.join(FST_PATH))?;
+        let header = Header::new(BufReader::new(&mut iso), 0)?;
+        let mut rom_fst_data = vec![0; fst_data.len()];
+        iso.seek(SeekFrom::Start(header.fst_offset))?;
+        let same_layout = header.fst_size == fst_data.len() && iso.read_exact(&mut rom_fst_data).is_ok();
+        if !same_layout || rom_fst_data != fst_data {
+            return Err(io::Error::other(format!(
+                "The ROM's file system table isn't the same as {}, so the ROM wasn't made from this root. It has to be fully rebuilt instead.",
+                root.join(FST_PATH).display(),
+            )));
+        }
+
+        let image_size = iso.seek(SeekFrom::End(0))?;
+        let mut plan = ROMRebuilder::plan_preserving_offsets(
+            root,
+            alignment,
+            false,
+            |_: &Path| false,
+            overrides,
+            DiscSize::Bytes(image_size),
+        )?;
+        if !plan.problems.is_empty() {
+            plan.problems.push("Nothing can be moved when rebuilding in place, so the ROM has to be fully rebuilt instead.".to_string());
+        }
+        Ok(plan)
+    }
+
+    // Makes a new ROM from the files in `root`, with a header made from
+    // `options`. There doesn't have to be an ISO.hdr or Game.toc, and nothing
+    // is written to `root`.
+    pub fn create(
+        root: impl AsRef<Path>,
+        alignment: u64,
+        options: &CreateOptions,
+        disc_size: DiscSize,
+        output: impl Write,
+        progress: impl FnMut(usize, usize),
+    ) -> io::Result<RebuildReport> {
+        ROMRebuilder::plan_create(root, alignment, options, disc_size)?.write(output, progress)
+    }
+
+    // Like `plan`, but for `create`
+    pub fn plan_create(
+        root: impl AsRef<Path>,
+        alignment: u64,
+        options: &CreateOptions,
+        disc_size: DiscSize,
+    ) -> io::Result<RebuildPlan> {
+        let header = Header::blank(options.game_code, options.maker_code, options.title)?;
+        let overrides = SystemFileOverrides {
+            header: None,
+            apploader: options.apploader,
+            dol: options.dol,
+            // The new header doesn't stream audio
+            stream_alignment: Some(false),
+            compress: false,
+            allow_missing: false,
+            allow_extra: false,
+        };
+        let exclude = |_: &Path| false;
+        let config = ROMConfig::new(root.as_ref(), alignment, &exclude, overrides, disc_size)?;
+        for (path, name) in [(&config.dol_path, "DOL"), (&config.apploader_path, "apploader")] {
+            if !path.is_file() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!(
+                    "There's no {} at {}, and a different one wasn't given",
+                    name,
+                    path.display(),
+                )));
+            }
+        }
+
+        let mut plan = FSTRebuilder::new(config, &OrderStrategy::FstOrder)?
+            .rebuild()?
+            .rebuild_with(header)?
+            .rebuild(false)?;
+        plan.save_system_files = false;
+        Ok(plan)
+    }
+}
+
+impl RebuildPlan {
+    fn new(
+        files: Vec<(u64, PathBuf)>,
+        system_files: Vec<(PathBuf, Vec<u8>)>,
+        compressed: HashMap<PathBuf, NamedTempFile>,
+        allow_overlaps: bool,
+        disc_size: DiscSize,
+    ) -> io::Result<RebuildPlan> {
+        let mut plan = RebuildPlan {
+            files: Vec::with_capacity(files.len()),
+            disc_size: 0,
+            problems: Vec::new(),
+            warnings: Vec::new(),
+            relocated: Vec::new(),
+            added: Vec::new(),
+            allow_overlaps,
+            system_files,
+            compressed,
+            save_system_files: true,
+            hashes: Vec::new(),
+            gap_fill: GapFill::Zeros,
+            handles: HandleBudget::shared(),
+        };
+        for (offset, path) in files {
+            let size = match plan.in_memory(&path) {
+                Some(data) => data.len() as u64,
+                None => metadata(plan.source_path(&path)).map(|m| m.len()).map_err(|e|
+                    io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+                )?,
+            };
+            plan.files.push(PlannedFile { path, offset, size });
+        }
+        plan.disc_size = disc_size.limit().unwrap_or_else(|| {
+            let end = plan.files.iter().map(|f| f.offset + f.size).max().unwrap_or(0);
+            align(end, FIT_ALIGNMENT)
+        });
+        plan.problems = plan.check_layout();
+        Ok(plan)
+    }
+
+    // The data for the file at `path`, if it's written from memory instead
+    // of being read from a file
+    fn in_memory(&self, path: &Path) -> Option<&[u8]> {
+        self.system_files.iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, data)| &data[..])
+    }
+
+    // Where to read the file at `path` from, which is somewhere else if it
+    // was compressed again
+    fn source_path<'a>(&'a self, path: &'a Path) -> &'a Path {
+        self.compressed.get(path).map_or(path, |temp| temp.path())
+    }
+
+    // Fills the gaps with junk seeded from the header that's going on the
+    // ROM, if the ROM that `root` was extracted from was checked and had junk
+    // in its gaps. If it had something else, its gaps are copied instead, or
+    // left as zeros if it isn't there anymore. Either way, or if it wasn't
+    // checked, there's a warning about it.
+    pub fn fill_gaps_with_junk(&mut self, root: &Path) -> io::Result<()> {
+        let header = self.files.iter()
+            .find(|f| f.offset == 0 && f.size > 0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "There's no header to seed the junk with."))?;
+        let header = match self.in_memory(&header.path) {
+            Some(data) => Header::new(io::Cursor::new(data), 0)?,
+            None => Header::new(BufReader::new(File::open(self.source_path(&header.path))?), 0)?,
+        };
+        let seed = JunkSeed::from_header(&header);
+
+        self.gap_fill = match JunkCheck::load(root)? {
+            Some(ref check) if check.matches() => GapFill::Junk(seed),
+            Some(JunkCheck { mismatch: Some(offset), source, .. }) => {
+                let reason = format!("The original ROM's gaps aren't the usual junk (starting at {:#x})", offset);
+                match source {
+                    Some(ref source) if source.is_file() => {
+                        self.warnings.push(format!("{}, so they're copied from {} instead", reason, source.display()));
+                        GapFill::CopyFromSource(source.clone())
+                    },
+                    _ => {
+                        self.warnings.push(format!("{}, and it isn't there to copy them from, so they're zeros instead", reason));
+                        GapFill::Zeros
+                    },
+                }
+            },
+            Some(_) => {
+                self.warnings.push("The original ROM didn't have any gaps to check for junk, so the junk might not match it".to_string());
+                GapFill::Junk(seed)
+            },
+            None => {
+                self.warnings.push(format!(
+                    "There's no {}, so it's not known if the original ROM's gaps were junk. Extract it again to check",
+                    JUNK_CHECK_PATH,
+                ));
+                GapFill::Junk(seed)
+            },
+        };
+        Ok(())
+    }
+
+    // The gaps between files that are filled with `gap_fill`, as (offset,
+    // size)
+    pub fn padding(&self) -> Vec<(u64, u64)> {
+        let mut padding = Vec::new();
+        let mut end = 0;
+        for f in self.files.iter().filter(|f| f.size > 0) {
+            if f.offset > end {
+                padding.push((end, f.offset - end));
+            }
+            end = cmp::max(end, f.offset + f.size);
+        }
+        if end < self.disc_size {
+            padding.push((end, self.disc_size - end));
+        }
+        padding
+    }
+
+    // Makes sure every file fits in the space it's been given. Returns what's
+    // wrong if they don't.
+    fn check_layout(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut past_end = false;
+        let mut overlapping = false;
+        // The end of the section that extends the furthest so far
+        let mut previous: Option<(u64, &Path)> = None;
+
+        for f in self.files.iter().filter(|f| f.size > 0) {
+            if let Some((end, previous_path)) = previous {
+                if f.offset < end && !self.allow_overlaps {
+                    overlapping = true;
+                    errors.push(format!(
+                        "{} runs {} bytes into {}",
+                        previous_path.display(),
+                        end - f.offset,
+                        f.path.display(),
+                    ));
+                }
+            }
+
+            let end = f.offset + f.size;
+            if end > self.disc_size {
+                past_end = true;
+                errors.push(format!(
+                    "{} ends {} bytes past the end of the ROM",
+                    f.path.display(),
+                    end - self.disc_size,
+                ));
+            }
+
+            if previous.is_none_or(|(e, _)| end > e) {
+                previous = Some((end, &f.path));
+            }
+        }
+
+        if past_end {
+            errors.push(format!(
+                "Not enough space. Try decreasing the file alignment with the -a option (the default is {} bytes), or making the disc bigger.",
+                DEFAULT_ALIGNMENT,
+            ));
+        }
+        if overlapping {
+            errors.push("The existing file system table has overlapping files. Use --force to rebuild anyway.".to_string());
+        }
+        errors
+    }
+
+    pub fn write(&self, output: impl Write, progress: impl FnMut(usize, usize)) -> io::Result<RebuildReport> {
+        self.write_with(output, write_zeros, progress)
+    }
+
+    // Like `write`, but the padding between files is skipped over instead of
+    // being written, so it's a lot faster and the ROM can be a sparse file.
+    // The file is removed if the rebuild fails.
+    pub fn write_to_file(&self, path: impl AsRef<Path>, progress: impl FnMut(usize, usize)) -> io::Result<RebuildReport> {
+        write_to_file(path.as_ref(), |f| self.write_with(f, skip_zeros, progress))
+    }
+
+    // Like `write_to_file`, but the ROM is split into parts. They're all
+    // removed if the rebuild fails.
+    pub fn write_to_split_files(
+        &self,
+        mut output: SplitFileWriter,
+        progress: impl FnMut(usize, usize),
+    ) -> io::Result<RebuildReport> {
+        let result = self.write_with(&mut output, skip_zeros, progress);
+        if result.is_err() {
+            let _ = output.remove();
+        }
+        result
+    }
+
+    // Like `write_to_file`, but the ROM is compressed into a GCZ file, with
+    // blocks of `block_size` bytes
+    #[cfg(feature = "gcz")]
+    pub fn write_to_gcz(
+        &self,
+        path: impl AsRef<Path>,
+        block_size: u32,
+        progress: impl FnMut(usize, usize),
+    ) -> io::Result<RebuildReport> {
+        write_to_file(path.as_ref(), |f| {
+            let mut gcz = GczWriter::new(io::BufWriter::new(f), self.disc_size, block_size)?;
+            let report = self.write_with(&mut gcz, write_zeros, progress)?;
+            gcz.finish()?;
+            Ok(report)
+        })
+    }
+
+    // `output` has to be empty, since the padding between files is skipped
+    // over rather than written.
+    pub fn write_sparse(&self, output: impl Write + Seek, progress: impl FnMut(usize, usize)) -> io::Result<RebuildReport> {
+        self.write_with(output, skip_zeros, progress)
+    }
+
+    // Writes the plan over the ROM it was made for with `plan_incremental`,
+    // only writing the chunks that are different. Whatever's left of a file
+    // that shrank is zeroed, like it'd be after a full rebuild. The report
+    // only has the files that were rewritten.
+    pub fn write_incremental(
+        &self,
+        mut iso: impl Read + Write + Seek,
+        mut progress: impl FnMut(usize, usize),
+    ) -> io::Result<RebuildReport> {
+        // How much space each section on the ROM took up before, by where it
+        // starts
+        let mut old_sizes = HashMap::new();
+        {
+            let game = Game::open(BufReader::new(&mut iso), 0)?;
+            for s in game.rom_layout().iter() {
+                let size = old_sizes.entry(s.start()).or_insert(0);
+                *size = cmp::max(*size, s.size() as u64);
+            }
+        }
+        // Empty files can start where another file does, and that file's data
+        // isn't theirs to clear
+        let starts = self.files.iter()
+            .filter(|f| f.size > 0)
+            .map(|f| f.offset)
+            .collect::<HashSet<_>>();
+
+        self.prepare_to_write()?;
+
+        let mut report = RebuildReport {
+            files_written: 0,
+            bytes_written: 0,
+            padding_bytes: 0,
+            files: Vec::new(),
+            relocated: Vec::new(),
+            added: self.added.clone(),
+            digests: Vec::new(),
+        };
+        let mut new_chunk = vec![0; WRITE_CHUNK_SIZE];
+        let mut old_chunk = vec![0; WRITE_CHUNK_SIZE];
+        for (i, f) in self.files.iter().enumerate() {
+            let source: Box<dyn Read> = match self.in_memory(&f.path) {
+                Some(data) => Box::new(data),
+                None => Box::new(File::open(self.source_path(&f.path)).map_err(|e|
+                    io::Error::new(e.kind(), format!("Couldn't read {}: {}", f.path.display(), e))
+                )?),
+            };
+            // Past the end of the file, it's compared with zeros, to clear
+            // out the rest of the old one
+            let mut source = source.take(f.size).chain(io::repeat(0));
+            let old_size = match old_sizes.get(&f.offset) {
+                Some(_) if f.size == 0 && starts.contains(&f.offset) => 0,
+                Some(&size) => size,
+                None => 0,
+            };
+            let end = f.offset + cmp::max(f.size, old_size);
+            let mut changed = false;
+            let mut pos = f.offset;
+            while pos < end {
+                let n = cmp::min(end - pos, WRITE_CHUNK_SIZE as u64) as usize;
+                source.read_exact(&mut new_chunk[..n])?;
+                iso.seek(SeekFrom::Start(pos))?;
+                iso.read_exact(&mut old_chunk[..n])?;
+                if new_chunk[..n] != old_chunk[..n] {
+                    iso.seek(SeekFrom::Start(pos))?;
+                    iso.write_all(&new_chunk[..n])?;
+                    report.bytes_written += n as u64;
+                    changed = true;
                 }
-            }.rebuild()?.write(output)
+                pos += n as u64;
+            }
+            if changed {
+                report.files_written += 1;
+                report.files.push((f.path.clone(), f.offset, f.size));
+            }
+            progress(i + 1, self.files.len());
         }
+        iso.flush()?;
+        Ok(report)
     }
 
-    fn write(
+    // Makes sure the plan can still be written, and saves the new system
+    // files if it should
+    fn prepare_to_write(&self) -> io::Result<()> {
+        if !self.problems.is_empty() {
+            return Err(io::Error::other(self.problems.join("\n")));
+        }
+        // The files might have been changed since the plan was made
+        let problems = self.check_layout();
+        if !problems.is_empty() {
+            return Err(io::Error::other(problems.join("\n")));
+        }
+
+        if self.save_system_files {
+            for (path, data) in &self.system_files {
+                File::create(path)?.write_all(data)?;
+            }
+        }
+        Ok(())
+    }
+
+    // `pad` is used to fill the space between files with zeros.
+    fn write_with<W: Write>(
         &self,
-        mut output: impl Write,
-    ) -> io::Result<()> {
-        let mut bytes_written = 0;
+        mut output: W,
+        mut pad: impl FnMut(u64, &mut W) -> io::Result<()>,
+        mut progress: impl FnMut(usize, usize),
+    ) -> io::Result<RebuildReport> {
+        self.prepare_to_write()?;
+
         let total_files = self.files.len();
 
-        for (i, &(offset, ref filename)) in self.files.iter().enumerate() {
-            let mut file = File::open(filename)?;
-            let size = file.metadata()?.len();
+        // Work out what each file writes up front, so the reader knows which
+        // bytes to send and the writer knows where they go
+        let mut queue = Vec::with_capacity(total_files);
+        let mut end = 0u64;
+        for (i, f) in self.files.iter().enumerate() {
+            // Empty files still go in the queue so they're counted and
+            // reported, even though there's nothing to write. Anything this
+            // overlaps was already written by the files before it.
+            let skip = cmp::min(end.saturating_sub(f.offset), f.size);
+            queue.push(QueuedFile { index: i, filename: &f.path, offset: f.offset, size: f.size, skip });
+            end = cmp::max(end, f.offset + f.size);
+        }
 
-            if size == 0 { continue }
+        // The reader reads ahead while the writer writes, and the channel is
+        // bounded so it can't get too far ahead. If the writer fails, it drops
+        // the receiver, so the reader's next send fails and it stops. If the
+        // reader fails, it sends the error and stops.
+        let mut hasher = MultiHasher::new(&self.hashes)?;
+        let mut fill = match self.gap_fill {
+            GapFill::Zeros => Filler::Zeros,
+            GapFill::Junk(seed) => Filler::Junk(seed),
+            GapFill::CopyFromSource(ref path) => Filler::Source(BufReader::new(File::open(path).map_err(|e|
+                io::Error::new(e.kind(), format!("Couldn't open {} to copy its gaps: {}", path.display(), e))
+            )?)),
+        };
+        // The reader only has one file open at a time. Its handle's reserved
+        // before anything's written, since it can't wait for one while the
+        // writer's waiting on it.
+        let reader_handle = self.handles.reserve(1);
+        let (sender, receiver) = sync_channel(PIPELINE_DEPTH);
+        let mut report = thread::scope(|s| {
+            s.spawn(|| read_queued_files(&queue, self, reader_handle, sender));
+            let output = Output {
+                writer: &mut output,
+                pad: &mut pad,
+                fill: &mut fill,
+                position: 0,
+                hasher: &mut hasher,
+            };
+            write_queued_files(&queue, receiver, self.disc_size, output, |i| progress(i + 1, total_files))
+        })?;
+        report.relocated = self.relocated.clone();
+        report.added = self.added.clone();
+        report.digests = hasher.finish();
+        Ok(report)
+    }
+}
+
+// Where a rebuild writes to. Everything that's written, including the
+// padding, also goes through `hasher`, in the same order.
+struct Output<'a, W: 'a, P: 'a> {
+    writer: &'a mut W,
+    // Fills the space between files with zeros
+    pad: &'a mut P,
+    // What to fill it with if it isn't zeros
+    fill: &'a mut Filler,
+    // How much has been written so far
+    position: u64,
+    hasher: &'a mut MultiHasher,
+}
 
-            write_zeros((offset - bytes_written) as usize, &mut output)?;
-            bytes_written = offset;
+// `GapFill`, ready to write
+enum Filler {
+    Zeros,
+    Junk(JunkSeed),
+    Source(BufReader<File>),
+}
 
-            extract_section(&mut file, size as usize, &mut output)?;
-            bytes_written += size;
+impl<'a, W: Write, P: FnMut(u64, &mut W) -> io::Result<()>> Output<'a, W, P> {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.hasher.update(data);
+        self.position += data.len() as u64;
+        self.writer.write_all(data)
+    }
 
-            if bytes_written as usize > ROM_SIZE {
-                println!();
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "Error: not enough space. Try decreasing the file alignment with the -a option (the default is {} bytes).",
-                        DEFAULT_ALIGNMENT,
-                    ),
-                ));
+    fn pad(&mut self, count: u64) -> io::Result<()> {
+        if count == 0 { return Ok(()) }
+        let start = self.position;
+        let mut buf = match *self.fill {
+            Filler::Zeros => {
+                self.hasher.update_zeros(count);
+                self.position += count;
+                return (self.pad)(count, self.writer);
+            },
+            _ => vec![0; cmp::min(count, WRITE_CHUNK_SIZE as u64) as usize],
+        };
+        let mut left = count;
+        match *self.fill {
+            Filler::Source(ref mut source) => { source.seek(SeekFrom::Start(start))?; },
+            // Junk starts on a multiple of 4, like after a file that doesn't
+            // end on one, and it's zeros until then
+            Filler::Junk(_) => {
+                let zeros = cmp::min(align(start, 4) - start, count);
+                self.write_all(&ZEROS[..zeros as usize])?;
+                left -= zeros;
+            },
+            Filler::Zeros => {},
+        }
+        while left > 0 {
+            let len = cmp::min(left, buf.len() as u64) as usize;
+            let chunk = &mut buf[..len];
+            match *self.fill {
+                Filler::Zeros => unreachable!(),
+                Filler::Junk(ref seed) => seed.fill(self.position, chunk),
+                Filler::Source(ref mut source) => {
+                    let read = read_up_to(source, chunk)?;
+                    chunk[read..].iter_mut().for_each(|b| *b = 0);
+                },
             }
-            print!("\r{}/{} files added.", i + 1, total_files);
+            self.write_all(chunk)?;
+            left -= len as u64;
+        }
+        Ok(())
+    }
+}
+
+// Reads until `buf` is full or `input` runs out, and returns how much it read
+fn read_up_to(mut input: impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
         }
-        println!();
-        write_zeros(ROM_SIZE - bytes_written as usize, &mut output)?;
+    }
+    Ok(read)
+}
 
-        if let Some(space) = self.space_used {
-            let percent_used = ((space as f64 / ROM_SIZE as f64) * 100.0) as usize;
-            println!("{:2}% of space filled ({}/{} bytes).", percent_used, space, ROM_SIZE);
+fn write_queued_files<W: Write, P: FnMut(u64, &mut W) -> io::Result<()>>(
+    queue: &[QueuedFile],
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    disc_size: u64,
+    mut output: Output<W, P>,
+    mut progress: impl FnMut(usize),
+) -> io::Result<RebuildReport> {
+    let mut bytes_written = 0;
+    let mut padding_bytes = 0;
+    let mut files = Vec::with_capacity(queue.len());
+
+    for file in queue {
+        // Empty files can start anywhere, even inside other files, like
+        // ISO.hdr at 0, and there's nothing to write for them
+        if file.size == 0 {
+            files.push((file.filename.to_path_buf(), file.offset, 0));
+            progress(file.index);
+            continue;
         }
 
-        Ok(())
+        debug!("Writing {} at {:#010x}", file.filename.display(), file.offset);
+        if file.skip == 0 {
+            output.pad(file.offset - bytes_written)?;
+            padding_bytes += file.offset - bytes_written;
+            bytes_written = file.offset;
+        }
+
+        let mut left = file.size - file.skip;
+        while left > 0 {
+            let chunk = receiver.recv().map_err(|_| io::Error::other(
+                "The rebuild's reader stopped early.",
+            ))??;
+            output.write_all(&chunk)?;
+            left -= chunk.len() as u64;
+        }
+        bytes_written = cmp::max(bytes_written, file.offset + file.size);
+        files.push((file.filename.to_path_buf(), file.offset, file.size));
+
+        progress(file.index);
     }
+    output.pad(disc_size - bytes_written)?;
+    output.writer.flush()?;
+    padding_bytes += disc_size - bytes_written;
+
+    Ok(RebuildReport {
+        files_written: files.len(),
+        bytes_written: disc_size,
+        padding_bytes,
+        files,
+        relocated: Vec::new(),
+        added: Vec::new(),
+        digests: Vec::new(),
+    })
 }
 
-fn write_zeros(count: usize, mut output: impl Write) -> io::Result<()> {
-    lazy_static! {
-        static ref ZEROS: Mutex<Vec<u8>> = Mutex::new(vec![]);
+// How many chunks the reader can get ahead of the writer during a rebuild
+const PIPELINE_DEPTH: usize = 4;
+
+// The part of a file that gets written to the ROM
+struct QueuedFile<'a> {
+    index: usize,
+    filename: &'a Path,
+    offset: u64,
+    size: u64,
+    // How many bytes at the start were already written by other files
+    skip: u64,
+}
+
+// `_handle` is released when the reader's done
+fn read_queued_files(
+    queue: &[QueuedFile],
+    plan: &RebuildPlan,
+    _handle: Handles,
+    sender: SyncSender<io::Result<Vec<u8>>>,
+) {
+    for file in queue {
+        let result = match plan.in_memory(file.filename) {
+            Some(data) => send_queued_data(file, data, &sender),
+            None => read_queued_file(file, plan.source_path(file.filename), &sender),
+        };
+        if let Err(e) = result {
+            // If the writer's already gone, there's no one to tell
+            let _ = sender.send(Err(e));
+            return;
+        }
     }
-    let mut zeros = ZEROS.lock().unwrap();
-    let block_size = cmp::min(count, WRITE_CHUNK_SIZE);
-    zeros.resize(block_size, 0);
-    for i in 0..(count / WRITE_CHUNK_SIZE + 1) {
-        (&mut output).write_all(
-            &zeros[..cmp::min(WRITE_CHUNK_SIZE, count - WRITE_CHUNK_SIZE * i)]
-        )?;
+}
+
+// Sends the file to the writer in chunks, reading it from `source`. Stops with
+// an error if the writer hangs up.
+fn read_queued_file(file: &QueuedFile, source: &Path, sender: &SyncSender<io::Result<Vec<u8>>>) -> io::Result<()> {
+    let mut f = File::open(source)?;
+    f.seek(SeekFrom::Start(file.skip))?;
+    let mut left = file.size - file.skip;
+    while left > 0 {
+        let mut chunk = vec![0; cmp::min(left, WRITE_CHUNK_SIZE as u64) as usize];
+        f.read_exact(&mut chunk).map_err(|e| io::Error::new(
+            e.kind(),
+            format!("Couldn't read {}: {}", file.filename.display(), e),
+        ))?;
+        left -= chunk.len() as u64;
+        sender.send(Ok(chunk)).map_err(|_| io::Error::other("The rebuild's writer stopped early."))?;
     }
     Ok(())
 }
+
+// The existing Game.toc, with its offset from the header
+fn read_fst_and_header(config: &ROMConfig) -> io::Result<(FST, Header)> {
+    let fst_file = File::open(config.root_path.join(FST_PATH))?;
+    let header_file = File::open(&config.header_path)?;
+
+    let mut fst = FST::new(BufReader::new(fst_file), 0)?;
+    let header = Header::new(BufReader::new(header_file), 0)?;
+    fst.offset = header.fst_offset;
+    // Renamed files, like decompressed ones with a suffix, are wherever the
+    // name map says they are
+    fst.apply_name_map(&config.original_names);
+    Ok((fst, header))
+}
+
+// Like `read_queued_file`, but for a file that's already in memory
+fn send_queued_data(file: &QueuedFile, data: &[u8], sender: &SyncSender<io::Result<Vec<u8>>>) -> io::Result<()> {
+    for chunk in data[file.skip as usize..file.size as usize].chunks(WRITE_CHUNK_SIZE) {
+        sender.send(Ok(chunk.to_vec())).map_err(|_| io::Error::other("The rebuild's writer stopped early."))?;
+    }
+    Ok(())
+}
+
+// The ROM can't boot without the system files
+fn check_exclusions(exclude: &dyn Fn(&Path) -> bool) -> io::Result<()> {
+    for &path in &[HEADER_PATH, APPLOADER_PATH, DOL_PATH, FST_PATH] {
+        if exclude(Path::new(path)) {
+            return Err(io::Error::other(format!("{} can't be excluded", path)));
+        }
+    }
+    Ok(())
+}
+
+fn write_to_file<T>(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<T>,
+) -> io::Result<T> {
+    let mut file = File::create(path)?;
+    let result = write(&mut file);
+    if result.is_err() {
+        drop(file);
+        let _ = remove_file(path);
+    }
+    result
+}
+
+// Seeks past the zeros instead of writing them. The last byte is still
+// written so the output ends up the right size, even if it's all padding.
+fn skip_zeros(count: u64, output: &mut (impl Write + Seek)) -> io::Result<()> {
+    if count == 0 { return Ok(()) }
+    output.seek(SeekFrom::Current(count as i64 - 1))?;
+    output.write_all(&[0])
+}
@@ -0,0 +1,289 @@
+// Getting files back off of a ROM whose FST is gone. This only carves: the
+// space after the system files is split into files where there's padding
+// between them or a block starts with a known magic number, and each one's
+// put in a directory for its type, with a made up name. Nothing's done to
+// work out the real names or directories, or where the files really end,
+// like running the apploader to see what it reads, so a salvaged ROM can be
+// rebuilt, but the game won't find its files in it.
+
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::fs::{create_dir, File, OpenOptions};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use filetype::{identify_magic, FileKind};
+use sections::apploader::{Apploader, APPLOADER_OFFSET};
+use sections::dol::DOLHeader;
+use sections::fst::{
+    entry::{encode_name, DirectoryEntry, Entry, EntryInfo, FileEntry, ENTRY_SIZE},
+    FST,
+    FST_SIZE_OFFSET,
+};
+use sections::header::Header;
+use sections::Section;
+use ::{
+    align,
+    extract_section,
+    paths::*,
+};
+
+pub const SALVAGE_REPORT_PATH: &str = "&&systemdata/salvage_report.txt";
+
+pub struct SalvagedFile {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub size: usize,
+    // How many zeros come after the file before the next one starts. Files
+    // can really end in a block or more of zeros, so some of these might be
+    // part of it.
+    pub zeros_after: u64,
+}
+
+pub struct SalvageReport {
+    // The system files, and whether or not they could be recovered
+    pub system_files: Vec<(&'static str, bool)>,
+    pub files: Vec<SalvagedFile>,
+}
+
+impl SalvageReport {
+    pub fn is_rebuildable(&self) -> bool {
+        self.system_files.iter().all(|&(_, recovered)| recovered)
+    }
+
+    pub fn write(&self, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "System files:")?;
+        for &(path, recovered) in &self.system_files {
+            let status = if recovered { "recovered" } else { "missing" };
+            writeln!(output, "  {}: {}", path, status)?;
+        }
+        writeln!(output)?;
+        writeln!(output, "Carved {} files. Their names were generated, and each one runs to the end", self.files.len())?;
+        writeln!(output, "of its last block, so it has the padding after it too. Files that end in a")?;
+        writeln!(output, "whole block of zeros or more are cut short, so the zeros after each one are")?;
+        writeln!(output, "listed too.")?;
+        for f in &self.files {
+            writeln!(
+                output,
+                "  {:#010x} {:>10} {} (then {} zeros)",
+                f.offset, f.size, f.path.display(), f.zeros_after,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Recovers as much as possible from a ROM whose file system table is missing
+// or too damaged to use. The system data that still parses is extracted as
+// usual, and the rest of the ROM is carved into files, grouped by type. A new
+// Game.toc is written that points at the carved files, so the result can be
+// rebuilt.
+pub fn salvage<R>(
+    mut iso: R,
+    path: impl AsRef<Path>,
+    alignment: u64,
+) -> io::Result<SalvageReport>
+where
+    R: BufRead + Seek,
+{
+    let path = path.as_ref();
+    create_dir(path)?;
+    let sys_data_path = path.join("&&systemdata");
+    create_dir(&sys_data_path)?;
+
+    let image_len = iso.seek(SeekFrom::End(0))?;
+    let header = Header::new(&mut iso, 0)?;
+    Header::extract(&mut iso, File::create(path.join(HEADER_PATH))?)?;
+
+    let mut system_files = vec![(HEADER_PATH, true)];
+    let mut system_end = header.fst_offset + header.fst_size as u64;
+
+    let apploader = Apploader::new(&mut iso, APPLOADER_OFFSET).ok()
+        .filter(|a| APPLOADER_OFFSET + a.total_size() as u64 <= image_len);
+    if let Some(ref a) = apploader {
+        Apploader::extract(&mut iso, File::create(path.join(APPLOADER_PATH))?)?;
+        system_end = max(system_end, a.start() + a.total_size() as u64);
+    }
+    system_files.push((APPLOADER_PATH, apploader.is_some()));
+
+    let dol = DOLHeader::new(&mut iso, header.dol_offset).ok()
+        .filter(|d| d.offset + d.dol_size as u64 <= image_len);
+    if let Some(ref d) = dol {
+        DOLHeader::extract(&mut iso, File::create(path.join(DOL_PATH))?, d.offset)?;
+        system_end = max(system_end, d.offset + d.dol_size as u64);
+    }
+    system_files.push((DOL_PATH, dol.is_some()));
+
+    let user_start = min(align(system_end, alignment), image_len);
+    let mut groups = BTreeMap::new();
+    let carved = carve(&mut iso, user_start, image_len, alignment)?;
+    for (i, &(offset, size)) in carved.iter().enumerate() {
+        let next = carved.get(i + 1).map_or(image_len, |&(o, _)| o);
+        let zeros_after = next - (offset + size as u64);
+        iso.seek(SeekFrom::Start(offset))?;
+        let mut magic = [0; 4];
+        let magic_len = iso.by_ref().take(magic.len() as u64).read(&mut magic)?;
+        // The kind's extension doubles as the directory carved files of that
+        // kind are written to
+        let file_type = identify_magic(&magic[..magic_len]).unwrap_or(FileKind::Unknown).extension();
+
+        let name = format!("file{:04}_{:08x}.{}", i, offset, file_type);
+        let dir = path.join(file_type);
+        if !dir.exists() {
+            create_dir(&dir)?;
+        }
+        iso.seek(SeekFrom::Start(offset))?;
+        extract_section(&mut iso, size, File::create(dir.join(&name))?)?;
+
+        groups.entry(file_type).or_insert_with(Vec::new).push(SalvagedFile {
+            path: Path::new(file_type).join(name),
+            offset,
+            size,
+            zeros_after,
+        });
+    }
+
+    let fst = make_fst(&groups, header.fst_offset);
+    fst.write(File::create(path.join(FST_PATH))?)?;
+    system_files.push((FST_PATH, true));
+
+    // Point the header at the new Game.toc
+    let mut header_file = OpenOptions::new().write(true).open(path.join(HEADER_PATH))?;
+    header_file.seek(SeekFrom::Start(FST_SIZE_OFFSET))?;
+    header_file.write_u32::<BigEndian>(fst.size as u32)?;
+    header_file.write_u32::<BigEndian>(fst.size as u32)?;
+
+    let report = SalvageReport {
+        system_files,
+        files: groups.into_values().flatten().collect(),
+    };
+    report.write(File::create(path.join(SALVAGE_REPORT_PATH))?)?;
+
+    Ok(report)
+}
+
+// Files on the disc start on an aligned offset and are padded with zeros, so
+// a file is assumed to be a run of non-empty blocks that ends with the first
+// block that has zeros at the end. A block that starts with a known signature
+// always starts a new file, in case there was no padding. Nothing's trimmed
+// off of the last block, since the next file can't start until the one after
+// it, and the zeros might be part of the file.
+fn carve(
+    mut iso: impl Read + Seek,
+    start: u64,
+    end: u64,
+    alignment: u64,
+) -> io::Result<Vec<(u64, usize)>> {
+    let mut files = Vec::new();
+    let mut current: Option<u64> = None;
+    let mut block = vec![0; alignment as usize];
+
+    iso.seek(SeekFrom::Start(start))?;
+    let mut offset = start;
+    while offset < end {
+        let len = min(alignment, end - offset) as usize;
+        iso.read_exact(&mut block[..len])?;
+        let block = &block[..len];
+        let block_end = offset + len as u64;
+
+        match block.iter().rposition(|&b| b != 0) {
+            None => {
+                if let Some(s) = current.take() {
+                    files.push((s, (offset - s) as usize));
+                }
+            },
+            Some(last) => {
+                if identify_magic(block).is_some() {
+                    if let Some(s) = current.take() {
+                        files.push((s, (offset - s) as usize));
+                    }
+                }
+                let file_start = current.unwrap_or(offset);
+                if last + 1 < len {
+                    files.push((file_start, (block_end - file_start) as usize));
+                    current = None;
+                } else {
+                    current = Some(file_start);
+                }
+            },
+        }
+        offset = block_end;
+    }
+    if let Some(s) = current {
+        files.push((s, (end - s) as usize));
+    }
+
+    Ok(files)
+}
+
+fn make_fst(groups: &BTreeMap<&str, Vec<SalvagedFile>>, offset: u64) -> FST {
+    let entry_count = 1 + groups.len() + groups.values().map(Vec::len).sum::<usize>();
+    let mut entries = Vec::with_capacity(entry_count);
+    entries.push(Entry::Directory(DirectoryEntry {
+        info: EntryInfo {
+            index: 0,
+            name: "/".to_string(),
+            raw_name: Vec::new(),
+            filename_offset: 0,
+            disk_name: String::new(),
+            directory_index: None,
+            full_path: "/".into(),
+        },
+        parent_index: 0,
+        next_index: entry_count,
+        file_count: groups.len(),
+    }));
+
+    let mut filename_offset = 0;
+    let mut total_file_system_size = 0;
+    for (dir_name, files) in groups {
+        let dir_index = entries.len();
+        entries.push(Entry::Directory(DirectoryEntry {
+            info: EntryInfo {
+                index: dir_index,
+                name: dir_name.to_string(),
+                raw_name: encode_name(dir_name),
+                filename_offset,
+                disk_name: dir_name.to_string(),
+                directory_index: Some(0),
+                full_path: Path::new("/").join(dir_name),
+            },
+            parent_index: 0,
+            next_index: dir_index + files.len() + 1,
+            file_count: files.len(),
+        }));
+        // plus 1 for the null byte
+        filename_offset += encode_name(dir_name).len() as u64 + 1;
+
+        for f in files {
+            let name = f.path.file_name().unwrap().to_string_lossy().into_owned();
+            let raw_name = encode_name(&name);
+            let name_len = raw_name.len() as u64;
+            entries.push(Entry::File(FileEntry {
+                info: EntryInfo {
+                    index: entries.len(),
+                    disk_name: name.clone(),
+                    name,
+                    raw_name,
+                    filename_offset,
+                    directory_index: Some(dir_index),
+                    full_path: Path::new("/").join(&f.path),
+                },
+                file_offset: f.offset,
+                size: f.size,
+            }));
+            filename_offset += name_len + 1;
+            total_file_system_size += f.size;
+        }
+    }
+
+    FST {
+        offset,
+        file_count: entries.len() - groups.len() - 1,
+        total_file_system_size,
+        size: entries.len() * ENTRY_SIZE + filename_offset as usize,
+        entries,
+    }
+}
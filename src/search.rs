@@ -0,0 +1,144 @@
+// Finding bytes anywhere on a ROM without extracting it first, like a string
+// from a menu or a few instructions, and working out which file they're in
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use ::{read_fully, Game, WRITE_CHUNK_SIZE};
+
+// Some bytes to look for. `None` matches any byte.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SearchPattern(Vec<Option<u8>>);
+
+impl SearchPattern {
+    pub fn bytes(bytes: &[u8]) -> io::Result<SearchPattern> {
+        SearchPattern::new(bytes.iter().map(|&b| Some(b)).collect())
+    }
+
+    pub fn string(s: &str) -> io::Result<SearchPattern> {
+        SearchPattern::bytes(s.as_bytes())
+    }
+
+    // Pairs of hex digits, like "3C 60 80 ?? 38 63", with `??` for any byte.
+    // Spaces are optional.
+    pub fn parse_hex(hex: &str) -> io::Result<SearchPattern> {
+        let digits = hex.chars().filter(|c| !c.is_whitespace()).collect::<Vec<_>>();
+        if digits.len() % 2 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Every byte needs two hex digits.",
+            ));
+        }
+        let bytes = digits.chunks(2)
+            .map(|pair| match pair {
+                ['?', '?'] => Ok(None),
+                _ => u8::from_str_radix(&pair.iter().collect::<String>(), 16)
+                    .map(Some)
+                    .map_err(|_| io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{}{} isn't a hex byte or ??.", pair[0], pair[1]),
+                    )),
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        SearchPattern::new(bytes)
+    }
+
+    fn new(bytes: Vec<Option<u8>>) -> io::Result<SearchPattern> {
+        if bytes.iter().all(Option::is_none) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "There has to be at least one byte to search for that isn't ??.",
+            ));
+        }
+        Ok(SearchPattern(bytes))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    // Never true, since there's always at least one byte that isn't a
+    // wildcard
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Where the pattern starts in `data`, for every match that fits in it
+    fn find_all<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        // Look for the first byte that isn't a wildcard, then check the rest
+        let (anchor, anchor_byte) = self.0.iter()
+            .enumerate()
+            .find_map(|(i, b)| b.map(|b| (i, b)))
+            .unwrap();
+        let last_start = (data.len() + 1).saturating_sub(self.len());
+        data[anchor..].iter()
+            .take(last_start)
+            .enumerate()
+            .filter(move |&(_, &b)| b == anchor_byte)
+            .map(|(i, _)| i)
+            .filter(move |&i| self.0.iter()
+                .zip(&data[i..])
+                .all(|(p, b)| p.is_none_or(|p| p == *b))
+            )
+    }
+}
+
+pub struct SearchHit {
+    // Where the match starts on the ROM
+    pub offset: u64,
+    // What the match is in, if it isn't in unused space
+    pub section: Option<HitSection>,
+}
+
+pub struct HitSection {
+    pub name: String,
+    pub section_type: &'static str,
+    // Where the match starts, from the start of the section
+    pub offset: u64,
+    // The FST entry, for files and matches in Game.toc's entries
+    pub fst_index: Option<usize>,
+    // Where the match is loaded in memory, for DOL segments
+    pub address: Option<u64>,
+}
+
+impl Game {
+    // Every place `pattern` is on the ROM, in order. The ROM is read a chunk
+    // at a time, keeping the end of the last chunk so matches that cross
+    // from one to the next are still found.
+    pub fn search(&self, mut iso: impl Read + Seek, pattern: &SearchPattern) -> io::Result<Vec<SearchHit>> {
+        self.require_image("Searching")?;
+        let overlap = pattern.len() - 1;
+        let mut buf = vec![0; WRITE_CHUNK_SIZE + overlap];
+        // Where `buf` starts on the ROM
+        let mut buf_start = 0;
+        let mut filled = 0;
+        let mut offsets = Vec::new();
+
+        iso.seek(SeekFrom::Start(0))?;
+        loop {
+            let to_read = buf.len() - filled;
+            let read = read_fully(&mut iso, &mut buf[filled..])?;
+            filled += read;
+            offsets.extend(pattern.find_all(&buf[..filled]).map(|i| buf_start + i as u64));
+            if read < to_read { break }
+
+            buf.copy_within(filled - overlap..filled, 0);
+            buf_start += (filled - overlap) as u64;
+            filled = overlap;
+        }
+
+        let layout = self.rom_layout();
+        Ok(offsets.into_iter().map(|offset| SearchHit {
+            offset,
+            section: layout.resolve(offset).map(|r| {
+                let s = r.section();
+                HitSection {
+                    name: s.name(),
+                    section_type: s.section_type(),
+                    offset: s.relative_offset(offset),
+                    fst_index: r.fst_index(offset),
+                    address: r.address(offset),
+                }
+            }),
+        }).collect())
+    }
+}
@@ -10,15 +10,37 @@ use ::{
     NumberStyle,
 };
 
-use sections::Section;
+use disassembler::CodeSection;
+#[cfg(feature = "sha1")]
+use hash::sha1_range;
+use sections::{ResolvedSection, Section};
 
 pub const APPLOADER_OFFSET: u64 = 0x2440;
 const APPLOADER_DATE_SIZE: usize = 0x0A;
 const APPLOADER_ENTRY_POINT_ADDR: u64 = 0x2450;
 const APPLOADER_ENTRY_POINT_SIZE: u64 = 0xA0;
-const APPLOADER_SIZE_ADDR: u64 = 0x2454;
+// The code comes right after the header, and gets loaded here by the IPL
+pub const APPLOADER_CODE_OFFSET: u64 = APPLOADER_OFFSET + 0x20;
+pub const APPLOADER_LOAD_ADDRESS: u64 = 0x8120_0000;
+// From the load address to the end of the GameCube's 24 MiB of RAM. A size
+// field saying it's bigger than this is corrupt.
+const MAX_APPLOADER_SIZE: usize = 0x60_0000;
 
-#[derive(Debug)]
+// Which SDK a game was built with, going by its apploader's date. Every game
+// built with the same SDK has the same apploader, but which date goes with
+// which release isn't written down anywhere, so this only narrows it down to
+// around when the SDK came out. Each entry is the first date in its range.
+const SDK_ERAS: &[(&str, &str)] = &[
+    ("0000/00/00", "a pre-release SDK"),
+    ("2001/01/01", "an early 2001 SDK"),
+    ("2001/07/01", "a launch SDK (late 2001)"),
+    ("2002/01/01", "a 2002 SDK"),
+    ("2003/01/01", "a 2003 SDK"),
+    ("2004/01/01", "a 2004 SDK"),
+    ("2005/01/01", "a late SDK (2005 or later)"),
+];
+
+#[derive(Debug, Serialize)]
 pub struct Apploader {
     pub date: String,
     pub entry_point: u64,
@@ -41,6 +63,16 @@ impl Apploader {
         let entry_point = reader.read_u32::<BigEndian>()? as u64;
         let code_size = reader.read_u32::<BigEndian>()? as usize;
         let trailer_size = reader.read_u32::<BigEndian>()? as usize;
+        if code_size == 0 || code_size + trailer_size > MAX_APPLOADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The apploader's code size ({:#x}) and trailer size ({:#x}) can't be right.",
+                    code_size,
+                    trailer_size,
+                ),
+            ));
+        }
 
         Ok(Apploader {
             date,
@@ -50,9 +82,63 @@ impl Apploader {
         })
     }
 
+    // For discs where something else is where the apploader would be, like
+    // Triforce ones. It's kept as `size` bytes of code, so it's still
+    // extracted and rebuilt as-is.
+    pub fn unparsed(size: usize) -> Apploader {
+        Apploader {
+            date: String::new(),
+            entry_point: 0,
+            code_size: size,
+            trailer_size: 0,
+        }
+    }
+
+    // See `SDK_ERAS`. `None` if the date isn't in the usual YYYY/MM/DD format.
+    pub fn sdk_version_guess(&self) -> Option<&'static str> {
+        let is_date = self.date.len() == APPLOADER_DATE_SIZE &&
+            self.date.char_indices().all(|(i, c)|
+                if i == 4 || i == 7 { c == '/' } else { c.is_ascii_digit() }
+            );
+        if !is_date {
+            return None;
+        }
+        SDK_ERAS.iter().rev()
+            .find(|&&(start, _)| self.date.as_str() >= start)
+            .map(|&(_, sdk)| sdk)
+    }
+
+    // Whether the apploader runs into the DOL, which means the ROM's corrupt
+    pub fn overlaps_dol(&self, dol_offset: u64) -> bool {
+        self.overlaps(dol_offset)
+    }
+
+    // Whether the apploader runs into a section that starts at `start`
+    pub fn overlaps(&self, start: u64) -> bool {
+        start > APPLOADER_OFFSET &&
+            APPLOADER_CODE_OFFSET + (self.code_size + self.trailer_size) as u64 > start
+    }
+
+    pub fn code_section(&self) -> CodeSection {
+        CodeSection {
+            name: "apploader".to_string(),
+            offset: APPLOADER_CODE_OFFSET,
+            size: self.code_size,
+            load_address: APPLOADER_LOAD_ADDRESS,
+        }
+    }
+
+    // The SHA-1 of the apploader, which is the same as the extracted
+    // Apploader.ldr's
+    #[cfg(feature = "sha1")]
+    pub fn hash(&self, iso: impl Read + Seek) -> io::Result<[u8; 20]> {
+        sha1_range(iso, APPLOADER_OFFSET, self.total_size() as u64)
+    }
+
+    // The whole apploader, including the 0x20 byte header before the code
     pub fn total_size(&self) -> usize {
-        // self.code_size + self.trailer_size
-        align((self.code_size + self.trailer_size) as u64, 32) as usize
+        let header_size = APPLOADER_CODE_OFFSET - APPLOADER_OFFSET;
+        align(header_size + (self.code_size + self.trailer_size) as u64, 32) as usize
     }
 
     pub fn extract<R, W>(mut iso: R, file: W) -> io::Result<()>
@@ -60,13 +146,9 @@ impl Apploader {
         R: Read + Seek,
         W: Write,
     {
-        iso.seek(SeekFrom::Start(APPLOADER_SIZE_ADDR))?;
-        let code_size = iso.read_u32::<BigEndian>()? as u64;
-        let trailer_size = iso.read_u32::<BigEndian>()? as u64;
+        let size = Apploader::new(&mut iso, APPLOADER_OFFSET)?.total_size();
         iso.seek(SeekFrom::Start(APPLOADER_OFFSET))?;
-
-        let aligned_size = align(code_size + trailer_size, 32);
-        extract_section(iso, aligned_size as usize, file)
+        extract_section(iso, size, file)
     }
 }
 
@@ -74,9 +156,12 @@ impl Section for Apploader {
     fn print_info(&self, style: NumberStyle) {
         println!("Offset: {}", format_u64(APPLOADER_OFFSET, style));
         println!("Date: {}", self.date);
+        if let Some(sdk) = self.sdk_version_guess() {
+            println!("Probably built with {}", sdk);
+        }
         println!("Code size: {} bytes", format_usize(self.code_size, style));
         println!("Trailer size: {} bytes", format_usize(self.trailer_size, style));
-        println!("Entry point: not yet implemented");
+        println!("Entry point: {}", format_u64(self.entry_point, style));
         println!(
             "Size (including code and trailer, aligned to 32 bytes): {}",
             format_usize(self.total_size(), style),
@@ -90,4 +175,16 @@ impl Section for Apploader {
     fn size(&self) -> usize {
         self.total_size()
     }
+
+    fn name(&self) -> String {
+        "Apploader.ldr".to_string()
+    }
+
+    fn section_type(&self) -> &'static str {
+        "apploader"
+    }
+
+    fn resolve(&self) -> ResolvedSection<'_> {
+        ResolvedSection::Apploader(self)
+    }
 }
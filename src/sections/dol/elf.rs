@@ -0,0 +1,358 @@
+// Converts a DOL to a 32-bit big endian PowerPC ELF, so it can be loaded in
+// tools like Ghidra and IDA that don't handle DOL files well, and back again.
+// The ELF spec: https://refspecs.linuxfoundation.org/elf/elf.pdf
+
+use std::cmp::{max, min};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ::align;
+use sections::dol::{DATA_SEG_COUNT, DOL_HEADER_LEN, DOLHeader, SEGMENT_ALIGNMENT, TEXT_SEG_COUNT};
+use sections::dol::segment::{Segment, SegmentType};
+
+pub const ELF_HEADER_SIZE: u64 = 52;
+pub const PROGRAM_HEADER_SIZE: u64 = 32;
+pub const SECTION_HEADER_SIZE: u64 = 40;
+pub const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+
+pub const ET_EXEC: u16 = 2;
+pub const EM_PPC: u16 = 20;
+
+pub const PT_LOAD: u32 = 1;
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHF_WRITE: u32 = 1;
+pub const SHF_ALLOC: u32 = 2;
+pub const SHF_EXECINSTR: u32 = 4;
+
+// A loadable segment from an ELF, and the DOL slot it goes in
+struct LoadSegment {
+    seg_type: SegmentType,
+    seg_num: Option<u64>,
+    elf_offset: u64,
+    address: u64,
+    size: u64,
+}
+
+struct ElfSection {
+    name: String,
+    section_type: u32,
+    flags: u32,
+    address: u64,
+    // Where it is in the ELF, and where to read it from in the DOL
+    elf_offset: u64,
+    dol_offset: u64,
+    size: u64,
+}
+
+impl DOLHeader {
+    // `dol` has to be what this header was read from, since the segment
+    // offsets are relative to it.
+    pub fn to_elf(&self, mut dol: impl Read + Seek, mut output: impl Write) -> io::Result<()> {
+        let mut sections = Vec::new();
+        let load_count = self.segments.len() + if self.bss_size > 0 { 1 } else { 0 };
+        let mut offset = align(
+            ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE * load_count as u64,
+            SEGMENT_ALIGNMENT,
+        );
+
+        for s in &self.segments {
+            let flags = match s.seg_type {
+                SegmentType::Text => SHF_ALLOC | SHF_EXECINSTR,
+                SegmentType::Data => SHF_ALLOC | SHF_WRITE,
+            };
+            sections.push(ElfSection {
+                name: s.to_string(),
+                section_type: SHT_PROGBITS,
+                flags,
+                address: s.loading_address,
+                elf_offset: offset,
+                dol_offset: s.offset,
+                size: s.size as u64,
+            });
+            offset = align(offset + s.size as u64, SEGMENT_ALIGNMENT);
+        }
+        if self.bss_size > 0 {
+            sections.push(ElfSection {
+                name: ".bss".to_string(),
+                section_type: SHT_NOBITS,
+                flags: SHF_ALLOC | SHF_WRITE,
+                address: self.bss_address,
+                elf_offset: offset,
+                dol_offset: 0,
+                size: self.bss_size as u64,
+            });
+        }
+
+        let mut names = vec![0];
+        let mut name_offsets = Vec::with_capacity(sections.len() + 1);
+        for name in sections.iter().map(|s| &s.name[..]).chain(Some(".shstrtab")) {
+            name_offsets.push(names.len() as u32);
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        let names_offset = offset;
+        let section_headers_offset = align(names_offset + names.len() as u64, 4);
+        // Plus the null section and the section name table
+        let section_count = sections.len() + 2;
+
+        // ELF header
+        output.write_all(ELF_MAGIC)?;
+        // 32-bit, big endian, version 1, System V ABI
+        output.write_all(&[1, 2, 1, 0])?;
+        output.write_all(&[0; 8])?;
+        output.write_u16::<BigEndian>(ET_EXEC)?;
+        output.write_u16::<BigEndian>(EM_PPC)?;
+        output.write_u32::<BigEndian>(1)?;
+        output.write_u32::<BigEndian>(self.entry_point as u32)?;
+        output.write_u32::<BigEndian>(ELF_HEADER_SIZE as u32)?;
+        output.write_u32::<BigEndian>(section_headers_offset as u32)?;
+        output.write_u32::<BigEndian>(0)?;
+        output.write_u16::<BigEndian>(ELF_HEADER_SIZE as u16)?;
+        output.write_u16::<BigEndian>(PROGRAM_HEADER_SIZE as u16)?;
+        output.write_u16::<BigEndian>(load_count as u16)?;
+        output.write_u16::<BigEndian>(SECTION_HEADER_SIZE as u16)?;
+        output.write_u16::<BigEndian>(section_count as u16)?;
+        output.write_u16::<BigEndian>(section_count as u16 - 1)?;
+
+        // Program headers, one for each section
+        for s in &sections {
+            let file_size = if s.section_type == SHT_NOBITS { 0 } else { s.size };
+            let flags = if s.flags & SHF_EXECINSTR != 0 { PF_R | PF_X } else { PF_R | PF_W };
+            output.write_u32::<BigEndian>(PT_LOAD)?;
+            output.write_u32::<BigEndian>(s.elf_offset as u32)?;
+            output.write_u32::<BigEndian>(s.address as u32)?;
+            output.write_u32::<BigEndian>(s.address as u32)?;
+            output.write_u32::<BigEndian>(file_size as u32)?;
+            output.write_u32::<BigEndian>(s.size as u32)?;
+            output.write_u32::<BigEndian>(flags)?;
+            output.write_u32::<BigEndian>(SEGMENT_ALIGNMENT as u32)?;
+        }
+
+        // The segments themselves
+        let mut position = ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE * load_count as u64;
+        for s in sections.iter().filter(|s| s.section_type != SHT_NOBITS) {
+            write_padding(&mut output, s.elf_offset - position)?;
+            dol.seek(SeekFrom::Start(s.dol_offset))?;
+            let copied = io::copy(&mut (&mut dol).take(s.size), &mut output)?;
+            if copied != s.size {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} is cut off", s.name)));
+            }
+            position = s.elf_offset + s.size;
+        }
+
+        write_padding(&mut output, names_offset - position)?;
+        output.write_all(&names)?;
+        write_padding(&mut output, section_headers_offset - names_offset - names.len() as u64)?;
+
+        // Section headers, starting with the null section
+        output.write_all(&[0; SECTION_HEADER_SIZE as usize])?;
+        sections.push(ElfSection {
+            name: ".shstrtab".to_string(),
+            section_type: SHT_STRTAB,
+            flags: 0,
+            address: 0,
+            elf_offset: names_offset,
+            dol_offset: 0,
+            size: names.len() as u64,
+        });
+        for (s, &name) in sections.iter().zip(&name_offsets) {
+            write_section_header(&mut output, name, s)?;
+        }
+        Ok(())
+    }
+}
+
+impl DOLHeader {
+    // Maps the loadable segments in an ELF to the text and data slots in a
+    // DOL. Segments go in the slot named by their section (like .text1 or
+    // .data5) if they have one, so a DOL converted with `to_elf` converts
+    // back to the same thing.
+    pub fn from_elf(mut elf: impl Read + Seek, mut output: impl Write) -> io::Result<()> {
+        let mut ident = [0; 16];
+        elf.read_exact(&mut ident)?;
+        if &ident[..4] != ELF_MAGIC || ident[4] != 1 || ident[5] != 2 {
+            return Err(invalid_data("not a 32-bit big endian ELF"));
+        }
+
+        elf.seek(SeekFrom::Start(0x18))?;
+        let entry_point = elf.read_u32::<BigEndian>()?;
+        let program_headers_offset = elf.read_u32::<BigEndian>()? as u64;
+        let section_headers_offset = elf.read_u32::<BigEndian>()? as u64;
+        elf.seek(SeekFrom::Start(0x2c))?;
+        let program_header_count = elf.read_u16::<BigEndian>()? as u64;
+        elf.seek(SeekFrom::Current(2))?;
+        let section_header_count = elf.read_u16::<BigEndian>()? as u64;
+        let names_index = elf.read_u16::<BigEndian>()? as u64;
+
+        // (type, name, flags, address, offset, size)
+        let mut sections = Vec::new();
+        for i in 0..section_header_count {
+            elf.seek(SeekFrom::Start(section_headers_offset + i * SECTION_HEADER_SIZE))?;
+            let name = elf.read_u32::<BigEndian>()? as u64;
+            let section_type = elf.read_u32::<BigEndian>()?;
+            let flags = elf.read_u32::<BigEndian>()?;
+            let address = elf.read_u32::<BigEndian>()? as u64;
+            let offset = elf.read_u32::<BigEndian>()? as u64;
+            let size = elf.read_u32::<BigEndian>()? as u64;
+            sections.push((section_type, name, flags, address, offset, size));
+        }
+        let names_offset = sections.get(names_index as usize).map(|s| s.4);
+        let mut section_names = Vec::with_capacity(sections.len());
+        for &(_, name, ..) in &sections {
+            section_names.push(match names_offset {
+                Some(o) => read_name(&mut elf, o + name)?,
+                None => String::new(),
+            });
+        }
+
+        let mut segments = Vec::new();
+        let mut bss: Option<(u64, u64)> = None;
+        let mut add_bss = |start: u64, end: u64| {
+            bss = Some(bss.map_or((start, end), |(s, e)| (min(s, start), max(e, end))));
+        };
+        for i in 0..program_header_count {
+            elf.seek(SeekFrom::Start(program_headers_offset + i * PROGRAM_HEADER_SIZE))?;
+            let segment_type = elf.read_u32::<BigEndian>()?;
+            let offset = elf.read_u32::<BigEndian>()? as u64;
+            let address = elf.read_u32::<BigEndian>()? as u64;
+            elf.seek(SeekFrom::Current(4))?;
+            let file_size = elf.read_u32::<BigEndian>()? as u64;
+            let mem_size = elf.read_u32::<BigEndian>()? as u64;
+            let flags = elf.read_u32::<BigEndian>()?;
+
+            if segment_type != PT_LOAD { continue }
+            if mem_size > file_size {
+                add_bss(address + file_size, address + mem_size);
+            }
+            if file_size == 0 { continue }
+
+            let seg_type = if flags & PF_X != 0 { SegmentType::Text } else { SegmentType::Data };
+            let seg_num = sections.iter().zip(&section_names)
+                .filter(|&(s, _)| s.0 == SHT_PROGBITS && s.4 == offset)
+                .filter_map(|(_, name)| Segment::parse_segment_name(name))
+                .find(|&(t, _)| t == seg_type)
+                .map(|(_, n)| n);
+            segments.push(LoadSegment { seg_type, seg_num, elf_offset: offset, address, size: file_size });
+        }
+        for &(section_type, _, flags, address, _, size) in &sections {
+            if section_type == SHT_NOBITS && flags & SHF_ALLOC != 0 && size > 0 {
+                add_bss(address, address + size);
+            }
+        }
+
+        let text_slots = assign_slots(&segments, SegmentType::Text, TEXT_SEG_COUNT)?;
+        let data_slots = assign_slots(&segments, SegmentType::Data, DATA_SEG_COUNT)?;
+        // In slot order, text then data
+        let slots = text_slots.into_iter().chain(data_slots).collect::<Vec<_>>();
+
+        let mut offsets = [0; TEXT_SEG_COUNT + DATA_SEG_COUNT];
+        let mut offset = DOL_HEADER_LEN as u64;
+        for (i, slot) in slots.iter().enumerate() {
+            if let Some(s) = slot.map(|s| &segments[s]) {
+                offsets[i] = offset;
+                offset = align(offset + s.size, SEGMENT_ALIGNMENT);
+            }
+        }
+
+        for (i, slot) in slots.iter().enumerate() {
+            output.write_u32::<BigEndian>(slot.map_or(0, |_| offsets[i]) as u32)?;
+        }
+        for slot in &slots {
+            output.write_u32::<BigEndian>(slot.map_or(0, |s| segments[s].address) as u32)?;
+        }
+        for slot in &slots {
+            output.write_u32::<BigEndian>(slot.map_or(0, |s| segments[s].size) as u32)?;
+        }
+        let (bss_start, bss_end) = bss.unwrap_or((0, 0));
+        output.write_u32::<BigEndian>(bss_start as u32)?;
+        output.write_u32::<BigEndian>((bss_end - bss_start) as u32)?;
+        output.write_u32::<BigEndian>(entry_point)?;
+        // The rest of the header is unused
+        write_padding(&mut output, DOL_HEADER_LEN as u64 - 0xE4)?;
+
+        let mut position = DOL_HEADER_LEN as u64;
+        for (i, slot) in slots.iter().enumerate() {
+            if let Some(s) = slot.map(|s| &segments[s]) {
+                write_padding(&mut output, offsets[i] - position)?;
+                elf.seek(SeekFrom::Start(s.elf_offset))?;
+                let copied = io::copy(&mut (&mut elf).take(s.size), &mut output)?;
+                if copied != s.size {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "a segment in the ELF is cut off"));
+                }
+                position = offsets[i] + s.size;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns the index in `segments` of the segment that goes in each slot
+fn assign_slots(
+    segments: &[LoadSegment],
+    seg_type: SegmentType,
+    slot_count: usize,
+) -> io::Result<Vec<Option<usize>>> {
+    let mut slots = vec![None; slot_count];
+    let of_type = segments.iter().enumerate().filter(|&(_, s)| s.seg_type == seg_type);
+
+    let mut unnamed = Vec::new();
+    for (i, s) in of_type {
+        match s.seg_num.map(|n| n as usize) {
+            Some(n) if n < slot_count && slots[n].is_none() => slots[n] = Some(i),
+            _ => unnamed.push(i),
+        }
+    }
+    let too_many = || invalid_data(&format!(
+        "the ELF has more {} segments than the DOL has room for ({})",
+        if seg_type == SegmentType::Text { "text" } else { "data" },
+        slot_count,
+    ));
+    for i in unnamed {
+        let free = slots.iter().position(Option::is_none).ok_or_else(too_many)?;
+        slots[free] = Some(i);
+    }
+    Ok(slots)
+}
+
+fn read_name(mut elf: impl Read + Seek, offset: u64) -> io::Result<String> {
+    elf.seek(SeekFrom::Start(offset))?;
+    let mut name = Vec::new();
+    loop {
+        match elf.read_u8()? {
+            0 => break,
+            b => name.push(b),
+        }
+    }
+    Ok(String::from_utf8_lossy(&name).into_owned())
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_section_header(mut output: impl Write, name: u32, section: &ElfSection) -> io::Result<()> {
+    let alignment = if section.section_type == SHT_STRTAB { 1 } else { SEGMENT_ALIGNMENT };
+    output.write_u32::<BigEndian>(name)?;
+    output.write_u32::<BigEndian>(section.section_type)?;
+    output.write_u32::<BigEndian>(section.flags)?;
+    output.write_u32::<BigEndian>(section.address as u32)?;
+    output.write_u32::<BigEndian>(section.elf_offset as u32)?;
+    output.write_u32::<BigEndian>(section.size as u32)?;
+    // link, info
+    output.write_u32::<BigEndian>(0)?;
+    output.write_u32::<BigEndian>(0)?;
+    output.write_u32::<BigEndian>(alignment as u32)?;
+    // entry size
+    output.write_u32::<BigEndian>(0)
+}
+
+fn write_padding(mut output: impl Write, count: u64) -> io::Result<()> {
+    io::copy(&mut io::repeat(0).take(count), &mut output).map(|_| ())
+}
@@ -1,12 +1,18 @@
+mod elf;
+pub mod patch;
 pub mod segment;
+pub mod validate;
 
 use std::cmp::max;
+use std::fs::{create_dir_all, File};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::iter::Iterator;
+use std::path::{Path, PathBuf};
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use ::{
+    align,
     extract_section,
     format_u64,
     format_usize,
@@ -15,23 +21,46 @@ use ::{
 
 use self::segment::{Segment, SegmentType};
 
-use sections::Section;
+#[cfg(feature = "sha1")]
+use hash::sha1_range;
 
-const TEXT_SEG_COUNT: usize = 7;
-const DATA_SEG_COUNT: usize = 11;
-const TOTAL_SEG_COUNT: usize = TEXT_SEG_COUNT + DATA_SEG_COUNT;
+use sections::{ResolvedSection, Section};
+
+pub const TEXT_SEG_COUNT: usize = 7;
+pub const DATA_SEG_COUNT: usize = 11;
+pub const TOTAL_SEG_COUNT: usize = TEXT_SEG_COUNT + DATA_SEG_COUNT;
 
 pub const DOL_OFFSET_OFFSET: u64 = 0x0420;
 pub const DOL_HEADER_LEN: usize = 0x100;
 
-#[derive(Debug)]
+// Segment data in the DOL (and ELFs made from it) is aligned to this
+pub const SEGMENT_ALIGNMENT: u64 = 32;
+
+// The whole DOL is loaded into the GameCube's 24 MiB of RAM, so a segment that
+// ends past that means the header is corrupt
+const MAX_DOL_SIZE: usize = 24 * 1024 * 1024;
+
+// Where the data at an address comes from, from `address_to_offset`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressOffset {
+    // An offset on the ROM, or in the DOL if it was read on its own
+    File(u64),
+    // The BSS is cleared when the game boots instead of being loaded from
+    // anywhere, so it has no offset
+    Bss,
+}
+
+#[derive(Debug, Serialize)]
 pub struct DOLHeader {
     pub offset: u64,
     pub dol_size: usize,
     pub entry_point: u64,
+    pub bss_address: u64,
+    pub bss_size: usize,
     segments: Vec<Segment>,
     // This is the index in `segments` where the data segments are. The segments
     // before this index are all text segments.
+    #[serde(skip)]
     data_segments_index: usize,
 }
 
@@ -47,9 +76,11 @@ impl DOLHeader {
         let mut is_text = true;
         for i in 0..TOTAL_SEG_COUNT {
             let mut num = i as u64;
-            if i >= TEXT_SEG_COUNT {
+            if i == TEXT_SEG_COUNT {
                 is_text = false;
                 data_segments_index = segments.len();
+            }
+            if i >= TEXT_SEG_COUNT {
                 num -= TEXT_SEG_COUNT as u64;
             }
             let size = file.read_u32::<BigEndian>()? as usize;
@@ -88,21 +119,55 @@ impl DOLHeader {
             s.loading_address = file.read_u32::<BigEndian>()? as u64;
         }
 
+        file.seek(SeekFrom::Start(offset + 0xD8))?;
+        let bss_address = file.read_u32::<BigEndian>()? as u64;
+        let bss_size = file.read_u32::<BigEndian>()? as usize;
+
         file.seek(SeekFrom::Start(offset + 0xE0))?;
         let entry_point = file.read_u32::<BigEndian>()? as u64;
 
+        for s in &segments {
+            let end = (s.offset - offset) as usize + s.size;
+            if end > MAX_DOL_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("The DOL's {} segment ends at {:#x}, which is too big for it to be loaded.", s.to_string(), end),
+                ));
+            }
+        }
         let dol_size = segments.iter()
-            .map(|s| (s.offset - offset) as usize + s.size).max().unwrap();
+            .map(|s| (s.offset - offset) as usize + s.size)
+            .max()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "The DOL doesn't have any segments."))?;
 
         Ok(DOLHeader {
             offset,
             dol_size,
             entry_point,
+            bss_address,
+            bss_size,
             segments,
             data_segments_index,
         })
     }
 
+    // A DOL holding nothing but `size` bytes of code at offset 0, for wrapping
+    // raw code in an ELF with `to_elf`
+    pub fn with_text_segment(size: usize, load_address: u64) -> DOLHeader {
+        let mut text = Segment::text();
+        text.size = size;
+        text.loading_address = load_address;
+        DOLHeader {
+            offset: 0,
+            dol_size: size,
+            entry_point: load_address,
+            bss_address: 0,
+            bss_size: 0,
+            segments: vec![text],
+            data_segments_index: 1,
+        }
+    }
+
     pub fn find_segment(
         &self,
         seg_type: SegmentType,
@@ -117,6 +182,13 @@ impl DOLHeader {
             .find(|s| s.seg_num == number && s.seg_type == seg_type)
     }
 
+    // The SHA-1 of the whole DOL, not just the header, so it's the same as the
+    // extracted Start.dol's. This is what decomp projects go by.
+    #[cfg(feature = "sha1")]
+    pub fn hash(&self, iso: impl Read + Seek) -> io::Result<[u8; 20]> {
+        sha1_range(iso, self.offset, self.dol_size as u64)
+    }
+
     pub fn iter_segments(&self) -> impl Iterator<Item = &Segment> {
         self.segments.iter()
     }
@@ -126,40 +198,215 @@ impl DOLHeader {
         R: Read + Seek,
         W: Write,
     {
+        let dol_size = DOLHeader::new(&mut iso, dol_addr)?.dol_size;
         iso.seek(SeekFrom::Start(dol_addr))?;
-        let mut dol_size = 0;
+        extract_section(iso, dol_size, file)
+    }
 
-        for i in 0..(TEXT_SEG_COUNT as u64) {
-            iso.seek(SeekFrom::Start(dol_addr + 0x00 + i * 4))?;
-            let seg_offset = iso.read_u32::<BigEndian>()?;
+    // Replaces a segment's data with `len` bytes from `data`, padded with zeros
+    // to a multiple of 32 bytes. If it's bigger than the old data, everything
+    // after the segment in the DOL is moved down to make room. The segment's
+    // load address stays the same.
+    pub fn replace_segment(
+        &mut self,
+        mut dol: impl Read + Write + Seek,
+        seg_type: SegmentType,
+        seg_num: u64,
+        data: impl Read,
+        len: u64,
+    ) -> io::Result<()> {
+        let index = self.segment_index(seg_type, seg_num)?;
+        let old = self.segments[index];
+        let old_end = old.offset + old.size as u64;
+        let new_size = align(len, SEGMENT_ALIGNMENT);
 
-            iso.seek(SeekFrom::Start(dol_addr + 0x90 + i * 4))?;
-            let seg_size = iso.read_u32::<BigEndian>()?;
+        if new_size > old.size as u64 {
+            let shift = new_size - old.size as u64;
+            let dol_end = self.offset + self.dol_size as u64;
+            let mut rest = Vec::new();
+            if dol_end > old_end {
+                dol.seek(SeekFrom::Start(old_end))?;
+                (&mut dol).take(dol_end - old_end).read_to_end(&mut rest)?;
+            }
+            dol.seek(SeekFrom::Start(old_end + shift))?;
+            dol.write_all(&rest)?;
+            for s in &mut self.segments {
+                if s.offset > old.offset {
+                    s.offset += shift;
+                }
+            }
+        }
 
-            dol_size = max(seg_offset + seg_size, dol_size);
+        dol.seek(SeekFrom::Start(old.offset))?;
+        let written = io::copy(&mut data.take(len), &mut dol)?;
+        if written != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("expected {} bytes for the new segment, but only got {}", len, written),
+            ));
         }
+        // Clear out whatever's left of the old data too
+        let padding = max(new_size, old.size as u64) - len;
+        io::copy(&mut io::repeat(0).take(padding), &mut dol)?;
 
-        for i in 0..(DATA_SEG_COUNT as u64) {
-            iso.seek(SeekFrom::Start(dol_addr + 0x1c + i * 4))?;
-            let seg_offset = iso.read_u32::<BigEndian>()?;
+        self.segments[index].size = new_size as usize;
+        self.dol_size = self.segments.iter()
+            .map(|s| (s.offset - self.offset) as usize + s.size).max().unwrap();
+        self.write_segment_table(dol)
+    }
+
+    // How big the DOL would be after `replace_segment`
+    pub fn size_with_segment(&self, seg_type: SegmentType, seg_num: u64, len: u64) -> Option<usize> {
+        let index = self.segment_index(seg_type, seg_num).ok()?;
+        let old = &self.segments[index];
+        let new_size = align(len, SEGMENT_ALIGNMENT) as usize;
+        let shift = new_size.saturating_sub(old.size) as u64;
+        self.segments.iter().enumerate().map(|(i, s)| {
+            let offset = if s.offset > old.offset { s.offset + shift } else { s.offset };
+            let size = if i == index { new_size } else { s.size };
+            (offset - self.offset) as usize + size
+        }).max()
+    }
 
-            iso.seek(SeekFrom::Start(dol_addr + 0xac + i * 4))?;
-            let seg_size = iso.read_u32::<BigEndian>()?;
+    fn segment_index(&self, seg_type: SegmentType, seg_num: u64) -> io::Result<usize> {
+        self.segments.iter()
+            .position(|s| s.seg_type == seg_type && s.seg_num == seg_num)
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("the DOL doesn't have a {} segment", seg_type.to_string(seg_num)),
+            ))
+    }
 
-            dol_size = max(seg_offset + seg_size, dol_size);
+    // Writes the offsets and sizes of the segments to the DOL's header
+    fn write_segment_table(&self, mut dol: impl Write + Seek) -> io::Result<()> {
+        for s in &self.segments {
+            let slot = match s.seg_type {
+                SegmentType::Text => s.seg_num,
+                SegmentType::Data => TEXT_SEG_COUNT as u64 + s.seg_num,
+            };
+            dol.seek(SeekFrom::Start(self.offset + slot * 4))?;
+            dol.write_u32::<BigEndian>((s.offset - self.offset) as u32)?;
+            dol.seek(SeekFrom::Start(self.offset + 0x90 + slot * 4))?;
+            dol.write_u32::<BigEndian>(s.size as u32)?;
         }
+        Ok(())
+    }
 
-        iso.seek(SeekFrom::Start(dol_addr))?;
+    // Prints where each segment and the BSS are loaded, in order. Segments
+    // that load on top of each other are flagged, since that usually means
+    // the DOL is corrupt or packed. The BSS isn't checked, since small data
+    // segments like .sdata are often loaded inside of it.
+    pub fn print_memory_map(&self, style: NumberStyle) {
+        let mut segments = self.segments.iter().collect::<Vec<_>>();
+        segments.sort_by_key(|s| s.loading_address);
+
+        println!(
+            "{:<8} {:>12} {:>12} {:>12} {:>12}",
+            "Name", "Offset", "Address", "Size", "End",
+        );
+        let mut previous: Option<&Segment> = None;
+        for s in segments {
+            let end = s.loading_address + s.size as u64;
+            let overlap = previous
+                .filter(|p| p.loading_address + p.size as u64 > s.loading_address)
+                .map(|p| format!("  overlaps {}!", p.to_string()))
+                .unwrap_or_default();
+            println!(
+                "{:<8} {:>12} {:>12} {:>12} {:>12}{}",
+                s.to_string(),
+                format_u64(s.offset, style),
+                format_u64(s.loading_address, style),
+                format_usize(s.size, style),
+                format_u64(end, style),
+                overlap,
+            );
+            if previous.is_none_or(|p| end > p.loading_address + p.size as u64) {
+                previous = Some(s);
+            }
+        }
+        println!(
+            "{:<8} {:>12} {:>12} {:>12} {:>12}",
+            ".bss",
+            "-",
+            format_u64(self.bss_address, style),
+            format_usize(self.bss_size, style),
+            format_u64(self.bss_address + self.bss_size as u64, style),
+        );
+    }
 
-        extract_section(iso, dol_size as usize, file)
+    // Prints each segment's name, where it is in the DOL and on the ROM, where
+    // it's loaded, and how big it is, in the order they're in the header. With
+    // `include_empty`, the slots in the header without a segment are listed
+    // too.
+    pub fn print_segments(&self, style: NumberStyle, include_empty: bool) {
+        println!(
+            "{:<8} {:>12} {:>12} {:>12} {:>12}",
+            "Name", "DOL offset", "ROM offset", "Address", "Size",
+        );
+        let slots = (0..TEXT_SEG_COUNT as u64).map(|n| (SegmentType::Text, n))
+            .chain((0..DATA_SEG_COUNT as u64).map(|n| (SegmentType::Data, n)));
+        for (seg_type, num) in slots {
+            match self.find_segment(seg_type, num) {
+                Some(s) => println!(
+                    "{:<8} {:>12} {:>12} {:>12} {:>12}",
+                    s.to_string(),
+                    format_u64(s.offset - self.offset, style),
+                    format_u64(s.offset, style),
+                    format_u64(s.loading_address, style),
+                    format_usize(s.size, style),
+                ),
+                None if include_empty => println!(
+                    "{:<8} {:>12} {:>12} {:>12} {:>12}",
+                    seg_type.to_string(num), "-", "-", "-", "empty",
+                ),
+                None => {},
+            }
+        }
+    }
+
+    // Writes each segment to `out_dir`, named like `text0.bin`, and returns
+    // where they were written
+    pub fn extract_all_segments(&self, mut iso: impl Read + Seek, out_dir: &Path) -> io::Result<Vec<PathBuf>> {
+        create_dir_all(out_dir)?;
+        let mut paths = Vec::with_capacity(self.segments.len());
+        for s in &self.segments {
+            let path = out_dir.join(format!("{}.bin", s.to_string().trim_start_matches('.')));
+            iso.seek(SeekFrom::Start(s.offset))?;
+            extract_section(&mut iso, s.size, File::create(&path)?)?;
+            paths.push(path);
+        }
+        Ok(paths)
     }
 
-    pub fn segment_at_addr(&self, mem_addr: u64) -> Option<&Segment> {
+    pub fn segment_at_address(&self, mem_addr: u64) -> Option<&Segment> {
         self.segments.iter().find(|s|
             s.loading_address <= mem_addr &&
             mem_addr < s.loading_address + s.size as u64
         )
     }
+
+    // Where the data loaded at `mem_addr` is. Segments are checked before the
+    // BSS, since small data segments are loaded inside of it. Subtract
+    // `self.offset` to get the offset in the DOL.
+    pub fn address_to_offset(&self, mem_addr: u64) -> Option<AddressOffset> {
+        if let Some(s) = self.segment_at_address(mem_addr) {
+            Some(AddressOffset::File(s.offset + mem_addr - s.loading_address))
+        } else if self.bss_address <= mem_addr &&
+            mem_addr < self.bss_address + self.bss_size as u64
+        {
+            Some(AddressOffset::Bss)
+        } else {
+            None
+        }
+    }
+
+    // Where the data at `offset` is loaded in memory. `offset` is on the ROM,
+    // like the segments' offsets.
+    pub fn offset_to_address(&self, offset: u64) -> Option<u64> {
+        self.segments.iter()
+            .find(|s| s.offset <= offset && offset < s.offset + s.size as u64)
+            .map(|s| s.loading_address + offset - s.offset)
+    }
 }
 
 impl Section for DOLHeader {
@@ -168,6 +415,8 @@ impl Section for DOLHeader {
         println!("Size: {} bytes", format_usize(self.dol_size, style));
         println!("Header Size: {} bytes", format_usize(DOL_HEADER_LEN, style));
         println!("Entry point: {}", format_u64(self.entry_point, style));
+        println!("BSS address: {}", format_u64(self.bss_address, style));
+        println!("BSS size: {} bytes", format_usize(self.bss_size, style));
         println!("Segments:");
         for s in &self.segments {
             println!();
@@ -182,4 +431,16 @@ impl Section for DOLHeader {
     fn size(&self) -> usize {
         DOL_HEADER_LEN
     }
+
+    fn name(&self) -> String {
+        "Start.dol".to_string()
+    }
+
+    fn section_type(&self) -> &'static str {
+        "dol"
+    }
+
+    fn resolve(&self) -> ResolvedSection<'_> {
+        ResolvedSection::Dol(self)
+    }
 }
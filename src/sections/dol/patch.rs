@@ -0,0 +1,208 @@
+// Applies Gecko codes to a DOL, so they're part of the game instead of
+// needing a cheat device. Only the codetypes that write to a fixed address
+// can be applied this way: 8, 16, and 32-bit writes and fills (00/02/04),
+// string writes (06), and ASM inserts (C2). Decrypted Action Replay write
+// codes are the same as Gecko's 00/02/04, so they work too.
+// http://wiibrew.org/wiki/Gecko_Codetypes
+
+use std::io;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use super::{DOLHeader, TEXT_SEG_COUNT};
+
+// C2 codes have to go somewhere, so they go here, in a new text segment.
+// This is the same area the Gecko code handler uses, which the game itself
+// leaves alone.
+pub const CODE_REGION_ADDRESS: u32 = 0x8000_1800;
+pub const CODE_REGION_SIZE: usize = 0x1800;
+
+#[derive(Debug, Default)]
+pub struct PatchReport {
+    // How many writes and inserts were applied
+    pub applied: usize,
+    // Addresses that aren't in any of the DOL's segments. Nothing was written
+    // for these.
+    pub outside_segments: Vec<u32>,
+    // The names of codes that were skipped entirely, and why
+    pub unsupported: Vec<String>,
+}
+
+// A code from a code list, like the ones in Dolphin's game INIs, which look
+// like this:
+//
+// $Infinite Health
+// 0434A4C8 00000064
+struct Code {
+    name: String,
+    lines: Vec<(u32, u32)>,
+}
+
+enum Instruction {
+    Write { address: u32, bytes: Vec<u8> },
+    // Runs `code` instead of the instruction at `address`
+    Insert { address: u32, code: Vec<u8> },
+}
+
+// Applies every code in `codes` to `dol_data`, which is the DOL `dol` was
+// read from. If there are any C2 codes, a new text segment is added for
+// them, so `dol` won't match the DOL anymore.
+pub fn apply_gecko(dol: &DOLHeader, dol_data: &mut Vec<u8>, codes: &str) -> io::Result<PatchReport> {
+    let mut report = PatchReport::default();
+    let mut inserted = Vec::new();
+
+    for code in parse_codes(codes) {
+        let instructions = match parse_instructions(&code.lines) {
+            Ok(i) => i,
+            Err(reason) => {
+                report.unsupported.push(format!("{} ({})", code.name, reason));
+                continue;
+            },
+        };
+        for instruction in instructions {
+            match instruction {
+                Instruction::Write { address, bytes } => {
+                    match file_offset(dol, address, bytes.len()) {
+                        Some(offset) => {
+                            dol_data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+                            report.applied += 1;
+                        },
+                        None => report.outside_segments.push(address),
+                    }
+                },
+                Instruction::Insert { address, mut code } => {
+                    let offset = match file_offset(dol, address, 4) {
+                        Some(o) => o,
+                        None => {
+                            report.outside_segments.push(address);
+                            continue;
+                        },
+                    };
+                    let start = CODE_REGION_ADDRESS + inserted.len() as u32;
+                    // The last instruction is a placeholder for the branch back
+                    let last = code.len() - 4;
+                    let back = branch(start + last as u32, address + 4);
+                    BigEndian::write_u32(&mut code[last..], back);
+                    BigEndian::write_u32(&mut dol_data[offset..], branch(address, start));
+                    inserted.extend(code);
+                    report.applied += 1;
+                },
+            }
+        }
+    }
+
+    if !inserted.is_empty() {
+        if inserted.len() > CODE_REGION_SIZE {
+            return Err(io::Error::other(format!(
+                "The C2 codes take up {:#x} bytes, but there's only room for {:#x}.",
+                inserted.len(), CODE_REGION_SIZE,
+            )));
+        }
+        add_text_segment(dol_data, CODE_REGION_ADDRESS, &inserted)?;
+    }
+    Ok(report)
+}
+
+fn parse_codes(text: &str) -> Vec<Code> {
+    let mut codes = Vec::new();
+    let mut current = Code { name: "codes".to_string(), lines: Vec::new() };
+    for line in text.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('$') {
+            let next = Code { name: name.trim().to_string(), lines: Vec::new() };
+            codes.push(std::mem::replace(&mut current, next));
+            continue;
+        }
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        if words.len() != 2 || words.iter().any(|w| w.len() != 8) { continue }
+        if let (Ok(a), Ok(b)) = (u32::from_str_radix(words[0], 16), u32::from_str_radix(words[1], 16)) {
+            current.lines.push((a, b));
+        }
+    }
+    codes.push(current);
+    codes.retain(|c| !c.lines.is_empty());
+    codes
+}
+
+fn parse_instructions(lines: &[(u32, u32)]) -> Result<Vec<Instruction>, String> {
+    let mut instructions = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let (first, value) = lines[i];
+        i += 1;
+        // The low bit of the codetype is the top bit of the address
+        let address = 0x8000_0000 | (first & 0x01ff_ffff);
+        let instruction = match (first >> 24) & 0xfe {
+            0x00 => Instruction::Write {
+                address,
+                bytes: vec![value as u8; (value >> 16) as usize + 1],
+            },
+            0x02 => Instruction::Write {
+                address,
+                bytes: (0..=(value >> 16)).flat_map(|_| (value as u16).to_be_bytes()).collect(),
+            },
+            0x04 => Instruction::Write { address, bytes: value.to_be_bytes().to_vec() },
+            0x06 => {
+                let line_count = (value as usize).div_ceil(8);
+                let bytes = code_bytes(lines, i, line_count)?;
+                i += line_count;
+                Instruction::Write { address, bytes: bytes[..value as usize].to_vec() }
+            },
+            0xc2 => {
+                if value == 0 {
+                    return Err(format!("the C2 code at {:08X} is empty", address));
+                }
+                let code = code_bytes(lines, i, value as usize)?;
+                i += value as usize;
+                Instruction::Insert { address, code }
+            },
+            t => return Err(format!("codetype {:02X} isn't supported", t)),
+        };
+        instructions.push(instruction);
+    }
+    Ok(instructions)
+}
+
+// The bytes in `count` lines of a code starting at `start`
+fn code_bytes(lines: &[(u32, u32)], start: usize, count: usize) -> Result<Vec<u8>, String> {
+    let lines = lines.get(start..start + count).ok_or("it ends early")?;
+    let mut bytes = Vec::with_capacity(lines.len() * 8);
+    for &(a, b) in lines {
+        bytes.extend(a.to_be_bytes());
+        bytes.extend(b.to_be_bytes());
+    }
+    Ok(bytes)
+}
+
+// Where `len` bytes at `address` are in the DOL, if they're all in one segment
+fn file_offset(dol: &DOLHeader, address: u32, len: usize) -> Option<usize> {
+    let address = address as u64;
+    let segment = dol.segment_at_address(address)?;
+    if address + len as u64 > segment.loading_address + segment.size as u64 {
+        return None;
+    }
+    Some((segment.offset - dol.offset + address - segment.loading_address) as usize)
+}
+
+fn branch(from: u32, to: u32) -> u32 {
+    0x4800_0000 | (to.wrapping_sub(from) & 0x03ff_fffc)
+}
+
+// Adds `code` to the end of the DOL and points an unused text segment at it
+fn add_text_segment(dol_data: &mut Vec<u8>, address: u32, code: &[u8]) -> io::Result<()> {
+    let slot = (0..TEXT_SEG_COUNT)
+        .find(|i| BigEndian::read_u32(&dol_data[0x90 + i * 4..]) == 0)
+        .ok_or_else(|| io::Error::other(
+            "All of the DOL's text segments are used, so there's nowhere to put the C2 codes.",
+        ))?;
+
+    let offset = dol_data.len().next_multiple_of(32);
+    let size = code.len().next_multiple_of(32);
+    dol_data.resize(offset, 0);
+    dol_data.extend_from_slice(code);
+    dol_data.resize(offset + size, 0);
+
+    BigEndian::write_u32(&mut dol_data[slot * 4..], offset as u32);
+    BigEndian::write_u32(&mut dol_data[0x48 + slot * 4..], address);
+    BigEndian::write_u32(&mut dol_data[0x90 + slot * 4..], size as u32);
+    Ok(())
+}
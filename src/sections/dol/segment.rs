@@ -1,11 +1,10 @@
-use std::io::{Write, Seek, SeekFrom, Read, self};
-
 use regex::Regex;
 
-use ::{format_u64, format_usize, NumberStyle, parse_as_u64, extract_section};
-use sections::Section;
+use ::{format_u64, format_usize, NumberStyle, parse_as_u64};
+use sections::{ResolvedSection, Section};
 
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SegmentType {
     Text, Data
 }
@@ -20,7 +19,7 @@ impl SegmentType {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize)]
 pub struct Segment {
     // NOTE: `offset` is not the offset stored on the ROM.
     // The ROM provides the offset relative to the start of the DOL header,
@@ -59,34 +58,24 @@ impl Segment {
         self.seg_type.to_string(self.seg_num)
     }
 
+    // Takes `.text0`, `text0`, or just `t0`, in any case
     pub fn parse_segment_name(name: &str) -> Option<(SegmentType, u64)> {
         use self::SegmentType::*;
         lazy_static! {
             static ref SEG_NAME_REGEX: Regex =
-                Regex::new(r"^\.?(text|data)(\d+)$").unwrap();
+                Regex::new(r"^\.?(text|data|t|d)(\d+)$").unwrap();
         }
-        SEG_NAME_REGEX.captures(name).and_then(|c| {
+        SEG_NAME_REGEX.captures(&name.to_lowercase()).and_then(|c| {
             parse_as_u64(c.get(2).unwrap().as_str()).map(|n| {
                 let t = match c.get(1).unwrap().as_str() {
-                    "text" => Text,
-                    "data" => Data,
+                    "text" | "t" => Text,
+                    "data" | "d" => Data,
                     _ => unreachable!(),
                 };
                 (t, n)
             }).ok()
         })
     }
-
-    // TODO: put in a trait
-    pub fn extract<R, W>(&self, mut iso: R, output: W) -> io::Result<()>
-    where
-        Self: Sized,
-        R: Read + Seek,
-        W: Write,
-    {
-        iso.seek(SeekFrom::Start(self.offset))?;
-        extract_section(iso, self.size, output)
-    }
 }
 
 
@@ -105,4 +94,16 @@ impl Section for Segment {
     fn size(&self) -> usize {
         self.size
     }
+
+    fn name(&self) -> String {
+        self.seg_type.to_string(self.seg_num)
+    }
+
+    fn section_type(&self) -> &'static str {
+        "segment"
+    }
+
+    fn resolve(&self) -> ResolvedSection<'_> {
+        ResolvedSection::Segment(self)
+    }
 }
@@ -0,0 +1,135 @@
+// Checks for DOLs that parse, but won't boot, like ones from a linker script
+// that's a little off
+
+use std::fmt;
+
+use super::{DOLHeader, DOL_HEADER_LEN};
+use super::segment::{Segment, SegmentType};
+
+// Everything below this is used by the OS and the apploader, and MEM1 ends at
+// 24 MiB
+pub const MEM1_START: u64 = 0x8000_3100;
+pub const MEM1_END: u64 = 0x8180_0000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DolIssue {
+    // The entry point isn't in any of the text segments
+    EntryPointOutsideText { entry_point: u64 },
+    // The segment (or the BSS) is loaded somewhere other than MEM1
+    OutOfMemory { name: String, start: u64, end: u64 },
+    // Two segments are loaded on top of each other
+    SegmentOverlap { name: String, other: String, start: u64, end: u64 },
+    // The segment is loaded on top of part of the BSS, which is cleared after
+    // it's loaded
+    BssOverlap { name: String, start: u64, end: u64, bss_start: u64, bss_end: u64 },
+    // The segment's data is in the DOL's header. `offset` is in the DOL.
+    InHeader { name: String, offset: u64 },
+    // The segments reach past the end of the file
+    Truncated { dol_size: usize, len: u64 },
+}
+
+impl fmt::Display for DolIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DolIssue::EntryPointOutsideText { entry_point } => write!(
+                f, "The DOL's entry point at {:#x} isn't in any of its text segments.",
+                entry_point,
+            ),
+            DolIssue::OutOfMemory { name, start, end } => write!(
+                f, "{} is loaded at {:#x}-{:#x}, outside of {:#x}-{:#x}.",
+                name, start, end, MEM1_START, MEM1_END,
+            ),
+            DolIssue::SegmentOverlap { name, other, start, end } => write!(
+                f, "{} and {} are both loaded at {:#x}-{:#x}.",
+                name, other, start, end,
+            ),
+            DolIssue::BssOverlap { name, start, end, bss_start, bss_end } => write!(
+                f, "{} is loaded at {:#x}-{:#x}, on top of the BSS at {:#x}-{:#x}.",
+                name, start, end, bss_start, bss_end,
+            ),
+            DolIssue::InHeader { name, offset } => write!(
+                f, "{} is at {:#x} in the DOL, inside its {:#x} byte header.",
+                name, offset, DOL_HEADER_LEN,
+            ),
+            DolIssue::Truncated { dol_size, len } => write!(
+                f, "The DOL's segments end at {:#x}, but it's only {:#x} bytes.",
+                dol_size, len,
+            ),
+        }
+    }
+}
+
+impl DOLHeader {
+    // Checks that everything's loaded into MEM1 without stepping on anything
+    // else, and that the game can start. Small data segments like .sdata are
+    // usually loaded inside of the BSS, so only segments that stick out of it
+    // (or text segments, which never go there) count as overlapping it.
+    pub fn validate(&self) -> Vec<DolIssue> {
+        let mut issues = Vec::new();
+
+        let in_text = self.iter_segments()
+            .filter(|s| s.seg_type == SegmentType::Text)
+            .any(|s| s.loading_address <= self.entry_point && self.entry_point < load_end(s));
+        if !in_text {
+            issues.push(DolIssue::EntryPointOutsideText { entry_point: self.entry_point });
+        }
+
+        let bss_end = self.bss_address + self.bss_size as u64;
+        let ranges = self.iter_segments()
+            .map(|s| (s.to_string(), s.loading_address, load_end(s)))
+            .chain(Some((".bss".to_string(), self.bss_address, bss_end)).filter(|_| self.bss_size > 0));
+        for (name, start, end) in ranges {
+            if start < MEM1_START || end > MEM1_END {
+                issues.push(DolIssue::OutOfMemory { name, start, end });
+            }
+        }
+
+        let segments = self.iter_segments().collect::<Vec<_>>();
+        for (i, s) in segments.iter().enumerate() {
+            let (start, end) = (s.loading_address, load_end(s));
+            for other in &segments[i + 1..] {
+                if other.loading_address < end && start < load_end(other) {
+                    issues.push(DolIssue::SegmentOverlap {
+                        name: s.to_string(),
+                        other: other.to_string(),
+                        start: start.max(other.loading_address),
+                        end: end.min(load_end(other)),
+                    });
+                }
+            }
+
+            let overlaps_bss = self.bss_size > 0 && start < bss_end && self.bss_address < end;
+            let inside_bss = self.bss_address <= start && end <= bss_end;
+            if overlaps_bss && (s.seg_type == SegmentType::Text || !inside_bss) {
+                issues.push(DolIssue::BssOverlap {
+                    name: s.to_string(),
+                    start,
+                    end,
+                    bss_start: self.bss_address,
+                    bss_end,
+                });
+            }
+
+            let offset = s.offset - self.offset;
+            if offset < DOL_HEADER_LEN as u64 {
+                issues.push(DolIssue::InHeader { name: s.to_string(), offset });
+            }
+        }
+
+        issues
+    }
+
+    // Like `validate`, but for a DOL that's a file on its own, which is `len`
+    // bytes long
+    pub fn validate_file(&self, len: u64) -> Vec<DolIssue> {
+        let mut issues = self.validate();
+        if self.dol_size as u64 > len {
+            issues.push(DolIssue::Truncated { dol_size: self.dol_size, len });
+        }
+        issues
+    }
+}
+
+fn load_end(s: &Segment) -> u64 {
+    s.loading_address + s.size as u64
+}
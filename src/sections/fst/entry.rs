@@ -1,14 +1,32 @@
 use std::fs::{create_dir_all, File};
-use std::io::{self, BufRead, Seek, SeekFrom, Write};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
 use std::path::{self, Path, PathBuf};
 
 use byteorder::{BigEndian, ReadBytesExt};
+use encoding_rs::SHIFT_JIS;
 
-use ::{extract_section, format_u64, format_usize, NumberStyle};
-use sections::Section;
+use ::{extract_buffered_section, format_u64, format_usize, ExistingCheck, ExtractCount, NumberStyle};
+use sections::{ResolvedSection, Section};
 
 pub const ENTRY_SIZE: usize = 12;
 
+// No real name is anywhere near this long, but it keeps a bad name offset from
+// reading the rest of the ROM
+const MAX_NAME_LEN: u64 = 0x400;
+
+// Decides which files get extracted, given their path on the ROM
+pub type FileFilter<'a> = &'a dyn Fn(&Path, &FileEntry) -> bool;
+
+// What to leave out while extracting
+#[derive(Clone, Copy, Default)]
+struct Skip<'a> {
+    // Files that were already extracted
+    existing: Option<ExistingCheck>,
+    // Files the filter doesn't accept. Directories are only made when
+    // something is written in them if there's a filter.
+    filter: Option<FileFilter<'a>>,
+}
+
 // writes in big endian
 fn write_int_to_buffer(num: u64, buf: &mut [u8]) {
     for i in 0..buf.len() {
@@ -16,21 +34,54 @@ fn write_int_to_buffer(num: u64, buf: &mut [u8]) {
     }
 }
 
-#[derive(Debug)]
+// Names in the string table are usually ASCII, but Japanese games use
+// Shift-JIS. Anything that isn't valid Shift-JIS is read as Latin-1, so
+// there's always some name to extract the entry to.
+pub fn decode_name(bytes: &[u8]) -> String {
+    if bytes.is_ascii() {
+        return String::from_utf8_lossy(bytes).into_owned();
+    }
+    SHIFT_JIS.decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|n| n.into_owned())
+        .unwrap_or_else(|| bytes.iter().map(|&b| b as char).collect())
+}
+
+// The bytes to store `name` as in the string table, for entries that weren't
+// read from one, like files added during a rebuild. Names Shift-JIS can't
+// store are kept as UTF-8.
+pub fn encode_name(name: &str) -> Vec<u8> {
+    if name.is_ascii() {
+        return name.as_bytes().to_vec();
+    }
+    match SHIFT_JIS.encode(name) {
+        (bytes, _, false) => bytes.into_owned(),
+        _ => name.as_bytes().to_vec(),
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct EntryInfo {
     pub index: usize,
     pub name: String,
+    // The name as it's stored in the string table, without the null byte.
+    // It's kept so names that were decoded can be written back exactly.
+    #[serde(skip)]
+    pub raw_name: Vec<u8>,
     pub filename_offset: u64,
 
     // The fields below are not actually stored on the ROM:
 
+    // What the entry's called when it's extracted, which is usually `name`,
+    // without the trailing separator for directories. See `names.rs`.
+    pub disk_name: String,
+
     // This is the index of the directory that the entry is in.
     // For directories, this'll be the same as the parent_index field.
     pub directory_index: Option<usize>,
     pub full_path: PathBuf,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct FileEntry {
     pub info: EntryInfo,
     pub file_offset: u64,
@@ -42,7 +93,7 @@ pub struct FileEntry {
  * For the root, this happens to be the amount of entries in the FST.
  * Also, `filename_offset` and `parent_index` are meaningless for the root
  */
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct DirectoryEntry {
     pub info: EntryInfo,
     pub parent_index: usize,
@@ -56,7 +107,8 @@ pub struct DirectoryEntry {
     pub file_count: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum Entry {
     File(FileEntry),
     Directory(DirectoryEntry),
@@ -80,7 +132,9 @@ impl Entry {
         let info = EntryInfo {
             index,
             name,
+            raw_name: Vec::new(),
             filename_offset,
+            disk_name: String::new(),
             directory_index,
             full_path,
         };
@@ -98,7 +152,10 @@ impl Entry {
                 // TODO: I don't like setting this to an incorrect, default value here...
                 file_count: 0,
             }),
-            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid byte in entry: {:#x}", entry[0]))),
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Entry {} in the FST has an invalid type: {:#x}", index, entry[0]),
+            )),
         })
     }
 
@@ -142,39 +199,85 @@ impl Entry {
         mut iso: impl BufRead + Seek,
         mut callback: impl FnMut(usize),
     ) -> io::Result<usize> {
-        self.extract_with_name_and_count(filename, fst, &mut iso, 0, &mut callback)
+        let mut count = ExtractCount::default();
+        self.extract_with_name_and_count(filename, fst, &mut iso, &mut count, Skip::default(), &mut callback)?;
+        Ok(count.written)
     }
 
+    // Like `extract_with_name`, but files that `check` says were already
+    // extracted aren't written again. They're still counted, as skipped.
+    pub fn extract_skipping_existing(
+        &self,
+        filename: impl AsRef<Path>,
+        fst: &[Entry],
+        mut iso: impl BufRead + Seek,
+        check: ExistingCheck,
+        mut callback: impl FnMut(usize),
+    ) -> io::Result<ExtractCount> {
+        let mut count = ExtractCount::default();
+        let skip = Skip { existing: Some(check), filter: None };
+        self.extract_with_name_and_count(filename, fst, &mut iso, &mut count, skip, &mut callback)?;
+        Ok(count)
+    }
+
+    // Only extracts the files `filter` accepts, and only the directories with
+    // one of them somewhere inside
+    pub fn extract_filtered(
+        &self,
+        filename: impl AsRef<Path>,
+        fst: &[Entry],
+        mut iso: impl BufRead + Seek,
+        filter: FileFilter,
+        skip_existing: Option<ExistingCheck>,
+        mut callback: impl FnMut(usize),
+    ) -> io::Result<ExtractCount> {
+        let mut count = ExtractCount::default();
+        let skip = Skip { existing: skip_existing, filter: Some(filter) };
+        self.extract_with_name_and_count(filename, fst, &mut iso, &mut count, skip, &mut callback)?;
+        Ok(count)
+    }
+
+    // `callback` is called with how many files have been written or
+    // skipped so far
     fn extract_with_name_and_count(
         &self,
         filename: impl AsRef<Path>,
         fst: &[Entry],
         iso: &mut (impl BufRead + Seek),
-        start_count: usize,
+        count: &mut ExtractCount,
+        skip: Skip,
         callback: &mut impl FnMut(usize),
-    ) -> io::Result<usize> {
-        let mut count = start_count;
+    ) -> io::Result<()> {
         match self {
             Entry::Directory(ref d) => {
-                create_dir_all(filename.as_ref())?;
+                if skip.filter.is_none() {
+                    create_dir_all(filename.as_ref())?;
+                }
                 for e in d.iter_contents(fst) {
-                    count += e.extract_with_name_and_count(
-                        filename.as_ref().join(&e.info().name),
+                    e.extract_with_name_and_count(
+                        filename.as_ref().join(&e.info().disk_name),
                         fst,
                         iso,
                         count,
+                        skip,
                         callback,
                     )?;
                 }
             },
             Entry::File(ref f) => {
-                let mut out = File::create(filename)?;
-                f.extract(iso, &mut out)?;
-                count += 1;
-                callback(count);
+                if let Some(filter) = skip.filter {
+                    if !filter(&f.info.full_path, f) {
+                        return Ok(());
+                    }
+                    if let Some(parent) = filename.as_ref().parent() {
+                        create_dir_all(parent)?;
+                    }
+                }
+                f.extract_counted(filename.as_ref(), &mut *iso, skip.existing, count)?;
+                callback(count.total());
             },
         }
-        Ok(count - start_count)
+        Ok(())
     }
 
     pub fn read_filename(
@@ -189,8 +292,12 @@ impl Entry {
         } else {
             reader.seek(SeekFrom::Start(str_tbl_addr + info.filename_offset))?;
             let mut bytes = Vec::new();
-            reader.read_until(0, &mut bytes)?;
-            info.name = String::from_utf8(bytes).unwrap_or_else(|_| String::new());
+            (&mut reader).take(MAX_NAME_LEN).read_until(0, &mut bytes)?;
+            if bytes.last() == Some(&0) {
+                bytes.pop();
+            }
+            info.name = decode_name(&bytes);
+            info.raw_name = bytes;
             if is_directory {
                 info.name.push(path::MAIN_SEPARATOR);
             }
@@ -256,7 +363,31 @@ impl FileEntry {
         W: Write,
     {
         reader.seek(SeekFrom::Start(self.file_offset))?;
-        extract_section(reader, self.size, file)
+        extract_buffered_section(reader, self.size, file)
+    }
+
+    // Writes the file to `output`, unless `check` says it's already there,
+    // and adds it to `count` either way
+    pub fn extract_counted(
+        &self,
+        output: &Path,
+        mut iso: impl BufRead + Seek,
+        check: Option<ExistingCheck>,
+        count: &mut ExtractCount,
+    ) -> io::Result<()> {
+        let extracted = match check {
+            Some(check) => check.is_extracted(output, &mut iso, self.file_offset, self.size as u64)?,
+            None => false,
+        };
+        if extracted {
+            debug!("Skipping {}, it's already there", self.info.full_path.display());
+            count.skipped += 1;
+        } else {
+            debug!("Extracting {}", self.info.full_path.display());
+            self.extract(iso, File::create(output)?)?;
+            count.written += 1;
+        }
+        Ok(())
     }
 }
 
@@ -287,10 +418,12 @@ impl<'a> Iterator for DirectoryIter<'a> {
 
     fn next(&mut self) -> Option<&'a Entry> {
         if self.current_index < self.dir.next_index {
-            let res = &self.fst[self.current_index];
+            let res = self.fst.get(self.current_index)?;
+            // A directory that doesn't end after itself would loop forever,
+            // so it's treated like it's empty
             let step = match res {
                 Entry::File(_) => 1,
-                Entry::Directory(ref d) => d.next_index - self.current_index,
+                Entry::Directory(ref d) => d.next_index.saturating_sub(self.current_index).max(1),
             };
             self.current_index += step;
             Some(res)
@@ -314,5 +447,17 @@ impl Section for FileEntry {
     fn size(&self) -> usize {
         self.size
     }
+
+    fn name(&self) -> String {
+        self.info.full_path.to_string_lossy().into_owned()
+    }
+
+    fn section_type(&self) -> &'static str {
+        "file"
+    }
+
+    fn resolve(&self) -> ResolvedSection<'_> {
+        ResolvedSection::File(self)
+    }
 }
 
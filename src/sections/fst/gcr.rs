@@ -0,0 +1,159 @@
+// File lists in the format GameCube Rebuilder exports, with a line for each
+// file giving its path, offset, and size, separated by tabs:
+//
+//     /audio/bgm.adp	0x0012a000	4194304
+//
+// Offsets and sizes can be decimal or hex. A list can be applied to the FST to
+// put the files where it says, so a ROM can be laid out the same way as one
+// from that tool.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use parse_as_u64;
+
+use super::FST;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GcrFile {
+    // Where it is in the list, starting at 1
+    pub line: usize,
+    // The full path in the FST, starting with "/"
+    pub path: PathBuf,
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GcrFileList {
+    pub files: Vec<GcrFile>,
+}
+
+// A difference between a file list and the FST it's applied to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GcrMismatch {
+    NotInFst { line: usize, path: PathBuf },
+    // The path's listed more than once
+    Duplicate { line: usize, path: PathBuf, first_line: usize },
+    SizeMismatch { line: usize, path: PathBuf, size: u64, fst_size: usize },
+    // The FST has a file that isn't in the list
+    NotListed { path: PathBuf },
+}
+
+impl fmt::Display for GcrMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GcrMismatch::NotInFst { line, path } => write!(
+                f, "line {}: {} isn't a file in the FST",
+                line, path.display(),
+            ),
+            GcrMismatch::Duplicate { line, path, first_line } => write!(
+                f, "line {}: {} was already listed on line {}",
+                line, path.display(), first_line,
+            ),
+            GcrMismatch::SizeMismatch { line, path, size, fst_size } => write!(
+                f, "line {}: {} is {} bytes in the list, but {} bytes in the FST",
+                line, path.display(), size, fst_size,
+            ),
+            GcrMismatch::NotListed { path } => write!(
+                f, "{} is in the FST, but not in the list",
+                path.display(),
+            ),
+        }
+    }
+}
+
+impl GcrFileList {
+    // Blank lines and lines starting with `#` are skipped
+    pub fn read(input: impl BufRead) -> io::Result<GcrFileList> {
+        let mut files = Vec::new();
+        for (i, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') { continue }
+
+            let invalid = |message: &str| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", i + 1, message),
+            );
+            let fields = line.split('\t').collect::<Vec<_>>();
+            if fields.len() != 3 {
+                return Err(invalid("expected a path, an offset, and a size, separated by tabs"));
+            }
+            let offset = parse_as_u64(fields[1].trim()).map_err(|_| invalid("the offset isn't a number"))?;
+            let size = parse_as_u64(fields[2].trim()).map_err(|_| invalid("the size isn't a number"))?;
+            files.push(GcrFile {
+                line: i + 1,
+                path: Path::new("/").join(fields[0].trim_start_matches('/')),
+                offset,
+                size,
+            });
+        }
+        Ok(GcrFileList { files })
+    }
+}
+
+impl FST {
+    // Writes a line for every file, in FST order
+    pub fn write_gcr_filelist(&self, mut out: impl Write) -> io::Result<()> {
+        for f in self.entries.iter().filter_map(|e| e.as_file()) {
+            writeln!(out, "{}\t{:#010x}\t{}", f.info.full_path.display(), f.file_offset, f.size)?;
+        }
+        Ok(())
+    }
+
+    // Moves every file to its offset in `list`. Each file has to be listed
+    // once, with the same size it has in the FST. If they don't match,
+    // nothing's moved, and every mismatch is returned.
+    pub fn apply_gcr_filelist(&mut self, list: &GcrFileList) -> Vec<GcrMismatch> {
+        let mut mismatches = Vec::new();
+        let mut listed = HashMap::new();
+        let files = self.entries.iter()
+            .filter_map(|e| e.as_file())
+            .map(|f| (f.info.full_path.as_path(), f))
+            .collect::<HashMap<_, _>>();
+        for g in &list.files {
+            let f = match files.get(g.path.as_path()) {
+                Some(f) => f,
+                None => {
+                    mismatches.push(GcrMismatch::NotInFst { line: g.line, path: g.path.clone() });
+                    continue;
+                },
+            };
+            if let Some(&first_line) = listed.get(&f.info.index) {
+                mismatches.push(GcrMismatch::Duplicate {
+                    line: g.line,
+                    path: g.path.clone(),
+                    first_line,
+                });
+                continue;
+            }
+            if g.size != f.size as u64 {
+                mismatches.push(GcrMismatch::SizeMismatch {
+                    line: g.line,
+                    path: g.path.clone(),
+                    size: g.size,
+                    fst_size: f.size,
+                });
+            }
+            listed.insert(f.info.index, g.line);
+        }
+        for f in self.entries.iter().filter_map(|e| e.as_file()) {
+            if !listed.contains_key(&f.info.index) {
+                mismatches.push(GcrMismatch::NotListed { path: f.info.full_path.clone() });
+            }
+        }
+
+        if mismatches.is_empty() {
+            let offsets = list.files.iter()
+                .map(|g| (g.path.as_path(), g.offset))
+                .collect::<HashMap<_, _>>();
+            for f in self.entries.iter_mut().filter_map(|e| e.as_file_mut()) {
+                f.file_offset = offsets[f.info.full_path.as_path()];
+            }
+        }
+        mismatches
+    }
+}
@@ -0,0 +1,315 @@
+// The FST as a tree of JSON, so it can be edited by hand or with a script and
+// turned back into a Game.toc. Each entry keeps where its name was in the
+// string table, so an FST that wasn't changed comes back exactly the same.
+
+use std::cmp::max;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{self, Path};
+
+use sections::apploader::APPLOADER_OFFSET;
+use Game;
+
+use super::entry::{decode_name, encode_name, DirectoryEntry, Entry, EntryInfo, FileEntry, ENTRY_SIZE};
+use super::{names, FstIssue, FST};
+
+// Name offsets are stored in 3 bytes
+const MAX_NAME_OFFSET: u64 = 0xFF_FFFF;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FstJson {
+    // Where everything else is, so the files can be checked against it
+    pub image_size: u64,
+    pub fst_offset: u64,
+    // The FST's size in the header, which can be more than the entries and
+    // names take up
+    pub fst_size: usize,
+    pub apploader: Range<u64>,
+    pub dol: Range<u64>,
+    // What's in the root directory
+    pub entries: Vec<JsonEntry>,
+}
+
+// `index` is only there to make the FST easier to follow, entries are
+// numbered in the order they're listed in when it's imported, so it can be
+// left out of new ones. So can `name_offset`, which puts the name at the end
+// of the string table.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum JsonEntry {
+    Directory {
+        #[serde(default)]
+        index: usize,
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name_offset: Option<u64>,
+        // The name's bytes in hex, for names that can't be stored exactly
+        // from `name`, like ones that aren't valid Shift-JIS
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        raw_name: Option<String>,
+        children: Vec<JsonEntry>,
+    },
+    File {
+        #[serde(default)]
+        index: usize,
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name_offset: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        raw_name: Option<String>,
+        offset: u64,
+        length: usize,
+    },
+}
+
+impl JsonEntry {
+    fn new(e: &Entry, children: Vec<JsonEntry>) -> JsonEntry {
+        let info = e.info();
+        let index = info.index;
+        let name = info.name.trim_end_matches(path::MAIN_SEPARATOR).to_string();
+        let name_offset = Some(info.filename_offset);
+        let raw_name = if encode_name(&name) == info.raw_name {
+            None
+        } else {
+            Some(info.raw_name.iter().map(|b| format!("{:02x}", b)).collect())
+        };
+        match e {
+            Entry::File(f) => JsonEntry::File {
+                index, name, name_offset, raw_name,
+                offset: f.file_offset,
+                length: f.size,
+            },
+            Entry::Directory(_) => JsonEntry::Directory { index, name, name_offset, raw_name, children },
+        }
+    }
+
+    fn name(&self) -> (&str, Option<u64>, Option<&str>) {
+        match self {
+            JsonEntry::Directory { name, name_offset, raw_name, .. } |
+            JsonEntry::File { name, name_offset, raw_name, .. } =>
+                (name, *name_offset, raw_name.as_ref().map(|r| r.as_str())),
+        }
+    }
+}
+
+impl Game {
+    pub fn export_fst(&self) -> FstJson {
+        // (where the directory ends, the directory, what's in it so far).
+        // This keeps its own stack for the same reason as `write_subtree`.
+        let mut dirs: Vec<(usize, &Entry, Vec<JsonEntry>)> = vec![(self.fst.entries.len(), &self.fst.entries[0], Vec::new())];
+        for e in &self.fst.entries[1..] {
+            let index = e.info().index;
+            while dirs.len() > 1 && dirs.last().unwrap().0 <= index {
+                let (_, dir, children) = dirs.pop().unwrap();
+                dirs.last_mut().unwrap().2.push(JsonEntry::new(dir, children));
+            }
+            match e {
+                Entry::Directory(d) => dirs.push((d.next_index, e, Vec::new())),
+                Entry::File(_) => dirs.last_mut().unwrap().2.push(JsonEntry::new(e, Vec::new())),
+            }
+        }
+        while dirs.len() > 1 {
+            let (_, dir, children) = dirs.pop().unwrap();
+            dirs.last_mut().unwrap().2.push(JsonEntry::new(dir, children));
+        }
+
+        FstJson {
+            image_size: self.image_size,
+            fst_offset: self.fst.offset,
+            fst_size: self.header.fst_size,
+            apploader: APPLOADER_OFFSET..APPLOADER_OFFSET + self.apploader.total_size() as u64,
+            dol: self.dol.offset..self.dol.offset + self.dol.dol_size as u64,
+            entries: dirs.pop().unwrap().2,
+        }
+    }
+}
+
+// Entries as they're being numbered, before their names have a place in the
+// string table
+struct Flattened {
+    entries: Vec<Entry>,
+    // Where each entry's name would like to be, if it says
+    name_offsets: Vec<Option<u64>>,
+}
+
+impl FstJson {
+    // The FST this describes. Anything that can't be stored in one is an
+    // error, but for things that can, like files past the end of the ROM,
+    // use `validate`.
+    pub fn to_fst(&self) -> io::Result<FST> {
+        let mut flat = Flattened {
+            entries: vec![Entry::Directory(DirectoryEntry {
+                info: EntryInfo {
+                    index: 0,
+                    name: path::MAIN_SEPARATOR.to_string(),
+                    raw_name: Vec::new(),
+                    filename_offset: 0,
+                    disk_name: String::new(),
+                    directory_index: None,
+                    full_path: path::MAIN_SEPARATOR.to_string().into(),
+                },
+                parent_index: 0,
+                next_index: 0,
+                file_count: 0,
+            })],
+            name_offsets: vec![None],
+        };
+        let (next_index, file_count) = flatten(&self.entries, 0, &mut flat)?;
+        if let Entry::Directory(root) = &mut flat.entries[0] {
+            root.next_index = next_index;
+            root.file_count = file_count;
+        }
+
+        let names_size = place_names(&mut flat.entries, &flat.name_offsets)?;
+        names::assign_disk_names(&mut flat.entries);
+
+        let files = flat.entries.iter().filter_map(|e| e.as_file());
+        Ok(FST {
+            offset: self.fst_offset,
+            file_count: files.clone().count(),
+            total_file_system_size: files.map(|f| f.size).sum(),
+            size: flat.entries.len() * ENTRY_SIZE + names_size as usize,
+            entries: flat.entries,
+        })
+    }
+
+    // Everything `check` would find wrong with a ROM with `fst` on it
+    pub fn validate(&self, fst: &FST) -> Vec<FstIssue> {
+        let mut issues = fst.validate(self.image_size);
+        issues.extend(fst.overlapping_files("apploader", self.apploader.start, self.apploader.end));
+        issues.extend(fst.overlapping_files("DOL", self.dol.start, self.dol.end));
+        issues
+    }
+
+    // Writes `fst` like it'd be extracted, padded to `fst_size` if it's
+    // smaller
+    pub fn write_fst(&self, fst: &FST, mut output: impl Write) -> io::Result<()> {
+        let mut data = Vec::with_capacity(max(fst.size, self.fst_size));
+        fst.write(&mut data)?;
+        if data.len() < self.fst_size {
+            data.resize(self.fst_size, 0);
+        }
+        output.write_all(&data)
+    }
+}
+
+// Adds `entries` and everything in them, returning the index after the last
+// one and how many files are directly in `parent`
+fn flatten(entries: &[JsonEntry], parent: usize, flat: &mut Flattened) -> io::Result<(usize, usize)> {
+    let parent_path = flat.entries[parent].info().full_path.clone();
+    let mut file_count = 0;
+    for e in entries {
+        let index = flat.entries.len();
+        let (name, name_offset, raw_name) = e.name();
+        if name.is_empty() || name.contains('/') {
+            return Err(invalid(format!("{:?} in {} isn't a valid name", name, parent_path.display())));
+        }
+        let raw_name = match raw_name {
+            Some(hex) => {
+                let bytes = parse_hex(hex)
+                    .ok_or_else(|| invalid(format!("The raw name of {} isn't hex", parent_path.join(name).display())))?;
+                // If the name was changed, the raw name is out of date
+                if decode_name(&bytes) == name { bytes } else { encode_name(name) }
+            },
+            None => encode_name(name),
+        };
+        if raw_name.contains(&0) {
+            return Err(invalid(format!("{} has a null byte in its name", parent_path.join(name).display())));
+        }
+        let info = EntryInfo {
+            index,
+            name: name.to_string(),
+            raw_name,
+            filename_offset: 0,
+            disk_name: String::new(),
+            directory_index: Some(parent),
+            full_path: parent_path.join(name),
+        };
+
+        flat.name_offsets.push(name_offset);
+        match e {
+            JsonEntry::File { offset, length, .. } => {
+                check_u32(*offset, "offset", &info.full_path)?;
+                check_u32(*length as u64, "length", &info.full_path)?;
+                flat.entries.push(Entry::File(FileEntry { info, file_offset: *offset, size: *length }));
+                file_count += 1;
+            },
+            JsonEntry::Directory { children, .. } => {
+                let mut info = info;
+                info.name.push(path::MAIN_SEPARATOR);
+                flat.entries.push(Entry::Directory(DirectoryEntry {
+                    info,
+                    parent_index: parent,
+                    next_index: 0,
+                    file_count: 0,
+                }));
+                let (next_index, count) = flatten(children, index, flat)?;
+                if let Entry::Directory(d) = &mut flat.entries[index] {
+                    d.next_index = next_index;
+                    d.file_count = count;
+                }
+                // Directories count as files in their parent, like when
+                // they're read
+                file_count += 1;
+            },
+        }
+    }
+    Ok((flat.entries.len(), file_count))
+}
+
+// Puts each name back where it was, if it still fits there without running
+// into the next one, and the rest after the last name, so a name that got
+// longer doesn't push the others out of place. Entries with the same name can
+// share one. Returns how big the string table is.
+fn place_names(entries: &mut [Entry], wanted: &[Option<u64>]) -> io::Result<u64> {
+    let mut order = (1..entries.len()).filter(|&i| wanted[i].is_some()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| (wanted[i], i));
+
+    let mut end = 0;
+    // (where the last name that was put back is, which entry it's from)
+    let mut last: Option<(u64, usize)> = None;
+    let mut moved = (1..entries.len()).filter(|&i| wanted[i].is_none()).collect::<Vec<_>>();
+    for (n, &i) in order.iter().enumerate() {
+        let offset = wanted[i].unwrap();
+        let name_end = offset + entries[i].info().raw_name.len() as u64 + 1;
+        let rest = &order[n + 1..];
+        let next = rest.get(rest.partition_point(|&j| wanted[j] == Some(offset)))
+            .map_or(u64::MAX, |&j| wanted[j].unwrap());
+        let shares_last = last.is_some_and(|(o, l)| o == offset && entries[l].info().raw_name == entries[i].info().raw_name);
+        if shares_last || (offset >= end && name_end <= next) {
+            entries[i].info_mut().filename_offset = offset;
+            end = max(end, name_end);
+            last = Some((offset, i));
+        } else {
+            moved.push(i);
+        }
+    }
+
+    moved.sort_unstable();
+    for i in moved {
+        entries[i].info_mut().filename_offset = end;
+        end += entries[i].info().raw_name.len() as u64 + 1;
+    }
+    if end > MAX_NAME_OFFSET + 1 {
+        return Err(invalid("The names don't fit in the string table".to_string()));
+    }
+    Ok(end)
+}
+
+fn check_u32(n: u64, what: &str, path: &Path) -> io::Result<()> {
+    if n > u32::MAX as u64 {
+        return Err(invalid(format!("The {} of {} is too big to be stored in the FST", what, path.display())));
+    }
+    Ok(())
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) { return None }
+    (0..hex.len()).step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
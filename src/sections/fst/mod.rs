@@ -1,27 +1,106 @@
 pub mod entry;
+pub mod gcr;
+pub mod json;
+pub mod names;
+mod validate;
 
-use std::cmp::max;
-use std::collections::BTreeMap;
+pub use self::validate::FstIssue;
+
+use std::cmp::{max, Reverse};
+use std::collections::HashMap;
+use std::fs::create_dir_all;
 use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
-use std::path::{Path, PathBuf};
+use std::path::{self, Path, PathBuf};
 
 use byteorder::{BigEndian, ReadBytesExt};
 
-use sections::Section;
+use filetype::FileKind;
+use sections::{ResolvedSection, Section};
 use ::{
     extract_section,
+    ExistingCheck,
+    ExtractCount,
+    ExtractMode,
+    ExtractOrder,
     format_u64,
     format_usize,
     NumberStyle,
 };
 
-use self::entry::{DirectoryEntry, Entry, EntryInfo, ENTRY_SIZE};
+use self::entry::{DirectoryEntry, Entry, EntryInfo, FileEntry, FileFilter, ENTRY_SIZE};
 
 
 pub const FST_OFFSET_OFFSET: u64 = 0x0424; 
 pub const FST_SIZE_OFFSET: u64 = 0x0428;
 
-#[derive(Debug)]
+// The FST is loaded into the GameCube's 24 MiB of RAM, so it can't be any
+// bigger than that. Anything claiming more entries than would fit is corrupt.
+const MAX_FST_SIZE: usize = 24 * 1024 * 1024;
+
+// An entry in the FST, for listing the files on a ROM
+#[derive(Clone, Debug, Serialize)]
+pub struct FileListing {
+    pub path: PathBuf,
+    pub index: usize,
+    pub is_dir: bool,
+    // Directories don't have an offset or size
+    pub offset: Option<u64>,
+    pub size: Option<usize>,
+    // What the file is, if it was identified
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<FileKind>,
+}
+
+// How `FST::resolve_path` found an entry
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PathMatch {
+    Exact,
+    // GameCube file systems keep the case of names, but games aren't always
+    // consistent about it
+    IgnoringCase,
+}
+
+impl PathMatch {
+    fn matches(self, entry: &Entry, name: &str) -> bool {
+        let entry_name = entry.info().name.trim_end_matches(path::MAIN_SEPARATOR);
+        match self {
+            PathMatch::Exact => entry_name == name,
+            PathMatch::IgnoringCase => entry_name.to_lowercase() == name.to_lowercase(),
+        }
+    }
+}
+
+// The order entries in a directory are printed in by `write_tree`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TreeOrder {
+    // The order they're in the FST
+    Fst,
+    Name,
+    // Largest first, using the total size of everything in directories
+    Size,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TreeOptions {
+    pub sizes: bool,
+    pub offsets: bool,
+    // How many levels below the top directory to print, or all of them
+    pub depth: Option<usize>,
+    pub order: TreeOrder,
+}
+
+impl Default for TreeOptions {
+    fn default() -> TreeOptions {
+        TreeOptions {
+            sizes: false,
+            offsets: false,
+            depth: None,
+            order: TreeOrder::Fst,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct FST {
     /*
      * `file_count` is different from `entries.len()` in that
@@ -35,17 +114,46 @@ pub struct FST {
 }
 
 impl FST {
-    pub fn new(mut iso: impl BufRead + Seek, offset: u64) -> io::Result<FST> {
+    pub fn new(iso: impl BufRead + Seek, offset: u64) -> io::Result<FST> {
+        FST::read(iso, offset, None)
+    }
+
+    // Like `new`, but the FST can't be any bigger than `size`, like the size
+    // the header gives it
+    pub fn with_size(iso: impl BufRead + Seek, offset: u64, size: usize) -> io::Result<FST> {
+        FST::read(iso, offset, Some(size))
+    }
+
+    fn read(mut iso: impl BufRead + Seek, offset: u64, size: Option<usize>) -> io::Result<FST> {
         let mut iso = &mut iso;
+        // Don't make room for more entries than there's data for
+        let available = iso.seek(SeekFrom::End(0))?.saturating_sub(offset);
+        let limit = size.map_or(available, |s| available.min(s as u64));
         iso.seek(SeekFrom::Start(offset))?;
 
         let mut entry_buffer: [u8; ENTRY_SIZE] = [0; ENTRY_SIZE];
         iso.take(ENTRY_SIZE as u64).read_exact(&mut entry_buffer)?;
-        let root = Entry::new(&entry_buffer, 0, None)
-            .expect("Couldn't read root fst entry.");
+        let root = Entry::new(&entry_buffer, 0, None)?;
         let entry_count = root.as_dir()
-            .expect("Root fst wasn't a directory.")
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The FST's first entry isn't a directory.",
+            ))?
             .next_index;
+        if entry_count == 0 || entry_count > MAX_FST_SIZE / ENTRY_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The FST can't have {} entries.", entry_count),
+            ));
+        }
+        if (entry_count * ENTRY_SIZE) as u64 > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The FST says it has {} entries, but there's only room for {}.", entry_count, limit / ENTRY_SIZE as u64),
+            ));
+        }
+        let string_table_size = limit - (entry_count * ENTRY_SIZE) as u64;
+        iso.seek(SeekFrom::Start(offset + ENTRY_SIZE as u64))?;
 
         let mut entries = Vec::with_capacity(entry_count);
         entries.push(root);
@@ -68,14 +176,36 @@ impl FST {
                 p.2 += 1;
             }
 
-            iso.take(ENTRY_SIZE as u64).read_exact(&mut entry_buffer)?;
+            iso.take(ENTRY_SIZE as u64).read_exact(&mut entry_buffer)
+                .map_err(|e| io::Error::new(e.kind(), format!("Couldn't read entry {} of the FST: {}", index, e)))?;
             let e = Entry::new(&entry_buffer, index, parents.last().map(|d| d.0))?;
+            if e.info().filename_offset >= string_table_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Entry {} in the FST has its name at {:#x}, past the end of the {:#x} byte string table.",
+                        index, e.info().filename_offset, string_table_size,
+                    ),
+                ));
+            }
             match &e {
                 Entry::File(f) => {
                     file_count += 1;
                     total_file_system_size += f.size;
                 },
                 Entry::Directory(d) => {
+                    // Everything in a directory comes right after it, so it
+                    // has to end after itself and before its parent does
+                    let parent_end = parents.last().map_or(entry_count, |p| p.1);
+                    if d.next_index <= index || d.next_index > parent_end {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "Entry {} in the FST is a directory that ends at entry {}, which isn't between it and entry {}, where its parent ends.",
+                                index, d.next_index, parent_end,
+                            ),
+                        ));
+                    }
                     parents.push((index, d.next_index, 0));
                 },
             }
@@ -87,7 +217,9 @@ impl FST {
 
         let mut end = 0;
         for e in entries.iter_mut() {
-            e.read_filename(&mut iso, str_tbl_addr)?;
+            let index = e.info().index;
+            e.read_filename(&mut iso, str_tbl_addr)
+                .map_err(|err| io::Error::new(err.kind(), format!("Couldn't read the name of entry {} in the FST: {}", index, err)))?;
 
             let curr_end = iso.seek(SeekFrom::Current(0))?;
             end = max(curr_end, end);
@@ -109,6 +241,7 @@ impl FST {
             let path = fst.get_full_path(fst.entries[i].info());
             fst.entries[i].info_mut().full_path = path;
         }
+        names::assign_disk_names(&mut fst.entries);
 
         Ok(fst)
     }
@@ -117,13 +250,92 @@ impl FST {
         self.entries[0].as_dir().unwrap()
     }
 
+    // `mode` only matters for individual files here, the directory `path`
+    // should already be set up for it
     pub fn extract_file_system(
         &mut self, 
         path: impl AsRef<Path>,
         iso: impl BufRead + Seek,
+        mode: ExtractMode,
+        order: ExtractOrder,
+        filter: Option<FileFilter>,
+        callback: impl FnMut(usize),
+    ) -> io::Result<ExtractCount> {
+        match (order, filter, mode.existing_check()) {
+            (ExtractOrder::OffsetOrder, filter, check) =>
+                self.extract_in_offset_order(path.as_ref(), iso, check, filter, callback),
+            (ExtractOrder::FstOrder, Some(filter), check) =>
+                self.entries[0].extract_filtered(path, &self.entries, iso, filter, check, callback),
+            (ExtractOrder::FstOrder, None, Some(check)) =>
+                self.entries[0].extract_skipping_existing(path, &self.entries, iso, check, callback),
+            (ExtractOrder::FstOrder, None, None) => self.entries[0].extract_with_name(path, &self.entries, iso, callback)
+                .map(|written| ExtractCount { written, skipped: 0 }),
+        }
+    }
+
+    fn extract_in_offset_order(
+        &self,
+        path: &Path,
+        mut iso: impl BufRead + Seek,
+        check: Option<ExistingCheck>,
+        filter: Option<FileFilter>,
+        mut callback: impl FnMut(usize),
+    ) -> io::Result<ExtractCount> {
+        let mut count = ExtractCount::default();
+        for (f, output) in self.files_by_offset(path, filter)? {
+            f.extract_counted(&output, &mut iso, check, &mut count)?;
+            callback(count.total());
+        }
+        Ok(count)
+    }
+
+    // The files `filter` accepts, with where they go under `path`, in the
+    // order they're in on the disc. Files at the same offset stay in FST
+    // order. The directories are made first, but with a filter, only the
+    // ones something is going in are.
+    pub fn files_by_offset(&self, path: &Path, filter: Option<FileFilter>) -> io::Result<Vec<(&FileEntry, PathBuf)>> {
+        if filter.is_none() {
+            create_dir_all(path)?;
+        }
+        let mut files = Vec::with_capacity(self.file_count);
+        for e in &self.entries[1..] {
+            let output = path.join(self.disk_path(e.info()));
+            match e {
+                Entry::Directory(_) if filter.is_none() => create_dir_all(output)?,
+                Entry::Directory(_) => {},
+                Entry::File(f) => match filter {
+                    Some(filter) if !filter(&f.info.full_path, f) => {},
+                    Some(_) => {
+                        if let Some(parent) = output.parent() {
+                            create_dir_all(parent)?;
+                        }
+                        files.push((f, output));
+                    },
+                    None => files.push((f, output)),
+                },
+            }
+        }
+        files.sort_by_key(|(f, _)| f.file_offset);
+        Ok(files)
+    }
+
+    // Extracts everything under the directory at `fst_path` (like `audio` or
+    // `/audio/`) into `output_dir`, returning how many files were written
+    pub fn extract_directory(
+        &self,
+        fst_path: &str,
+        output_dir: &Path,
+        iso: impl BufRead + Seek,
         callback: impl FnMut(usize),
     ) -> io::Result<usize> {
-        self.entries[0].extract_with_name(path, &self.entries, iso, callback)
+        let entry = self.entry_for_path(fst_path).ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} doesn't exist on the ROM", fst_path),
+        ))?;
+        if !entry.is_dir() {
+            return Err(io::Error::other(format!("{} isn't a directory", fst_path)));
+        }
+        entry.extract_with_name(output_dir, &self.entries, iso, callback)
     }
 
     pub fn extract(
@@ -138,47 +350,197 @@ impl FST {
         extract_section(iso, size, file)
     }
 
+    // Each name is written at its `filename_offset`, so names can be shared
+    // and the string table doesn't have to be in order
     pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
-        let mut sorted_names = BTreeMap::new();
+        let mut names = Vec::new();
         for e in &self.entries {
             e.write(&mut writer)?;
-            sorted_names.insert(e.info().filename_offset, &e.info().name);
-        }
-        let null_byte = [0];
-        for (_, name) in &sorted_names {
-            (&mut writer).write(name.as_bytes())?;
-            (&mut writer).write(&null_byte[..])?;
+            // The root doesn't have a name
+            if e.info().index == 0 { continue }
+            let start = e.info().filename_offset as usize;
+            let raw_name = &e.info().raw_name;
+            if names.len() < start + raw_name.len() + 1 {
+                names.resize(start + raw_name.len() + 1, 0);
+            }
+            names[start..start + raw_name.len()].copy_from_slice(raw_name);
         }
-        Ok(())
+        writer.write_all(&names)
     }
 
     pub fn entry_for_path(&self, path: impl AsRef<Path>) -> Option<&Entry> {
-        let path = path.as_ref();
-        if path.is_relative() {
-            // Just treat the entire `path` like a single filename in this case
-            self.entry_with_name(path, self.root())
-        } else {
-            // For each component in `path` (skipping the initial "/"),
-            // try to find the corresponding file with that name
-            path.iter().skip(1).try_fold(&self.entries[0], |entry, name| {
-                entry.as_dir().and_then(|dir| {
-                    dir.iter_contents(&self.entries).find(|e| &e.info().name[..] == name)
+        self.resolve_path(path).map(|(e, _)| e)
+    }
+
+    // Finds the entry at `path`, which can use either kind of slash, and can
+    // start with `/` or `./`. A path that's just a name, like `title.dsp`, can
+    // be anywhere. If nothing has exactly that path, a match ignoring case is
+    // used instead, and the `PathMatch` says so.
+    pub fn resolve_path(&self, path: impl AsRef<Path>) -> Option<(&Entry, PathMatch)> {
+        let path = path.as_ref().to_string_lossy().replace('\\', "/");
+        let names = path.split('/')
+            .filter(|&n| !n.is_empty() && n != ".")
+            .collect::<Vec<_>>();
+        let anywhere = names.len() == 1 && !path.starts_with('/');
+
+        [PathMatch::Exact, PathMatch::IgnoringCase].iter().find_map(|&kind| {
+            let entry = if anywhere {
+                self.entry_with_name(names[0], self.root(), kind)
+            } else {
+                // For each component in `path`, try to find the corresponding
+                // file with that name
+                names.iter().try_fold(&self.entries[0], |entry, name| {
+                    entry.as_dir().and_then(|dir| {
+                        dir.iter_contents(&self.entries).find(|e| kind.matches(e, name))
+                    })
                 })
-            })
-        }
+            };
+            entry.map(|e| (e, kind))
+        })
     }
 
-    fn entry_with_name<'a>(&'a self, name: impl AsRef<Path>, dir: &'a DirectoryEntry) -> Option<&'a Entry> {
-        let name = name.as_ref();
+    fn entry_with_name<'a>(&'a self, name: &str, dir: &'a DirectoryEntry, kind: PathMatch) -> Option<&'a Entry> {
         dir.iter_contents(&self.entries).find_map(|e| {
-            if name.as_os_str() == &e.info().name[..] {
+            if kind.matches(e, name) {
                 Some(e)
             } else {
-                e.as_dir().and_then(|subdir| self.entry_with_name(name, subdir))
+                e.as_dir().and_then(|subdir| self.entry_with_name(name, subdir, kind))
             }
         })
     }
 
+    // Every entry except the root, in the order they're stored in the FST
+    pub fn to_listing(&self) -> Vec<FileListing> {
+        self.entries[1..].iter().map(|e| {
+            let info = e.info();
+            FileListing {
+                path: info.full_path.clone(),
+                index: info.index,
+                is_dir: e.is_dir(),
+                offset: e.as_file().map(|f| f.file_offset),
+                size: e.as_file().map(|f| f.size),
+                kind: None,
+            }
+        }).collect()
+    }
+
+    // Like `to_listing`, but in the order the files are on the ROM, with the
+    // directories first
+    pub fn to_listing_by_offset(&self) -> Vec<FileListing> {
+        let mut listing = self.to_listing();
+        listing.sort_by_key(|l| l.offset);
+        listing
+    }
+
+    pub fn write_tree(&self, out: impl Write, opts: TreeOptions) -> io::Result<()> {
+        self.write_subtree(self.root(), out, opts, &HashMap::new())
+    }
+
+    // Prints `dir` and everything under it with `tree` style connectors.
+    // This keeps its own stack instead of recursing, since there's no limit
+    // on how deep directories can go. If `kinds` isn't empty, each file's
+    // kind from it is printed too, like from `Game::identify_files`.
+    pub fn write_subtree(
+        &self,
+        dir: &DirectoryEntry,
+        mut out: impl Write,
+        opts: TreeOptions,
+        kinds: &HashMap<usize, FileKind>,
+    ) -> io::Result<()> {
+        writeln!(out, "{}{}", self.tree_columns(&self.entries[dir.info.index], opts, kinds), dir.info.full_path.display())?;
+
+        let mut stack = Vec::new();
+        self.push_tree_contents(&mut stack, dir, 1, String::new(), opts.order);
+
+        while let Some((entry, depth, prefix, last)) = stack.pop() {
+            writeln!(
+                out,
+                "{}{}{}{}",
+                self.tree_columns(entry, opts, kinds),
+                prefix,
+                if last { "└── " } else { "├── " },
+                entry.info().name,
+            )?;
+            if let Some(d) = entry.as_dir() {
+                if opts.depth.is_none_or(|max| depth < max) {
+                    let prefix = prefix + if last { "    " } else { "│   " };
+                    self.push_tree_contents(&mut stack, d, depth + 1, prefix, opts.order);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Pushes (entry, depth, the connectors for the directories above it,
+    // whether it's the last thing in its directory) for everything in `dir`,
+    // in reverse so the first entry gets popped first
+    fn push_tree_contents<'a>(
+        &'a self,
+        stack: &mut Vec<(&'a Entry, usize, String, bool)>,
+        dir: &'a DirectoryEntry,
+        depth: usize,
+        prefix: String,
+        order: TreeOrder,
+    ) {
+        let contents = self.sorted_contents(dir, order);
+        let count = contents.len();
+        for (i, e) in contents.into_iter().enumerate().rev() {
+            stack.push((e, depth, prefix.clone(), i == count - 1));
+        }
+    }
+
+    // The total size of every file under `dir`
+    pub fn directory_size(&self, dir: &DirectoryEntry) -> u64 {
+        // Everything in a directory comes right after it in the FST
+        let end = dir.next_index.clamp(dir.info.index + 1, self.entries.len());
+        self.entries[dir.info.index + 1..end].iter()
+            .filter_map(|e| e.as_file())
+            .map(|f| f.size as u64)
+            .sum()
+    }
+
+    fn sorted_contents<'a>(&'a self, dir: &'a DirectoryEntry, order: TreeOrder) -> Vec<&'a Entry> {
+        let mut contents = dir.iter_contents(&self.entries).collect::<Vec<_>>();
+        match order {
+            TreeOrder::Fst => {},
+            TreeOrder::Name => contents.sort_by(|a, b| a.info().name.cmp(&b.info().name)),
+            TreeOrder::Size => contents.sort_by_key(|e| Reverse(self.entry_size(e))),
+        }
+        contents
+    }
+
+    fn entry_size(&self, entry: &Entry) -> u64 {
+        match entry {
+            Entry::File(f) => f.size as u64,
+            Entry::Directory(d) => self.directory_size(d),
+        }
+    }
+
+    fn tree_columns(&self, entry: &Entry, opts: TreeOptions, kinds: &HashMap<usize, FileKind>) -> String {
+        let mut columns = String::new();
+        if !kinds.is_empty() {
+            match kinds.get(&entry.info().index) {
+                Some(kind) => columns += &format!("{:<7} ", kind),
+                None => columns += &format!("{:<7} ", "-"),
+            }
+        }
+        if opts.offsets {
+            match entry.as_file() {
+                Some(f) => columns += &format!("{:#010x} ", f.file_offset),
+                None => columns += &format!("{:>10} ", "-"),
+            }
+        }
+        if opts.sizes {
+            columns += &format!("{:>10} ", self.entry_size(entry));
+        }
+        if columns.is_empty() {
+            columns
+        } else {
+            // Without the space after the last column
+            format!("[{}]  ", &columns[..columns.len() - 1])
+        }
+    }
+
     pub fn get_parent_for_entry(&self, entry: &EntryInfo) -> Option<&Entry> {
         entry.directory_index.map(|i| &self.entries[i])
     }
@@ -215,4 +577,16 @@ impl Section for FST {
     fn size(&self) -> usize {
         self.size
     }
+
+    fn name(&self) -> String {
+        "Game.toc".to_string()
+    }
+
+    fn section_type(&self) -> &'static str {
+        "fst"
+    }
+
+    fn resolve(&self) -> ResolvedSection<'_> {
+        ResolvedSection::Fst(self)
+    }
 }
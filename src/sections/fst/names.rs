@@ -0,0 +1,193 @@
+// Works out what to call each entry when it's extracted. Some names can't be
+// used on every file system, like ones with `?` or `:` in them on Windows, and
+// some directories have entries whose names only differ by case, which would
+// be extracted on top of each other on Windows and macOS. Those entries get a
+// different name on disk, which is recorded in `NAME_MAP_PATH` so rebuilding
+// can put the original back.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{self, Path, PathBuf};
+
+use paths::NAME_MAP_PATH;
+
+use super::entry::{Entry, EntryInfo};
+use super::FST;
+
+const ILLEGAL_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// Sets every entry's `disk_name`. The first entry in a directory with a name
+// keeps it, and the ones after it with the same name (ignoring case) get a
+// number, like `Effect~1.dat`, so the same FST always gets the same names.
+pub fn assign_disk_names(entries: &mut [Entry]) {
+    let mut disk_names = vec![String::new(); entries.len()];
+    for dir in entries.iter().filter_map(|e| e.as_dir()) {
+        let children = dir.iter_contents(entries)
+            .map(|e| (e.info().index, sanitize(e.info().name.trim_end_matches(path::MAIN_SEPARATOR))))
+            .collect::<Vec<_>>();
+        let all_names = children.iter().map(|(_, n)| n.to_lowercase()).collect::<HashSet<_>>();
+        let mut used = HashSet::new();
+        for (index, name) in children {
+            let name = if used.contains(&name.to_lowercase()) {
+                (1..)
+                    .map(|n| numbered(&name, n))
+                    .find(|n| !used.contains(&n.to_lowercase()) && !all_names.contains(&n.to_lowercase()))
+                    .unwrap()
+            } else {
+                name
+            };
+            used.insert(name.to_lowercase());
+            disk_names[index] = name;
+        }
+    }
+    for (e, name) in entries.iter_mut().zip(disk_names) {
+        e.info_mut().disk_name = name;
+    }
+}
+
+// Replaces anything Windows doesn't allow in a name with `_`
+fn sanitize(name: &str) -> String {
+    let mut name = name.chars()
+        .map(|c| if c < ' ' || ILLEGAL_CHARS.contains(&c) { '_' } else { c })
+        .collect::<String>();
+
+    // Windows drops dots and spaces at the end of a name, and `.` and `..`
+    // aren't names at all
+    let trimmed = name.trim_end_matches(['.', ' ']).len();
+    let removed = name.len() - trimmed;
+    name.truncate(trimmed);
+    name.extend(std::iter::repeat_n('_', removed));
+    if name.is_empty() {
+        name.push('_');
+    }
+
+    let stem_len = name.find('.').unwrap_or(name.len());
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(&name[..stem_len])) {
+        name.insert(stem_len, '_');
+    }
+    name
+}
+
+// `Effect.dat` -> `Effect~1.dat`
+fn numbered(name: &str, n: usize) -> String {
+    match name.rfind('.').filter(|&i| i > 0) {
+        Some(i) => format!("{}~{}{}", &name[..i], n, &name[i..]),
+        None => format!("{}~{}", name, n),
+    }
+}
+
+impl FST {
+    // Where `entry` is extracted to, relative to the root of the extracted ROM
+    pub fn disk_path(&self, entry: &EntryInfo) -> PathBuf {
+        let mut names = vec![&entry.disk_name];
+        let mut parent = entry;
+        while let Some(p) = self.get_parent_for_entry(parent) {
+            parent = p.info();
+            names.push(&parent.disk_name);
+        }
+        names.iter().rev().collect()
+    }
+
+    // Gives the entries in `map`, from `read_name_map`, the names they were
+    // extracted with, for an FST that was read back from an extracted ROM
+    pub fn apply_name_map(&mut self, map: &HashMap<PathBuf, String>) {
+        if map.is_empty() { return }
+        // By the directory they're in and their name on the ROM
+        let disk_names = map.iter()
+            .filter_map(|(path, name)| {
+                let disk_name = path.file_name()?.to_string_lossy().into_owned();
+                Some(((path.parent()?.to_path_buf(), name.clone()), disk_name))
+            })
+            .collect::<HashMap<_, _>>();
+        // Directories come before what's in them, so their names are always
+        // already right
+        for i in 1..self.entries.len() {
+            let info = self.entries[i].info();
+            let parent = self.disk_path(info).parent().map(Path::to_path_buf).unwrap_or_default();
+            let name = info.name.trim_end_matches(path::MAIN_SEPARATOR).to_string();
+            if let Some(disk_name) = disk_names.get(&(parent, name)) {
+                self.entries[i].info_mut().disk_name = disk_name.clone();
+            }
+        }
+    }
+
+    // Whether any entries are extracted with a different name than the one
+    // in the FST, so there needs to be a name map
+    pub fn has_renamed_entries(&self) -> bool {
+        self.renamed_entries().next().is_some()
+    }
+
+    fn renamed_entries(&self) -> impl Iterator<Item = &EntryInfo> {
+        self.entries[1..].iter()
+            .map(|e| e.info())
+            .filter(|i| i.disk_name != i.name.trim_end_matches(path::MAIN_SEPARATOR))
+    }
+
+    // Writes a line for each renamed entry, with where it was extracted to, a
+    // tab, and its original name
+    pub fn write_name_map(&self, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "# These were renamed when they were extracted. Each line is where one")?;
+        writeln!(output, "# was extracted to, then its name on the ROM, separated by a tab.")?;
+        for info in self.renamed_entries() {
+            let disk_path = self.disk_path(info).iter()
+                .map(|c| c.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let name = info.name.trim_end_matches(path::MAIN_SEPARATOR);
+            writeln!(output, "{}\t{}", disk_path, escape(name))?;
+        }
+        Ok(())
+    }
+}
+
+// The original names from the name map in the extracted ROM at `root`, by
+// the paths they were extracted to. Empty if there isn't a name map.
+pub fn read_name_map(root: &Path) -> io::Result<HashMap<PathBuf, String>> {
+    let file = match File::open(root.join(NAME_MAP_PATH)) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let mut names = HashMap::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') { continue }
+        let (disk_path, name) = line.split_once('\t').ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has a line without a tab: {}", NAME_MAP_PATH, line),
+        ))?;
+        names.insert(disk_path.split('/').collect(), unescape(name));
+    }
+    Ok(names)
+}
+
+// Names can have tabs and newlines in them, which would break up the lines
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
@@ -0,0 +1,164 @@
+// Checks for FSTs that parse, but don't make sense, like ones from corrupt or
+// hand-edited images
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{self, PathBuf};
+
+use sections::apploader::APPLOADER_OFFSET;
+
+use super::entry::Entry;
+use super::FST;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FstIssue {
+    // The file reaches past the end of the image
+    FileOutOfBounds { index: usize, path: PathBuf, offset: u64, size: usize, image_len: u64 },
+    // The file's data is on top of the header, apploader, DOL, or FST
+    OverlapsSystemData { index: usize, path: PathBuf, offset: u64, size: usize, section: &'static str },
+    // The entry's name doesn't point anywhere useful in the string table
+    BadName { index: usize, filename_offset: u64 },
+    // Another entry in the same directory has the same name
+    DuplicateName { index: usize, path: PathBuf, other: usize },
+    // The directory's entries end before it starts, or after its parent's do
+    BadDirectoryRange { index: usize, path: PathBuf, next_index: usize, limit: usize },
+    // The directory's parent_index isn't the directory it's actually in
+    WrongParent { index: usize, path: PathBuf, parent_index: usize, expected: usize },
+    // The string table reaches past the FST's size in the header
+    NamesOutOfRange { end: u64, fst_end: u64 },
+    // The apploader's sizes make it run into the DOL or FST
+    ApploaderOverlap { end: u64, section: &'static str, start: u64 },
+}
+
+impl fmt::Display for FstIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FstIssue::FileOutOfBounds { index, path, offset, size, image_len } => write!(
+                f, "Entry {} ({}) is at {:#x}-{:#x}, past the end of the image at {:#x}.",
+                index, path.display(), offset, offset + *size as u64, image_len,
+            ),
+            FstIssue::OverlapsSystemData { index, path, offset, size, section } => write!(
+                f, "Entry {} ({}) is at {:#x}-{:#x}, on top of the {}.",
+                index, path.display(), offset, offset + *size as u64, section,
+            ),
+            FstIssue::BadName { index, filename_offset } => write!(
+                f, "Entry {}'s name at {:#x} in the string table is empty or unreadable.",
+                index, filename_offset,
+            ),
+            FstIssue::DuplicateName { index, path, other } => write!(
+                f, "Entry {} ({}) has the same name as entry {}.",
+                index, path.display(), other,
+            ),
+            FstIssue::BadDirectoryRange { index, path, next_index, limit } => write!(
+                f, "Directory {} ({}) ends at entry {}, but has to end after itself and by entry {}.",
+                index, path.display(), next_index, limit,
+            ),
+            FstIssue::WrongParent { index, path, parent_index, expected } => write!(
+                f, "Directory {} ({}) says its parent is entry {}, but it's in entry {}.",
+                index, path.display(), parent_index, expected,
+            ),
+            FstIssue::NamesOutOfRange { end, fst_end } => write!(
+                f, "The FST's names end at {:#x}, past the end of the FST at {:#x}.",
+                end, fst_end,
+            ),
+            FstIssue::ApploaderOverlap { end, section, start } => write!(
+                f, "The apploader ends at {:#x}, past the start of the {} at {:#x}.",
+                end, section, start,
+            ),
+        }
+    }
+}
+
+impl FST {
+    // Checks that the entries all fit together and that every file is
+    // somewhere in the image, and not on top of the header or the FST. Use
+    // `overlapping_files` to check other sections, like the DOL.
+    pub fn validate(&self, image_len: u64) -> Vec<FstIssue> {
+        let mut issues = Vec::new();
+
+        for e in &self.entries[1..] {
+            let info = e.info();
+            if info.name.trim_end_matches(path::MAIN_SEPARATOR).is_empty() {
+                issues.push(FstIssue::BadName {
+                    index: info.index,
+                    filename_offset: info.filename_offset,
+                });
+            }
+            match e {
+                Entry::File(f) => {
+                    if f.file_offset + f.size as u64 > image_len {
+                        issues.push(FstIssue::FileOutOfBounds {
+                            index: info.index,
+                            path: info.full_path.clone(),
+                            offset: f.file_offset,
+                            size: f.size,
+                            image_len,
+                        });
+                    }
+                },
+                Entry::Directory(d) => {
+                    // Entries are always after the directory they're in, so
+                    // this is where it's read from, even if `parent_index`
+                    // says something else
+                    let parent = info.directory_index.unwrap_or(0);
+                    let limit = self.entries[parent].as_dir()
+                        .map_or(self.entries.len(), |p| p.next_index.min(self.entries.len()));
+                    if d.next_index <= info.index || d.next_index > limit {
+                        issues.push(FstIssue::BadDirectoryRange {
+                            index: info.index,
+                            path: info.full_path.clone(),
+                            next_index: d.next_index,
+                            limit,
+                        });
+                    }
+                    if d.parent_index != parent {
+                        issues.push(FstIssue::WrongParent {
+                            index: info.index,
+                            path: info.full_path.clone(),
+                            parent_index: d.parent_index,
+                            expected: parent,
+                        });
+                    }
+                },
+            }
+        }
+
+        for dir in self.entries.iter().filter_map(|e| e.as_dir()) {
+            let mut seen = HashMap::new();
+            for e in dir.iter_contents(&self.entries) {
+                let info = e.info();
+                let name = info.name.trim_end_matches(path::MAIN_SEPARATOR);
+                if name.is_empty() { continue }
+                if let Some(&other) = seen.get(name) {
+                    issues.push(FstIssue::DuplicateName {
+                        index: info.index,
+                        path: info.full_path.clone(),
+                        other,
+                    });
+                } else {
+                    seen.insert(name, info.index);
+                }
+            }
+        }
+
+        issues.extend(self.overlapping_files("header", 0, APPLOADER_OFFSET));
+        issues.extend(self.overlapping_files("FST", self.offset, self.offset + self.size as u64));
+        issues
+    }
+
+    // The files with data between `start` and `end`. Empty files are left
+    // out, since they don't really have a place.
+    pub fn overlapping_files(&self, section: &'static str, start: u64, end: u64) -> Vec<FstIssue> {
+        self.entries.iter()
+            .filter_map(|e| e.as_file())
+            .filter(|f| f.size > 0 && f.file_offset < end && start < f.file_offset + f.size as u64)
+            .map(|f| FstIssue::OverlapsSystemData {
+                index: f.info.index,
+                path: f.info.full_path.clone(),
+                offset: f.file_offset,
+                size: f.size,
+                section,
+            })
+            .collect()
+    }
+}
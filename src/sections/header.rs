@@ -1,18 +1,21 @@
 // This chapter of yagcd was invaluable to working on this file:
 // http://hitmen.c02.at/files/yagcd/yagcd/chap13.html
 
+use std::error::Error;
+use std::fmt;
 use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
 
 use ::{
     extract_section,
     format_u64,
     format_usize,
     NumberStyle,
+    ROM_SIZE,
 };
 
-use sections::Section;
+use sections::{ResolvedSection, Section};
 
 pub const GAME_HEADER_SIZE: usize = 0x2440;
 
@@ -24,6 +27,11 @@ pub const TITLE_SIZE: usize = 0x60;
 pub const TITLE_OFFSET: u64 = 0x20;
 
 pub const MAGIC_WORD: u32 = 0xc2339f3d;
+pub const MAGIC_WORD_OFFSET: usize = 0x1c;
+// Wii discs have the same header layout, but with their own magic word just
+// before the GameCube's
+pub const WII_MAGIC_WORD: u32 = 0x5d1c9ea3;
+pub const WII_MAGIC_WORD_OFFSET: usize = 0x18;
 
 pub const GAME_CODE_SIZE: usize = 4;
 pub const MAKER_CODE_SIZE: usize = 2;
@@ -46,7 +54,7 @@ pub const USER_LENGTH_SIZE: usize = 4;
 pub const UNKNOWN_REGION_SIZE: usize = 4;
 pub const UNUSED_REGION_3_SIZE: usize = 4;
 
-#[derive(Debug)]
+#[derive(Debug, Default, Serialize)]
 pub struct Header {
     pub game_code: String,
     pub maker_code: String,
@@ -67,8 +75,66 @@ pub struct Header {
     // yagcd separates this from the rest of the header,
     // calling it "Disk header information". Idk why...
     pub information: HeaderInformation,
+    // What the header says about the kind of disc this is. `Game::variant`
+    // also goes by the rest of the disc, so use that if there's a `Game`.
+    pub variant: DiscVariant,
+}
+
+// Discs that are laid out like a GameCube game, but aren't one that was sold
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub enum DiscVariant {
+    #[default]
+    Retail,
+    // Arcade games for the Triforce, which has its own loader, so what's
+    // where the apploader would be isn't one. They have a `boot.id` file
+    // for the loader.
+    Triforce,
+    // Development discs for the NR Reader, which boot into a debug monitor
+    // that the header points to. Otherwise they're like retail discs.
+    NrDisc,
+}
+
+impl DiscVariant {
+    // Whether the disc has a normal GameCube apploader
+    pub fn has_apploader(self) -> bool {
+        self != DiscVariant::Triforce
+    }
+}
+
+impl fmt::Display for DiscVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiscVariant::Retail => write!(f, "retail"),
+            DiscVariant::Triforce => write!(f, "Triforce"),
+            DiscVariant::NrDisc => write!(f, "NR disc"),
+        }
+    }
 }
 
+// Something that can't be done with a kind of disc, in place of whatever
+// nonsense would come out of treating it like a retail one. It's in an
+// `io::Error` with the `Unsupported` kind, so it can be found with
+// `get_ref` and `downcast_ref`.
+#[derive(Debug)]
+pub struct UnsupportedVariant {
+    pub variant: DiscVariant,
+    pub what: String,
+}
+
+impl UnsupportedVariant {
+    pub fn error(variant: DiscVariant, what: impl Into<String>) -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, UnsupportedVariant { variant, what: what.into() })
+    }
+}
+
+impl fmt::Display for UnsupportedVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} isn't supported for {} images", self.what, self.variant)
+    }
+}
+
+impl Error for UnsupportedVariant {}
+
 // DISK HEADER INFORMATION DATA
 pub const DEBUG_MONITOR_SIZE_SIZE: usize = 4;
 pub const SIMULATED_MEMORY_SIZE: usize = 4;
@@ -79,7 +145,53 @@ pub const TRACK_SIZE_SIZE: usize = 4;
 pub const COUNTRY_CODE_SIZE: usize = 4;
 pub const INFO_UNKNOWN_SIZE: usize = 4;
 
-#[derive(Debug)]
+// Where a game was released, going by the last letter of its game code
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Region {
+    NtscJ,
+    NtscU,
+    NtscK,
+    Pal,
+    Unknown(char),
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Region::NtscJ => write!(f, "NTSC-J"),
+            Region::NtscU => write!(f, "NTSC-U"),
+            Region::NtscK => write!(f, "NTSC-K"),
+            Region::Pal => write!(f, "PAL"),
+            Region::Unknown(c) => write!(f, "Unknown({:?})", c),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VideoMode {
+    Ntsc,
+    Pal,
+}
+
+impl fmt::Display for VideoMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VideoMode::Ntsc => write!(f, "NTSC"),
+            VideoMode::Pal => write!(f, "PAL"),
+        }
+    }
+}
+
+// The values of `HeaderInformation::country_code`
+pub const COUNTRY_JAPAN: u32 = 0;
+pub const COUNTRY_USA: u32 = 1;
+pub const COUNTRY_PAL: u32 = 2;
+pub const COUNTRY_KOREA: u32 = 4;
+
+// 24MiB, which is how much memory a retail GameCube has
+pub const DEFAULT_SIMULATED_MEMORY: u32 = 0x0180_0000;
+
+#[derive(Debug, Default, Serialize)]
 pub struct HeaderInformation {
     pub debug_monitor_size: u32,
     pub simulated_memory_size: u32,
@@ -128,6 +240,27 @@ impl Header {
         R: BufRead + Seek,
     {
         file.seek(SeekFrom::Start(offset as u64))?;
+        // Check that this is actually a GameCube image before reading
+        // anything else, so other files get a useful error
+        let mut start = [0; TITLE_OFFSET as usize];
+        file.read_exact(&mut start).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid_image("the file is too small to be a GameCube image".to_string()),
+            _ => e,
+        })?;
+        let magic_at = |o: usize| BigEndian::read_u32(&start[o..]);
+        if magic_at(WII_MAGIC_WORD_OFFSET) == WII_MAGIC_WORD {
+            return Err(invalid_image(
+                "not a GameCube image (found the Wii disc magic word, and Wii discs aren't supported)".to_string(),
+            ));
+        }
+        if magic_at(MAGIC_WORD_OFFSET) != MAGIC_WORD {
+            return Err(invalid_image(format!(
+                "unrecognized file (no GameCube magic word at {:#x})",
+                MAGIC_WORD_OFFSET,
+            )));
+        }
+        file.seek(SeekFrom::Start(offset))?;
+
         let mut game_code = String::with_capacity(GAME_CODE_SIZE);
         file.by_ref().take(GAME_CODE_SIZE as u64)
             .read_to_string(&mut game_code)?;
@@ -143,12 +276,8 @@ impl Header {
 
         file.seek(SeekFrom::Current(UNUSED_REGION_1_SIZE as i64))?;
 
-        if file.read_u32::<BigEndian>()? != MAGIC_WORD {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Invalid file type",
-            ));
-        }
+        // The magic word was already checked
+        file.seek(SeekFrom::Current(MAGIC_WORD_SIZE as i64))?;
 
         let mut title = Vec::with_capacity(GAME_NAME_SIZE);
         let bytes_read = file.by_ref().take(GAME_NAME_SIZE as u64)
@@ -174,6 +303,11 @@ impl Header {
 
         let dol_offset = file.read_u32::<BigEndian>()? as u64;
         let fst_offset = file.read_u32::<BigEndian>()? as u64;
+        for &(name, o) in &[("DOL", dol_offset), ("FST", fst_offset)] {
+            if o == 0 || o % 4 != 0 || o >= ROM_SIZE as u64 {
+                return Err(invalid_image(format!("the {} offset, {:#x}, isn't valid", name, o)));
+            }
+        }
 
         let fst_size = file.read_u32::<BigEndian>()? as usize;
         let max_fst_size = file.read_u32::<BigEndian>()? as usize;
@@ -182,10 +316,19 @@ impl Header {
         let user_length = file.read_u32::<BigEndian>()?;
         let unknown = file.read_u32::<BigEndian>()?;
 
+        file.seek(SeekFrom::Current(UNUSED_REGION_3_SIZE as i64))?;
+
         let pos = file.seek(SeekFrom::Current(0))?;
 
         let information = HeaderInformation::new(file, pos)?;
 
+        // Retail discs don't have a debug monitor
+        let variant = if debug_monitor_offset != 0 && information.debug_monitor_size != 0 {
+            DiscVariant::NrDisc
+        } else {
+            DiscVariant::Retail
+        };
+
         Ok(Header {
             game_code,
             maker_code,
@@ -204,9 +347,28 @@ impl Header {
             user_length,
             unknown,
             information,
+            variant,
         })
     }
 
+    // A header for a ROM that's being made from scratch. The offsets are
+    // filled in once everything's been laid out, and everything else that
+    // isn't given is zero, besides what games expect to be set.
+    pub fn blank(game_code: &str, maker_code: &str, title: &str) -> io::Result<Header> {
+        let mut header = Header::default();
+        header.set_game_code(game_code)?;
+        header.set_maker_code(maker_code)?;
+        header.set_title(title)?;
+        header.information.simulated_memory_size = DEFAULT_SIMULATED_MEMORY;
+        header.information.country_code = match header.region() {
+            Region::NtscJ => COUNTRY_JAPAN,
+            Region::NtscK => COUNTRY_KOREA,
+            Region::Pal => COUNTRY_PAL,
+            _ => COUNTRY_USA,
+        };
+        Ok(header)
+    }
+
     pub fn extract<R, W>(mut iso: R, output: W) -> io::Result<()>
     where
         R: Read + Seek,
@@ -216,26 +378,78 @@ impl Header {
         extract_section(iso, GAME_HEADER_SIZE, output)
     }
 
-    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
-        let mut buf = Vec::new();
+    // Games that stream music from the disc, which is usually ADP files. The
+    // drive reads them in 32 KiB chunks, so they have to be aligned to that.
+    pub fn streams_audio(&self) -> bool {
+        self.audio_streaming != 0
+    }
 
-        writer.write_all(self.game_code.as_bytes())?;
-        writer.write_all(self.maker_code.as_bytes())?;
+    // The last letter of the game code says where the game's from. Letters
+    // that don't mean anything fall back on the country code, which isn't
+    // always set properly, so it only breaks ties.
+    pub fn region(&self) -> Region {
+        let letter = self.game_code.chars().nth(3).unwrap_or('?');
+        match letter {
+            'J' => Region::NtscJ,
+            'E' => Region::NtscU,
+            'K' => Region::NtscK,
+            // Europe, Germany, France, Italy, Spain, the Netherlands,
+            // Australia, and a few multi-language releases
+            'P' | 'D' | 'F' | 'I' | 'S' | 'H' | 'U' | 'X' | 'Y' => Region::Pal,
+            _ => match self.information.country_code {
+                COUNTRY_JAPAN => Region::NtscJ,
+                COUNTRY_USA => Region::NtscU,
+                COUNTRY_KOREA => Region::NtscK,
+                COUNTRY_PAL => Region::Pal,
+                _ => Region::Unknown(letter),
+            },
+        }
+    }
 
-        writer.write_u8(self.disk_id)?;
-        writer.write_u8(self.version)?;
-        writer.write_u8(self.audio_streaming)?;
-        writer.write_u8(self.stream_buffer_size)?;
+    // A guess, since PAL games can usually run at 60Hz too
+    pub fn video_mode(&self) -> VideoMode {
+        match self.region() {
+            Region::Pal => VideoMode::Pal,
+            Region::Unknown(_) if self.information.country_code == COUNTRY_PAL => VideoMode::Pal,
+            _ => VideoMode::Ntsc,
+        }
+    }
 
-        buf.resize(UNUSED_REGION_1_SIZE, 0);
-        writer.write_all(&buf[..])?;
+    pub fn set_title(&mut self, title: &str) -> io::Result<()> {
+        if title.len() > GAME_NAME_SIZE {
+            return Err(invalid_value(format!(
+                "the title can be at most {} bytes, but it's {}", GAME_NAME_SIZE, title.len(),
+            )));
+        }
+        if title.contains('\0') {
+            return Err(invalid_value("the title can't contain a NUL byte".to_string()));
+        }
+        self.title = title.to_string();
+        Ok(())
+    }
 
-        writer.write_u32::<BigEndian>(MAGIC_WORD)?;
+    pub fn set_game_code(&mut self, game_code: &str) -> io::Result<()> {
+        self.game_code = validate_code("game code", game_code, GAME_CODE_SIZE)?;
+        Ok(())
+    }
 
-        buf.resize(GAME_NAME_SIZE, 0);
-        writer.write_all(self.title.as_bytes())?;
-        buf.resize(GAME_NAME_SIZE - self.title.len(), 0);
-        writer.write_all(&buf[..])?;
+    pub fn set_maker_code(&mut self, maker_code: &str) -> io::Result<()> {
+        self.maker_code = validate_code("maker code", maker_code, MAKER_CODE_SIZE)?;
+        Ok(())
+    }
+
+    pub fn set_disc_id(&mut self, disk_id: u8) {
+        self.disk_id = disk_id;
+    }
+
+    pub fn set_version(&mut self, version: u8) {
+        self.version = version;
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut buf = Vec::new();
+
+        self.write_ids_and_title(&mut writer)?;
 
         writer.write_u32::<BigEndian>(self.debug_monitor_offset)?;
         writer.write_u32::<BigEndian>(self.debug_monitor_load_addr)?;
@@ -264,6 +478,47 @@ impl Header {
 
         Ok(())
     }
+
+    // Writes everything up to the end of the title, which is all the setters
+    // change. This is the part of `write` that can go straight over the old
+    // header, without touching the rest.
+    pub fn write_ids_and_title(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(self.game_code.as_bytes())?;
+        writer.write_all(self.maker_code.as_bytes())?;
+
+        writer.write_u8(self.disk_id)?;
+        writer.write_u8(self.version)?;
+        writer.write_u8(self.audio_streaming)?;
+        writer.write_u8(self.stream_buffer_size)?;
+
+        writer.write_all(&[0; UNUSED_REGION_1_SIZE])?;
+
+        writer.write_u32::<BigEndian>(MAGIC_WORD)?;
+
+        writer.write_all(self.title.as_bytes())?;
+        writer.write_all(&vec![0; GAME_NAME_SIZE - self.title.len()])
+    }
+}
+
+// Game and maker codes are always uppercase letters and numbers
+fn validate_code(name: &str, code: &str, len: usize) -> io::Result<String> {
+    if code.len() != len {
+        return Err(invalid_value(format!("the {} has to be {} characters", name, len)));
+    }
+    if !code.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+        return Err(invalid_value(format!(
+            "the {} can only have uppercase letters and numbers", name,
+        )));
+    }
+    Ok(code.to_string())
+}
+
+fn invalid_value(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn invalid_image(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
 }
 
 impl Section for Header {
@@ -282,4 +537,16 @@ impl Section for Header {
     fn size(&self) -> usize {
         GAME_HEADER_SIZE
     }
+
+    fn name(&self) -> String {
+        "ISO.hdr".to_string()
+    }
+
+    fn section_type(&self) -> &'static str {
+        "header"
+    }
+
+    fn resolve(&self) -> ResolvedSection<'_> {
+        ResolvedSection::Header(self)
+    }
 }
@@ -2,6 +2,9 @@ pub mod apploader;
 pub mod dol;
 pub mod fst;
 pub mod header;
+pub mod rel;
 
 mod section;
-pub use self::section::Section;
+pub use self::section::{ResolvedSection, Section};
+mod section_type;
+pub use self::section_type::{ExtractOutcome, SectionType, UniqueSectionType};
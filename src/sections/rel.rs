@@ -0,0 +1,282 @@
+// Relocatable modules (.rel files), which games load after the DOL, a lot
+// like DLLs. Plenty of games keep most of their code in them. The header has
+// a table of the module's sections, then a table of the modules it imports
+// from, each with a list of relocations to apply when it's linked.
+// https://wiki.tockdom.com/wiki/REL_(File_Format)
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use ::{format_u64, format_usize, NumberStyle};
+use disassembler::CodeSection;
+
+// Version 1 headers end after the unresolved function's offset, version 2
+// adds the alignment, and version 3 adds the fix size
+const HEADER_SIZES: [u64; 3] = [0x40, 0x48, 0x4C];
+const SECTION_ENTRY_SIZE: u64 = 8;
+const IMPORT_ENTRY_SIZE: u64 = 8;
+const RELOCATION_ENTRY_SIZE: u64 = 8;
+// Every module's relocation list ends with one of these
+const R_DOLPHIN_END: u8 = 203;
+// Set on the offsets of sections with code in them
+const EXECUTABLE_FLAG: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct RelHeader {
+    pub id: u32,
+    pub version: u32,
+    // Where the module's name is in the game's .str file, if it has one
+    pub name_offset: u32,
+    pub name_size: u32,
+    pub sections: Vec<RelSection>,
+    pub bss_size: u32,
+    pub relocation_offset: u32,
+    pub imports: Vec<RelImport>,
+    // These are given as an offset into a section, which is 0 if there
+    // isn't one
+    pub prolog: (u8, u32),
+    pub epilog: (u8, u32),
+    pub unresolved: (u8, u32),
+    // Only in version 2 and later
+    pub alignment: Option<u32>,
+    pub bss_alignment: Option<u32>,
+    // Only in version 3. Everything after this can be freed once the
+    // module's linked.
+    pub fix_size: Option<u32>,
+    // How big the file is
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelSection {
+    // Relocations refer to sections by this
+    pub index: usize,
+    // From the start of the file. It's 0 for the BSS, which isn't in the
+    // file, and for sections that aren't used.
+    pub offset: u64,
+    pub size: usize,
+    pub executable: bool,
+}
+
+impl RelSection {
+    pub fn is_bss(&self) -> bool {
+        self.offset == 0 && self.size > 0
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelImport {
+    // 0 is the DOL
+    pub module_id: u32,
+    // Where its relocations start
+    pub offset: u32,
+    pub relocations: usize,
+}
+
+impl RelHeader {
+    // `file` is just the REL, like one opened with `Game::open_file`
+    pub fn new(mut file: impl Read + Seek) -> io::Result<RelHeader> {
+        let size = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+        let file = &mut file;
+
+        let id = file.read_u32::<BigEndian>()?;
+        // The next and previous modules, which are only set once it's loaded
+        file.seek(SeekFrom::Current(8))?;
+        let section_count = file.read_u32::<BigEndian>()? as u64;
+        let section_table_offset = file.read_u32::<BigEndian>()? as u64;
+        let name_offset = file.read_u32::<BigEndian>()?;
+        let name_size = file.read_u32::<BigEndian>()?;
+        let version = file.read_u32::<BigEndian>()?;
+        let bss_size = file.read_u32::<BigEndian>()?;
+        let relocation_offset = file.read_u32::<BigEndian>()?;
+        let import_offset = file.read_u32::<BigEndian>()? as u64;
+        let import_size = file.read_u32::<BigEndian>()? as u64;
+        let mut sections = [0; 4];
+        file.read_exact(&mut sections)?;
+        let [prolog_section, epilog_section, unresolved_section, _bss_section] = sections;
+        let prolog = file.read_u32::<BigEndian>()?;
+        let epilog = file.read_u32::<BigEndian>()?;
+        let unresolved = file.read_u32::<BigEndian>()?;
+
+        let header_size = match version {
+            1..=3 => HEADER_SIZES[version as usize - 1],
+            _ => return Err(invalid(format!("Unknown REL version {}.", version))),
+        };
+        if size < header_size {
+            return Err(invalid("The REL is smaller than its header.".to_string()));
+        }
+        let (alignment, bss_alignment) = if version >= 2 {
+            (Some(file.read_u32::<BigEndian>()?), Some(file.read_u32::<BigEndian>()?))
+        } else {
+            (None, None)
+        };
+        let fix_size = if version >= 3 { Some(file.read_u32::<BigEndian>()?) } else { None };
+
+        if section_table_offset + section_count * SECTION_ENTRY_SIZE > size {
+            return Err(invalid(format!(
+                "The REL's {} sections at {:#x} go past the end of the file.",
+                section_count,
+                section_table_offset,
+            )));
+        }
+        file.seek(SeekFrom::Start(section_table_offset))?;
+        let mut sections = Vec::with_capacity(section_count as usize);
+        for index in 0..section_count as usize {
+            let offset = file.read_u32::<BigEndian>()?;
+            let section_size = file.read_u32::<BigEndian>()? as usize;
+            let section = RelSection {
+                index,
+                offset: (offset & !EXECUTABLE_FLAG) as u64,
+                size: section_size,
+                executable: offset & EXECUTABLE_FLAG != 0,
+            };
+            if section.offset != 0 && section.offset + section.size as u64 > size {
+                return Err(invalid(format!(
+                    "Section {} of the REL goes past the end of the file.",
+                    index,
+                )));
+            }
+            sections.push(section);
+        }
+        for (name, s) in [("prolog", prolog_section), ("epilog", epilog_section), ("unresolved", unresolved_section)] {
+            if s as u64 >= section_count.max(1) {
+                return Err(invalid(format!("The REL's {} is in section {}, which it doesn't have.", name, s)));
+            }
+        }
+
+        if !import_size.is_multiple_of(IMPORT_ENTRY_SIZE) || import_offset + import_size > size {
+            return Err(invalid(format!(
+                "The REL's import table ({:#x} bytes at {:#x}) isn't valid.",
+                import_size,
+                import_offset,
+            )));
+        }
+        file.seek(SeekFrom::Start(import_offset))?;
+        let mut imports = Vec::with_capacity((import_size / IMPORT_ENTRY_SIZE) as usize);
+        for _ in 0..import_size / IMPORT_ENTRY_SIZE {
+            let module_id = file.read_u32::<BigEndian>()?;
+            let offset = file.read_u32::<BigEndian>()?;
+            imports.push(RelImport { module_id, offset, relocations: 0 });
+        }
+        for import in &mut imports {
+            import.relocations = count_relocations(&mut *file, import, size)?;
+        }
+
+        Ok(RelHeader {
+            id,
+            version,
+            name_offset,
+            name_size,
+            sections,
+            bss_size,
+            relocation_offset,
+            imports,
+            prolog: (prolog_section, prolog),
+            epilog: (epilog_section, epilog),
+            unresolved: (unresolved_section, unresolved),
+            alignment,
+            bss_alignment,
+            fix_size,
+            size,
+        })
+    }
+
+    pub fn section(&self, index: usize) -> Option<&RelSection> {
+        self.sections.get(index)
+    }
+
+    // The sections with code in them, for when the REL is at `file_offset`
+    // on the ROM and loaded to `base` in memory. `name` is what to call the
+    // REL in their names.
+    pub fn code_sections(&self, name: &str, file_offset: u64, base: u64) -> Vec<CodeSection> {
+        self.sections.iter()
+            .filter(|s| s.executable && !s.is_bss() && s.size > 0)
+            .map(|s| self.code_section(s, name, file_offset, base))
+            .collect()
+    }
+
+    pub fn code_section(&self, section: &RelSection, name: &str, file_offset: u64, base: u64) -> CodeSection {
+        CodeSection {
+            name: format!("{}:{}", name, section.index),
+            offset: file_offset + section.offset,
+            size: section.size,
+            load_address: base + section.offset,
+        }
+    }
+
+    pub fn print_info(&self, style: NumberStyle) {
+        println!("Module ID: {}", self.id);
+        println!("Version: {}", self.version);
+        println!("Size: {} bytes", format_u64(self.size, style));
+        println!("Name: {} bytes at {} in the .str file", self.name_size, format_u64(self.name_offset as u64, style));
+        println!("Sections:");
+        for s in &self.sections {
+            let kind = if s.is_bss() {
+                "bss"
+            } else if s.size == 0 {
+                "unused"
+            } else if s.executable {
+                "code"
+            } else {
+                "data"
+            };
+            println!(
+                "  {:>2}: {:<6} offset {}, {} bytes",
+                s.index,
+                kind,
+                format_u64(s.offset, style),
+                format_usize(s.size, style),
+            );
+        }
+        println!("BSS size: {} bytes", format_u64(self.bss_size as u64, style));
+        for (name, (section, offset)) in [("Prolog", self.prolog), ("Epilog", self.epilog), ("Unresolved", self.unresolved)] {
+            if section == 0 {
+                println!("{}: none", name);
+            } else {
+                println!("{}: section {} + {}", name, section, format_u64(offset as u64, style));
+            }
+        }
+        if let (Some(alignment), Some(bss_alignment)) = (self.alignment, self.bss_alignment) {
+            println!("Alignment: {}", format_u64(alignment as u64, style));
+            println!("BSS alignment: {}", format_u64(bss_alignment as u64, style));
+        }
+        if let Some(fix_size) = self.fix_size {
+            println!("Fix size: {}", format_u64(fix_size as u64, style));
+        }
+        println!("Relocations start at: {}", format_u64(self.relocation_offset as u64, style));
+        println!("Imports:");
+        for i in &self.imports {
+            let module = if i.module_id == 0 { "the DOL".to_string() } else { format!("module {}", i.module_id) };
+            println!("  {}: {} relocations at {}", module, i.relocations, format_u64(i.offset as u64, style));
+        }
+    }
+}
+
+// Relocations go until an R_DOLPHIN_END, which has to be before the end of
+// the file
+fn count_relocations(mut file: impl Read + Seek, import: &RelImport, size: u64) -> io::Result<usize> {
+    let mut offset = import.offset as u64;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut count = 0;
+    loop {
+        if offset + RELOCATION_ENTRY_SIZE > size {
+            return Err(invalid(format!(
+                "The REL's relocations for module {} go past the end of the file.",
+                import.module_id,
+            )));
+        }
+        let mut entry = [0; RELOCATION_ENTRY_SIZE as usize];
+        file.read_exact(&mut entry)?;
+        if entry[2] == R_DOLPHIN_END {
+            return Ok(count);
+        }
+        count += 1;
+        offset += RELOCATION_ENTRY_SIZE;
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -1,6 +1,17 @@
 use crate::NumberStyle;
+use crate::extract_section;
+use crate::hexdump::hexdump;
+use crate::sections::apploader::Apploader;
+use crate::sections::dol::DOLHeader;
+use crate::sections::dol::segment::Segment;
+use crate::sections::fst::FST;
+use crate::sections::fst::entry::{FileEntry, ENTRY_SIZE};
+use crate::sections::header::Header;
+use crate::source::ImageSource;
 use std::cmp::Ordering::*;
 use std::cmp::Ordering;
+use std::io::{self, Read, SeekFrom, Write};
+use std::ops::Range;
 
 pub trait Section {
     fn print_info(&self, style: NumberStyle);
@@ -9,12 +20,53 @@ pub trait Section {
 
     fn size(&self) -> usize;
 
+    // What to call this in listings, like "Start.dol" or a file's path
+    fn name(&self) -> String;
+
+    // What kind of section this is, like "dol" or "file"
+    fn section_type(&self) -> &'static str;
+
+    // The section as what it really is, for details that aren't in this
+    // trait, like a file's FST index
+    fn resolve(&self) -> ResolvedSection<'_>;
+
+    // How far `offset` on the ROM is into the section
+    fn relative_offset(&self, offset: u64) -> u64 {
+        offset.saturating_sub(self.start())
+    }
+
+    // The last byte. Empty sections don't have one, so this is just before
+    // their start.
     fn end(&self) -> u64 {
-        self.start() + self.size() as u64 - 1
+        (self.start() + self.size() as u64).saturating_sub(1)
     }
 
+    // Copies the whole section out of `iso`. This takes trait objects so it
+    // can be called on the sections in a `ROMLayout`.
+    fn extract(&self, iso: &mut dyn ImageSource, output: &mut dyn Write) -> io::Result<()> {
+        iso.seek(SeekFrom::Start(self.start()))?;
+        extract_section(iso, self.size(), output)
+    }
+
+    // Writes a hex dump of the section, or just `range` of it, with offsets
+    // from the start of the section. A range that goes past the end stops at
+    // the end.
+    fn hexdump(
+        &self,
+        iso: &mut dyn ImageSource,
+        output: &mut dyn Write,
+        range: Option<Range<u64>>,
+        style: NumberStyle,
+    ) -> io::Result<()> {
+        let size = self.size() as u64;
+        let range = range.map_or(0..size, |r| r.start.min(size)..r.end.min(size));
+        iso.seek(SeekFrom::Start(self.start() + range.start))?;
+        hexdump(iso.take(range.end.saturating_sub(range.start)), output, range.start, style)
+    }
+
+    // Empty sections never contain `offset`
     fn compare_offset(&self, offset: u64) -> Ordering {
-        if self.end() < offset {
+        if self.start() + self.size() as u64 <= offset {
             Less
         } else if self.start() > offset {
             Greater
@@ -23,3 +75,50 @@ pub trait Section {
         }
     }
 }
+
+// What `Section::resolve` and `ROMLayout::resolve` give back
+#[derive(Clone, Copy)]
+pub enum ResolvedSection<'a> {
+    Header(&'a Header),
+    Apploader(&'a Apploader),
+    Dol(&'a DOLHeader),
+    Segment(&'a Segment),
+    Fst(&'a FST),
+    File(&'a FileEntry),
+}
+
+impl<'a> ResolvedSection<'a> {
+    pub fn section(self) -> &'a dyn Section {
+        match self {
+            ResolvedSection::Header(h) => h,
+            ResolvedSection::Apploader(a) => a,
+            ResolvedSection::Dol(d) => d,
+            ResolvedSection::Segment(s) => s,
+            ResolvedSection::Fst(f) => f,
+            ResolvedSection::File(f) => f,
+        }
+    }
+
+    // The FST entry at `offset` on the ROM. That's the file's own entry, or
+    // the one whose 12 bytes are there in Game.toc, but not for its string
+    // table.
+    pub fn fst_index(self, offset: u64) -> Option<usize> {
+        match self {
+            ResolvedSection::File(f) => Some(f.info.index),
+            ResolvedSection::Fst(fst) => {
+                let index = (fst.relative_offset(offset) / ENTRY_SIZE as u64) as usize;
+                Some(index).filter(|&i| i < fst.entries.len())
+            },
+            _ => None,
+        }
+    }
+
+    // Where the byte at `offset` on the ROM is loaded in memory, for DOL
+    // segments
+    pub fn address(self, offset: u64) -> Option<u64> {
+        match self {
+            ResolvedSection::Segment(s) => Some(s.loading_address + s.relative_offset(offset)),
+            _ => None,
+        }
+    }
+}
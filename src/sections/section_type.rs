@@ -0,0 +1,154 @@
+// What a name given to `extract --section` and the like turned out to mean.
+// The system files can be called by a few names each: where they're
+// extracted to, just their file name, a short name, or what other tools call
+// them when they extract a ROM, like Dolphin's `sys/main.dol`.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use paths::*;
+use sections::dol::segment::SegmentType;
+use AppError;
+
+// The sections there's only one of on a ROM
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum UniqueSectionType {
+    Header,
+    Apploader,
+    Dol,
+    Fst,
+}
+
+impl UniqueSectionType {
+    pub const ALL: [UniqueSectionType; 4] = [
+        UniqueSectionType::Header,
+        UniqueSectionType::Apploader,
+        UniqueSectionType::Dol,
+        UniqueSectionType::Fst,
+    ];
+
+    // Every name the section goes by, ignoring case. The first one is where
+    // it's extracted to.
+    pub fn aliases(self) -> &'static [&'static str] {
+        match self {
+            UniqueSectionType::Header => &[HEADER_PATH, "ISO.hdr", "header"],
+            UniqueSectionType::Apploader =>
+                &[APPLOADER_PATH, "Apploader.ldr", "apploader", "sys/apploader.img", "apploader.img"],
+            UniqueSectionType::Dol => &[DOL_PATH, "Start.dol", "dol", "sys/main.dol", "main.dol"],
+            UniqueSectionType::Fst => &[FST_PATH, "Game.toc", "fst", "sys/fst.bin", "fst.bin"],
+        }
+    }
+
+    pub fn path(self) -> &'static str {
+        self.aliases()[0]
+    }
+}
+
+impl FromStr for UniqueSectionType {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<UniqueSectionType, AppError> {
+        let s = s.replace('\\', "/");
+        UniqueSectionType::ALL.iter()
+            .find(|t| t.aliases().iter().any(|a| a.eq_ignore_ascii_case(&s)))
+            .cloned()
+            .ok_or_else(|| AppError::new(format!("{} isn't a system file", s)))
+    }
+}
+
+impl fmt::Display for UniqueSectionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path())
+    }
+}
+
+// Which kind of section a name matched, and which one
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum SectionType {
+    System(UniqueSectionType),
+    File(PathBuf),
+    // `files` is how many files were in it
+    Directory { path: PathBuf, files: usize },
+    Segment(SegmentType, u64),
+    // Section `index` of the REL at `path`
+    RelSection { path: PathBuf, index: usize },
+}
+
+impl fmt::Display for SectionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SectionType::System(t) => write!(f, "{}", t),
+            SectionType::File(path) => write!(f, "{}", path.display()),
+            SectionType::Directory { path, .. } => write!(f, "{}", path.display()),
+            SectionType::Segment(t, n) => write!(f, "{}", t.to_string(*n)),
+            SectionType::RelSection { path, index } => write!(f, "{}:{}", path.display(), index),
+        }
+    }
+}
+
+// What came of extracting a section by its name
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ExtractOutcome {
+    Extracted(SectionType),
+    NotFound,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::UniqueSectionType::*;
+
+    #[test]
+    fn every_alias_parses() {
+        let names: &[(&str, UniqueSectionType)] = &[
+            ("&&systemdata/ISO.hdr", Header),
+            ("ISO.hdr", Header),
+            ("header", Header),
+            ("&&systemdata/Apploader.ldr", Apploader),
+            ("Apploader.ldr", Apploader),
+            ("apploader", Apploader),
+            ("sys/apploader.img", Apploader),
+            ("apploader.img", Apploader),
+            ("&&systemdata/Start.dol", Dol),
+            ("Start.dol", Dol),
+            ("dol", Dol),
+            ("sys/main.dol", Dol),
+            ("main.dol", Dol),
+            ("&&systemdata/Game.toc", Fst),
+            ("Game.toc", Fst),
+            ("fst", Fst),
+            ("sys/fst.bin", Fst),
+            ("fst.bin", Fst),
+        ];
+        for &(name, t) in names {
+            assert_eq!(name.parse::<UniqueSectionType>().ok(), Some(t), "{}", name);
+        }
+        // And that's all of them
+        let count = UniqueSectionType::ALL.iter().map(|t| t.aliases().len()).sum::<usize>();
+        assert_eq!(names.len(), count);
+    }
+
+    #[test]
+    fn aliases_ignore_case_and_slashes() {
+        assert_eq!("iso.HDR".parse::<UniqueSectionType>().ok(), Some(Header));
+        assert_eq!("SYS\\Main.dol".parse::<UniqueSectionType>().ok(), Some(Dol));
+        assert_eq!("&&SystemData\\game.toc".parse::<UniqueSectionType>().ok(), Some(Fst));
+    }
+
+    #[test]
+    fn other_names_are_errors() {
+        for name in &["", "Start", "Start.dol.bak", "/Start.dol", "sys/Start.dol", "text0", "files/fst.bin"] {
+            assert!(name.parse::<UniqueSectionType>().is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn the_first_alias_is_the_path() {
+        for &t in &UniqueSectionType::ALL {
+            assert_eq!(t.path().parse::<UniqueSectionType>().ok(), Some(t));
+            assert!(t.path().starts_with("&&systemdata/"));
+            assert_eq!(t.to_string(), t.path());
+        }
+    }
+}
@@ -0,0 +1,122 @@
+// An extracted ROM's &&systemdata, read like it was the image it came from.
+// Each system file is where the header says it goes, and everything else,
+// like the files in the FST, reads as zeros, so only what's in the system
+// data (the header, apploader, DOL, and FST) can come from it.
+
+use std::cmp::max;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use paths::{APPLOADER_PATH, DOL_PATH, FST_PATH, HEADER_PATH};
+use sections::apploader::APPLOADER_OFFSET;
+use sections::header::Header;
+use ROM_SIZE;
+
+use super::ImageSource;
+
+struct SystemFiles {
+    // (where it'd be on the ROM, how big it is, the file), in the order
+    // they're on the ROM
+    files: Vec<(u64, u64, File)>,
+    len: u64,
+    pos: u64,
+}
+
+impl Read for SystemFiles {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() { return Ok(0) }
+        let pos = self.pos;
+        let max = (buf.len() as u64).min(self.len - pos);
+        let n = match self.files.iter_mut().find(|(start, size, _)| pos < start + size) {
+            Some((start, size, file)) if *start <= pos => {
+                let max = max.min(*start + *size - pos) as usize;
+                file.seek(SeekFrom::Start(pos - *start))?;
+                file.read(&mut buf[..max])?
+            },
+            // Zeros up to the next file, or the end
+            next => {
+                let max = next.map_or(max, |(start, _, _)| max.min(*start - pos)) as usize;
+                buf[..max].fill(0);
+                max
+            },
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for SystemFiles {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(p) => self.len.checked_add_signed(p),
+            SeekFrom::Current(p) => self.pos.checked_add_signed(p),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Can't seek before the start of the image.",
+        ))?;
+        Ok(self.pos)
+    }
+}
+
+pub struct SystemDataImage {
+    files: BufReader<SystemFiles>,
+}
+
+impl SystemDataImage {
+    // Reads the system data in `root/&&systemdata`. The image is as big as a
+    // whole ROM, unless the system files go past that.
+    pub fn open(root: impl AsRef<Path>) -> io::Result<SystemDataImage> {
+        let root = root.as_ref();
+        let open = |path: &str| File::open(root.join(path)).map_err(|e| io::Error::new(
+            e.kind(),
+            format!("Couldn't open {}: {}", root.join(path).display(), e),
+        ));
+        let header_file = open(HEADER_PATH)?;
+        let header = Header::new(BufReader::new(&header_file), 0)?;
+        let mut files = Vec::new();
+        for (offset, file) in [
+            (0, header_file),
+            (APPLOADER_OFFSET, open(APPLOADER_PATH)?),
+            (header.dol_offset, open(DOL_PATH)?),
+            (header.fst_offset, open(FST_PATH)?),
+        ] {
+            files.push((offset, file.metadata()?.len(), file));
+        }
+        files.sort_by_key(|&(offset, _, _)| offset);
+        let len = files.iter().map(|(offset, size, _)| offset + size).fold(ROM_SIZE as u64, max);
+        Ok(SystemDataImage {
+            files: BufReader::new(SystemFiles { files, len, pos: 0 }),
+        })
+    }
+}
+
+impl Read for SystemDataImage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.files.read(buf)
+    }
+}
+
+impl BufRead for SystemDataImage {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.files.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.files.consume(amt)
+    }
+}
+
+impl Seek for SystemDataImage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.files.seek(pos)
+    }
+}
+
+impl ImageSource for SystemDataImage {
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.files.get_ref().len)
+    }
+}
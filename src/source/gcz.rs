@@ -0,0 +1,411 @@
+// GCZ files, Dolphin's compressed format. The image is split into blocks of
+// the same size, and each one is compressed with zlib on its own, or stored
+// as-is if that doesn't make it smaller, so any block can be read without the
+// ones before it. After the header there's a table of where each block is,
+// and one of their Adler-32 checksums.
+// https://github.com/dolphin-emu/dolphin/blob/master/Source/Core/DiscIO/CompressedBlob.h
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use super::ImageSource;
+
+const GCZ_MAGIC: u32 = 0xB10BC001;
+// The magic word, the sub type, the compressed and uncompressed sizes, the
+// block size, and the number of blocks
+const GCZ_HEADER_SIZE: u64 = 32;
+// Each block has a pointer and a checksum
+const BLOCK_TABLE_ENTRY_SIZE: u64 = 12;
+// The sub type of GameCube discs. Wii discs are 1.
+const GAMECUBE_SUB_TYPE: u32 = 0;
+// Set on the pointers of blocks that are stored uncompressed
+const UNCOMPRESSED_FLAG: u64 = 1 << 63;
+
+// What Dolphin uses by default
+pub const DEFAULT_BLOCK_SIZE: u32 = 0x4000;
+// Dolphin won't open GCZ files with blocks bigger than this
+const MAX_BLOCK_SIZE: u32 = 0x4000000;
+
+pub struct GczImage<R> {
+    blocks: BufReader<Blocks<R>>,
+}
+
+impl GczImage<File> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<GczImage<File>> {
+        GczImage::new(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> GczImage<R> {
+    pub fn new(mut file: R) -> io::Result<GczImage<R>> {
+        let mut header = [0; GCZ_HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        if LittleEndian::read_u32(&header[0..]) != GCZ_MAGIC {
+            return Err(invalid("This isn't a GCZ file."));
+        }
+        let compressed_size = LittleEndian::read_u64(&header[8..]);
+        let len = LittleEndian::read_u64(&header[16..]);
+        let block_size = LittleEndian::read_u32(&header[24..]);
+        let block_count = LittleEndian::read_u32(&header[28..]) as u64;
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE || block_count != len.div_ceil(block_size as u64) {
+            return Err(invalid("The GCZ header has an invalid block size or count."));
+        }
+
+        // The tables are read all at once, so a header with a huge block
+        // count can't be allowed to ask for more memory than the file has
+        // bytes
+        let file_len = file.seek(SeekFrom::End(0))?;
+        let tables_size = block_count.checked_mul(BLOCK_TABLE_ENTRY_SIZE)
+            .filter(|&size| size <= file_len.saturating_sub(GCZ_HEADER_SIZE))
+            .ok_or_else(|| invalid("The GCZ file is too small for its block tables."))?;
+        if compressed_size > file_len - GCZ_HEADER_SIZE - tables_size {
+            return Err(invalid("The GCZ file is too small for the compressed size in its header."));
+        }
+        file.seek(SeekFrom::Start(GCZ_HEADER_SIZE))?;
+        let mut tables = vec![0; tables_size as usize];
+        file.read_exact(&mut tables)?;
+        let (pointers, hashes) = tables.split_at(block_count as usize * 8);
+        let pointers = pointers.chunks(8).map(LittleEndian::read_u64).collect::<Vec<_>>();
+        let hashes = hashes.chunks(4).map(LittleEndian::read_u32).collect();
+        Ok(GczImage {
+            blocks: BufReader::new(Blocks {
+                file,
+                block_size: block_size as u64,
+                data_offset: GCZ_HEADER_SIZE + block_count * BLOCK_TABLE_ENTRY_SIZE,
+                compressed_size,
+                pointers,
+                hashes,
+                len,
+                pos: 0,
+                block: None,
+                data: Vec::new(),
+                decompressor: Decompress::new(true),
+            }),
+        })
+    }
+}
+
+// Reads the image a block at a time, keeping the last block that was read
+// decompressed in `data`
+struct Blocks<R> {
+    file: R,
+    block_size: u64,
+    // Where the first block starts, which the pointers are relative to
+    data_offset: u64,
+    compressed_size: u64,
+    pointers: Vec<u64>,
+    hashes: Vec<u32>,
+    len: u64,
+    pos: u64,
+    block: Option<usize>,
+    data: Vec<u8>,
+    decompressor: Decompress,
+}
+
+impl<R: Read + Seek> Blocks<R> {
+    fn load_block(&mut self, block: usize) -> io::Result<()> {
+        if self.block == Some(block) { return Ok(()) }
+        self.block = None;
+        let start = self.pointers[block] & !UNCOMPRESSED_FLAG;
+        let end = self.pointers.get(block + 1).map_or(self.compressed_size, |p| p & !UNCOMPRESSED_FLAG);
+        if end < start || end - start > self.block_size {
+            return Err(invalid(&format!("Block {} of the GCZ file has an invalid size.", block)));
+        }
+        let mut stored = vec![0; (end - start) as usize];
+        self.file.seek(SeekFrom::Start(self.data_offset + start))?;
+        self.file.read_exact(&mut stored)?;
+        if adler32(&stored) != self.hashes[block] {
+            return Err(invalid(&format!("Block {} of the GCZ file doesn't match its checksum.", block)));
+        }
+
+        if self.pointers[block] & UNCOMPRESSED_FLAG != 0 {
+            self.data = stored;
+        } else {
+            self.data.clear();
+            self.data.reserve(self.block_size as usize);
+            self.decompressor.reset(true);
+            let status = self.decompressor.decompress_vec(&stored, &mut self.data, FlushDecompress::Finish)
+                .map_err(|e| invalid(&format!("Block {} of the GCZ file couldn't be decompressed: {}", block, e)))?;
+            if status != Status::StreamEnd || self.data.len() as u64 > self.block_size {
+                return Err(invalid(&format!("Block {} of the GCZ file is bigger than the block size.", block)));
+            }
+        }
+        self.block = Some(block);
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for Blocks<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() { return Ok(0) }
+        let block = (self.pos / self.block_size) as usize;
+        let in_block = (self.pos % self.block_size) as usize;
+        self.load_block(block)?;
+        // The last block can be short
+        let available = self.data.get(in_block..).unwrap_or_default();
+        let max = (buf.len() as u64).min(available.len() as u64).min(self.len - self.pos) as usize;
+        if max == 0 {
+            return Err(invalid(&format!("Block {} of the GCZ file is too short.", block)));
+        }
+        buf[..max].copy_from_slice(&available[..max]);
+        self.pos += max as u64;
+        Ok(max)
+    }
+}
+
+impl<R> Seek for Blocks<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(p) => self.len.checked_add_signed(p),
+            SeekFrom::Current(p) => self.pos.checked_add_signed(p),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Can't seek before the start of the image.",
+        ))?;
+        Ok(self.pos)
+    }
+}
+
+impl<R: Read + Seek> Read for GczImage<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.blocks.read(buf)
+    }
+}
+
+impl<R: Read + Seek> BufRead for GczImage<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.blocks.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.blocks.consume(amt)
+    }
+}
+
+impl<R: Read + Seek> Seek for GczImage<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.blocks.seek(pos)
+    }
+}
+
+impl<R: Read + Seek> ImageSource for GczImage<R> {
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.blocks.get_ref().len)
+    }
+}
+
+// Compresses an image as it's written. How big the image is has to be known up
+// front, so there's room for the block tables before the first block. They're
+// written with the header by `finish`, which has to be called once all `len`
+// bytes have been written.
+pub struct GczWriter<W> {
+    output: W,
+    block_size: usize,
+    len: u64,
+    written: u64,
+    // The block that's being filled. Blocks are only written once they're
+    // full.
+    block: Vec<u8>,
+    pointers: Vec<u64>,
+    hashes: Vec<u32>,
+    compressed_size: u64,
+    compressor: Compress,
+    compressed: Vec<u8>,
+    // Images are mostly padding, so a block of zeros is kept as it was
+    // stored, instead of compressing it again every time
+    zero_block: Option<(u64, Vec<u8>)>,
+}
+
+impl<W: Write + Seek> GczWriter<W> {
+    pub fn new(mut output: W, len: u64, block_size: u32) -> io::Result<GczWriter<W>> {
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid GCZ block size."));
+        }
+        let block_count = len.div_ceil(block_size as u64);
+        output.seek(SeekFrom::Start(GCZ_HEADER_SIZE + block_count * BLOCK_TABLE_ENTRY_SIZE))?;
+        Ok(GczWriter {
+            output,
+            block_size: block_size as usize,
+            len,
+            written: 0,
+            block: Vec::with_capacity(block_size as usize),
+            pointers: Vec::with_capacity(block_count as usize),
+            hashes: Vec::with_capacity(block_count as usize),
+            compressed_size: 0,
+            compressor: Compress::new(Compression::default(), true),
+            compressed: Vec::with_capacity(block_size as usize),
+            zero_block: None,
+        })
+    }
+
+    // Writes the last block, then the header and block tables, and gives
+    // back the output
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.written != self.len {
+            return Err(io::Error::other(format!(
+                "Only {} of the image's {} bytes were written.", self.written, self.len,
+            )));
+        }
+        // Dolphin expects every block to be full size, so the last one is
+        // padded with zeros
+        if !self.block.is_empty() {
+            self.block.resize(self.block_size, 0);
+            self.write_block()?;
+        }
+
+        let mut header = Vec::with_capacity(GCZ_HEADER_SIZE as usize + self.pointers.len() * 12);
+        header.write_u32::<LittleEndian>(GCZ_MAGIC)?;
+        header.write_u32::<LittleEndian>(GAMECUBE_SUB_TYPE)?;
+        header.write_u64::<LittleEndian>(self.compressed_size)?;
+        header.write_u64::<LittleEndian>(self.len)?;
+        header.write_u32::<LittleEndian>(self.block_size as u32)?;
+        header.write_u32::<LittleEndian>(self.pointers.len() as u32)?;
+        for &p in &self.pointers {
+            header.write_u64::<LittleEndian>(p)?;
+        }
+        for &h in &self.hashes {
+            header.write_u32::<LittleEndian>(h)?;
+        }
+        self.output.seek(SeekFrom::Start(0))?;
+        self.output.write_all(&header)?;
+        self.output.flush()?;
+        Ok(self.output)
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        let is_zeros = self.block.iter().all(|&b| b == 0);
+        let (flag, stored) = match &self.zero_block {
+            Some((flag, stored)) if is_zeros => (*flag, stored.clone()),
+            _ => {
+                self.compressed.clear();
+                self.compressor.reset();
+                let status = self.compressor.compress_vec(&self.block, &mut self.compressed, FlushCompress::Finish)
+                    .map_err(io::Error::other)?;
+                // It only doesn't finish if it didn't fit in the space for
+                // the uncompressed block, in which case it's stored as-is
+                if status == Status::StreamEnd && self.compressed.len() < self.block.len() {
+                    (0, self.compressed.clone())
+                } else {
+                    (UNCOMPRESSED_FLAG, self.block.clone())
+                }
+            },
+        };
+        if is_zeros {
+            self.zero_block = Some((flag, stored.clone()));
+        }
+
+        self.output.write_all(&stored)?;
+        self.pointers.push(self.compressed_size | flag);
+        self.hashes.push(adler32(&stored));
+        self.compressed_size += stored.len() as u64;
+        self.block.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Write for GczWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() { return Ok(0) }
+        if self.written >= self.len {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "The image is already as big as it was meant to be."));
+        }
+        let max = buf.len()
+            .min(self.block_size - self.block.len())
+            .min((self.len - self.written) as usize);
+        self.block.extend_from_slice(&buf[..max]);
+        self.written += max as u64;
+        if self.block.len() == self.block_size {
+            self.write_block()?;
+        }
+        Ok(max)
+    }
+
+    // Partly filled blocks aren't written until they're full, or the image
+    // is finished
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// What GCZ files checksum each block with
+// https://datatracker.ietf.org/doc/html/rfc1950#section-9
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    // The most bytes that can be summed before `b` could overflow
+    const NMAX: usize = 5552;
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(NMAX) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn gcz(data: &[u8], block_size: u32) -> Vec<u8> {
+        let mut writer = GczWriter::new(Cursor::new(Vec::new()), data.len() as u64, block_size).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn error(file: Vec<u8>) -> io::Error {
+        GczImage::new(Cursor::new(file)).err().expect("the GCZ file was opened")
+    }
+
+    #[test]
+    fn images_round_trip() {
+        let mut data = vec![0; 0x10000];
+        data.extend((0..0x9000u32).map(|i| (i * 31 / 7) as u8));
+        let mut image = GczImage::new(Cursor::new(gcz(&data, 0x4000))).unwrap();
+        assert_eq!(image.len().unwrap(), data.len() as u64);
+        let mut read = Vec::new();
+        image.read_to_end(&mut read).unwrap();
+        assert!(read == data);
+    }
+
+    #[test]
+    fn huge_block_counts_are_errors() {
+        // Billions of one byte blocks, which would be 48 GiB of tables
+        let mut file = gcz(&[1, 2, 3], 1);
+        LittleEndian::write_u64(&mut file[16..], u32::MAX as u64);
+        LittleEndian::write_u32(&mut file[28..], u32::MAX);
+        assert_eq!(error(file).kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_tables_are_errors() {
+        let file = gcz(&vec![7; 0x20000], 0x4000);
+        let tables_end = (GCZ_HEADER_SIZE + 8 * BLOCK_TABLE_ENTRY_SIZE) as usize;
+        assert_eq!(error(file[..tables_end - 1].to_vec()).kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error(file[..GCZ_HEADER_SIZE as usize].to_vec()).kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn compressed_sizes_past_the_end_are_errors() {
+        let mut file = gcz(&vec![7; 0x8000], 0x4000);
+        let compressed_size = LittleEndian::read_u64(&file[8..]);
+        LittleEndian::write_u64(&mut file[8..], compressed_size + 1);
+        assert_eq!(error(file.clone()).kind(), io::ErrorKind::InvalidData);
+        LittleEndian::write_u64(&mut file[8..], u64::MAX);
+        assert_eq!(error(file).kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,210 @@
+use std::cmp::min;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+
+use super::ImageSource;
+
+// 64KiB
+const BLOCK_SIZE: u64 = 64 * 1024;
+// So at most 4MiB is kept in memory
+const CACHED_BLOCKS: usize = 64;
+
+struct Response {
+    status: u16,
+    // The names are lowercase
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| &v[..])
+    }
+}
+
+// Reads an image from an HTTP server that supports range requests, a block
+// at a time. Only plain http is supported.
+pub struct HttpImage {
+    host: String,
+    path: String,
+    len: u64,
+    pos: u64,
+    // (block index, data), the most recently used block is last
+    cache: Vec<(u64, Vec<u8>)>,
+    pub bytes_fetched: u64,
+    pub requests: usize,
+}
+
+impl HttpImage {
+    pub fn open(url: &str) -> io::Result<HttpImage> {
+        let rest = url.strip_prefix("http://")
+            .ok_or_else(|| invalid_data(format!("{} isn't an http:// url", url)))?;
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+
+        let mut image = HttpImage {
+            host: host.to_string(),
+            path: path.to_string(),
+            len: 0,
+            pos: 0,
+            cache: Vec::with_capacity(CACHED_BLOCKS),
+            bytes_fetched: 0,
+            requests: 0,
+        };
+        image.len = image.fetch_len()?;
+        Ok(image)
+    }
+
+    fn fetch_len(&mut self) -> io::Result<u64> {
+        let response = self.request("HEAD", None)?;
+        if response.status != 200 {
+            return Err(invalid_data(format!("HEAD {} returned {}", self.path, response.status)));
+        }
+        if let Some(len) = response.header("content-length").and_then(|l| l.parse().ok()) {
+            return Ok(len);
+        }
+
+        // Fall back to asking for a single byte and reading the total size
+        // from the Content-Range header
+        self.request("GET", Some((0, 0)))?
+            .header("content-range")
+            .and_then(|r| r.rsplit('/').next())
+            .and_then(|l| l.parse().ok())
+            .ok_or_else(|| invalid_data(format!("couldn't get the size of {}", self.path)))
+    }
+
+    fn request(&mut self, method: &str, range: Option<(u64, u64)>) -> io::Result<Response> {
+        let address = if self.host.contains(':') {
+            self.host.clone()
+        } else {
+            format!("{}:80", self.host)
+        };
+        let mut stream = TcpStream::connect(address)?;
+
+        write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", method, self.path, self.host)?;
+        if let Some((start, end)) = range {
+            write!(stream, "Range: bytes={}-{}\r\n", start, end)?;
+        }
+        write!(stream, "\r\n")?;
+        self.requests += 1;
+
+        let mut response = BufReader::new(stream);
+        let mut line = String::new();
+        response.read_line(&mut line)?;
+        let status = line.split_whitespace().nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid_data(format!("invalid response: {}", line.trim_end())))?;
+
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if response.read_line(&mut line)? == 0 { break }
+            let l = line.trim_end();
+            if l.is_empty() { break }
+            if let Some(i) = l.find(':') {
+                headers.push((l[..i].to_lowercase(), l[i + 1..].trim().to_string()));
+            }
+        }
+
+        let mut result = Response { status, headers, body: Vec::new() };
+        if method != "HEAD" {
+            if result.header("transfer-encoding").is_some_and(|e| e.contains("chunked")) {
+                return Err(invalid_data("chunked responses aren't supported".to_string()));
+            }
+            match result.header("content-length").and_then(|l| l.parse().ok()) {
+                Some(len) => { response.take(len).read_to_end(&mut result.body)?; },
+                None => { response.read_to_end(&mut result.body)?; },
+            }
+            self.bytes_fetched += result.body.len() as u64;
+        }
+
+        Ok(result)
+    }
+
+    // Returns the index of the block in the cache, fetching it if necessary
+    fn block(&mut self, index: u64) -> io::Result<usize> {
+        if let Some(i) = self.cache.iter().position(|&(b, _)| b == index) {
+            let block = self.cache.remove(i);
+            self.cache.push(block);
+            return Ok(self.cache.len() - 1);
+        }
+
+        let start = index * BLOCK_SIZE;
+        let end = min(start + BLOCK_SIZE, self.len) - 1;
+        let response = self.request("GET", Some((start, end)))?;
+        // A 200 means the whole image is being sent, which is exactly what
+        // this is trying to avoid.
+        if response.status != 206 {
+            return Err(invalid_data(format!(
+                "GET {} returned {}, the server needs to support range requests",
+                self.path,
+                response.status,
+            )));
+        }
+        let data = response.body;
+        if data.len() as u64 != end - start + 1 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the server sent a short block"));
+        }
+
+        if self.cache.len() == CACHED_BLOCKS {
+            self.cache.remove(0);
+        }
+        self.cache.push((index, data));
+        Ok(self.cache.len() - 1)
+    }
+}
+
+impl Read for HttpImage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let data = self.fill_buf()?;
+            let n = min(data.len(), buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for HttpImage {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.len {
+            return Ok(&[]);
+        }
+        let i = self.block(self.pos / BLOCK_SIZE)?;
+        let offset = (self.pos % BLOCK_SIZE) as usize;
+        Ok(&self.cache[i].1[offset..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+    }
+}
+
+impl Seek for HttpImage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(p) => self.len.checked_add_signed(p),
+            SeekFrom::Current(p) => self.pos.checked_add_signed(p),
+        };
+        self.pos = new_pos.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative position",
+        ))?;
+        Ok(self.pos)
+    }
+}
+
+impl ImageSource for HttpImage {
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.len)
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,54 @@
+use std::fs::File;
+use std::io::{self, BufRead, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use super::ImageSource;
+
+// Reads an image through a memory map instead of read calls. `fill_buf` hands
+// back everything from the current position to the end of the image, so
+// anything that checks the buffer first (like extracting a file) can write
+// straight from the map without copying it in chunks.
+pub struct MmapDisc {
+    map: Cursor<Mmap>,
+}
+
+impl MmapDisc {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<MmapDisc> {
+        let file = File::open(path)?;
+        // This is only unsafe if the file changes while it's mapped. gcmod
+        // never writes to an image it's reading, and other gcmod commands
+        // take a lock on the paths they write to.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(MmapDisc { map: Cursor::new(map) })
+    }
+}
+
+impl Read for MmapDisc {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.map.read(buf)
+    }
+}
+
+impl BufRead for MmapDisc {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.map.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.map.consume(amt)
+    }
+}
+
+impl Seek for MmapDisc {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.map.seek(pos)
+    }
+}
+
+impl ImageSource for MmapDisc {
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.map.get_ref().len() as u64)
+    }
+}
@@ -0,0 +1,159 @@
+pub mod dir;
+#[cfg(feature = "gcz")]
+pub mod gcz;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod split;
+pub mod wbfs;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Anything a ROM can be read from. Everything that reads a ROM (Game::open,
+// extracting, and so on) only needs BufRead + Seek, so a source that isn't a
+// local file just has to implement those, and keep seeks cheap.
+pub trait ImageSource: BufRead + Seek {
+    // The size of the whole image in bytes
+    fn len(&mut self) -> io::Result<u64>;
+
+    fn is_empty(&mut self) -> io::Result<bool> {
+        self.len().map(|l| l == 0)
+    }
+}
+
+impl ImageSource for BufReader<File> {
+    fn len(&mut self) -> io::Result<u64> {
+        self.get_ref().metadata().map(|m| m.len())
+    }
+}
+
+impl<T: AsRef<[u8]>> ImageSource for Cursor<T> {
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.get_ref().as_ref().len() as u64)
+    }
+}
+
+impl<S: ImageSource + ?Sized> ImageSource for Box<S> {
+    fn len(&mut self) -> io::Result<u64> {
+        (**self).len()
+    }
+}
+
+// Opens a local file, or a URL if the http feature is enabled. With the mmap
+// feature, local files are memory-mapped if they can be, and read normally if
+// not. If `path` is the first part of a split image, like `game.part0.iso`,
+// the rest of the parts are read after it, and if it's a WBFS file, its first
+// disc is read. GCZ files are decompressed as they're read, if the gcz
+// feature is enabled. A directory is read as an extracted ROM, which only has
+// its system data.
+pub fn open(path: impl AsRef<Path>) -> io::Result<Box<dyn ImageSource>> {
+    let path = path.as_ref();
+    if path.is_dir() {
+        return Ok(Box::new(dir::SystemDataImage::open(path)?));
+    }
+    if let Some(url) = path.to_str().filter(|p| p.starts_with("http://")) {
+        #[cfg(feature = "http")]
+        return Ok(Box::new(http::HttpImage::open(url)?));
+        #[cfg(not(feature = "http"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: gcmod was built without the http feature", url),
+        ));
+    }
+    if let Some(parts) = split::part_paths(path).filter(|p| p.len() > 1) {
+        return Ok(Box::new(split::SplitFileReader::open_parts(&parts)?));
+    }
+    if is_wbfs(path) {
+        return Ok(Box::new(wbfs::WbfsFile::open(path)?.into_disc(0)?));
+    }
+    if is_gcz(path) {
+        #[cfg(feature = "gcz")]
+        return Ok(Box::new(gcz::GczImage::open(path)?));
+        #[cfg(not(feature = "gcz"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: gcmod was built without the gcz feature", path.display()),
+        ));
+    }
+    #[cfg(feature = "mmap")]
+    {
+        if let Ok(disc) = mmap::MmapDisc::open(path) {
+            return Ok(Box::new(disc));
+        }
+    }
+    Ok(Box::new(BufReader::new(File::open(path)?)))
+}
+
+// How many files `open` keeps open for `path`
+pub fn handles_needed(path: impl AsRef<Path>) -> usize {
+    split::part_paths(path.as_ref()).map_or(1, |p| p.len().max(1))
+}
+
+pub fn is_wbfs(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("wbfs"))
+}
+
+pub fn is_gcz(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("gcz"))
+}
+
+// Keeps track of how much is read from a source, to make it easier to see how
+// much of an image an operation actually needs.
+pub struct CountingReader<R> {
+    inner: R,
+    pub bytes_read: u64,
+    pub reads: usize,
+    pub seeks: usize,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R) -> CountingReader<R> {
+        CountingReader {
+            inner,
+            bytes_read: 0,
+            reads: 0,
+            seeks: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes_read += n as u64;
+        self.reads += 1;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reads += 1;
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.bytes_read += amt as u64;
+        self.inner.consume(amt)
+    }
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.seeks += 1;
+        self.inner.seek(pos)
+    }
+}
+
+impl<R: ImageSource> ImageSource for CountingReader<R> {
+    fn len(&mut self) -> io::Result<u64> {
+        self.inner.len()
+    }
+}
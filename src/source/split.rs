@@ -0,0 +1,302 @@
+// Images that are split into several files, like `game.part0.iso`,
+// `game.part1.iso`, and so on, which some dumpers do so the parts fit on
+// FAT32 SD cards. The `part0` can be anywhere in the name, like
+// `game.iso.part0`, as long as it's between dots.
+
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use handles::{HandleBudget, Handles};
+
+use super::ImageSource;
+
+// Where part `n` is, given where the first part is. `None` if `first` isn't
+// named like a first part.
+fn part_path(first: &Path, n: usize) -> Option<PathBuf> {
+    let name = first.file_name()?.to_str()?;
+    let mut found = false;
+    let parts = name.split('.')
+        .map(|p| if p == "part0" && !found {
+            found = true;
+            format!("part{}", n)
+        } else {
+            p.to_string()
+        })
+        .collect::<Vec<_>>();
+    if !found { return None }
+    Some(first.with_file_name(parts.join(".")))
+}
+
+// Every part of the image, in order, if `first` is named like a first part.
+// Stops at the first part that doesn't exist.
+pub fn part_paths(first: &Path) -> Option<Vec<PathBuf>> {
+    part_path(first, 0)?;
+    Some((0..).map_while(|n| part_path(first, n).filter(|p| p.is_file())).collect())
+}
+
+// Where part `n` goes when writing split files to `path`. If `path` isn't
+// already named like a first part, the part number goes before the
+// extension, so `game.iso` is written to `game.part0.iso` and so on.
+pub fn output_part_path(path: &Path, n: usize) -> PathBuf {
+    part_path(path, n).unwrap_or_else(|| {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(ext) => format!("{}.part{}.{}", stem, n, ext.to_string_lossy()),
+            None => format!("{}.part{}", stem, n),
+        };
+        path.with_file_name(name)
+    })
+}
+
+// Reads the parts one after another, as if they were one file
+struct Parts {
+    files: Vec<File>,
+    // Where each part starts in the image
+    starts: Vec<u64>,
+    len: u64,
+    pos: u64,
+    // Which part's file is at `pos`, if any
+    current: Option<usize>,
+}
+
+impl Read for Parts {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() { return Ok(0) }
+        let i = self.starts.partition_point(|&s| s <= self.pos) - 1;
+        if self.current != Some(i) {
+            self.files[i].seek(SeekFrom::Start(self.pos - self.starts[i]))?;
+            self.current = Some(i);
+        }
+        let part_end = self.starts.get(i + 1).copied().unwrap_or(self.len);
+        let max = buf.len().min((part_end - self.pos) as usize);
+        let n = self.files[i].read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Parts {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(p) => self.len.checked_add_signed(p),
+            SeekFrom::Current(p) => self.pos.checked_add_signed(p),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Can't seek before the start of the image.",
+        ))?;
+        self.current = None;
+        Ok(self.pos)
+    }
+}
+
+pub struct SplitFileReader {
+    parts: BufReader<Parts>,
+}
+
+impl SplitFileReader {
+    // Opens the image whose first part is at `first`, finding the rest of
+    // the parts next to it
+    pub fn open(first: impl AsRef<Path>) -> io::Result<SplitFileReader> {
+        let first = first.as_ref();
+        let paths = part_paths(first).unwrap_or_else(|| vec![first.to_path_buf()]);
+        SplitFileReader::open_parts(&paths)
+    }
+
+    pub fn open_parts(paths: &[impl AsRef<Path>]) -> io::Result<SplitFileReader> {
+        let mut files = Vec::with_capacity(paths.len());
+        let mut starts = Vec::with_capacity(paths.len());
+        let mut len = 0;
+        for path in paths {
+            let file = File::open(path)?;
+            starts.push(len);
+            len += file.metadata()?.len();
+            files.push(file);
+        }
+        if files.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "There aren't any parts to read."));
+        }
+        Ok(SplitFileReader {
+            parts: BufReader::new(Parts { files, starts, len, pos: 0, current: None }),
+        })
+    }
+}
+
+impl Read for SplitFileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.parts.read(buf)
+    }
+}
+
+impl BufRead for SplitFileReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.parts.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.parts.consume(amt)
+    }
+}
+
+impl Seek for SplitFileReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.parts.seek(pos)
+    }
+}
+
+impl ImageSource for SplitFileReader {
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.parts.get_ref().len)
+    }
+}
+
+// Writes an image in parts of `part_size` bytes, starting a new one whenever
+// the last one is full. Seeking past the end of a part (like when skipping
+// padding) makes it full size, so every part but the last always is. Each
+// open part takes a handle from the budget, and if there isn't one free for
+// the next part, the others are closed, and opened again if they're needed.
+// If there still isn't one, the part's opened anyway.
+pub struct SplitFileWriter {
+    path: PathBuf,
+    part_size: u64,
+    // Every part that's been started, and its file if it's open
+    files: Vec<Option<File>>,
+    // One for each open part
+    handles: Handles,
+    pos: u64,
+    // Which part's file is at `pos`, if any
+    current: Option<usize>,
+}
+
+impl SplitFileWriter {
+    pub fn create(path: impl AsRef<Path>, part_size: u64) -> io::Result<SplitFileWriter> {
+        SplitFileWriter::create_with_handles(path, part_size, &HandleBudget::shared())
+    }
+
+    pub fn create_with_handles(
+        path: impl AsRef<Path>,
+        part_size: u64,
+        handles: &Arc<HandleBudget>,
+    ) -> io::Result<SplitFileWriter> {
+        if part_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The parts can't be empty."));
+        }
+        Ok(SplitFileWriter {
+            path: path.as_ref().to_path_buf(),
+            part_size,
+            files: Vec::new(),
+            handles: handles.reserve(0),
+            pos: 0,
+            current: None,
+        })
+    }
+
+    // Every part that's been written so far
+    pub fn part_paths(&self) -> Vec<PathBuf> {
+        (0..self.files.len()).map(|n| output_part_path(&self.path, n)).collect()
+    }
+
+    // Deletes every part, like after a failed rebuild
+    pub fn remove(self) -> io::Result<()> {
+        let paths = self.part_paths();
+        drop(self.files);
+        paths.iter().try_for_each(remove_file)
+    }
+
+    fn part(&mut self, i: usize) -> io::Result<&mut File> {
+        while self.files.len() <= i {
+            if let Some(last) = self.files.len().checked_sub(1) {
+                let part_size = self.part_size;
+                self.open_part(last)?.set_len(part_size)?;
+            }
+            let next = self.files.len();
+            self.open_part(next)?;
+        }
+        if self.current != Some(i) {
+            let pos = self.pos - i as u64 * self.part_size;
+            self.open_part(i)?.seek(SeekFrom::Start(pos))?;
+            self.current = Some(i);
+        }
+        self.open_part(i)
+    }
+
+    // Opens part `i` if it isn't open, or starts it if it's the next one
+    fn open_part(&mut self, i: usize) -> io::Result<&mut File> {
+        if self.files.get(i).is_none_or(|f| f.is_none()) {
+            // This can't wait for a handle, since whatever's writing to it
+            // could be holding up the ones that would be released
+            if !self.handles.try_grow(1) {
+                self.close_parts();
+                self.handles.budget().note_reopened();
+                if !self.handles.try_grow(1) {
+                    self.handles.force_grow(1);
+                }
+            }
+            let path = output_part_path(&self.path, i);
+            let file = if i == self.files.len() {
+                File::create(path)
+            } else {
+                OpenOptions::new().write(true).open(path)
+            };
+            let file = match file {
+                Ok(f) => f,
+                Err(e) => {
+                    self.handles.release(1);
+                    return Err(e);
+                },
+            };
+            if i == self.files.len() {
+                self.files.push(None);
+            }
+            self.files[i] = Some(file);
+        }
+        Ok(self.files[i].as_mut().unwrap())
+    }
+
+    fn close_parts(&mut self) {
+        for file in &mut self.files {
+            if file.take().is_some() {
+                self.handles.release(1);
+            }
+        }
+        self.current = None;
+    }
+}
+
+impl Write for SplitFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() { return Ok(0) }
+        let i = (self.pos / self.part_size) as usize;
+        let left_in_part = self.part_size - self.pos % self.part_size;
+        let max = buf.len().min(left_in_part as usize);
+        let n = self.part(i)?.write(&buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.files.iter_mut().flatten().try_for_each(|f| f.flush())
+    }
+}
+
+impl Seek for SplitFileWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::Current(p) => self.pos.checked_add_signed(p),
+            SeekFrom::End(_) => return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Split files can't be seeked from the end.",
+            )),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Can't seek before the start of the image.",
+        ))?;
+        self.current = None;
+        Ok(self.pos)
+    }
+}
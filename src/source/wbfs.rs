@@ -0,0 +1,239 @@
+// WBFS files, which Wii USB loaders keep games in, GameCube ones too when
+// they're set up like Nintendont's. A WBFS file is made of blocks of the same
+// size. The first one has the header, which says which discs are in the file,
+// and each disc has a table of which block each part of it is in. Parts of a
+// disc that aren't used, like the padding, aren't stored at all.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use sections::header::{MAGIC_WORD, MAGIC_WORD_OFFSET, TITLE_OFFSET, TITLE_SIZE};
+use ROM_SIZE;
+
+use super::ImageSource;
+
+const WBFS_MAGIC: &[u8] = b"WBFS";
+// The magic word, the number of sectors, the two sector sizes (as powers of
+// 2), and the version and a byte of padding
+const WBFS_HEADER_SIZE: usize = 12;
+
+// Discs are laid out in Wii sectors, and the block tables are big enough for
+// a dual layer Wii disc
+const WII_SECTOR_SIZE: u64 = 0x8000;
+const WII_SECTORS_PER_DISC: u64 = 143432 * 2;
+
+// Each disc's table starts with a copy of the start of its header
+const DISC_HEADER_COPY_SIZE: u64 = 0x100;
+const BLOCK_INDEX_SIZE: u64 = 2;
+
+// A disc in a WBFS file, from the copy of its header there
+#[derive(Clone, Debug)]
+pub struct WbfsDisc {
+    // Which slot in the WBFS file it's in
+    pub slot: usize,
+    pub game_id: String,
+    pub title: String,
+    pub is_gamecube: bool,
+    // Where its block table is
+    table_offset: u64,
+}
+
+pub struct WbfsFile<R> {
+    file: R,
+    block_size: u64,
+    blocks_per_disc: u64,
+    discs: Vec<WbfsDisc>,
+}
+
+impl WbfsFile<File> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<WbfsFile<File>> {
+        WbfsFile::new(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> WbfsFile<R> {
+    pub fn new(mut file: R) -> io::Result<WbfsFile<R>> {
+        let mut header = [0; WBFS_HEADER_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        if &header[..4] != WBFS_MAGIC {
+            return Err(invalid("This isn't a WBFS file."));
+        }
+        let (sector_shift, block_shift) = (header[8] as u32, header[9] as u32);
+        if !(9..=16).contains(&sector_shift) || !(15..=30).contains(&block_shift) {
+            return Err(invalid("The WBFS header has an invalid sector size."));
+        }
+        let sector_size = 1u64 << sector_shift;
+        let block_size = 1u64 << block_shift;
+        let blocks_per_disc = WII_SECTORS_PER_DISC * WII_SECTOR_SIZE / block_size;
+        // Each disc's table takes up whole sectors
+        let table_size = (DISC_HEADER_COPY_SIZE + blocks_per_disc * BLOCK_INDEX_SIZE).div_ceil(sector_size) * sector_size;
+
+        // After the header, the rest of the first sector has a byte for each
+        // slot, which isn't 0 if there's a disc in it
+        let mut slots = vec![0; sector_size as usize - WBFS_HEADER_SIZE];
+        file.read_exact(&mut slots)?;
+        let mut discs = Vec::new();
+        for slot in (0..slots.len()).filter(|&s| slots[s] != 0) {
+            let table_offset = sector_size + slot as u64 * table_size;
+            let mut disc_header = [0; DISC_HEADER_COPY_SIZE as usize];
+            file.seek(SeekFrom::Start(table_offset))?;
+            file.read_exact(&mut disc_header)?;
+            let title = &disc_header[TITLE_OFFSET as usize..TITLE_OFFSET as usize + TITLE_SIZE];
+            discs.push(WbfsDisc {
+                slot,
+                game_id: String::from_utf8_lossy(&disc_header[..6]).into_owned(),
+                title: String::from_utf8_lossy(title.split(|&b| b == 0).next().unwrap()).into_owned(),
+                is_gamecube: BigEndian::read_u32(&disc_header[MAGIC_WORD_OFFSET..]) == MAGIC_WORD,
+                table_offset,
+            });
+        }
+        Ok(WbfsFile { file, block_size, blocks_per_disc, discs })
+    }
+
+    // Every disc in the file, in the order they're in it
+    pub fn discs(&self) -> &[WbfsDisc] {
+        &self.discs
+    }
+
+    // A reader for `discs()[index]`
+    pub fn into_disc(self, index: usize) -> io::Result<WbfsDiscReader<R>> {
+        let disc = self.discs.get(index).ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("There are only {} discs in this WBFS file.", self.discs.len()),
+        ))?;
+        // GameCube discs go up to the usual size, since their block tables
+        // are sized for Wii discs
+        let len = if disc.is_gamecube {
+            ROM_SIZE as u64
+        } else {
+            self.blocks_per_disc * self.block_size
+        };
+        Ok(WbfsDiscReader {
+            blocks: BufReader::new(Blocks {
+                file: self.file,
+                table_offset: disc.table_offset + DISC_HEADER_COPY_SIZE,
+                block_size: self.block_size,
+                blocks_per_disc: self.blocks_per_disc,
+                len,
+                pos: 0,
+                block: None,
+                file_pos: None,
+            }),
+        })
+    }
+}
+
+// Reads a disc through its block table. Only the entry for the block that's
+// being read is kept, so the table is never read all at once.
+struct Blocks<R> {
+    file: R,
+    table_offset: u64,
+    block_size: u64,
+    blocks_per_disc: u64,
+    len: u64,
+    pos: u64,
+    // The last block that was looked up, and which block of the file it's
+    // in, or 0 if it isn't stored
+    block: Option<(u64, u64)>,
+    // Where `file` is, if it's known
+    file_pos: Option<u64>,
+}
+
+impl<R: Read + Seek> Blocks<R> {
+    fn block_location(&mut self, block: u64) -> io::Result<u64> {
+        if let Some((b, location)) = self.block {
+            if b == block { return Ok(location) }
+        }
+        let location = if block < self.blocks_per_disc {
+            let mut index = [0; BLOCK_INDEX_SIZE as usize];
+            self.file.seek(SeekFrom::Start(self.table_offset + block * BLOCK_INDEX_SIZE))?;
+            self.file.read_exact(&mut index)?;
+            self.file_pos = None;
+            BigEndian::read_u16(&index) as u64
+        } else {
+            0
+        };
+        self.block = Some((block, location));
+        Ok(location)
+    }
+}
+
+impl<R: Read + Seek> Read for Blocks<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() { return Ok(0) }
+        let block = self.pos / self.block_size;
+        let in_block = self.pos % self.block_size;
+        let max = (buf.len() as u64).min(self.block_size - in_block).min(self.len - self.pos) as usize;
+        let location = self.block_location(block)?;
+        let n = if location == 0 {
+            buf[..max].fill(0);
+            max
+        } else {
+            let file_pos = location * self.block_size + in_block;
+            if self.file_pos != Some(file_pos) {
+                self.file.seek(SeekFrom::Start(file_pos))?;
+            }
+            let n = self.file.read(&mut buf[..max])?;
+            self.file_pos = Some(file_pos + n as u64);
+            n
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R> Seek for Blocks<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(p) => self.len.checked_add_signed(p),
+            SeekFrom::Current(p) => self.pos.checked_add_signed(p),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Can't seek before the start of the image.",
+        ))?;
+        Ok(self.pos)
+    }
+}
+
+// One disc in a WBFS file, which reads like it was a plain image
+pub struct WbfsDiscReader<R> {
+    blocks: BufReader<Blocks<R>>,
+}
+
+impl<R: Read + Seek> Read for WbfsDiscReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.blocks.read(buf)
+    }
+}
+
+impl<R: Read + Seek> BufRead for WbfsDiscReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.blocks.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.blocks.consume(amt)
+    }
+}
+
+impl<R: Read + Seek> Seek for WbfsDiscReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.blocks.seek(pos)
+    }
+}
+
+impl<R: Read + Seek> ImageSource for WbfsDiscReader<R> {
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.blocks.get_ref().len)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,99 @@
+// Where the space on a ROM goes: how much is files, how much is system data,
+// how much isn't used at all, and which files and kinds of files take up the
+// most of it. It's all worked out from the FST, so nothing but the system
+// data has to be read.
+
+use std::cmp::{self, Reverse};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use sections::Section;
+use {Game, ROM_SIZE};
+
+// How many of the biggest files `Game::stats` keeps
+pub const LARGEST_FILES: usize = 10;
+
+#[derive(Debug, Serialize)]
+pub struct DiscStats {
+    pub files: usize,
+    // Not counting the root
+    pub directories: usize,
+    pub file_bytes: u64,
+    // The header, apploader, DOL, and FST
+    pub system_bytes: u64,
+    // Unused space between sections, like alignment and the space games
+    // leave to put files in a certain place on the disc
+    pub padding_bytes: u64,
+    // Unused space after the last section, up to the end of a full size ROM
+    pub free_bytes: u64,
+    // How much of a full size ROM has something on it, as a percentage
+    pub used_percent: f64,
+    // Biggest first
+    pub largest_files: Vec<FileSize>,
+    // Most bytes first
+    pub extensions: Vec<ExtensionStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileSize {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+// Files with the same extension, ignoring case. Files without one have an
+// empty `extension`.
+#[derive(Debug, Default, Serialize)]
+pub struct ExtensionStats {
+    pub extension: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl Game {
+    pub fn stats(&self) -> DiscStats {
+        let mut directories = 0;
+        let mut files = Vec::new();
+        let mut extensions = HashMap::<String, ExtensionStats>::new();
+        for e in self.fst.entries.iter().skip(1) {
+            let f = match e.as_file() {
+                Some(f) => f,
+                None => {
+                    directories += 1;
+                    continue;
+                },
+            };
+            let extension = Path::new(&f.info.name).extension()
+                .map_or_else(String::new, |e| e.to_string_lossy().to_lowercase());
+            let stats = extensions.entry(extension.clone())
+                .or_insert_with(|| ExtensionStats { extension, ..Default::default() });
+            stats.files += 1;
+            stats.bytes += f.size as u64;
+            files.push(FileSize { path: f.info.full_path.clone(), size: f.size as u64 });
+        }
+
+        let rom_size = cmp::max(ROM_SIZE as u64, self.image_size);
+        let gaps = self.rom_layout().gaps(rom_size);
+        let free_bytes = gaps.last().filter(|g| g.after.is_none()).map_or(0, |g| g.size);
+        let unused = gaps.iter().map(|g| g.size).sum::<u64>();
+        let system: [&dyn Section; 4] = [&self.header, &self.apploader, &self.dol, &self.fst];
+
+        let file_bytes = files.iter().map(|f| f.size).sum();
+        files.sort_by_key(|f| Reverse(f.size));
+        let file_count = files.len();
+        files.truncate(LARGEST_FILES);
+        let mut extensions = extensions.into_values().collect::<Vec<_>>();
+        extensions.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.extension.cmp(&b.extension)));
+
+        DiscStats {
+            files: file_count,
+            directories,
+            file_bytes,
+            system_bytes: system.iter().map(|s| s.size() as u64).sum(),
+            padding_bytes: unused - free_bytes,
+            free_bytes,
+            used_percent: (rom_size - unused) as f64 / ROM_SIZE as f64 * 100.0,
+            largest_files: files,
+            extensions,
+        }
+    }
+}
@@ -0,0 +1,85 @@
+// Small helpers for writing ROMs that don't belong to any one section
+
+use std::io::{self, Write};
+
+use ::WRITE_CHUNK_SIZE;
+
+// Zeros to write from, so padding doesn't need a buffer of its own
+pub static ZEROS: [u8; WRITE_CHUNK_SIZE] = [0; WRITE_CHUNK_SIZE];
+
+// Writes `count` zeros, in chunks of at most `WRITE_CHUNK_SIZE`. Nothing's
+// written if `count` is 0.
+pub fn write_zeros(count: u64, output: &mut impl Write) -> io::Result<()> {
+    let mut left = count;
+    while left > 0 {
+        let n = left.min(WRITE_CHUNK_SIZE as u64) as usize;
+        output.write_all(&ZEROS[..n])?;
+        left -= n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keeps track of each write, to check that there aren't any empty ones
+    #[derive(Default)]
+    struct Writes {
+        sizes: Vec<usize>,
+        nonzero: bool,
+    }
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sizes.push(buf.len());
+            self.nonzero |= buf.iter().any(|&b| b != 0);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn writes(count: u64) -> Vec<usize> {
+        let mut w = Writes::default();
+        write_zeros(count, &mut w).unwrap();
+        assert!(!w.nonzero);
+        assert_eq!(w.sizes.iter().sum::<usize>() as u64, count);
+        assert!(w.sizes.iter().all(|&n| n > 0));
+        w.sizes
+    }
+
+    #[test]
+    fn no_zeros() {
+        assert!(writes(0).is_empty());
+    }
+
+    #[test]
+    fn one_zero() {
+        assert_eq!(writes(1), [1]);
+    }
+
+    #[test]
+    fn exactly_one_chunk() {
+        assert_eq!(writes(WRITE_CHUNK_SIZE as u64), [WRITE_CHUNK_SIZE]);
+    }
+
+    #[test]
+    fn chunks_and_a_remainder() {
+        let c = WRITE_CHUNK_SIZE;
+        assert_eq!(writes(3 * c as u64), [c, c, c]);
+        assert_eq!(writes(2 * c as u64 + 5), [c, c, 5]);
+        assert_eq!(writes(c as u64 - 1), [c - 1]);
+    }
+
+    #[test]
+    fn into_a_vec() {
+        let mut v = vec![1, 2, 3];
+        write_zeros(WRITE_CHUNK_SIZE as u64 + 7, &mut v).unwrap();
+        assert_eq!(v.len(), WRITE_CHUNK_SIZE + 10);
+        assert_eq!(&v[..3], &[1, 2, 3]);
+        assert!(v[3..].iter().all(|&b| b == 0));
+    }
+}
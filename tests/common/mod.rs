@@ -0,0 +1,219 @@
+// Small ROMs built from scratch for the tests, like the bench fixture, but
+// with a DOL that has data segments, and files the tests choose. Everything's
+// deterministic, and it all goes in a temporary directory that's removed when
+// the fixture is dropped.
+
+#![allow(dead_code)]
+
+use std::env;
+use std::fs::{self, create_dir_all, remove_dir_all, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use gcmod::{paths::*, DiscSize, OrderStrategy, ROMRebuilder, SystemFileOverrides};
+
+pub const TEXT_ADDRESS: u64 = 0x8000_3100;
+pub const DATA_ADDRESS: u64 = 0x8000_4000;
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TestDir {
+    pub path: PathBuf,
+}
+
+impl TestDir {
+    pub fn new(name: &str) -> TestDir {
+        let path = env::temp_dir().join(format!(
+            "gcmod-test-{}-{}-{}",
+            name,
+            process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::SeqCst),
+        ));
+        if path.exists() {
+            remove_dir_all(&path).unwrap();
+        }
+        create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.path);
+    }
+}
+
+pub struct TestRom {
+    pub dir: TestDir,
+    pub root: PathBuf,
+    pub iso: PathBuf,
+    // (path relative to the root, contents)
+    pub files: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl TestRom {
+    // A ROM with `sample_files`
+    pub fn new(name: &str) -> TestRom {
+        TestRom::with_files(name, &sample_files(), 32)
+    }
+
+    pub fn with_files(name: &str, files: &[(&str, Vec<u8>)], alignment: u64) -> TestRom {
+        let dir = TestDir::new(name);
+        let root = dir.join("root");
+        write_root(&root, files).unwrap();
+
+        let iso = dir.join("rom.iso");
+        let plan = ROMRebuilder::plan(
+            &root,
+            alignment,
+            Some(&OrderStrategy::FstOrder),
+            false,
+            |_: &Path| false,
+            SystemFileOverrides::default(),
+            DiscSize::Fit,
+        ).unwrap();
+        plan.write_to_file(&iso, |_, _| {}).unwrap();
+
+        let files = files.iter().map(|(p, d)| (PathBuf::from(p), d.clone())).collect();
+        TestRom { dir, root, iso, files }
+    }
+
+    pub fn data(&self) -> Vec<u8> {
+        fs::read(&self.iso).unwrap()
+    }
+}
+
+// Bytes that don't repeat for a while, so a file that's read from the wrong
+// place doesn't match by accident
+pub fn pattern(len: usize, seed: usize) -> Vec<u8> {
+    let mut x = seed as u32 ^ 0x9e37_79b9;
+    (0..len).map(|_| {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        (x >> 24) as u8
+    }).collect()
+}
+
+pub fn sample_files() -> Vec<(&'static str, Vec<u8>)> {
+    let mut ends_in_zeros = pattern(3000, 4);
+    ends_in_zeros.extend(vec![0; 200]);
+    vec![
+        ("opening.bnr", [&b"BNR1"[..], &pattern(5000, 1)].concat()),
+        ("audio/title.dsp", pattern(70000, 2)),
+        ("audio/bgm/stage1.adp", pattern(40000, 3)),
+        ("data/ends_in_zeros.bin", ends_in_zeros),
+        ("data/empty.bin", Vec::new()),
+        ("movies/intro.thp", [&b"THP\0"[..], &pattern(100000, 5)].concat()),
+        ("Effect.dat", pattern(300, 6)),
+    ]
+}
+
+pub fn write_root(root: &Path, files: &[(&str, Vec<u8>)]) -> io::Result<()> {
+    create_dir_all(root.join("&&systemdata"))?;
+    write_header(File::create(root.join(HEADER_PATH))?)?;
+    write_apploader(File::create(root.join(APPLOADER_PATH))?)?;
+    File::create(root.join(DOL_PATH))?.write_all(&dol())?;
+    for (path, data) in files {
+        let path = root.join(path);
+        create_dir_all(path.parent().unwrap())?;
+        File::create(path)?.write_all(data)?;
+    }
+    Ok(())
+}
+
+pub fn write_header(mut w: impl Write) -> io::Result<()> {
+    let mut header = vec![0; 0x2440];
+    header[..6].copy_from_slice(b"GTSTE8");
+    (&mut header[0x1c..]).write_u32::<BigEndian>(0xc2339f3d)?;
+    header[0x20..0x29].copy_from_slice(b"Test Game");
+    // The rebuild fills in the real DOL and FST offsets, but these have to
+    // look valid for the header to be read at all
+    (&mut header[0x420..]).write_u32::<BigEndian>(0x10000)?;
+    (&mut header[0x424..]).write_u32::<BigEndian>(0x8000)?;
+    w.write_all(&header)
+}
+
+pub fn write_apploader(mut w: impl Write) -> io::Result<()> {
+    let code_size = 0x100;
+    let mut apploader = vec![0; 0x20 + code_size];
+    apploader[..10].copy_from_slice(b"2003/04/17");
+    (&mut apploader[0x10..]).write_u32::<BigEndian>(0x81200000)?;
+    (&mut apploader[0x14..]).write_u32::<BigEndian>(code_size as u32)?;
+    for word in apploader[0x20..].chunks_mut(4) {
+        // blr
+        (&mut word[..]).write_u32::<BigEndian>(0x4e800020)?;
+    }
+    w.write_all(&apploader)
+}
+
+// The code in the DOL's text segment, starting at `TEXT_ADDRESS`
+pub const TEXT: &[u32] = &[
+    0x9421fff0, // stwu r1, -0x10(r1)
+    0x7c0802a6, // mflr r0
+    0x90010014, // stw r0, 0x14(r1)
+    0x38600001, // li r3, 1
+    0x48000011, // bl 0x80003120
+    0x80010014, // lwz r0, 0x14(r1)
+    0x7c0803a6, // mtlr r0
+    0x38210010, // addi r1, r1, 0x10
+    0x4e800020, // blr
+    0x2c030000, // cmpwi r3, 0
+    0x4182000c, // beq 0x80003134
+    0x38630001, // addi r3, r3, 1
+    0x4bfffff4, // b 0x80003124
+    0x4e800020, // blr
+    0x60000000, // nop
+    0x60000000, // nop
+];
+
+// A DOL with `TEXT` in .text0, a string and some bytes in .data0, and a BSS
+pub fn dol() -> Vec<u8> {
+    let text = TEXT.iter().flat_map(|w| w.to_be_bytes()).collect::<Vec<_>>();
+    let mut data = b"Hello, world!\0\0\0".to_vec();
+    data.extend(0..32);
+
+    let mut dol = vec![0; 0x100];
+    let data_offset = 0x100 + text.len();
+    (&mut dol[0x00..]).write_u32::<BigEndian>(0x100).unwrap();
+    (&mut dol[0x1c..]).write_u32::<BigEndian>(data_offset as u32).unwrap();
+    (&mut dol[0x48..]).write_u32::<BigEndian>(TEXT_ADDRESS as u32).unwrap();
+    (&mut dol[0x64..]).write_u32::<BigEndian>(DATA_ADDRESS as u32).unwrap();
+    (&mut dol[0x90..]).write_u32::<BigEndian>(text.len() as u32).unwrap();
+    (&mut dol[0xac..]).write_u32::<BigEndian>(data.len() as u32).unwrap();
+    // BSS address and size, then the entry point
+    (&mut dol[0xd8..]).write_u32::<BigEndian>(0x8000_6000).unwrap();
+    (&mut dol[0xdc..]).write_u32::<BigEndian>(0x2000).unwrap();
+    (&mut dol[0xe0..]).write_u32::<BigEndian>(TEXT_ADDRESS as u32).unwrap();
+    dol.extend(text);
+    dol.extend(data);
+    dol
+}
+
+// Every file under `dir`, relative to it, with its contents
+pub fn read_tree(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut files = Vec::new();
+    read_tree_into(dir, Path::new(""), &mut files);
+    files.sort();
+    files
+}
+
+fn read_tree_into(dir: &Path, relative: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) {
+    for e in fs::read_dir(dir).unwrap() {
+        let e = e.unwrap();
+        let relative = relative.join(e.file_name());
+        if e.file_type().unwrap().is_dir() {
+            read_tree_into(&e.path(), &relative, files);
+        } else {
+            files.push((relative, fs::read(e.path()).unwrap()));
+        }
+    }
+}
@@ -0,0 +1,70 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use gcmod::compression::Compression;
+use gcmod::{DiscSize, ExtractMode, Game, OrderStrategy, ROMRebuilder, SystemFileOverrides};
+
+use common::{pattern, TestRom};
+
+fn stage() -> Vec<u8> {
+    "a stage with a lot of repeated parts in it. ".repeat(2000).into_bytes()
+}
+
+fn model() -> Vec<u8> {
+    let mut data = pattern(3000, 7);
+    data.extend(vec![0; 20000]);
+    data.extend(pattern(3000, 7));
+    data
+}
+
+// The contents of the file at `path` on a ROM
+fn rom_file(rom: &[u8], path: &str) -> Vec<u8> {
+    let game = Game::open(Cursor::new(rom), 0).unwrap();
+    let f = game.fst.entries.iter()
+        .filter_map(|e| e.as_file())
+        .find(|f| f.info.full_path == Path::new("/").join(path))
+        .unwrap_or_else(|| panic!("{} isn't on the ROM", path));
+    rom[f.file_offset as usize..f.file_offset as usize + f.size].to_vec()
+}
+
+#[test]
+fn decompressed_files_are_compressed_again_when_rebuilding() {
+    let files = [
+        ("stage.szs", Compression::Yaz0.compress(&stage()).unwrap()),
+        ("models/model.bin", Compression::Yay0.compress(&model()).unwrap()),
+        ("plain.bin", pattern(5000, 8)),
+    ];
+    let rom = TestRom::with_files("compression", &files, 32);
+    let data = rom.data();
+
+    let extracted = rom.dir.join("extracted");
+    let mut game = Game::open(Cursor::new(&data), 0).unwrap();
+    game.extract(BufReader::new(Cursor::new(&data)), &extracted, ExtractMode::FailIfExists).unwrap();
+    let report = game.decompress_extracted(&extracted, None).unwrap();
+    assert_eq!(report.decompressed.len(), 2);
+    assert!(report.failed.is_empty());
+
+    let rebuild = |compress| {
+        let mut rebuilt = Vec::new();
+        let overrides = SystemFileOverrides { compress, ..SystemFileOverrides::default() };
+        ROMRebuilder::plan(&extracted, 32, Some(&OrderStrategy::FstOrder), false, |_: &Path| false, overrides, DiscSize::Fit)
+            .unwrap()
+            .write(&mut rebuilt, |_, _| {})
+            .unwrap();
+        rebuilt
+    };
+
+    let compressed = rebuild(true);
+    assert_eq!(rom_file(&compressed, "stage.szs"), files[0].1);
+    assert_eq!(rom_file(&compressed, "models/model.bin"), files[1].1);
+    assert_eq!(rom_file(&compressed, "plain.bin"), files[2].1);
+
+    let decompressed = rebuild(false);
+    assert_eq!(rom_file(&decompressed, "stage.szs"), stage());
+    assert_eq!(rom_file(&decompressed, "models/model.bin"), model());
+}
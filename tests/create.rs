@@ -0,0 +1,85 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use gcmod::sections::header::{COUNTRY_USA, DEFAULT_SIMULATED_MEMORY};
+use gcmod::{CreateOptions, DiscSize, ExtractMode, Game, ROMRebuilder, SystemFileOverrides};
+
+use common::{dol, read_tree, sample_files, write_apploader, TestDir};
+
+// A ROM made from a directory of files that has no system files, with a
+// DOL and apploader from outside it
+fn created_rom(dir: &TestDir) -> Vec<u8> {
+    let root = dir.join("files");
+    for (path, data) in sample_files() {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+    let dol_path = dir.join("main.dol");
+    fs::write(&dol_path, dol()).unwrap();
+    let apploader_path = dir.join("apploader.img");
+    write_apploader(File::create(&apploader_path).unwrap()).unwrap();
+
+    let options = CreateOptions {
+        title: "Created Game",
+        game_code: "GTSE",
+        maker_code: "01",
+        dol: Some(&dol_path),
+        apploader: Some(&apploader_path),
+    };
+    let mut rom = Vec::new();
+    ROMRebuilder::create(&root, 32, &options, DiscSize::Fit, &mut rom, |_, _| {}).unwrap();
+    rom
+}
+
+#[test]
+fn created_headers_have_the_default_memory_size() {
+    let dir = TestDir::new("create-header");
+    let rom = created_rom(&dir);
+    assert_eq!(BigEndian::read_u32(&rom[0x444..]), DEFAULT_SIMULATED_MEMORY);
+    assert_eq!(BigEndian::read_u32(&rom[0x458..]), COUNTRY_USA);
+
+    let game = Game::open(Cursor::new(&rom), 0).unwrap();
+    assert_eq!(game.header.title, "Created Game");
+    assert_eq!(game.header.information.simulated_memory_size, DEFAULT_SIMULATED_MEMORY);
+    assert_eq!(game.header.information.country_code, COUNTRY_USA);
+}
+
+#[test]
+fn created_roms_extract_and_rebuild_the_same() {
+    let dir = TestDir::new("create-rebuild");
+    let rom = created_rom(&dir);
+
+    let extracted = dir.join("extracted");
+    let mut game = Game::open(Cursor::new(&rom), 0).unwrap();
+    game.extract(BufReader::new(Cursor::new(&rom)), &extracted, ExtractMode::FailIfExists).unwrap();
+    for (path, data) in sample_files() {
+        assert_eq!(fs::read(extracted.join(path)).unwrap(), data, "{} changed", path);
+    }
+
+    let mut rebuilt = Vec::new();
+    ROMRebuilder::plan(
+        &extracted,
+        32,
+        None,
+        false,
+        |_: &Path| false,
+        SystemFileOverrides::default(),
+        DiscSize::Fit,
+    ).unwrap().write(&mut rebuilt, |_, _| {}).unwrap();
+    assert!(rebuilt == rom, "rebuilding the extracted ROM changed it");
+
+    // And extracting that again gives the same files
+    let again = dir.join("again");
+    let mut game = Game::open(Cursor::new(&rebuilt), 0).unwrap();
+    game.extract(BufReader::new(Cursor::new(&rebuilt)), &again, ExtractMode::FailIfExists).unwrap();
+    assert!(read_tree(&extracted) == read_tree(&again));
+}
@@ -0,0 +1,154 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::io::Cursor;
+
+use gcmod::disassembler::{decode, Disassembler, Instruction, ObjdumpLine, Symbol, SymbolMap};
+use gcmod::sections::dol::segment::SegmentType;
+use gcmod::sections::dol::DOLHeader;
+use gcmod::Game;
+
+use common::{dol, TestRom};
+
+const LISTING: &str = "\
+# Entry point: 0x80003100
+
+.text0:  # 0x80003100, 0x40 bytes
+__start:
+80003100:  9421fff0  stwu    r1,-16(r1)
+80003104:  7c0802a6  mflr    r0
+80003108:  90010014  stw     r0,20(r1)
+8000310c:  38600001  li      r3,1
+80003110:  48000011  bl      fn_80003120
+80003114:  80010014  lwz     r0,20(r1)
+80003118:  7c0803a6  mtlr    r0
+8000311c:  38210010  addi    r1,r1,16
+fn_80003120:
+80003120:  4e800020  blr
+fn_80003124:
+80003124:  2c030000  cmpwi   r3,0
+80003128:  4182000c  beq     loc_80003134
+8000312c:  38630001  addi    r3,r3,1
+80003130:  4bfffff4  b       fn_80003124
+loc_80003134:
+80003134:  4e800020  blr
+fn_80003138:
+80003138:  60000000  nop
+8000313c:  60000000  nop
+
+.data0:  # 0x80004000, 0x30 bytes
+80004000:  .byte 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x21, 0x00  # \"Hello, world!\"
+8000400e:  .byte 0x00, 0x00
+80004010:  .word 0x00010203
+80004014:  .word 0x04050607
+80004018:  .word 0x08090a0b
+8000401c:  .word 0x0c0d0e0f
+80004020:  .word 0x10111213
+80004024:  .word 0x14151617
+80004028:  .word 0x18191a1b
+8000402c:  .word 0x1c1d1e1f
+
+.bss:  # 0x80006000, 0x2000 bytes
+80006000:  .skip 0x2000
+";
+
+fn listing(dol: &[u8], symbols: Option<&SymbolMap>) -> String {
+    let header = DOLHeader::new(Cursor::new(dol), 0).unwrap();
+    let mut out = Vec::new();
+    Disassembler::new().write_listing(Cursor::new(dol), &header, symbols, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn listing_matches_snapshot() {
+    assert_eq!(listing(&dol(), None), LISTING);
+}
+
+#[test]
+fn listing_is_stable() {
+    let dol = dol();
+    assert_eq!(listing(&dol, None), listing(&dol, None));
+}
+
+// With a map, only its names are used, and everything else gets a `loc_`
+#[test]
+fn listing_uses_symbol_map() {
+    let mut symbols = SymbolMap::default();
+    symbols.insert(0x8000_3100, Symbol { name: "main".to_string(), size: 0x24 });
+    symbols.insert(0x8000_3124, Symbol { name: "count_down".to_string(), size: 0x14 });
+    let listing = listing(&dol(), Some(&symbols));
+
+    assert!(listing.contains("main:\n80003100:"));
+    assert!(listing.contains("count_down:\n80003124:"));
+    assert!(listing.contains("bl      loc_80003120"));
+    assert!(listing.contains("b       count_down"));
+    assert!(!listing.contains("fn_"));
+}
+
+// Strings longer than a line are split up, and ones that don't start on a
+// word still get found
+#[test]
+fn listing_finds_strings_anywhere() {
+    let mut dol = dol();
+    let data = dol.len() - 0x30;
+    dol[data..data + 0x30].copy_from_slice(b"\x01A long string that takes up a few lines\0\x02\x03\x04\x05\x06\x07\x08");
+    let listing = listing(&dol, None);
+
+    assert!(listing.contains("80004000:  .byte 0x01\n"), "{}", listing);
+    assert!(listing.contains("80004001:  .byte 0x41, 0x20, 0x6c, 0x6f, 0x6e, 0x67, 0x20, 0x73, 0x74, 0x72, 0x69, 0x6e, 0x67, 0x20, 0x74, 0x68  # \"A long string that takes up a few lines\"\n"));
+    assert!(listing.contains("80004011:  .byte 0x61, 0x74,"));
+    assert!(listing.contains("80004029:  .byte 0x02, 0x03, 0x04\n"));
+    assert!(listing.contains("8000402c:  .word 0x05060708\n"));
+}
+
+// Every text segment's instructions, as (address, opcode, text)
+fn text_segments(dol: &DOLHeader, data: &[u8]) -> Vec<Vec<(u64, u32, String)>> {
+    dol.iter_segments()
+        .filter(|s| s.seg_type == SegmentType::Text)
+        .map(|s| Disassembler::new().disasm(Cursor::new(data), s, None).unwrap()
+            .map(|ins| ins.map(|i| (i.location, i.opcode, i.text)).unwrap())
+            .collect())
+        .collect()
+}
+
+// A DOL that's been extracted disassembles the same as it does on the ROM,
+// even though its segments are at different offsets
+#[test]
+fn extracted_dols_match_the_rom() {
+    let rom = TestRom::new("disasm-dol");
+    let data = rom.data();
+    let game = Game::open(Cursor::new(&data), 0).unwrap();
+    let mut extracted = Vec::new();
+    game.extract_section_to("Start.dol", &mut extracted, Cursor::new(&data)).unwrap();
+    let dol = DOLHeader::new(Cursor::new(&extracted), 0).unwrap();
+    assert!(game.dol.offset > 0);
+    assert_eq!(dol.offset, 0);
+
+    let from_rom = text_segments(&game.dol, &data);
+    assert!(!from_rom.is_empty() && from_rom.iter().all(|s| !s.is_empty()));
+    assert_eq!(from_rom, text_segments(&dol, &extracted));
+    assert_eq!(listing(&extracted, None), LISTING);
+}
+
+// GNU objdump's listing of a run of Gekko instructions, from
+// `objdump -D -b binary -m powerpc -M 750cl -EB --adjust-vma=0x80003100`.
+// The decoder should print every one of them the same way.
+#[test]
+fn decoder_matches_objdump() {
+    let mut count = 0;
+    for line in include_str!("fixtures/gekko.objdump").lines() {
+        let expected = match Instruction::from_objdump(line) {
+            ObjdumpLine::Instruction(ins) => ins,
+            ObjdumpLine::Other => continue,
+            _ => panic!("couldn't read {:?}", line),
+        };
+        let ins = decode(expected.opcode, expected.location);
+        assert_eq!(ins.text, expected.text, "at {:#x}", expected.location);
+        assert_eq!(ins.mnemonic, expected.mnemonic);
+        assert_eq!(ins.operands, expected.operands, "at {:#x}", expected.location);
+        count += 1;
+    }
+    assert_eq!(count, 164);
+}
@@ -0,0 +1,74 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use gcmod::sections::dol::DOLHeader;
+
+use common::{dol, pattern};
+
+fn round_trip(dol: &[u8]) -> Vec<u8> {
+    let header = DOLHeader::new(Cursor::new(dol), 0).unwrap();
+    let mut elf = Vec::new();
+    header.to_elf(Cursor::new(dol), &mut elf).unwrap();
+    assert_eq!(&elf[..4], b"\x7fELF");
+    let mut converted = Vec::new();
+    DOLHeader::from_elf(Cursor::new(&elf), &mut converted).unwrap();
+    converted
+}
+
+// (type, slot, address, size, contents)
+type SegmentInfo = (String, u64, u64, usize, Vec<u8>);
+
+// Every segment, and the entry point and BSS
+fn describe(dol: &[u8]) -> (Vec<SegmentInfo>, u64, u64, usize) {
+    let header = DOLHeader::new(Cursor::new(dol), 0).unwrap();
+    let mut reader = Cursor::new(dol);
+    let segments = header.iter_segments().map(|s| {
+        let mut data = vec![0; s.size];
+        reader.seek(SeekFrom::Start(s.offset)).unwrap();
+        reader.read_exact(&mut data).unwrap();
+        (format!("{:?}", s.seg_type), s.seg_num, s.loading_address, s.size, data)
+    }).collect();
+    (segments, header.entry_point, header.bss_address, header.bss_size)
+}
+
+#[test]
+fn dol_survives_a_round_trip() {
+    let dol = dol();
+    assert_eq!(round_trip(&dol), dol);
+}
+
+// Segments in slots other than the first few keep their slots, and
+// everything in them is the same
+#[test]
+fn segments_keep_their_slots() {
+    // (header offset of the slot, address, size)
+    let segments: &[(usize, u32, usize)] = &[
+        (0x00, 0x8000_3100, 0x120),      // .text0
+        (0x08, 0x8000_4000, 0x40),       // .text2
+        (0x1c + 4, 0x8000_5000, 0x60),   // .data1
+        (0x1c + 20, 0x8000_6000, 0x24),  // .data5
+    ];
+    let mut dol = vec![0; 0x100];
+    for (i, &(slot, address, size)) in segments.iter().enumerate() {
+        let offset = dol.len() as u32;
+        BigEndian::write_u32(&mut dol[slot..], offset);
+        BigEndian::write_u32(&mut dol[slot + 0x48..], address);
+        BigEndian::write_u32(&mut dol[slot + 0x90..], size as u32);
+        dol.extend(pattern(size, i + 1));
+        dol.resize(dol.len().next_multiple_of(32), 0);
+    }
+    BigEndian::write_u32(&mut dol[0xd8..], 0x8000_7000);
+    BigEndian::write_u32(&mut dol[0xdc..], 0x1000);
+    BigEndian::write_u32(&mut dol[0xe0..], 0x8000_3100);
+
+    let converted = round_trip(&dol);
+    assert_eq!(describe(&converted), describe(&dol));
+    // And again, since a DOL that's been through it once shouldn't change
+    assert_eq!(round_trip(&converted), converted);
+}
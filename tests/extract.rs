@@ -0,0 +1,221 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use gcmod::handles::HandleBudget;
+use gcmod::sections::dol::segment::SegmentType;
+use gcmod::sections::{ExtractOutcome, SectionType, UniqueSectionType};
+use gcmod::{ExistingCheck, ExtractMode, ExtractOptions, Game};
+
+use common::{dol, pattern, read_tree, sample_files, TestDir, TestRom, TEXT};
+
+#[test]
+fn parallel_extraction_matches_serial() {
+    let mut files = sample_files();
+    // Enough files that the threads all have something to do
+    let extra = (0..40)
+        .map(|i| (format!("extra/{}/file{}.bin", i % 5, i), pattern(i * 331, i + 10)))
+        .collect::<Vec<_>>();
+    files.extend(extra.iter().map(|(p, d)| (p.as_str(), d.clone())));
+    let rom = TestRom::with_files("parallel", &files, 32);
+    let mut game = Game::open(Cursor::new(rom.data()), 0).unwrap();
+    let dir = TestDir::new("parallel-out");
+
+    let serial = dir.join("serial");
+    let iso = BufReader::new(File::open(&rom.iso).unwrap());
+    let count = game.extract_file_system(iso, &serial, 0, ExtractMode::FailIfExists, None).unwrap();
+    let serial = read_tree(&serial);
+    assert_eq!(count.total(), files.len());
+    assert_eq!(serial.len(), files.len());
+
+    for &threads in &[1, 4, 0] {
+        let out = dir.join(format!("j{}", threads));
+        let calls = AtomicUsize::new(0);
+        let last = AtomicUsize::new(0);
+        let written = game.extract_files_parallel(&rom.iso, &out, threads, &HandleBudget::shared(), |n| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            // The counts only ever go up
+            assert!(last.fetch_max(n, Ordering::SeqCst) < n);
+        }).unwrap();
+        assert_eq!(written, files.len());
+        assert_eq!(calls.into_inner(), files.len());
+        assert!(read_tree(&out) == serial, "-j {} doesn't match the serial extraction", threads);
+    }
+}
+
+// System file names come first, then FST paths, then DOL segments. A
+// leading / always means the FST.
+#[test]
+fn section_names_are_matched_in_order() {
+    let fake_dol = pattern(200, 1);
+    let nested = pattern(300, 2);
+    let rom = TestRom::with_files("section-names", &[("Start.dol", fake_dol.clone()), ("dir/Start.dol", nested.clone())], 32);
+    let data = rom.data();
+    let game = Game::open(Cursor::new(&data), 0).unwrap();
+    let extract = |name: &str| {
+        let mut out = Vec::new();
+        let outcome = game.extract_section_to(name, &mut out, Cursor::new(&data)).unwrap();
+        (outcome, out)
+    };
+    let extracted = |t| ExtractOutcome::Extracted(t);
+
+    for name in &["Start.dol", "sys/main.dol", "&&systemdata/Start.dol"] {
+        let (outcome, out) = extract(name);
+        assert_eq!(outcome, extracted(SectionType::System(UniqueSectionType::Dol)), "{}", name);
+        assert!(out == dol(), "{} isn't the real DOL", name);
+    }
+
+    let (outcome, out) = extract("/Start.dol");
+    assert_eq!(outcome, extracted(SectionType::File(PathBuf::from("/Start.dol"))));
+    assert!(out == fake_dol);
+    let (outcome, out) = extract("dir/Start.dol");
+    assert_eq!(outcome, extracted(SectionType::File(PathBuf::from("/dir/Start.dol"))));
+    assert!(out == nested);
+
+    let (outcome, out) = extract("text0");
+    assert_eq!(outcome, extracted(SectionType::Segment(SegmentType::Text, 0)));
+    // It's the first thing after the DOL's header
+    assert_eq!(out.len(), TEXT.len() * 4);
+    assert!(out[..] == dol()[0x100..0x100 + out.len()]);
+
+    assert_eq!(extract("missing.bin").0, ExtractOutcome::NotFound);
+    assert_eq!(extract("text5").0, ExtractOutcome::NotFound);
+}
+
+fn extract_with(rom: &TestRom, out: &Path, options: &ExtractOptions) -> io::Result<usize> {
+    let mut game = Game::open(Cursor::new(rom.data()), 0)?;
+    let iso = BufReader::new(File::open(&rom.iso)?);
+    game.extract_with(iso, out, options).map(|r| r.count.written)
+}
+
+// The paths under `dir`, split into the system data and everything else
+fn extracted(dir: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    read_tree(dir).into_iter().map(|(p, _)| p).partition(|p| p.starts_with("&&systemdata"))
+}
+
+fn rom_paths(rom: &TestRom) -> Vec<PathBuf> {
+    let mut paths = rom.files.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn conflicting_options_are_rejected() {
+    let conflicts = vec![
+        ExtractOptions::new().overwrite(true).merge(true),
+        ExtractOptions::new().overwrite(true).resume(Some(ExistingCheck::Size)),
+        ExtractOptions::new().system_data(false).files(false),
+        ExtractOptions::new().files(false).filter(|_, _| true),
+        ExtractOptions::new().files(false).decompress(true),
+        ExtractOptions::new().decompress(true).resume(Some(ExistingCheck::Ends)),
+        ExtractOptions::new().decompressed_suffix(".dec"),
+    ];
+    for options in conflicts {
+        let e = options.build().err().expect("the options were accepted");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(!e.to_string().is_empty());
+    }
+
+    let fine = vec![
+        ExtractOptions::new(),
+        ExtractOptions::new().overwrite(true).system_data(false),
+        ExtractOptions::new().merge(true).resume(Some(ExistingCheck::Size)),
+        ExtractOptions::new().files(false).overwrite(true),
+        ExtractOptions::new().decompress(true).decompressed_suffix(".dec"),
+    ];
+    for options in fine {
+        assert!(options.build().is_ok());
+    }
+}
+
+#[test]
+fn options_pick_what_gets_extracted() {
+    let rom = TestRom::new("options");
+    let dir = TestDir::new("options-out");
+    let options = |name: &str, options: ExtractOptions| {
+        let out = dir.join(name);
+        extract_with(&rom, &out, &options.build().unwrap()).unwrap();
+        extracted(&out)
+    };
+
+    let (system, files) = options("all", ExtractOptions::new());
+    assert_eq!(system.len(), 4);
+    assert_eq!(files, rom_paths(&rom));
+
+    let (system, files) = options("files", ExtractOptions::new().system_data(false));
+    assert!(system.is_empty());
+    assert_eq!(files, rom_paths(&rom));
+
+    let (system, files) = options("system", ExtractOptions::new().files(false));
+    assert_eq!(system.len(), 4);
+    assert!(files.is_empty());
+
+    let audio = |p: &Path| p.extension().is_some_and(|e| e == "dsp" || e == "adp");
+    let (system, files) = options("audio", ExtractOptions::new().system_data(false).filter(move |p, _| audio(p)));
+    assert!(system.is_empty());
+    assert_eq!(files, rom_paths(&rom).into_iter().filter(|p| audio(p)).collect::<Vec<_>>());
+}
+
+#[test]
+fn options_handle_existing_directories() {
+    let rom = TestRom::new("options-existing");
+    let dir = TestDir::new("options-existing-out");
+    let out = dir.join("out");
+    let extra = out.join("extra.txt");
+    let total = 4 + rom.files.len();
+    assert_eq!(extract_with(&rom, &out, &ExtractOptions::new()).unwrap(), total);
+    fs::write(&extra, b"not from the ROM").unwrap();
+
+    let e = extract_with(&rom, &out, &ExtractOptions::new()).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+
+    // Resuming skips everything that's already there
+    let resume = ExtractOptions::new().resume(Some(ExistingCheck::Ends)).build().unwrap();
+    let mut game = Game::open(Cursor::new(rom.data()), 0).unwrap();
+    let iso = BufReader::new(File::open(&rom.iso).unwrap());
+    let report = game.extract_with(iso, &out, &resume).unwrap();
+    assert_eq!((report.count.written, report.count.skipped), (0, total));
+
+    // Merging leaves other files alone, and overwriting doesn't
+    assert_eq!(extract_with(&rom, &out, &ExtractOptions::new().merge(true)).unwrap(), total);
+    assert!(extra.exists());
+    assert_eq!(extract_with(&rom, &out, &ExtractOptions::new().overwrite(true)).unwrap(), total);
+    assert!(!extra.exists());
+}
+
+// One set of options, with a progress callback, can be used for more than one
+// game
+#[test]
+fn options_are_reused_across_games() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let seen = calls.clone();
+    let options = ExtractOptions::new()
+        .system_data(false)
+        .progress(move |done, total| seen.lock().unwrap().push((done, total)))
+        .build()
+        .unwrap();
+
+    let dir = TestDir::new("options-reused");
+    let roms = [
+        TestRom::new("options-reused-a"),
+        TestRom::with_files("options-reused-b", &[("one.bin", pattern(10, 1)), ("two.bin", pattern(20, 2))], 32),
+    ];
+    for (i, rom) in roms.iter().enumerate() {
+        let out = dir.join(format!("{}", i));
+        let cloned = options.clone();
+        assert_eq!(extract_with(rom, &out, &cloned).unwrap(), rom.files.len());
+        assert_eq!(extracted(&out).1, rom_paths(rom));
+
+        // It finishes with every file done
+        let last = *calls.lock().unwrap().last().unwrap();
+        assert_eq!(last, (rom.files.len(), rom.files.len()));
+        calls.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,209 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use gcmod::sections::fst::FST;
+use gcmod::{DiscSize, ExtractMode, Game, OrderStrategy, ROMRebuilder, SystemFileOverrides};
+
+use common::{pattern, read_tree, TestDir, TestRom};
+
+const ENTRY_SIZE: usize = 12;
+const FST_OFFSET_ADDR: usize = 0x424;
+const FST_SIZE_ADDR: usize = 0x428;
+
+// The ROM, and where its FST is
+fn rom(name: &str) -> (Vec<u8>, usize, usize) {
+    let rom = TestRom::new(name);
+    let data = rom.data();
+    let offset = BigEndian::read_u32(&data[FST_OFFSET_ADDR..]) as usize;
+    let size = BigEndian::read_u32(&data[FST_SIZE_ADDR..]) as usize;
+    (data, offset, size)
+}
+
+fn open_error(data: &[u8]) -> String {
+    match Game::open(Cursor::new(data), 0) {
+        Ok(_) => panic!("the ROM opened"),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn entry_count_is_capped_by_the_header() {
+    let (mut data, offset, size) = rom("fst-count");
+    assert!(Game::open(Cursor::new(&data), 0).is_ok());
+
+    // There's plenty of disc after the FST, but the header says the FST
+    // doesn't go that far
+    let count = size / ENTRY_SIZE + 1;
+    BigEndian::write_u32(&mut data[offset + 8..], count as u32);
+    let e = open_error(&data);
+    assert!(e.contains(&format!("{} entries", count)), "{}", e);
+
+    let fst = &data[offset..];
+    assert!(FST::with_size(Cursor::new(fst), 0, size).is_err());
+}
+
+#[test]
+fn errors_name_the_entry() {
+    let (data, offset, _) = rom("fst-errors");
+    let game = Game::open(Cursor::new(&data), 0).unwrap();
+    let dir = game.fst.entries.iter()
+        .filter_map(|e| e.as_dir())
+        .find(|d| d.info.index > 0)
+        .unwrap()
+        .info.index;
+    let file = game.fst.entries.iter()
+        .filter_map(|e| e.as_file())
+        .next_back()
+        .unwrap()
+        .info.index;
+
+    let mut bad_type = data.clone();
+    bad_type[offset + file * ENTRY_SIZE] = 7;
+    let e = open_error(&bad_type);
+    assert!(e.contains(&format!("Entry {} ", file)), "{}", e);
+
+    let mut bad_dir = data.clone();
+    BigEndian::write_u32(&mut bad_dir[offset + dir * ENTRY_SIZE + 8..], dir as u32);
+    let e = open_error(&bad_dir);
+    assert!(e.contains(&format!("Entry {} ", dir)), "{}", e);
+
+    let mut bad_name = data.clone();
+    bad_name[offset + file * ENTRY_SIZE + 1] = 0xff;
+    let e = open_error(&bad_name);
+    assert!(e.contains(&format!("Entry {} ", file)), "{}", e);
+}
+
+// Damaged FSTs have to be errors, not panics or huge allocations
+#[test]
+fn mutated_fsts_dont_panic() {
+    let (data, offset, size) = rom("fst-fuzz");
+    let mut x: u32 = 0x1234_5678;
+    let mut next = || {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        x as usize
+    };
+
+    for _ in 0..3000 {
+        let mut data = data.clone();
+        for _ in 0..1 + next() % 4 {
+            let i = offset + next() % size;
+            data[i] = match next() % 3 {
+                0 => next() as u8,
+                1 => data[i] ^ (1 << (next() % 8)),
+                _ => 0xff,
+            };
+        }
+        let _ = Game::open(Cursor::new(&data), 0);
+        let fst = &data[offset..offset + size];
+        let _ = FST::new(Cursor::new(fst), 0);
+        let _ = FST::with_size(Cursor::new(&data[offset..]), 0, size);
+    }
+}
+
+// Shift-JIS for サウンド and タイトル
+const SOUND: &[u8] = b"\x83\x54\x83\x45\x83\x93\x83\x68";
+const TITLE: &[u8] = b"\x83\x5e\x83\x43\x83\x67\x83\x8b";
+
+// An FST like a Japanese game's:
+// /
+//   サウンド/
+//     タイトル.adp
+//   caf\xe9.bin, which isn't valid Shift-JIS
+fn shift_jis_fst() -> Vec<u8> {
+    let names = [SOUND, &[TITLE, b".adp"].concat(), b"caf\xe9.bin"];
+    let mut name_offsets = Vec::new();
+    let mut string_table = Vec::new();
+    for name in &names {
+        name_offsets.push(string_table.len() as u32);
+        string_table.extend_from_slice(name);
+        string_table.push(0);
+    }
+
+    // (type and name offset, parent or file offset, next entry or file size)
+    let entries = [
+        (1 << 24, 0, 4),
+        (1 << 24 | name_offsets[0], 0, 3),
+        (name_offsets[1], 0x10000, 0x100),
+        (name_offsets[2], 0x10100, 0x20),
+    ];
+    let mut fst = vec![0; entries.len() * ENTRY_SIZE];
+    for (i, &(name, offset, size)) in entries.iter().enumerate() {
+        BigEndian::write_u32(&mut fst[i * ENTRY_SIZE..], name);
+        BigEndian::write_u32(&mut fst[i * ENTRY_SIZE + 4..], offset);
+        BigEndian::write_u32(&mut fst[i * ENTRY_SIZE + 8..], size);
+    }
+    fst.extend(string_table);
+    fst
+}
+
+#[test]
+fn shift_jis_names_are_decoded() {
+    let data = shift_jis_fst();
+    let fst = FST::with_size(Cursor::new(&data), 0, data.len()).unwrap();
+    let names = fst.entries.iter().skip(1).map(|e| e.info().name.clone()).collect::<Vec<_>>();
+    assert_eq!(names, ["サウンド/", "タイトル.adp", "café.bin"]);
+
+    let title = fst.entry_for_path("サウンド/タイトル.adp").and_then(|e| e.as_file()).unwrap();
+    assert_eq!(title.info.raw_name, [TITLE, b".adp"].concat());
+    assert_eq!(title.info.full_path, Path::new("/サウンド/タイトル.adp"));
+    assert_eq!(fst.entries[1].info().raw_name, SOUND);
+    // Names that were read as Latin-1 keep their original bytes too
+    assert_eq!(fst.entries[3].info().raw_name, b"caf\xe9.bin");
+
+    // So writing it back gives exactly what was read
+    let mut written = Vec::new();
+    fst.write(&mut written).unwrap();
+    assert!(written == data);
+}
+
+#[test]
+fn shift_jis_names_survive_extraction_and_rebuild() {
+    let files = [("サウンド/タイトル.adp", pattern(3000, 1)), ("readme.txt", pattern(100, 2))];
+    let rom = TestRom::with_files("fst-sjis", &files, 32);
+    let data = rom.data();
+    let offset = BigEndian::read_u32(&data[FST_OFFSET_ADDR..]) as usize;
+    let size = BigEndian::read_u32(&data[FST_SIZE_ADDR..]) as usize;
+    let fst = &data[offset..offset + size];
+    // The names are stored as Shift-JIS, not UTF-8
+    assert!(fst.windows(SOUND.len()).any(|w| w == SOUND));
+    assert!(fst.windows(TITLE.len()).any(|w| w == TITLE));
+    assert!(!fst.windows(3).any(|w| w == "サ".as_bytes()));
+
+    // Extracting writes the decoded names
+    let mut game = Game::open(Cursor::new(&data), 0).unwrap();
+    let out = TestDir::new("fst-sjis-out");
+    let root = out.join("root");
+    let iso = BufReader::new(File::open(&rom.iso).unwrap());
+    game.extract(iso, &root, ExtractMode::FailIfExists).unwrap();
+    let extracted = read_tree(&root).into_iter()
+        .filter(|(p, _)| !p.starts_with("&&systemdata"))
+        .collect::<Vec<_>>();
+    let mut expected = rom.files.clone();
+    expected.sort();
+    assert!(extracted == expected);
+
+    // And rebuilding from them gives back the same FST
+    let plan = ROMRebuilder::plan(
+        &root,
+        32,
+        Some(&OrderStrategy::FstOrder),
+        false,
+        |_: &Path| false,
+        SystemFileOverrides::default(),
+        DiscSize::Fit,
+    ).unwrap();
+    let mut rebuilt = Vec::new();
+    plan.write(Cursor::new(&mut rebuilt), |_, _| {}).unwrap();
+    assert_eq!(BigEndian::read_u32(&rebuilt[FST_OFFSET_ADDR..]) as usize, offset);
+    assert!(&rebuilt[offset..offset + size] == fst);
+}
@@ -0,0 +1,114 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::fs;
+use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use gcmod::handles::HandleBudget;
+use gcmod::source::split::{output_part_path, SplitFileWriter};
+use gcmod::{DiscSize, Game, OrderStrategy, ROMRebuilder, SystemFileOverrides};
+
+use common::{read_tree, TestDir, TestRom};
+
+// Extracts a ROM's files with `threads` threads, keeping to `budget`, and
+// checks they all came out right
+fn extract(name: &str, threads: usize, budget: &Arc<HandleBudget>, callback: impl Fn(usize) + Sync) {
+    let rom = TestRom::new(name);
+    let game = Game::open(Cursor::new(rom.data()), 0).unwrap();
+    let out = TestDir::new(&format!("{}-out", name));
+    let written = game.extract_files_parallel(&rom.iso, &out.path, threads, budget, callback).unwrap();
+    assert_eq!(written, rom.files.len());
+
+    let mut expected = rom.files.clone();
+    expected.sort();
+    assert!(read_tree(&out.path) == expected);
+    assert_eq!(budget.open(), 0);
+}
+
+#[test]
+fn extraction_fits_in_a_tiny_budget() {
+    for &(threads, limit) in &[(4, 1), (4, 2), (4, 3), (2, 4)] {
+        let budget = Arc::new(HandleBudget::new(limit));
+        extract(&format!("handles-{}-{}", threads, limit), threads, &budget, |_| {});
+    }
+}
+
+#[test]
+fn readers_are_reopened_when_the_budget_runs_out() {
+    let budget = Arc::new(HandleBudget::new(2));
+    let taken = AtomicBool::new(false);
+    extract("handles-reopen", 1, &budget, |_| {
+        // Hold on to the handle the next file needs for a moment, so the
+        // thread has to close the ROM and wait to open it again
+        if !taken.swap(true, Ordering::SeqCst) {
+            let handle = budget.try_reserve(1).unwrap();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                drop(handle);
+            });
+        }
+    });
+    assert!(budget.reopened() > 0);
+}
+
+#[test]
+fn split_parts_are_reopened_when_the_budget_runs_out() {
+    let dir = TestDir::new("handles-split");
+    let path = dir.join("rom.iso");
+    let budget = Arc::new(HandleBudget::new(1));
+    {
+        let mut output = SplitFileWriter::create_with_handles(&path, 10, &budget).unwrap();
+        output.write_all(&[1; 25]).unwrap();
+        assert_eq!(budget.open(), 1);
+        output.seek(SeekFrom::Start(5)).unwrap();
+        output.write_all(&[2; 10]).unwrap();
+        output.flush().unwrap();
+        assert_eq!(budget.open(), 1);
+    }
+    assert_eq!(budget.open(), 0);
+    assert!(budget.reopened() > 0);
+
+    let parts = (0..3).map(|n| fs::read(output_part_path(&path, n)).unwrap()).collect::<Vec<_>>();
+    assert_eq!(parts[0], [&[1; 5][..], &[2; 5][..]].concat());
+    assert_eq!(parts[1], [&[2; 5][..], &[1; 5][..]].concat());
+    assert_eq!(parts[2], vec![1; 5]);
+    assert!(!output_part_path(&path, 3).exists());
+}
+
+// The reader and the writer each need a handle, and neither can wait for the
+// other's
+#[test]
+fn split_rebuilds_fit_in_a_tiny_budget() {
+    let rom = TestRom::new("handles-rebuild");
+    for &limit in &[1, 2] {
+        let out = TestDir::new("handles-rebuild-out");
+        let path = out.join("rom.iso");
+        let mut plan = ROMRebuilder::plan(
+            &rom.root,
+            32,
+            Some(&OrderStrategy::FstOrder),
+            false,
+            |_: &Path| false,
+            SystemFileOverrides::default(),
+            DiscSize::Fit,
+        ).unwrap();
+        let budget = Arc::new(HandleBudget::new(limit));
+        plan.handles = budget.clone();
+        let output = SplitFileWriter::create_with_handles(&path, 0x1000, &budget).unwrap();
+        plan.write_to_split_files(output, |_, _| {}).unwrap();
+        assert_eq!(budget.open(), 0);
+
+        let data = (0..).map(|n| output_part_path(&path, n))
+            .take_while(|p| p.exists())
+            .flat_map(|p| fs::read(p).unwrap())
+            .collect::<Vec<_>>();
+        assert!(data == rom.data(), "the ROM doesn't match with {} handles", limit);
+    }
+}
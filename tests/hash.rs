@@ -0,0 +1,120 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use gcmod::hash::{read_manifest, Digest, HashAlgo};
+
+use common::pattern;
+
+#[test]
+fn digests_round_trip_for_every_algorithm() {
+    let data = pattern(10000, 1);
+    for &algo in HashAlgo::ALL.iter().filter(|a| a.is_supported()) {
+        let digest = Digest::of(algo, &data[..]).unwrap();
+        assert_eq!(digest.hex.len(), algo.digest_len());
+        assert_eq!(digest.to_string().parse::<Digest>().unwrap(), digest);
+        // Without the prefix, the algorithm comes from the length
+        assert_eq!(digest.hex.parse::<Digest>().unwrap(), digest);
+        assert!(digest.verify(&data[..]).unwrap());
+        assert!(!digest.verify(&data[1..]).unwrap());
+    }
+}
+
+#[test]
+fn digests_only_match_their_own_algorithm() {
+    let data = pattern(1000, 2);
+    let digests = HashAlgo::ALL.iter()
+        .filter(|a| a.is_supported())
+        .map(|&a| Digest::of(a, &data[..]).unwrap())
+        .collect::<Vec<_>>();
+    for (i, a) in digests.iter().enumerate() {
+        for b in &digests[i + 1..] {
+            assert_ne!(a, b);
+        }
+    }
+    // The right length, but for the wrong algorithm
+    assert!("crc32:0123456789abcdef".parse::<Digest>().is_err());
+}
+
+#[test]
+fn manifests_can_mix_algorithms() {
+    let text = "\
+        xxh3:0123456789abcdef  audio/title.dsp\n\
+        \n\
+        crc32:DEADBEEF  opening.bnr\n\
+        da39a3ee5e6b4b0d3255bfef95601890afd80709 *data/empty.bin\n\
+        sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  has spaces.bin\n";
+    let entries = read_manifest(Cursor::new(text)).unwrap();
+    let algos = entries.iter().map(|e| e.digest.algo).collect::<Vec<_>>();
+    assert_eq!(algos, [HashAlgo::Xxh3, HashAlgo::Crc32, HashAlgo::Sha1, HashAlgo::Sha256]);
+    assert_eq!(entries[1].digest.hex, "deadbeef");
+    assert_eq!(entries[2].path, PathBuf::from("data/empty.bin"));
+    assert_eq!(entries[3].path, PathBuf::from("has spaces.bin"));
+
+    // Written back out, the sha1 and sha256 lines are bare, like sha1sum's,
+    // and the others say which algorithm they used
+    let written = entries.iter().map(|e| format!("{}\n", e)).collect::<String>();
+    assert_eq!(written, "\
+        xxh3:0123456789abcdef  audio/title.dsp\n\
+        crc32:deadbeef  opening.bnr\n\
+        da39a3ee5e6b4b0d3255bfef95601890afd80709  data/empty.bin\n\
+        e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  has spaces.bin\n");
+    assert_eq!(read_manifest(Cursor::new(written)).unwrap(), entries);
+    // Unless every line's asked to
+    let prefixed = entries.iter().map(|e| format!("{:#}\n", e)).collect::<String>();
+    assert!(prefixed.lines().all(|l| l.contains(':')));
+    assert_eq!(read_manifest(Cursor::new(prefixed)).unwrap(), entries);
+
+    assert!(read_manifest(Cursor::new("xxh3:0123  a.bin\n")).is_err());
+    assert!(read_manifest(Cursor::new("0123456789abcdef\n")).is_err());
+}
+
+#[test]
+#[cfg(all(feature = "crc32", feature = "md5", feature = "sha1", feature = "xxh3"))]
+fn roms_verify_against_mixed_manifests() {
+    use std::path::Path;
+
+    use gcmod::hash::{ManifestEntry, CHANGE_DETECTION_ALGO};
+    use gcmod::Game;
+
+    use common::TestRom;
+
+    let rom = TestRom::new("hash-manifest");
+    let data = rom.data();
+    let game = Game::open(Cursor::new(&data), 0).unwrap();
+    let manifest = game.hash_files(Cursor::new(&data), CHANGE_DETECTION_ALGO).unwrap();
+    assert!(manifest.iter().all(|e| e.digest.algo == HashAlgo::Xxh3));
+
+    // Rehash some of the entries with other algorithms, like a manifest
+    // that's been partly upgraded
+    let sha1 = game.hash_files(Cursor::new(&data), HashAlgo::Sha1).unwrap();
+    let crc32 = game.hash_files(Cursor::new(&data), HashAlgo::Crc32).unwrap();
+    let mixed = manifest.iter().enumerate()
+        .map(|(i, e)| match i % 3 {
+            0 => e.clone(),
+            1 => sha1[i].clone(),
+            _ => crc32[i].clone(),
+        })
+        .collect::<Vec<_>>();
+    let report = game.verify_manifest(Cursor::new(&data), &mixed).unwrap();
+    assert!(report.is_unchanged(), "{:?}", report);
+    assert_eq!(report.matching.len(), manifest.len());
+
+    // A wrong digest, a file that isn't listed, and one that isn't on the ROM
+    let mut changed = mixed.clone();
+    let title = changed.iter().position(|e| e.path == Path::new("audio/title.dsp")).unwrap();
+    changed[title].digest = Digest::of(changed[title].digest.algo, &b"something else"[..]).unwrap();
+    let unlisted = changed.remove(0).path;
+    changed.push(ManifestEntry {
+        digest: Digest::of(HashAlgo::Md5, &b""[..]).unwrap(),
+        path: PathBuf::from("not/on/the/rom.bin"),
+    });
+    let report = game.verify_manifest(Cursor::new(&data), &changed).unwrap();
+    assert_eq!(report.modified, [PathBuf::from("audio/title.dsp")]);
+    assert_eq!(report.missing, [unlisted]);
+    assert_eq!(report.extra, [PathBuf::from("not/on/the/rom.bin")]);
+}
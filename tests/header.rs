@@ -0,0 +1,119 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::io::{self, Cursor};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use gcmod::sections::header::{
+    Header,
+    Region,
+    COUNTRY_PAL,
+    DEFAULT_SIMULATED_MEMORY,
+    GAME_HEADER_SIZE,
+    WII_MAGIC_WORD,
+};
+use gcmod::Game;
+
+use common::write_header;
+
+// The disc header information starts at 0x440, after 4 unused bytes
+fn header_with_information() -> Vec<u8> {
+    let mut data = Vec::new();
+    write_header(&mut data).unwrap();
+    for (i, value) in [0, DEFAULT_SIMULATED_MEMORY, 0x1234, 0, 0x5678, 0x9abc, COUNTRY_PAL, 0].iter().enumerate() {
+        BigEndian::write_u32(&mut data[0x440 + i * 4..], *value);
+    }
+    data
+}
+
+#[test]
+fn header_information_is_read_from_0x440() {
+    let header = Header::new(Cursor::new(header_with_information()), 0).unwrap();
+    let info = &header.information;
+    assert_eq!(info.debug_monitor_size, 0);
+    assert_eq!(info.simulated_memory_size, DEFAULT_SIMULATED_MEMORY);
+    assert_eq!(info.argument_offset, 0x1234);
+    assert_eq!(info.track_location, 0x5678);
+    assert_eq!(info.track_size, 0x9abc);
+    assert_eq!(info.country_code, COUNTRY_PAL);
+}
+
+#[test]
+fn headers_round_trip() {
+    let data = header_with_information();
+    let header = Header::new(Cursor::new(&data), 0).unwrap();
+    let mut written = Vec::new();
+    header.write(&mut written).unwrap();
+    assert_eq!(written.len(), GAME_HEADER_SIZE);
+    assert!(written == data, "the header changed when it was written back out");
+}
+
+#[test]
+fn blank_headers_round_trip() {
+    let mut header = Header::blank("GTSP", "01", "Blank").unwrap();
+    // These have to look valid for the header to be read back
+    header.dol_offset = 0x10000;
+    header.fst_offset = 0x8000;
+    let mut data = Vec::new();
+    header.write(&mut data).unwrap();
+    assert_eq!(BigEndian::read_u32(&data[0x444..]), DEFAULT_SIMULATED_MEMORY);
+    assert_eq!(BigEndian::read_u32(&data[0x458..]), COUNTRY_PAL);
+
+    let read = Header::new(Cursor::new(&data), 0).unwrap();
+    assert_eq!(read.game_code, "GTSP");
+    assert_eq!(read.title, "Blank");
+    assert_eq!(read.region(), Region::Pal);
+    assert_eq!(read.information.simulated_memory_size, DEFAULT_SIMULATED_MEMORY);
+    assert_eq!(read.information.country_code, COUNTRY_PAL);
+}
+
+fn open_error(data: &[u8]) -> io::Error {
+    let e = Game::open(Cursor::new(data), 0).expect_err("the image was opened");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", e);
+    e
+}
+
+#[test]
+fn zeroed_files_are_not_images() {
+    let e = open_error(&[0; 0x2440]);
+    assert_eq!(e.to_string(), "unrecognized file (no GameCube magic word at 0x1c)");
+}
+
+#[test]
+fn truncated_headers_are_too_small() {
+    let mut data = Vec::new();
+    write_header(&mut data).unwrap();
+    for &len in &[0, 0x10, 0x1f] {
+        let e = open_error(&data[..len]);
+        assert_eq!(e.to_string(), "the file is too small to be a GameCube image");
+    }
+    // Past the magic word, but cut off before the rest of the header
+    let e = Game::open(Cursor::new(&data[..0x100]), 0).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof, "{}", e);
+}
+
+#[test]
+fn wii_discs_are_recognized() {
+    let mut data = Vec::new();
+    write_header(&mut data).unwrap();
+    BigEndian::write_u32(&mut data[0x18..], WII_MAGIC_WORD);
+    // Wii discs don't have the GameCube magic word
+    BigEndian::write_u32(&mut data[0x1c..], 0);
+    let e = open_error(&data);
+    assert!(e.to_string().contains("Wii discs aren't supported"), "{}", e);
+}
+
+#[test]
+fn bad_dol_and_fst_offsets_are_rejected() {
+    let mut data = Vec::new();
+    write_header(&mut data).unwrap();
+    for &(at, offset, name) in &[(0x420, 0, "DOL"), (0x420, 0x10002, "DOL"), (0x424, 0x8000_0000, "FST")] {
+        let mut data = data.clone();
+        BigEndian::write_u32(&mut data[at..], offset);
+        let e = open_error(&data);
+        assert_eq!(e.to_string(), format!("the {} offset, {:#x}, isn't valid", name, offset));
+    }
+}
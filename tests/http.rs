@@ -0,0 +1,115 @@
+#![cfg(feature = "http")]
+
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use gcmod::sections::ExtractOutcome;
+use gcmod::source::http::HttpImage;
+use gcmod::source::{CountingReader, ImageSource};
+use gcmod::Game;
+
+use common::{pattern, TestRom};
+
+const BIG_FILE_SIZE: usize = 8 * 1024 * 1024;
+// Everything here together shouldn't need more than a few 64KiB blocks
+const BYTE_BUDGET: u64 = 512 * 1024;
+
+// Serves `data` over HTTP with range requests, and returns the URL for it and
+// how many bytes of it have been sent
+fn serve(data: Vec<u8>) -> (String, Arc<AtomicU64>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/rom.iso", listener.local_addr().unwrap());
+    let sent = Arc::new(AtomicU64::new(0));
+    let counter = sent.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            respond(stream.unwrap(), &data, &counter);
+        }
+    });
+    (url, sent)
+}
+
+fn respond(mut stream: TcpStream, data: &[u8], sent: &AtomicU64) {
+    let mut request = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    request.read_line(&mut line).unwrap();
+    let method = line.split_whitespace().next().unwrap_or("").to_string();
+    let mut range = None;
+    loop {
+        line.clear();
+        if request.read_line(&mut line).unwrap() == 0 || line.trim_end().is_empty() { break }
+        if let Some(r) = line.trim_end().strip_prefix("Range: bytes=") {
+            let (start, end) = r.split_once('-').unwrap();
+            range = Some((start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap()));
+        }
+    }
+
+    match (&method[..], range) {
+        ("HEAD", _) => {
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", data.len()).unwrap();
+        },
+        ("GET", Some((start, end))) => {
+            let body = &data[start..=end];
+            write!(
+                stream,
+                "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                body.len(), start, end, data.len(),
+            ).unwrap();
+            stream.write_all(body).unwrap();
+            sent.fetch_add(body.len() as u64, Ordering::SeqCst);
+        },
+        _ => write!(stream, "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n").unwrap(),
+    }
+}
+
+// Opening the ROM, listing it, finding an offset, and extracting one small
+// file only read the parts of the image they need, not the big file in the
+// middle of it
+#[test]
+fn reads_stay_under_the_byte_budget() {
+    let small = pattern(1000, 2);
+    let rom = TestRom::with_files(
+        "http",
+        &[("a/big.bin", pattern(BIG_FILE_SIZE, 1)), ("b/small.bin", small.clone())],
+        32,
+    );
+    let data = rom.data();
+    let len = data.len() as u64;
+    let (url, sent) = serve(data);
+
+    let mut iso = CountingReader::new(HttpImage::open(&url).unwrap());
+    assert_eq!(iso.len().unwrap(), len);
+    let game = Game::open(&mut iso, 0).unwrap();
+
+    // ls
+    let names = game.fst.root().iter_contents(&game.fst.entries)
+        .map(|e| e.info().name.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(names.len(), 2);
+
+    // find-offset, for somewhere in the middle of the big file
+    let big = game.fst.entry_for_path("a/big.bin").and_then(|e| e.as_file()).unwrap();
+    let layout = game.rom_layout();
+    assert!(layout.resolve(big.file_offset + BIG_FILE_SIZE as u64 / 2).is_some());
+
+    // extract, of a file after the big one
+    let mut out = Vec::new();
+    let outcome = game.extract_section_to("b/small.bin", &mut out, &mut iso).unwrap();
+    assert!(matches!(outcome, ExtractOutcome::Extracted(_)));
+    assert!(out == small);
+
+    let bytes_read = iso.bytes_read;
+    let image = iso.into_inner();
+    assert!(bytes_read < BYTE_BUDGET, "{} bytes were read", bytes_read);
+    assert!(image.bytes_fetched < BYTE_BUDGET, "{} bytes were fetched", image.bytes_fetched);
+    assert_eq!(image.bytes_fetched, sent.load(Ordering::SeqCst));
+    assert!(len > 10 * BYTE_BUDGET);
+}
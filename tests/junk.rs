@@ -0,0 +1,142 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::fs;
+use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+
+use gcmod::junk::{JunkCheck, JunkSeed};
+use gcmod::{DiscSize, ExtractMode, Game, GapFill, OrderStrategy, RebuildPlan, ROMRebuilder, SystemFileOverrides};
+
+use common::{sample_files, TestDir, TestRom};
+
+// Big enough that there are gaps between the files
+const ALIGNMENT: u64 = 0x8000;
+
+fn plan_for(root: &Path) -> RebuildPlan {
+    ROMRebuilder::plan(
+        root,
+        ALIGNMENT,
+        Some(&OrderStrategy::FstOrder),
+        false,
+        |_: &Path| false,
+        SystemFileOverrides::default(),
+        DiscSize::Fit,
+    ).unwrap()
+}
+
+fn write(plan: &RebuildPlan) -> Vec<u8> {
+    let mut rom = Vec::new();
+    plan.write(&mut rom, |_, _| {}).unwrap();
+    rom
+}
+
+// The ROM, extracted
+fn extracted(rom: &TestRom) -> PathBuf {
+    let data = rom.data();
+    let root = rom.dir.join("extracted");
+    let mut game = Game::open(Cursor::new(&data), 0).unwrap();
+    game.extract(BufReader::new(Cursor::new(&data)), &root, ExtractMode::FailIfExists).unwrap();
+    root
+}
+
+#[test]
+fn rebuilt_junk_passes_the_check() {
+    let rom = TestRom::with_files("junk-check", &sample_files(), ALIGNMENT);
+    let data = rom.data();
+    let game = Game::open(Cursor::new(&data), 0).unwrap();
+
+    // The test ROM's gaps are zeros
+    let check = game.check_junk(Cursor::new(&data)).unwrap();
+    assert!(check.sampled > 0);
+    assert!(check.mismatch.is_some());
+    assert!(!check.matches());
+
+    let mut plan = plan_for(&rom.root);
+    plan.gap_fill = GapFill::Junk(JunkSeed::from_header(&game.header));
+    let junk = write(&plan);
+    assert_eq!(junk.len(), data.len());
+    let game = Game::open(Cursor::new(&junk), 0).unwrap();
+    let check = game.check_junk(Cursor::new(&junk)).unwrap();
+    assert!(check.matches(), "{:?}", check);
+
+    // Only the gaps changed
+    for (offset, size) in plan.padding() {
+        let (start, end) = (offset as usize, (offset + size) as usize);
+        assert!(data[start..end].iter().all(|&b| b == 0));
+        assert!(junk[start..end].iter().any(|&b| b != 0), "the gap at {:#x} wasn't filled", offset);
+    }
+    let zeroed = plan.padding().iter().fold(junk.clone(), |mut rom, &(offset, size)| {
+        rom[offset as usize..(offset + size) as usize].iter_mut().for_each(|b| *b = 0);
+        rom
+    });
+    assert!(zeroed == data);
+}
+
+#[test]
+fn check_is_saved_and_loaded() {
+    let dir = TestDir::new("junk-save");
+    let root = dir.join("root");
+    fs::create_dir_all(root.join("&&systemdata")).unwrap();
+    assert_eq!(JunkCheck::load(&root).unwrap(), None);
+
+    let check = JunkCheck {
+        sampled: 0x3000,
+        mismatch: Some(0x12_3456),
+        source: Some(PathBuf::from("/roms/some game.iso")),
+    };
+    check.save(&root).unwrap();
+    assert_eq!(JunkCheck::load(&root).unwrap(), Some(check));
+
+    let check = JunkCheck { sampled: 0x1000, mismatch: None, source: None };
+    check.save(&root).unwrap();
+    assert_eq!(JunkCheck::load(&root).unwrap(), Some(check));
+
+    fs::write(root.join(gcmod::paths::JUNK_CHECK_PATH), "sampled\tlots\n").unwrap();
+    assert!(JunkCheck::load(&root).is_err());
+}
+
+#[test]
+fn mismatched_junk_is_copied_from_the_source() {
+    let rom = TestRom::with_files("junk-copy", &sample_files(), ALIGNMENT);
+    let root = extracted(&rom);
+    let game = Game::open(Cursor::new(rom.data()), 0).unwrap();
+    let mut check = game.check_junk(Cursor::new(rom.data())).unwrap();
+    check.source = Some(rom.iso.clone());
+    check.save(&root).unwrap();
+
+    let mut plan = plan_for(&root);
+    plan.fill_gaps_with_junk(&root).unwrap();
+    assert_eq!(plan.gap_fill, GapFill::CopyFromSource(rom.iso.clone()));
+    assert_eq!(plan.warnings.len(), 1);
+    assert!(write(&plan) == rom.data());
+
+    // Without the source, there's nothing to copy
+    check.source = Some(rom.dir.join("gone.iso"));
+    check.save(&root).unwrap();
+    let mut plan = plan_for(&root);
+    plan.fill_gaps_with_junk(&root).unwrap();
+    assert_eq!(plan.gap_fill, GapFill::Zeros);
+    assert_eq!(plan.warnings.len(), 1);
+}
+
+#[test]
+fn matching_or_missing_checks_use_junk() {
+    let rom = TestRom::with_files("junk-fill", &sample_files(), ALIGNMENT);
+    let root = extracted(&rom);
+    let game = Game::open(Cursor::new(rom.data()), 0).unwrap();
+    let seed = JunkSeed::from_header(&game.header);
+
+    let mut plan = plan_for(&root);
+    plan.fill_gaps_with_junk(&root).unwrap();
+    assert_eq!(plan.gap_fill, GapFill::Junk(seed));
+    assert_eq!(plan.warnings.len(), 1);
+
+    JunkCheck { sampled: 0x1000, mismatch: None, source: None }.save(&root).unwrap();
+    let mut plan = plan_for(&root);
+    plan.fill_gaps_with_junk(&root).unwrap();
+    assert_eq!(plan.gap_fill, GapFill::Junk(seed));
+    assert!(plan.warnings.is_empty(), "{:?}", plan.warnings);
+}
@@ -0,0 +1,163 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::io::Cursor;
+
+use gcmod::sections::dol::segment::SegmentType;
+use gcmod::sections::ResolvedSection;
+use gcmod::Game;
+
+use common::{pattern, TestRom, TEXT_ADDRESS};
+
+const LAYOUTS: usize = 20;
+
+// xorshift, so every run tries the same layouts
+struct Rng(u32);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as usize % n
+    }
+}
+
+// A ROM with a random number of files of random sizes, some of them empty,
+// with gaps between them from the alignment
+fn random_rom(rng: &mut Rng, n: usize) -> (TestRom, Vec<u8>) {
+    let names = (0..1 + rng.below(30))
+        .map(|i| format!("dir{}/file{}.bin", rng.below(4), i))
+        .collect::<Vec<_>>();
+    let files = names.iter().enumerate()
+        .map(|(i, name)| {
+            let size = if rng.below(5) == 0 { 0 } else { 1 + rng.below(5000) };
+            (name.as_str(), pattern(size, i))
+        })
+        .collect::<Vec<_>>();
+    let alignment = [4, 32, 0x800, 0x8000][rng.below(4)];
+    let rom = TestRom::with_files(&format!("layout-{}", n), &files, alignment);
+    let data = rom.data();
+    (rom, data)
+}
+
+// Offsets at the start, end and somewhere in the middle of `start..end`
+fn offsets_in(rng: &mut Rng, start: u64, end: u64) -> [u64; 3] {
+    [start, end - 1, start + rng.below((end - start) as usize) as u64]
+}
+
+#[test]
+fn offsets_in_sections_are_found() {
+    let mut rng = Rng(0x1234_5678);
+    for n in 0..LAYOUTS {
+        let (_rom, data) = random_rom(&mut rng, n);
+        let game = Game::open(Cursor::new(data), 0).unwrap();
+        let layout = game.rom_layout();
+        assert!(!layout.is_empty());
+
+        for s in layout.iter().filter(|s| s.size() > 0) {
+            for offset in offsets_in(&mut rng, s.start(), s.start() + s.size() as u64) {
+                let found = layout.find_offset(offset)
+                    .unwrap_or_else(|| panic!("{:#x} in {} wasn't found", offset, s.name()));
+                assert_eq!((found.start(), found.size()), (s.start(), s.size()), "at {:#x}", offset);
+
+                // And the index points at the same section
+                let i = layout.index_of_offset(offset).unwrap();
+                assert_eq!(layout[i].start(), s.start());
+            }
+        }
+    }
+}
+
+#[test]
+fn offsets_in_gaps_are_not_found() {
+    let mut rng = Rng(0x9abc_def0);
+    let mut gaps = 0;
+    for n in 0..LAYOUTS {
+        let (_rom, data) = random_rom(&mut rng, n + LAYOUTS);
+        let game = Game::open(Cursor::new(data), 0).unwrap();
+        let layout = game.rom_layout();
+
+        for gap in layout.gaps(game.image_size + 0x1000) {
+            for offset in offsets_in(&mut rng, gap.start, gap.end) {
+                assert!(layout.find_offset(offset).is_none(), "{:#x} is in a gap", offset);
+            }
+            gaps += 1;
+        }
+    }
+    assert!(gaps > LAYOUTS);
+}
+
+// Empty files don't take up any space, so nothing's ever found in one
+#[test]
+fn empty_files_are_never_found() {
+    let mut rng = Rng(0x0f0f_0f0f);
+    for n in 0..LAYOUTS {
+        let (_rom, data) = random_rom(&mut rng, n + 2 * LAYOUTS);
+        let game = Game::open(Cursor::new(data), 0).unwrap();
+        let layout = game.rom_layout();
+
+        for s in layout.iter().filter(|s| s.size() == 0) {
+            if let Some(found) = layout.find_offset(s.start()) {
+                assert!(found.size() > 0);
+            }
+        }
+    }
+}
+
+// What the layout says about offsets in each kind of section on the sample
+// ROM, and in the space between them
+#[test]
+fn offsets_resolve_to_their_sections() {
+    let rom = TestRom::new("layout-resolve");
+    let game = Game::open(Cursor::new(rom.data()), 0).unwrap();
+    let layout = game.rom_layout();
+
+    // A file
+    let file = game.fst.entry_for_path("audio/bgm/stage1.adp").and_then(|e| e.as_file()).unwrap();
+    let offset = file.file_offset + 0x4380;
+    let found = layout.file_at(offset).unwrap();
+    assert_eq!(found.info.full_path, file.info.full_path);
+    let resolved = layout.resolve(offset).unwrap();
+    assert_eq!(resolved.section().relative_offset(offset), 0x4380);
+    assert_eq!(resolved.fst_index(offset), Some(file.info.index));
+    assert_eq!(resolved.address(offset), None);
+    assert!(layout.segment_at(offset).is_none());
+
+    // A DOL segment
+    let text = game.dol.iter_segments().find(|s| s.seg_type == SegmentType::Text).unwrap();
+    let offset = text.offset + 0x10;
+    let segment = layout.segment_at(offset).unwrap();
+    assert_eq!((segment.seg_type, segment.seg_num), (SegmentType::Text, 0));
+    let resolved = layout.resolve(offset).unwrap();
+    assert_eq!(resolved.section().relative_offset(offset), 0x10);
+    assert_eq!(resolved.address(offset), Some(TEXT_ADDRESS + 0x10));
+    assert_eq!(resolved.fst_index(offset), None);
+    assert!(layout.file_at(offset).is_none());
+
+    // The DOL's header, before its segments
+    assert!(matches!(layout.resolve(game.dol.offset + 4), Some(ResolvedSection::Dol(_))));
+
+    // The FST's entries, which are 12 bytes each, and then its string table
+    let fst_offset = game.header.fst_offset;
+    let resolved = layout.resolve(fst_offset + 12 * 3 + 5).unwrap();
+    assert!(matches!(resolved, ResolvedSection::Fst(_)));
+    assert_eq!(resolved.fst_index(fst_offset + 12 * 3 + 5), Some(3));
+    let names = fst_offset + 12 * game.fst.entries.len() as u64;
+    assert!(matches!(layout.resolve(names), Some(ResolvedSection::Fst(_))));
+    assert_eq!(layout.resolve(names).unwrap().fst_index(names), None);
+
+    // The header
+    let resolved = layout.resolve(0x20).unwrap();
+    assert!(matches!(resolved, ResolvedSection::Header(_)));
+    assert_eq!(resolved.section().relative_offset(0x20), 0x20);
+
+    // A gap, after the last file
+    let gap = layout.gaps(game.image_size).into_iter().next_back().unwrap();
+    assert!(gap.after.is_none());
+    assert!(layout.resolve(gap.start).is_none());
+    assert!(layout.file_at(gap.start).is_none());
+    assert!(layout.segment_at(gap.start).is_none());
+}
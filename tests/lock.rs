@@ -0,0 +1,150 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gcmod::lock::Lock;
+
+use common::TestDir;
+
+#[test]
+fn held_locks_name_their_holder() {
+    let dir = TestDir::new("lock-holder");
+    let target = dir.join("rom.iso");
+    let lock = Lock::acquire(&target, "rebuild root rom.iso", None).unwrap();
+
+    let holder = Lock::holder(&target).unwrap().unwrap();
+    assert_eq!(holder.pid, process::id());
+    assert_eq!(holder.command, "rebuild root rom.iso");
+
+    let e = Lock::acquire(&target, "extract rom.iso", None).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+    assert!(e.to_string().contains("rebuild root rom.iso"), "{}", e);
+
+    drop(lock);
+    assert!(Lock::holder(&target).unwrap().is_none());
+    Lock::acquire(&target, "extract rom.iso", None).unwrap();
+}
+
+#[test]
+fn waiting_gets_the_lock_once_its_released() {
+    let dir = TestDir::new("lock-wait");
+    let target = dir.join("root");
+    let lock = Lock::acquire(&target, "first", None).unwrap();
+    let release = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        drop(lock);
+    });
+    Lock::acquire(&target, "second", Some(Duration::from_secs(10))).unwrap();
+    release.join().unwrap();
+
+    // And times out if it isn't
+    let _held = Lock::acquire(dir.join("other"), "first", None).unwrap();
+    let e = Lock::acquire(dir.join("other"), "second", Some(Duration::from_millis(200))).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+}
+
+// A holder that hasn't finished writing its details still holds the lock
+#[test]
+fn half_written_locks_arent_stale() {
+    let dir = TestDir::new("lock-partial");
+    let target = dir.join("rom.iso");
+    let _lock = Lock::acquire(&target, "rebuild", None).unwrap();
+    let lock_file = dir.join(".rom.iso.gcmod-lock");
+    fs::write(&lock_file, "12").unwrap();
+
+    let e = Lock::acquire(&target, "extract", None).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+    assert_eq!(fs::read(&lock_file).unwrap(), b"12");
+}
+
+// A lock file that's left behind, but isn't locked, is from a holder that's
+// gone
+#[test]
+fn leftover_lock_files_are_stale() {
+    let dir = TestDir::new("lock-stale");
+    let target = dir.join("rom.iso");
+    fs::write(dir.join(".rom.iso.gcmod-lock"), "1\n0\nsomething that crashed\n").unwrap();
+    Lock::acquire(&target, "rebuild", None).unwrap();
+}
+
+// Run by `killed_holders_release_the_lock` in a process of its own, to take
+// the lock and then hang on to it until it's killed
+#[test]
+#[ignore]
+fn hold_lock_until_killed() {
+    let target = match env::var_os("GCMOD_LOCK_TARGET") {
+        Some(t) => PathBuf::from(t),
+        None => return,
+    };
+    let _lock = Lock::acquire(&target, "held until killed", None).unwrap();
+    fs::write(target.with_extension("ready"), "").unwrap();
+    thread::sleep(Duration::from_secs(60));
+}
+
+#[test]
+fn killed_holders_release_the_lock() {
+    let dir = TestDir::new("lock-killed");
+    let target = dir.join("rom.iso");
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(["hold_lock_until_killed", "--exact", "--ignored"])
+        .env("GCMOD_LOCK_TARGET", &target)
+        .spawn()
+        .unwrap();
+
+    let start = Instant::now();
+    while !dir.join("rom.ready").exists() {
+        assert!(start.elapsed() < Duration::from_secs(30), "the other process never took the lock");
+        thread::sleep(Duration::from_millis(10));
+    }
+    let holder = Lock::holder(&target).unwrap().unwrap();
+    assert_eq!(holder.pid, child.id());
+    let e = Lock::acquire(&target, "rebuild", None).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+    assert!(e.to_string().contains("held until killed"), "{}", e);
+
+    // It never gets to clean up, but the lock's gone with it
+    child.kill().unwrap();
+    child.wait().unwrap();
+    Lock::acquire(&target, "rebuild", Some(Duration::from_secs(10))).unwrap();
+}
+
+#[test]
+fn only_one_acquirer_holds_the_lock_at_a_time() {
+    const THREADS: usize = 4;
+    const ROUNDS: usize = 20;
+
+    let dir = TestDir::new("lock-contention");
+    let target = Arc::new(dir.join("rom.iso"));
+    let holders = Arc::new(AtomicUsize::new(0));
+    let acquired = Arc::new(AtomicUsize::new(0));
+    let barrier = Arc::new(Barrier::new(THREADS));
+
+    let threads = (0..THREADS).map(|i| {
+        let (target, holders, acquired, barrier) = (target.clone(), holders.clone(), acquired.clone(), barrier.clone());
+        thread::spawn(move || {
+            barrier.wait();
+            for _ in 0..ROUNDS {
+                let _lock = Lock::acquire(&*target, &format!("thread {}", i), Some(Duration::from_secs(30))).unwrap();
+                assert_eq!(holders.fetch_add(1, Ordering::SeqCst), 0, "two threads held the lock at once");
+                acquired.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(1));
+                holders.fetch_sub(1, Ordering::SeqCst);
+            }
+        })
+    }).collect::<Vec<_>>();
+    for t in threads {
+        t.join().unwrap();
+    }
+    assert_eq!(acquired.load(Ordering::SeqCst), THREADS * ROUNDS);
+}
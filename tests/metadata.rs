@@ -0,0 +1,20 @@
+extern crate byteorder;
+extern crate gcmod;
+extern crate serde_json;
+
+mod common;
+
+use std::io::Cursor;
+
+use gcmod::Game;
+
+use common::TestRom;
+
+// Everything that was parsed from the sample ROM, and nothing that wasn't
+#[test]
+fn metadata_matches_snapshot() {
+    let rom = TestRom::new("metadata");
+    let game = Game::open(Cursor::new(rom.data()), 0).unwrap();
+    let json = serde_json::to_string_pretty(&game.metadata()).unwrap();
+    assert_eq!(json + "\n", include_str!("fixtures/metadata.json"));
+}
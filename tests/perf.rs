@@ -0,0 +1,76 @@
+// Rough checks that nothing's gotten 10x slower, for when the benchmarks
+// aren't being run. The limits are about 10 times what a debug build takes.
+// The large FST makes 50,000 files, so it's ignored by default:
+// cargo test --test perf -- --ignored
+
+extern crate byteorder;
+extern crate gcmod;
+
+#[path = "../benches/fixture/mod.rs"]
+mod fixture;
+
+use std::fs::File;
+use std::io::{self, BufReader, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use gcmod::hash::{hash_reader, HashAlgo};
+use gcmod::{extract_section, Game, OrderStrategy, ROMRebuilder, MIN_ALIGNMENT};
+
+use fixture::Fixture;
+
+fn open_game(iso: &Path) -> (Game, BufReader<File>) {
+    let mut iso = BufReader::new(File::open(iso).unwrap());
+    let game = Game::open(&mut iso, 0).unwrap();
+    (game, iso)
+}
+
+// Runs `f`, and fails if it takes longer than `limit`
+fn time<T>(what: &str, limit: Duration, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    assert!(elapsed < limit, "{} took {:?}, which is over {:?}", what, elapsed, limit);
+    result
+}
+
+#[test]
+#[ignore]
+fn opening_a_large_fst() {
+    let fixture = Fixture::new("perf-large", 100, 500, 64, MIN_ALIGNMENT).unwrap();
+    let (game, _) = time("Game::open (50k entries)", Duration::from_secs(4), || open_game(&fixture.iso));
+    assert_eq!(game.fst.file_count, 50_000);
+
+    let last = Path::new("/").join(fixture.files.last().unwrap());
+    time("1000 lookups", Duration::from_millis(500), || {
+        for _ in 0..1000 {
+            assert!(game.fst.entry_for_path(&last).is_some());
+        }
+    });
+}
+
+#[test]
+fn extracting_and_rebuilding_small_files() {
+    let fixture = Fixture::new("perf-small", 10, 100, 16 * 1024, 32).unwrap();
+    let (game, mut iso) = open_game(&fixture.iso);
+    let files = game.fst.entries.iter().filter_map(|e| e.as_file()).collect::<Vec<_>>();
+    assert_eq!(files.len(), 1000);
+
+    time("extracting 1000 files", Duration::from_millis(300), || {
+        for f in &files {
+            iso.seek(SeekFrom::Start(f.file_offset)).unwrap();
+            extract_section(&mut iso, f.size, io::sink()).unwrap();
+        }
+    });
+    time("rebuilding 1000 files", Duration::from_millis(300), || {
+        ROMRebuilder::rebuild(&fixture.root, 32, io::sink(), Some(&OrderStrategy::FstOrder), false, |_| false, |_, _| {}).unwrap()
+    });
+}
+
+#[test]
+fn hashing_16mib() {
+    let data = (0..16 * 1024 * 1024).map(|i| (i * 31 % 251) as u8).collect::<Vec<_>>();
+    for &algo in HashAlgo::ALL.iter().filter(|a| a.is_supported()) {
+        time(algo.name(), Duration::from_secs(5), || hash_reader(&[algo], &data[..]).unwrap());
+    }
+}
@@ -0,0 +1,206 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Cursor, Write};
+use std::path::Path;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use gcmod::paths::HEADER_PATH;
+use gcmod::{DiscSize, ExtractMode, Game, OrderStrategy, RebuildPlan, ROMRebuilder, SystemFileOverrides};
+
+use common::{pattern, read_tree, write_header, write_root, TestDir, TestRom};
+
+// Longer than anything here should take, so a rebuild that's still going
+// after this is stuck
+const TIMEOUT: Duration = Duration::from_secs(30);
+const BIG_FILE_SIZE: usize = 3 * 1024 * 1024;
+const STREAM_ALIGNMENT: u64 = 0x8000;
+// The audio streaming flag in ISO.hdr
+const AUDIO_STREAMING_OFFSET: usize = 8;
+
+fn plan(root: &Path) -> RebuildPlan {
+    ROMRebuilder::plan(
+        root,
+        32,
+        Some(&OrderStrategy::FstOrder),
+        false,
+        |_: &Path| false,
+        SystemFileOverrides::default(),
+        DiscSize::Fit,
+    ).unwrap()
+}
+
+// Writes the plan on another thread, and fails if it doesn't finish in time
+fn write_with_timeout(plan: RebuildPlan, output: impl Write + Send + 'static) -> io::Result<()> {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let _ = sender.send(plan.write(output, |_, _| {}).map(|_| ()));
+    });
+    receiver.recv_timeout(TIMEOUT).expect("the rebuild hung")
+}
+
+// Fails once it's been given `limit` bytes
+struct FailingWriter {
+    written: usize,
+    limit: usize,
+}
+
+impl Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written + buf.len() > self.limit {
+            return Err(io::Error::other("the disk is full"));
+        }
+        self.written += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn read_failures_stop_the_rebuild() {
+    let rom = TestRom::with_files("rebuild-read-error", &[("big.bin", pattern(BIG_FILE_SIZE, 1))], 32);
+    let plan = plan(&rom.root);
+
+    // The file's cut short after it's planned, so it fails partway through
+    // being read, after some of it's already been sent to the writer
+    OpenOptions::new().write(true).open(rom.root.join("big.bin")).unwrap()
+        .set_len(BIG_FILE_SIZE as u64 / 2).unwrap();
+    let e = write_with_timeout(plan, io::sink()).unwrap_err();
+    assert!(e.to_string().contains("big.bin"), "{}", e);
+}
+
+#[test]
+fn write_failures_stop_the_reader() {
+    let rom = TestRom::with_files("rebuild-write-error", &[("big.bin", pattern(BIG_FILE_SIZE, 2))], 32);
+    let plan = plan(&rom.root);
+    let e = write_with_timeout(plan, FailingWriter { written: 0, limit: BIG_FILE_SIZE / 2 }).unwrap_err();
+    assert!(e.to_string().contains("the disk is full"), "{}", e);
+}
+
+// Rebuilds `root` into memory
+fn rebuild(root: &Path) -> Vec<u8> {
+    let mut data = Vec::new();
+    plan(root).write(Cursor::new(&mut data), |_, _| {}).unwrap();
+    data
+}
+
+#[test]
+fn empty_files_and_directories_round_trip() {
+    let dir = TestDir::new("rebuild-empty");
+    let root = dir.join("root");
+    // The empty file is the first one, so it's right after the system data
+    let files = [("a_empty.bin", Vec::new()), ("b.bin", pattern(500, 1)), ("dir/c_empty.bin", Vec::new())];
+    write_root(&root, &files).unwrap();
+    fs::create_dir_all(root.join("empty_dir")).unwrap();
+    fs::create_dir_all(root.join("dir/nested_empty_dir")).unwrap();
+    let data = rebuild(&root);
+
+    // The header's still there, and wasn't replaced by an empty file at 0
+    let mut header = Vec::new();
+    write_header(&mut header).unwrap();
+    assert!(data[..0x20] == header[..0x20]);
+
+    let mut game = Game::open(Cursor::new(&data), 0).unwrap();
+    for path in &["a_empty.bin", "dir/c_empty.bin"] {
+        let file = game.fst.entry_for_path(path).and_then(|e| e.as_file()).unwrap();
+        assert_eq!(file.size, 0, "{}", path);
+    }
+    for path in &["empty_dir", "dir/nested_empty_dir"] {
+        let d = game.fst.entry_for_path(path).and_then(|e| e.as_dir()).unwrap();
+        assert_eq!(d.iter_contents(&game.fst.entries).count(), 0, "{}", path);
+    }
+
+    // Extracting makes the same files and directories
+    let iso_path = dir.join("rom.iso");
+    fs::write(&iso_path, &data).unwrap();
+    let extracted = dir.join("extracted");
+    let iso = BufReader::new(File::open(&iso_path).unwrap());
+    game.extract(iso, &extracted, ExtractMode::FailIfExists).unwrap();
+    let without_system_data = |d: &Path| read_tree(d).into_iter()
+        .filter(|(p, _)| !p.starts_with("&&systemdata"))
+        .collect::<Vec<_>>();
+    assert!(without_system_data(&extracted) == without_system_data(&root));
+    assert!(extracted.join("empty_dir").is_dir());
+    assert!(extracted.join("dir/nested_empty_dir").is_dir());
+
+    // And rebuilding that gives the same ROM
+    assert!(rebuild(&extracted) == data);
+}
+
+fn plan_with_alignment(root: &Path, alignment: u64, stream_alignment: Option<bool>) -> RebuildPlan {
+    let overrides = SystemFileOverrides { stream_alignment, ..SystemFileOverrides::default() };
+    ROMRebuilder::plan(
+        root,
+        alignment,
+        Some(&OrderStrategy::FstOrder),
+        false,
+        |_: &Path| false,
+        overrides,
+        DiscSize::Fit,
+    ).unwrap()
+}
+
+// (whether it's streamed audio, offset) for every file in the file system
+fn offsets(plan: &RebuildPlan) -> Vec<(bool, u64)> {
+    plan.files.iter()
+        .filter(|f| !f.path.to_string_lossy().contains("&&systemdata"))
+        .map(|f| {
+            let ext = f.path.extension().map(|e| e.to_string_lossy().to_lowercase());
+            (matches!(ext.as_deref(), Some("adp") | Some("dsp")), f.offset)
+        })
+        .collect()
+}
+
+fn all_aligned(offsets: &[(bool, u64)], audio: bool) -> bool {
+    offsets.iter().filter(|&&(a, _)| a == audio).all(|&(_, o)| o.is_multiple_of(STREAM_ALIGNMENT))
+}
+
+// With the header's audio streaming flag set, .adp and .dsp files start on
+// 32 KiB boundaries no matter what the alignment is, and nothing else does
+#[test]
+fn streamed_audio_is_aligned_only_when_the_flag_is_set() {
+    let rom = TestRom::new("rebuild-streaming");
+    let header = rom.root.join(HEADER_PATH);
+
+    let plan = plan_with_alignment(&rom.root, 4, None);
+    let off = offsets(&plan);
+    assert_eq!(off.iter().filter(|&&(a, _)| a).count(), 2);
+    assert!(!all_aligned(&off, true), "{:x?}", off);
+    assert!(plan.warnings.is_empty());
+
+    let mut data = fs::read(&header).unwrap();
+    data[AUDIO_STREAMING_OFFSET] = 1;
+    fs::write(&header, &data).unwrap();
+
+    let plan = plan_with_alignment(&rom.root, 4, None);
+    let on = offsets(&plan);
+    assert!(all_aligned(&on, true), "{:x?}", on);
+    assert!(!all_aligned(&on, false), "{:x?}", on);
+    assert!(plan.warnings.is_empty());
+
+    // Bigger alignments still apply to everything
+    let plan = plan_with_alignment(&rom.root, 0x10000, None);
+    assert!(offsets(&plan).iter().all(|&(_, o)| o.is_multiple_of(0x10000)));
+
+    // Turning it off packs them again, with a warning for each one that
+    // ends up unaligned
+    let plan = plan_with_alignment(&rom.root, 4, Some(false));
+    let forced_off = offsets(&plan);
+    assert_eq!(forced_off, off);
+    let unaligned = off.iter().filter(|&&(a, o)| a && !o.is_multiple_of(STREAM_ALIGNMENT)).count();
+    assert_eq!(plan.warnings.len(), unaligned, "{:?}", plan.warnings);
+
+    // And it can be forced on for a header without the flag
+    data[AUDIO_STREAMING_OFFSET] = 0;
+    fs::write(&header, &data).unwrap();
+    let plan = plan_with_alignment(&rom.root, 4, Some(true));
+    assert_eq!(offsets(&plan), on);
+}
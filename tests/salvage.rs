@@ -0,0 +1,98 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor};
+
+use gcmod::salvage::salvage;
+use gcmod::Game;
+
+use common::{TestDir, TestRom, sample_files};
+
+const ALIGNMENT: u64 = 0x8000;
+
+// (path, offset, contents)
+type KnownFile = (String, u64, Vec<u8>);
+
+// The ROM, with its Game.toc overwritten with junk, and where each of its
+// files really was
+fn damaged_rom(name: &str) -> (TestRom, Vec<u8>, Vec<KnownFile>) {
+    let rom = TestRom::with_files(name, &sample_files(), ALIGNMENT);
+    let mut data = rom.data();
+    let game = Game::open(Cursor::new(&data), 0).unwrap();
+    let files = game.fst.entries.iter()
+        .filter_map(|e| e.as_file())
+        .filter(|f| f.size > 0)
+        .map(|f| {
+            let start = f.file_offset as usize;
+            (f.info.full_path.display().to_string(), f.file_offset, data[start..start + f.size].to_vec())
+        })
+        .collect::<Vec<_>>();
+
+    let fst = game.fst.offset as usize..(game.fst.offset as usize + game.fst.size);
+    for (i, b) in data[fst].iter_mut().enumerate() {
+        *b = (i * 37 + 11) as u8;
+    }
+    assert!(Game::open(Cursor::new(&data), 0).is_err());
+    (rom, data, files)
+}
+
+#[test]
+fn carved_files_keep_trailing_zeros() {
+    let (rom, data, files) = damaged_rom("salvage");
+    let iso = rom.dir.join("damaged.iso");
+    fs::write(&iso, &data).unwrap();
+    let out = TestDir::new("salvage-out");
+    let out = out.join("salvaged");
+
+    let report = salvage(BufReader::new(File::open(&iso).unwrap()), &out, ALIGNMENT).unwrap();
+    assert!(report.is_rebuildable());
+
+    for (path, offset, original) in &files {
+        let carved = report.files.iter()
+            .find(|f| f.offset == *offset)
+            .unwrap_or_else(|| panic!("{} at {:#x} wasn't carved", path, offset));
+        let carved_data = fs::read(out.join(&carved.path)).unwrap();
+        assert_eq!(carved_data.len(), carved.size);
+        assert!(carved_data.len() >= original.len(), "{} was cut short", path);
+        assert_eq!(&carved_data[..original.len()], &original[..], "{} doesn't match", path);
+        assert!(carved_data[original.len()..].iter().all(|&b| b == 0), "{} has extra data", path);
+    }
+
+    // Nothing between the carved files but zeros
+    for f in &report.files {
+        let end = (f.offset + f.size as u64) as usize;
+        assert!(data[end..end + f.zeros_after as usize].iter().all(|&b| b == 0));
+    }
+}
+
+#[test]
+fn salvaged_rom_can_be_rebuilt() {
+    let (rom, data, files) = damaged_rom("salvage-rebuild");
+    let iso = rom.dir.join("damaged.iso");
+    fs::write(&iso, &data).unwrap();
+    let out = TestDir::new("salvage-rebuild-out");
+    let root = out.join("salvaged");
+    salvage(BufReader::new(File::open(&iso).unwrap()), &root, ALIGNMENT).unwrap();
+
+    let mut rebuilt = Vec::new();
+    gcmod::ROMRebuilder::rebuild(&root, ALIGNMENT, &mut rebuilt, None, false, |_| false, |_, _| {}).unwrap();
+    let game = Game::open(Cursor::new(&rebuilt), 0).unwrap();
+
+    // Everything check looks at
+    let fst_issues = game.validate();
+    assert!(fst_issues.is_empty(), "{:?}", fst_issues);
+    let dol_issues = game.dol.validate();
+    assert!(dol_issues.is_empty(), "{:?}", dol_issues);
+
+    for (path, offset, original) in &files {
+        let f = game.fst.entries.iter()
+            .filter_map(|e| e.as_file())
+            .find(|f| f.file_offset == *offset)
+            .unwrap_or_else(|| panic!("{} isn't on the rebuilt ROM", path));
+        let start = f.file_offset as usize;
+        assert_eq!(&rebuilt[start..start + original.len()], &original[..]);
+    }
+}
@@ -0,0 +1,132 @@
+extern crate byteorder;
+extern crate gcmod;
+
+mod common;
+
+use std::fs;
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use gcmod::source::split::{output_part_path, SplitFileReader, SplitFileWriter};
+use gcmod::source::{self, ImageSource};
+use gcmod::Game;
+
+use common::{pattern, TestDir, TestRom};
+
+const PART_SIZE: u64 = 100;
+
+// Writes `data` in pieces that don't line up with the parts, and returns
+// where the first part is
+fn write_parts(dir: &TestDir, data: &[u8]) -> PathBuf {
+    let path = dir.join("image.iso");
+    let mut output = SplitFileWriter::create(&path, PART_SIZE).unwrap();
+    for chunk in data.chunks(37) {
+        output.write_all(chunk).unwrap();
+    }
+    output.flush().unwrap();
+    output_part_path(&path, 0)
+}
+
+fn read_at(reader: &mut SplitFileReader, offset: u64, len: usize) -> Vec<u8> {
+    assert_eq!(reader.seek(SeekFrom::Start(offset)).unwrap(), offset);
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn writes_are_split_at_the_part_size() {
+    let dir = TestDir::new("split-write");
+    let data = pattern(350, 1);
+    let first = write_parts(&dir, &data);
+
+    let parts = (0..4).map(|n| fs::read(output_part_path(&dir.join("image.iso"), n)).unwrap()).collect::<Vec<_>>();
+    assert_eq!(first, dir.join("image.part0.iso"));
+    assert_eq!(parts.iter().map(|p| p.len()).collect::<Vec<_>>(), [100, 100, 100, 50]);
+    assert_eq!(parts.concat(), data);
+    assert!(!output_part_path(&dir.join("image.iso"), 4).exists());
+}
+
+#[test]
+fn reads_cross_part_boundaries() {
+    let dir = TestDir::new("split-read");
+    let data = pattern(350, 2);
+    let first = write_parts(&dir, &data);
+    let mut reader = SplitFileReader::open(&first).unwrap();
+    assert_eq!(reader.len().unwrap(), 350);
+
+    // Spanning one boundary, two boundaries, and starting or ending exactly
+    // on one
+    for &(offset, len) in &[(95, 10), (90, 120), (100, 10), (200, 100), (190, 10), (0, 350), (299, 51)] {
+        assert_eq!(read_at(&mut reader, offset, len), &data[offset as usize..offset as usize + len], "at {}", offset);
+    }
+
+    // Relative seeks from the end and from where it is now
+    assert_eq!(reader.seek(SeekFrom::End(-150)).unwrap(), 200);
+    let mut buf = [0; 5];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, &data[200..205]);
+    assert_eq!(reader.seek(SeekFrom::Current(-10)).unwrap(), 195);
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, &data[195..200]);
+
+    // Buffered reads stop at the end of the image, not the end of a part
+    reader.seek(SeekFrom::Start(98)).unwrap();
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, &data[98..]);
+    assert!(reader.fill_buf().unwrap().is_empty());
+}
+
+#[test]
+fn seeks_back_into_earlier_parts_are_written_there() {
+    let dir = TestDir::new("split-seek");
+    let path = dir.join("image.iso");
+    let mut data = pattern(250, 3);
+    {
+        let mut output = SplitFileWriter::create(&path, PART_SIZE).unwrap();
+        output.write_all(&data).unwrap();
+        // Exactly on a boundary, then across one
+        output.seek(SeekFrom::Start(100)).unwrap();
+        output.write_all(&[0xaa; 4]).unwrap();
+        output.seek(SeekFrom::Start(98)).unwrap();
+        output.write_all(&[0xbb; 4]).unwrap();
+        // Past the end, like when padding's skipped
+        output.seek(SeekFrom::Start(320)).unwrap();
+        output.write_all(&[0xcc; 5]).unwrap();
+        output.flush().unwrap();
+    }
+    data[98..102].copy_from_slice(&[0xbb; 4]);
+    data[102..104].copy_from_slice(&[0xaa; 2]);
+    data.resize(320, 0);
+    data.extend([0xcc; 5]);
+
+    let mut reader = SplitFileReader::open(output_part_path(&path, 0)).unwrap();
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).unwrap();
+    assert_eq!(read, data);
+    // Every part but the last is full size
+    assert_eq!(fs::metadata(output_part_path(&path, 2)).unwrap().len(), PART_SIZE);
+    assert_eq!(fs::metadata(output_part_path(&path, 3)).unwrap().len(), 25);
+}
+
+#[test]
+fn split_roms_open_from_the_first_part() {
+    let rom = TestRom::new("split-rom");
+    let data = rom.data();
+    let dir = TestDir::new("split-rom-parts");
+    let path = dir.join("rom.iso");
+    let mut output = SplitFileWriter::create(&path, 0x8000).unwrap();
+    output.write_all(&data).unwrap();
+    output.flush().unwrap();
+    drop(output);
+
+    let mut image = source::open(output_part_path(&path, 0)).unwrap();
+    assert_eq!(image.len().unwrap(), data.len() as u64);
+    let mut read = Vec::new();
+    image.read_to_end(&mut read).unwrap();
+    assert!(read == data);
+
+    let game = Game::open(source::open(output_part_path(&path, 0)).unwrap(), 0).unwrap();
+    assert_eq!(game.fst.file_count, rom.files.len());
+}